@@ -0,0 +1,33 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use silv::testdata::SyntheticVolume;
+use silv::{Field, RadyOptions};
+
+/// Decoding whole files from memory, so the time isn't spent reading from disk
+fn read(c: &mut Criterion) {
+    let synth = SyntheticVolume {
+        nrays: 720,
+        ngates: 1832,
+        fields: vec![Field::REF, Field::VEL, Field::SW, Field::ZDR],
+        ..Default::default()
+    };
+    let compressed = SyntheticVolume {
+        compress: true,
+        ..synth.clone()
+    };
+
+    let options = RadyOptions::default();
+
+    for (name, bytes) in [
+        ("dorade", synth.dorade(0)),
+        ("compressed dorade", compressed.dorade(0)),
+        ("nexrad", synth.nexrad()),
+        ("compressed nexrad", compressed.nexrad()),
+    ] {
+        c.bench_function(&format!("read {}", name), |b| {
+            b.iter(|| silv::read_from_bytes(&bytes, &options).unwrap().unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, read);
+criterion_main!(benches);
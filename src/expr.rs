@@ -0,0 +1,531 @@
+//! Arithmetic and logical expressions over the fields of a gate, such as `REF < 5 || RHO < 0.8`
+
+use crate::{is_missing, Error, Field, ParamDescription, RadarFile, Sweep, MISSING};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use tracing::instrument;
+
+/// Binary operators, from the loosest binding to the tightest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+impl BinaryOp {
+    /// How tightly the operator binds, and whether it groups to the right
+    fn precedence(&self) -> (u8, bool) {
+        match self {
+            BinaryOp::Or => (1, false),
+            BinaryOp::And => (2, false),
+            BinaryOp::Equal | BinaryOp::NotEqual => (3, false),
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => {
+                (4, false)
+            }
+            BinaryOp::Add | BinaryOp::Subtract => (5, false),
+            BinaryOp::Multiply | BinaryOp::Divide => (6, false),
+            BinaryOp::Power => (8, true),
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Power => "^",
+        }
+    }
+}
+
+/// Functions that can be called in an expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+}
+
+impl Function {
+    fn name(&self) -> &'static str {
+        match self {
+            Function::Abs => "abs",
+            Function::Sqrt => "sqrt",
+            Function::Exp => "exp",
+            Function::Ln => "ln",
+            Function::Log10 => "log10",
+        }
+    }
+
+    fn apply(&self, x: f64) -> f64 {
+        match self {
+            Function::Abs => x.abs(),
+            Function::Sqrt => x.sqrt(),
+            Function::Exp => x.exp(),
+            Function::Ln => x.ln(),
+            Function::Log10 => x.log10(),
+        }
+    }
+}
+
+/// Expression over the fields of a gate. Comparisons and logical operators give 1 for true and
+/// 0 for false
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Field(Field),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Call(Function, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Fields used by the expression
+    pub fn fields(&self) -> Vec<Field> {
+        let mut fields = Vec::new();
+        self.collect_fields(&mut fields);
+        fields
+    }
+
+    fn collect_fields(&self, fields: &mut Vec<Field>) {
+        match self {
+            Expr::Number(_) => (),
+            Expr::Field(field) if !fields.contains(field) => fields.push(field.clone()),
+            Expr::Field(_) => (),
+            Expr::Negate(expr) | Expr::Not(expr) | Expr::Call(_, expr) => {
+                expr.collect_fields(fields)
+            }
+            Expr::Binary(_, left, right) => {
+                left.collect_fields(fields);
+                right.collect_fields(fields);
+            }
+        }
+    }
+
+    /// Evaluates the expression with the values of fields at a gate. The result is None where a
+    /// field it needs is missing, except that `||` is true if either side is and `&&` is false
+    /// if either side is
+    pub fn eval(&self, value: &impl Fn(&Field) -> Option<f64>) -> Option<f64> {
+        let truth = |b: bool| if b { 1.0 } else { 0.0 };
+
+        match self {
+            Expr::Number(n) => Some(*n),
+            Expr::Field(field) => value(field),
+            Expr::Negate(expr) => expr.eval(value).map(|x| -x),
+            Expr::Not(expr) => expr.eval(value).map(|x| truth(x == 0.0)),
+            Expr::Call(function, expr) => expr.eval(value).map(|x| function.apply(x)),
+            Expr::Binary(BinaryOp::Or, left, right) => {
+                match (left.eval(value), right.eval(value)) {
+                    (Some(l), _) if l != 0.0 => Some(1.0),
+                    (_, Some(r)) if r != 0.0 => Some(1.0),
+                    (Some(_), Some(_)) => Some(0.0),
+                    _ => None,
+                }
+            }
+            Expr::Binary(BinaryOp::And, left, right) => match (left.eval(value), right.eval(value))
+            {
+                (Some(0.0), _) | (_, Some(0.0)) => Some(0.0),
+                (Some(_), Some(_)) => Some(1.0),
+                _ => None,
+            },
+            Expr::Binary(op, left, right) => {
+                let (l, r) = (left.eval(value)?, right.eval(value)?);
+
+                Some(match op {
+                    BinaryOp::Equal => truth(l == r),
+                    BinaryOp::NotEqual => truth(l != r),
+                    BinaryOp::Less => truth(l < r),
+                    BinaryOp::LessEqual => truth(l <= r),
+                    BinaryOp::Greater => truth(l > r),
+                    BinaryOp::GreaterEqual => truth(l >= r),
+                    BinaryOp::Add => l + r,
+                    BinaryOp::Subtract => l - r,
+                    BinaryOp::Multiply => l * r,
+                    BinaryOp::Divide => l / r,
+                    BinaryOp::Power => l.powf(r),
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                })
+            }
+        }
+    }
+
+    /// Evaluates the expression at every gate of a ray. Gates past the end of a field, or where
+    /// it is missing, don't have a value for it. Fields are matched ignoring case
+    pub fn eval_ray(&self, data: &HashMap<Field, Vec<f64>>) -> Vec<Option<f64>> {
+        let fields: Vec<(Field, Option<&Vec<f64>>)> = self
+            .fields()
+            .into_iter()
+            .map(|field| {
+                let values = find_key(data, &field).map(|name| &data[name]);
+                (field, values)
+            })
+            .collect();
+
+        let ngates = fields
+            .iter()
+            .filter_map(|(_, values)| values.map(|v| v.len()))
+            .max()
+            .unwrap_or(0);
+
+        (0..ngates)
+            .map(|gate| {
+                let value = |field: &Field| {
+                    let values = fields.iter().find(|(name, _)| name == field)?.1?;
+                    values.get(gate).copied().filter(|v| !is_missing(*v))
+                };
+
+                self.eval(&value).filter(|v| v.is_finite())
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Field(field) => write!(f, "{}", field),
+            Expr::Negate(expr) => write!(f, "-{}", expr),
+            Expr::Not(expr) => write!(f, "!{}", expr),
+            Expr::Call(function, expr) => write!(f, "{}({})", function.name(), expr),
+            Expr::Binary(op, left, right) => write!(f, "({} {} {})", left, op.symbol(), right),
+        }
+    }
+}
+
+/// Piece of an expression's text
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Op(BinaryOp),
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c.is_ascii_digit() || (c == '.' && next.is_some_and(|n| n.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+
+            // Exponents such as 1e-3
+            if i < chars.len() && matches!(chars[i], 'e' | 'E') {
+                let sign = usize::from(matches!(chars.get(i + 1), Some('+' | '-')));
+                if chars.get(i + 1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                    i += 1 + sign;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+
+            let number: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(
+                number
+                    .parse()
+                    .map_err(|_| format!("Invalid number {}", number))?,
+            ));
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+            continue;
+        }
+
+        let (token, len) = match (c, next) {
+            ('|', Some('|')) => (Token::Op(BinaryOp::Or), 2),
+            ('&', Some('&')) => (Token::Op(BinaryOp::And), 2),
+            ('=', Some('=')) => (Token::Op(BinaryOp::Equal), 2),
+            ('!', Some('=')) => (Token::Op(BinaryOp::NotEqual), 2),
+            ('<', Some('=')) => (Token::Op(BinaryOp::LessEqual), 2),
+            ('>', Some('=')) => (Token::Op(BinaryOp::GreaterEqual), 2),
+            ('*', Some('*')) => (Token::Op(BinaryOp::Power), 2),
+            ('<', _) => (Token::Op(BinaryOp::Less), 1),
+            ('>', _) => (Token::Op(BinaryOp::Greater), 1),
+            ('+', _) => (Token::Op(BinaryOp::Add), 1),
+            ('-', _) => (Token::Op(BinaryOp::Subtract), 1),
+            ('*', _) => (Token::Op(BinaryOp::Multiply), 1),
+            ('/', _) => (Token::Op(BinaryOp::Divide), 1),
+            ('^', _) => (Token::Op(BinaryOp::Power), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            _ => return Err(format!("Unexpected character {} in {}", c, s)),
+        };
+
+        tokens.push(token);
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+/// Precedence climbing parser over the tokens of an expression
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Parses operators that bind at least as tightly as `min`
+    fn expr(&mut self, min: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+
+        while let Some(&Token::Op(op)) = self.peek() {
+            let (precedence, right_assoc) = op.precedence();
+            if precedence < min {
+                break;
+            }
+
+            self.next();
+            let right = self.expr(if right_assoc {
+                precedence
+            } else {
+                precedence + 1
+            })?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    /// Parses negation, which binds looser than powers so that -2^2 is -4
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Op(BinaryOp::Subtract)) => {
+                self.next();
+                Ok(Expr::Negate(Box::new(self.expr(7)?)))
+            }
+            Some(Token::Not) => {
+                self.next();
+                Ok(Expr::Not(Box::new(self.expr(7)?)))
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Open) => {
+                let expr = self.expr(0)?;
+                self.close()?;
+                Ok(expr)
+            }
+            Some(Token::Name(name)) if self.peek() == Some(&Token::Open) => {
+                let function = match name.to_lowercase().as_str() {
+                    "abs" => Function::Abs,
+                    "sqrt" => Function::Sqrt,
+                    "exp" => Function::Exp,
+                    "ln" => Function::Ln,
+                    "log10" => Function::Log10,
+                    _ => return Err(format!("Unknown function {}", name)),
+                };
+
+                self.next();
+                let expr = self.expr(0)?;
+                self.close()?;
+                Ok(Expr::Call(function, Box::new(expr)))
+            }
+            Some(Token::Name(name)) => Ok(Expr::Field(name.parse().unwrap())),
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Close) => Ok(()),
+            _ => Err("Missing )".to_string()),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    /// Parses an expression of numbers, fields, `+ - * / ^`, comparisons, `&& || !`,
+    /// parentheses, and the functions abs, sqrt, exp, ln, and log10
+    fn from_str(s: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.expr(0)?;
+
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("Unexpected {:?} in {}", token, s)),
+        }
+    }
+}
+
+/// Censors every field at gates where a filter expression is true
+#[instrument(skip_all)]
+pub fn filter(sweep: &mut Sweep, expr: &Expr) {
+    for ray in &mut sweep.rays {
+        let censored: Vec<usize> = expr
+            .eval_ray(&ray.data)
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_some_and(|v| v != 0.0))
+            .map(|(gate, _)| gate)
+            .collect();
+
+        for data in ray.data.values_mut() {
+            for &gate in &censored {
+                if let Some(value) = data.get_mut(gate) {
+                    *value = MISSING;
+                }
+            }
+        }
+    }
+}
+
+/// New field computed at every gate from an expression, such as `VEL_KTS = VEL * 1.944`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Derived {
+    pub field: Field,
+    pub expr: Expr,
+}
+
+impl FromStr for Derived {
+    type Err = String;
+
+    /// Parses `NAME = expression`
+    fn from_str(s: &str) -> Result<Derived, String> {
+        let (name, expr) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected NAME = expression, got {}", s))?;
+        let name = name.trim();
+
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("Invalid field name {}", name));
+        }
+
+        Ok(Derived {
+            field: name.parse().unwrap(),
+            expr: expr.parse()?,
+        })
+    }
+}
+
+/// Key of a field in a map, matching its name ignoring case like the fields of an expression
+fn find_key<'a, V>(map: &'a HashMap<Field, V>, field: &Field) -> Option<&'a Field> {
+    match map.get_key_value(field) {
+        Some((name, _)) => Some(name),
+        None => map
+            .keys()
+            .find(|name| name.as_str().eq_ignore_ascii_case(field.as_str())),
+    }
+}
+
+/// Adds a derived field on the gates of the first field of its expression that the radar has.
+/// Gates where the expression has no value are missing. Fields are matched ignoring case, so a
+/// field the radar already has under another case is replaced. Returns an error if the radar has
+/// none of the fields of the expression
+#[instrument(skip_all, fields(field = derived.field.as_str()))]
+pub fn derive(radar: &mut RadarFile, derived: &Derived) -> Result<(), Error> {
+    let fields = derived.expr.fields();
+    let Some(source) = fields
+        .iter()
+        .find_map(|field| find_key(&radar.params, field))
+        .cloned()
+    else {
+        return Err(Error::Unsupported(format!(
+            "Can't derive {}, the radar has none of {:?}",
+            derived.field, fields
+        )));
+    };
+
+    let name = find_key(&radar.params, &derived.field)
+        .cloned()
+        .unwrap_or_else(|| derived.field.clone());
+    let param = ParamDescription {
+        description: format!("Derived from {}", derived.expr),
+        units: String::new(),
+        ..radar.params[&source].clone()
+    };
+    radar.params.insert(name.clone(), param);
+
+    for sweep in &mut radar.sweeps {
+        if let Some(&geometry) = sweep.gates.get(&source) {
+            sweep.gates.insert(name.clone(), geometry);
+        }
+
+        for ray in &mut sweep.rays {
+            if !fields
+                .iter()
+                .any(|field| find_key(&ray.data, field).is_some())
+            {
+                continue;
+            }
+
+            let values = derived
+                .expr
+                .eval_ray(&ray.data)
+                .into_iter()
+                .map(|value| value.unwrap_or(MISSING))
+                .collect();
+            ray.data.insert(name.clone(), values);
+        }
+    }
+
+    Ok(())
+}
@@ -1,3 +1,215 @@
+#[cfg(feature = "netcdf")]
+pub mod cfradial;
 pub mod dorade;
-// pub mod cfradial;
 pub mod nexrad;
+
+use crate::{Error, Field, ParamDescription, RadarFile, RadyOptions, Sweep};
+use lazy_static::lazy_static;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// A radar format that can be read
+pub trait RadarRead: Send + Sync {
+    /// Lowercase name of the format, e.g. "dorade"
+    fn name(&self) -> &'static str;
+
+    /// Checks the magic bytes of a file to see if it is in this format
+    fn sniff(&self, path: &Path) -> bool;
+
+    /// Reads a file in this format
+    fn read(&self, path: &Path, options: &RadyOptions) -> Result<RadarFile, Error>;
+
+    /// Reads a file held in memory, or returns None if it isn't in this format. Formats that can
+    /// only be read from a path also return None
+    fn decode(&self, _bytes: &[u8], _options: &RadyOptions) -> Option<Result<RadarFile, Error>> {
+        None
+    }
+}
+
+/// A radar format that can be written
+pub trait RadarWrite: Send + Sync {
+    /// Lowercase name of the format, e.g. "nexrad"
+    fn name(&self) -> &'static str;
+
+    /// Writes the radar into the output directory
+    fn write(&self, radar: RadarFile, path: &Path, options: &RadyOptions) -> Result<(), Error>;
+
+    /// Writes the radar into a single file held in memory, for formats that can write without a
+    /// path
+    fn encode(&self, _radar: RadarFile, _options: &RadyOptions) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Largest change to a value of a field from writing it, such as from packing it into integers
+    fn tolerance(&self, _field: &Field) -> f64 {
+        0.0
+    }
+
+    /// Opens a volume that is written sweep by sweep as the sweeps arrive, for formats that
+    /// can append to an open file. `radar` has the metadata of the volume but no sweeps
+    fn stream(
+        &self,
+        _radar: RadarFile,
+        _path: &Path,
+        _options: &RadyOptions,
+    ) -> Option<Box<dyn SweepSink>> {
+        None
+    }
+}
+
+/// Receives the sweeps of a volume one at a time
+pub trait SweepSink {
+    /// Adds the next sweep, along with the descriptions of its fields
+    fn push(
+        &mut self,
+        sweep: Sweep,
+        params: &HashMap<Field, ParamDescription>,
+    ) -> Result<(), Error>;
+
+    /// Finishes the volume after its last sweep
+    fn finish(self: Box<Self>) -> Result<(), Error>;
+}
+
+/// Readers and writers known to `read()` and `write()`
+struct Registry {
+    /// Readers, sniffed in the order they were registered
+    readers: Vec<Arc<dyn RadarRead>>,
+
+    /// Writers
+    writers: Vec<Arc<dyn RadarWrite>>,
+}
+
+lazy_static! {
+    static ref REGISTRY: RwLock<Registry> = RwLock::new(Registry {
+        readers: vec![
+            Arc::new(dorade::Dorade),
+            #[cfg(feature = "netcdf")]
+            Arc::new(cfradial::CfRadial),
+            Arc::new(nexrad::Nexrad),
+        ],
+        writers: vec![
+            Arc::new(nexrad::Nexrad),
+            #[cfg(feature = "netcdf")]
+            Arc::new(cfradial::CfRadial),
+            #[cfg(feature = "netcdf")]
+            Arc::new(cfradial::CfRadialFields),
+        ],
+    });
+}
+
+/// Registers a reader. A reader with the same name as an existing one replaces it,
+/// otherwise it is sniffed after all of the existing readers
+pub fn register_reader(reader: impl RadarRead + 'static) {
+    let mut registry = REGISTRY.write().unwrap();
+
+    match registry
+        .readers
+        .iter()
+        .position(|r| r.name() == reader.name())
+    {
+        Some(i) => registry.readers[i] = Arc::new(reader),
+        None => registry.readers.push(Arc::new(reader)),
+    }
+}
+
+/// Registers a writer. A writer with the same name as an existing one replaces it
+pub fn register_writer(writer: impl RadarWrite + 'static) {
+    let mut registry = REGISTRY.write().unwrap();
+
+    match registry
+        .writers
+        .iter()
+        .position(|w| w.name() == writer.name())
+    {
+        Some(i) => registry.writers[i] = Arc::new(writer),
+        None => registry.writers.push(Arc::new(writer)),
+    }
+}
+
+/// Finds the first reader whose sniffer accepts the file
+pub fn detect(path: &Path) -> Option<Arc<dyn RadarRead>> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .readers
+        .iter()
+        .find(|r| r.sniff(path))
+        .cloned()
+}
+
+/// Reads a file held in memory with the first reader that can decode it
+pub fn decode(bytes: &[u8], options: &RadyOptions) -> Option<Result<RadarFile, Error>> {
+    let readers = REGISTRY.read().unwrap().readers.clone();
+    readers.iter().find_map(|r| r.decode(bytes, options))
+}
+
+/// Finds a reader by name
+pub fn reader(name: &str) -> Option<Arc<dyn RadarRead>> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .readers
+        .iter()
+        .find(|r| r.name() == name)
+        .cloned()
+}
+
+/// Finds a writer by name
+pub fn writer(name: &str) -> Option<Arc<dyn RadarWrite>> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .writers
+        .iter()
+        .find(|w| w.name() == name)
+        .cloned()
+}
+
+/// Contents of a file being read
+pub(crate) enum FileBytes {
+    #[cfg(feature = "mmap")]
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl std::ops::Deref for FileBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(feature = "mmap")]
+            FileBytes::Mapped(mmap) => mmap,
+            FileBytes::Read(buf) => buf,
+        }
+    }
+}
+
+/// Maps a file into memory for reading. Files that can't be mapped, such as pipes and files on
+/// some network filesystems, are read into memory in one pass instead
+#[cfg(feature = "mmap")]
+pub(crate) fn map_file(path: &Path) -> std::io::Result<FileBytes> {
+    let mut file = File::open(path)?;
+
+    // Safety: the map is only read, and radar files are not modified while they are converted
+    match unsafe { Mmap::map(&file) } {
+        Ok(mmap) => Ok(FileBytes::Mapped(mmap)),
+        Err(_) => {
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)?;
+            Ok(FileBytes::Read(buf))
+        }
+    }
+}
+
+/// Reads a file into memory, on platforms without memory maps
+#[cfg(not(feature = "mmap"))]
+pub(crate) fn map_file(path: &Path) -> std::io::Result<FileBytes> {
+    let mut buf = Vec::new();
+    File::open(path)?.read_to_end(&mut buf)?;
+    Ok(FileBytes::Read(buf))
+}
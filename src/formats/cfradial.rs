@@ -1,77 +1,374 @@
-use crate::{ParamDescription, RadarFile, ScanMode, Sweep, Ray};
-use chrono::{offset::TimeZone, Duration, Utc};
+use super::{RadarRead, RadarWrite};
+use crate::{
+    elevation_label, is_missing, resolve_conflict, signed_elevation, Error, Field, Format,
+    InstrumentInfo, Naming, ParamDescription, RadarFile, RadyOptions, Ray, ScanMode, Sweep,
+    MISSING, SPEED_OF_LIGHT,
+};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use netcdf::AttrValue;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
 use std::{collections::HashMap, path::Path};
 
-pub fn is_cfradial() -> bool {
-    true
+/// Magic bytes of the netCDF classic, 64-bit offset, and CDF5 formats
+const CDF_MAGIC: [&[u8]; 3] = [b"CDF\x01", b"CDF\x02", b"CDF\x05"];
+
+/// Magic bytes of HDF5, which netCDF-4 files are stored as
+const HDF5_MAGIC: &[u8] = b"\x89HDF\r\n\x1a\n";
+
+/// Value written for missing gates
+const FILL_VALUE: f32 = -9999.0;
+
+/// Standard name of velocities that are positive away from the radar, as NEXRAD expects
+const VELOCITY_AWAY: &str = "radial_velocity_of_scatterers_away_from_instrument";
+
+/// CfRadial netCDF files
+pub struct CfRadial;
+
+impl RadarRead for CfRadial {
+    fn name(&self) -> &'static str {
+        "cfradial"
+    }
+
+    fn sniff(&self, path: &Path) -> bool {
+        is_cfradial(path)
+    }
+
+    fn read(&self, path: &Path, options: &RadyOptions) -> Result<RadarFile, Error> {
+        read_cfradial(path, options)
+    }
+}
+
+impl RadarWrite for CfRadial {
+    fn name(&self) -> &'static str {
+        "cfradial"
+    }
+
+    fn write(&self, radar: RadarFile, path: &Path, options: &RadyOptions) -> Result<(), Error> {
+        write_cfradial(&radar, path, options)
+    }
 }
 
-fn to_generic_name(name: &str) -> &str {
+pub fn is_cfradial(path: impl AsRef<Path>) -> bool {
+    // Checks if a file is a netCDF file following the CfRadial conventions
+
+    let mut magic = [0u8; 8];
+
+    if File::open(path.as_ref())
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_err()
+    {
+        return false;
+    }
+
+    if !CDF_MAGIC.iter().any(|m| magic.starts_with(m)) && magic != HDF5_MAGIC {
+        return false;
+    }
+
+    let reader = match netcdf::open(path.as_ref()) {
+        Ok(reader) => reader,
+        Err(_) => return false,
+    };
+
+    let conventions = match reader.attribute("Conventions").map(|v| v.value()) {
+        Some(Ok(AttrValue::Str(s))) => s,
+        _ => String::new(),
+    };
+
+    conventions.to_lowercase().contains("radial")
+        || [
+            "time",
+            "range",
+            "azimuth",
+            "elevation",
+            "sweep_start_ray_index",
+            "sweep_end_ray_index",
+        ]
+        .iter()
+        .all(|var| reader.variable(var).is_some())
+}
+
+fn to_generic_name(name: &str, options: &RadyOptions) -> Field {
+    if let Some(field) = options.field_map.get(name) {
+        return field.clone();
+    }
+
     match name {
-        "DBZ" | "DBZHC" | "DBZHC_F" => "REF",
-        "VEL" | "VEL_F" => "VEL",
-        "WIDTH" => "SW",
-        "RHOHV" | "RHOHV_F" => "RHO",
-        "PHIDP" => "PHI",
-        "KDP" => "KDP",
-        "ZDR" | "ZDR_F" => "ZDR",
-        _ => name,
+        "DBZ" | "DBZHC" | "DBZHC_F" => Field::REF,
+        "VEL" | "VEL_F" => Field::VEL,
+        "WIDTH" => Field::SW,
+        "RHOHV" | "RHOHV_F" => Field::RHO,
+        "PHIDP" => Field::PHI,
+        "KDP" => Field::KDP,
+        "ZDR" | "ZDR_F" => Field::ZDR,
+        _ => NAMED_FIELDS
+            .into_iter()
+            .find(|field| convention_names(field).is_some_and(|names| names.contains(&name)))
+            .unwrap_or_else(|| name.parse().unwrap()),
     }
 }
 
-pub fn read_cfradial(path: impl AsRef<Path>) -> RadarFile {
-    let data_types = [
+/// Fields with names in the Py-ART, CF, and OPERA conventions
+const NAMED_FIELDS: [Field; 8] = [
+    Field::REF,
+    Field::VEL,
+    Field::SW,
+    Field::ZDR,
+    Field::PHI,
+    Field::RHO,
+    Field::KDP,
+    Field::RATE,
+];
+
+/// Names of a field in the Py-ART, CF, and OPERA conventions, in that order. The CF name is also
+/// the standard name of the field
+fn convention_names(field: &Field) -> Option<[&'static str; 3]> {
+    Some(match field {
+        Field::REF => ["reflectivity", "equivalent_reflectivity_factor", "DBZH"],
+        Field::VEL => ["velocity", VELOCITY_AWAY, "VRADH"],
+        Field::SW => ["spectrum_width", "doppler_spectrum_width", "WRADH"],
+        Field::ZDR => [
+            "differential_reflectivity",
+            "log_differential_reflectivity_hv",
+            "ZDR",
+        ],
+        Field::PHI => ["differential_phase", "differential_phase_hv", "PHIDP"],
+        Field::RHO => [
+            "cross_correlation_ratio",
+            "cross_correlation_ratio_hv",
+            "RHOHV",
+        ],
+        Field::KDP => [
+            "specific_differential_phase",
+            "specific_differential_phase_hv",
+            "KDP",
+        ],
+        Field::RATE => ["radar_estimated_rain_rate", "rainfall_rate", "RATE"],
+        _ => return None,
+    })
+}
+
+fn to_cfradial_name(field: &Field, naming: Naming) -> &str {
+    let index = match naming {
+        Naming::Short => None,
+        Naming::PyArt => Some(0),
+        Naming::Cf => Some(1),
+        Naming::Opera => Some(2),
+    };
+
+    if let (Some(index), Some(names)) = (index, convention_names(field)) {
+        return names[index];
+    }
+
+    match field {
+        Field::REF => "DBZ",
+        Field::SW => "WIDTH",
+        Field::RHO => "RHOHV",
+        Field::PHI => "PHIDP",
+        _ => field.as_str(),
+    }
+}
+
+/// Adds the variable of a field, named and described in a naming convention. Short names only
+/// describe velocity, whose standard name says which way it is positive
+fn write_field(
+    file: &mut netcdf::MutableFile,
+    field: &Field,
+    param: &ParamDescription,
+    dims: &[&str],
+    data: &[f32],
+    naming: Naming,
+) {
+    let mut var = file
+        .add_variable::<f32>(to_cfradial_name(field, naming), dims)
+        .unwrap();
+    var.set_fill_value(FILL_VALUE).unwrap();
+    var.add_attribute("units", param.units.as_str()).unwrap();
+
+    if let Some(names) =
+        convention_names(field).filter(|_| naming != Naming::Short || *field == Field::VEL)
+    {
+        var.add_attribute("standard_name", names[1]).unwrap();
+    }
+
+    if naming != Naming::Short && !param.description.is_empty() {
+        var.add_attribute("long_name", param.description.as_str())
+            .unwrap();
+    }
+
+    var.put_values(data, ..).unwrap();
+}
+
+fn to_scan_mode(sweep_mode: &str) -> ScanMode {
+    match sweep_mode.trim_end_matches('\0').trim() {
+        "calibration" | "sunscan" => ScanMode::Calibration,
+        "coplane" => ScanMode::Coplane,
+        "rhi" | "manual_rhi" => ScanMode::RHI,
+        "vertical_pointing" => ScanMode::Vertical,
+        "pointing" => ScanMode::Stationary,
+        "manual_ppi" => ScanMode::Manual,
+        "sector" => ScanMode::Sector,
+        "idle" => ScanMode::Idle,
+        "elevation_surveillance" => ScanMode::Horizontal,
+        _ => ScanMode::PPI,
+    }
+}
+
+fn to_sweep_mode(scan_mode: ScanMode) -> &'static str {
+    match scan_mode {
+        ScanMode::Calibration => "calibration",
+        ScanMode::Coplane => "coplane",
+        ScanMode::RHI => "rhi",
+        ScanMode::Vertical => "vertical_pointing",
+        ScanMode::Stationary => "pointing",
+        ScanMode::Manual => "manual_ppi",
+        ScanMode::Sector => "sector",
+        ScanMode::Idle => "idle",
+        ScanMode::Horizontal => "elevation_surveillance",
+        ScanMode::PPI | ScanMode::Surveillance | ScanMode::Airborne => "azimuth_surveillance",
+    }
+}
+
+/// One CfRadial file per field per sweep, each field in its own directory, for tools that
+/// map single variable files
+pub struct CfRadialFields;
+
+impl RadarWrite for CfRadialFields {
+    fn name(&self) -> &'static str {
+        "cfradial_fields"
+    }
+
+    fn write(&self, radar: RadarFile, path: &Path, options: &RadyOptions) -> Result<(), Error> {
+        write_cfradial_fields(&radar, path, options)
+    }
+}
+
+/// Reads the sweep mode of a sweep, stored either as a string or as a char array
+fn read_sweep_mode(reader: &netcdf::File, sweep: usize) -> Option<String> {
+    let var = reader.variable("sweep_mode")?;
+
+    if let Ok(mode) = var.string_value(sweep) {
+        return Some(mode);
+    }
+
+    let len = var.dimensions().get(1)?.len();
+    let mut buf = vec![0u8; len];
+    var.raw_values(&mut buf, [sweep..sweep + 1, 0..len]).ok()?;
+
+    Some(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Parses the reference time out of a CF `units` string, e.g. "seconds since 2020-01-01T00:00:00Z"
+fn parse_time_units(units: &str) -> Option<DateTime<Utc>> {
+    let start = units.split("since").nth(1)?.trim().trim_end_matches('Z');
+
+    [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+    ]
+    .iter()
+    .find_map(|fmt| NaiveDateTime::parse_from_str(start, fmt).ok())
+    .map(|time| DateTime::from_naive_utc_and_offset(time, Utc))
+}
+
+/// Describes a netCDF error from reading a file
+fn read_error(e: netcdf::error::Error) -> Error {
+    Error::Format(format!("Can't read the netCDF file: {}", e))
+}
+
+/// A variable that every CfRadial file has
+fn variable<'f>(reader: &'f netcdf::File, name: &str) -> Result<netcdf::Variable<'f>, Error> {
+    reader
+        .variable(name)
+        .ok_or_else(|| Error::Format(format!("The file has no {} variable", name)))
+}
+
+/// Length of a dimension that every CfRadial file has
+fn dimension(reader: &netcdf::File, name: &str) -> Result<usize, Error> {
+    reader
+        .dimension(name)
+        .map(|dimension| dimension.len())
+        .ok_or_else(|| Error::Format(format!("The file has no {} dimension", name)))
+}
+
+/// Value of an attribute, if it is present and readable
+fn attribute_value(attribute: Option<netcdf::Attribute>) -> Option<AttrValue> {
+    attribute?.value().ok()
+}
+
+/// Parses a number stored as a string attribute
+fn parse_number(s: &str, name: &str) -> Result<f32, Error> {
+    s.trim()
+        .parse()
+        .map_err(|_| Error::Format(format!("{} isn't a number: {}", name, s)))
+}
+
+/// Reads the index of the first or last ray of a sweep
+fn ray_index(reader: &netcdf::File, name: &str, sweep: usize) -> Result<usize, Error> {
+    let index = variable(reader, name)?
+        .value::<i32, _>(sweep)
+        .map_err(read_error)?;
+
+    usize::try_from(index)
+        .map_err(|_| Error::Format(format!("{} has a negative index {}", name, index)))
+}
+
+pub fn read_cfradial(path: impl AsRef<Path>, options: &RadyOptions) -> Result<RadarFile, Error> {
+    let mut data_types = vec![
         "DBZ", "DBZHC", "DBZHC_F", "VEL", "VEL_F", "WIDTH", "KDP", "KDF_F", "PHIDP", "RHOHV",
-        "RHOHV_F", "ZDR", "ZDR_F",
+        "RHOHV_F", "ZDR", "ZDR_F", "RATE", "SRV", "ETH",
     ];
 
-    let reader = netcdf::open(path.as_ref()).unwrap();
-    // reader
-    //     .groups()
-    //     .unwrap()
-    //     .for_each(|x| println!("{:?}", x.name()));
-
-    // let str_start_time = {
-    //     if let netcdf::AttrValue::Str(s) = reader
-    //         .attribute("time_coverage_start")
-    //         .unwrap()
-    //         .value()
-    //         .unwrap()
-    //     {
-    //         s
-    //     } else {
-    //         panic!("No start time provided")
-    //     }
-    // };
-
-    let name = if let Some(AttrValue::Str(s)) = reader
-        .attribute("instrument_name")
-        .map(|v| v.value().unwrap())
-    {
-        s
-    } else {
-        panic!("Instrument name is not a string")
+    // As are the names of the other conventions
+    for name in NAMED_FIELDS.iter().filter_map(convention_names).flatten() {
+        if !data_types.contains(&name) {
+            data_types.push(name);
+        }
+    }
+
+    // Names from the field map are read as well
+    for name in options.field_map.keys() {
+        if !data_types.contains(&name.as_str()) {
+            data_types.push(name);
+        }
+    }
+
+    let reader = netcdf::open(path.as_ref())
+        .map_err(|e| Error::Format(format!("Can't open the netCDF file: {}", e)))?;
+
+    let name = match attribute_value(reader.attribute("instrument_name")) {
+        Some(AttrValue::Str(s)) => s,
+        _ => {
+            return Err(Error::Format(
+                "The instrument name isn't a string".to_string(),
+            ))
+        }
     };
 
     let mut radar = RadarFile {
         name,
         sweeps: Vec::new(),
         params: HashMap::new(),
-        scan_mode: ScanMode::PPI,
+        instrument: read_instrument(&reader),
+        scan_strategy: None,
+        volume_number: reader
+            .variable("volume_number")
+            .and_then(|var| var.value::<i32, _>(..).ok())
+            .and_then(|number| u32::try_from(number).ok()),
+        corrections: None,
     };
 
-    let range_var = reader.variable("range").unwrap();
+    let range_var = variable(&reader, "range")?;
 
-    let first_gate = match range_var
-        .attribute("meters_to_center_of_first_gate")
-        .map(|v| v.value().unwrap())
-    {
-        Some(AttrValue::Str(s)) => s.parse::<f32>().unwrap(),
+    let first_gate = match attribute_value(range_var.attribute("meters_to_center_of_first_gate")) {
+        Some(AttrValue::Str(s)) => parse_number(&s, "meters_to_center_of_first_gate")?,
         Some(AttrValue::Double(s)) => s as f32,
         Some(AttrValue::Float(s)) => s,
         v => {
-            println!(
+            eprintln!(
                 "Unknown meters_to_center_of_first_gate: {:?}, defaulting to 0",
                 v
             );
@@ -79,28 +376,27 @@ pub fn read_cfradial(path: impl AsRef<Path>) -> RadarFile {
         }
     };
 
-    let gate_range = match range_var
-        .attribute("meters_between_gates")
-        .map(|v| v.value().unwrap())
-    {
-        Some(AttrValue::Str(s)) => s.parse::<f32>().unwrap(),
+    let gate_range = match attribute_value(range_var.attribute("meters_between_gates")) {
+        Some(AttrValue::Str(s)) => parse_number(&s, "meters_between_gates")?,
         Some(AttrValue::Double(s)) => s as f32,
         Some(AttrValue::Float(s)) => s,
         Some(v) => {
-            println!("Unknown type for meters_between_gates: {:?}, defaulting to 100", v);
+            eprintln!(
+                "Unknown type for meters_between_gates: {:?}, defaulting to 100",
+                v
+            );
             100.0
         }
-        None => {
-            range_var.value::<f32>(Some(&[1])).unwrap() - range_var.value::<f32>(Some(&[0])).unwrap()
-        }
+        None => match (range_var.value::<f32, _>(0), range_var.value::<f32, _>(1)) {
+            (Ok(first), Ok(second)) => second - first,
+            _ => 100.0,
+        },
     };
 
-    dbg!(gate_range);
+    for &var in &data_types {
+        let corr_name = to_generic_name(var, options);
 
-    for var in data_types {
-        let corr_name = to_generic_name(var);
-
-        if reader.variable(var).is_none() {
+        if reader.variable(var).is_none() || !options.wants_field(&corr_name) {
             continue;
         }
 
@@ -111,120 +407,176 @@ pub fn read_cfradial(path: impl AsRef<Path>) -> RadarFile {
             meters_between_cells: gate_range,
         };
 
-        radar.params.insert(corr_name.to_string(), new_param);
+        radar.params.insert(corr_name, new_param);
+    }
+
+    if options.print_products {
+        println!(
+            "Products: {}",
+            radar
+                .params
+                .keys()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
     }
 
-    for i in 0..reader.dimension("sweep").unwrap().len() {
+    let time_var = variable(&reader, "time")?;
+
+    // Ray times are stored as offsets from the reference time in the units
+    let start_time = match attribute_value(time_var.attribute("units")) {
+        Some(AttrValue::Str(s)) => parse_time_units(&s),
+        _ => None,
+    }
+    .or_else(
+        || match attribute_value(reader.attribute("time_coverage_start")) {
+            Some(AttrValue::Str(s)) => parse_time_units(&format!("seconds since {}", s)),
+            _ => None,
+        },
+    )
+    .ok_or_else(|| Error::Format("The file has no start time".to_string()))?;
+
+    let ngates = dimension(&reader, "range")?;
+
+    for i in 0..dimension(&reader, "sweep")? {
         let mut sweep = Sweep::default();
 
-        let start_idx = reader
-            .variable("sweep_start_ray_index")
-            .unwrap()
-            .value::<u32>(Some(&[i]))
-            .unwrap() as usize;
-        let end_idx = reader
-            .variable("sweep_end_ray_index")
-            .unwrap()
-            .value::<u32>(Some(&[i]))
-            .unwrap() as usize;
+        // CfRadial ray indices are inclusive on both ends
+        let start_idx = ray_index(&reader, "sweep_start_ray_index", i)?;
+        let end_idx = ray_index(&reader, "sweep_end_ray_index", i)?;
 
-        sweep.elevation = reader
-            .variable("elevation")
-            .unwrap()
-            .value::<f32>(Some(&[start_idx]))
-            .unwrap();
+        sweep.scan_mode = read_sweep_mode(&reader, i)
+            .map(|mode| to_scan_mode(&mode))
+            .unwrap_or_default();
+
+        // The first ray may still be moving to the scheduled angle, which is an azimuth for RHIs
+        let rhi = sweep.scan_mode == ScanMode::RHI;
+        let angle = |angle: f32| {
+            if rhi {
+                angle.rem_euclid(360.0)
+            } else {
+                signed_elevation(angle)
+            }
+        };
+        sweep.fixed_angle = reader
+            .variable("fixed_angle")
+            .and_then(|var| var.value::<f32, _>(i).ok())
+            .map(angle);
+        sweep.elevation = match sweep.fixed_angle {
+            Some(fixed_angle) => fixed_angle,
+            None => angle(
+                variable(&reader, if rhi { "azimuth" } else { "elevation" })?
+                    .value::<f32, _>(start_idx)
+                    .map_err(read_error)?,
+            ),
+        };
         sweep.nyquist_velocity = reader
             .variable("nyquist_velocity")
-            .unwrap()
-            .value::<f32>(Some(&[start_idx]))
-            .unwrap();
-        sweep.latitude = reader
-            .variable("latitude")
-            .unwrap()
-            .value::<f32>(None)
-            .unwrap();
-        sweep.longitude = reader
-            .variable("longitude")
-            .unwrap()
-            .value::<f32>(None)
-            .unwrap();
+            .and_then(|var| var.value::<f32, _>(start_idx).ok())
+            .unwrap_or_default();
+        sweep.latitude = variable(&reader, "latitude")?
+            .value::<f32, _>(..)
+            .map_err(read_error)?;
+        sweep.longitude = variable(&reader, "longitude")?
+            .value::<f32, _>(..)
+            .map_err(read_error)?;
 
-        let times = reader
-            .variable("time")
-            .unwrap()
-            .values::<f64>(Some(&[start_idx]), Some(&[end_idx - start_idx]))
-            .unwrap();
-        let azims = reader
-            .variable("azimuth")
-            .unwrap()
-            .values::<f32>(Some(&[start_idx]), Some(&[end_idx - start_idx]))
-            .unwrap();
+        let rays = start_idx..=end_idx;
+        let times = time_var
+            .values::<f64, _>(rays.clone())
+            .map_err(read_error)?;
+        let azims = variable(&reader, "azimuth")?
+            .values::<f32, _>(rays.clone())
+            .map_err(read_error)?;
+        let elevs = variable(&reader, "elevation")?
+            .values::<f32, _>(rays.clone())
+            .map_err(read_error)?;
+        let raw_azims = reader
+            .variable("raw_azimuth")
+            .map(|var| var.values::<f32, _>(rays.clone()))
+            .transpose()
+            .map_err(read_error)?;
+        let transitions = reader
+            .variable("antenna_transition")
+            .map(|var| var.values::<i8, _>(rays.clone()))
+            .transpose()
+            .map_err(read_error)?;
 
-        let ngates = reader.dimension("range").unwrap().len();
-        // dbg!(ngates);
+        for (j, ray_idx) in rays.enumerate() {
+            let time = (times[j] * 1000.0).round() as i64;
 
-        for i in 0..(end_idx - start_idx) {
-            let time = (times[i] * 1000.0) as i64;
+            let mut data = HashMap::<Field, Vec<f64>>::new();
 
-            let mut data = HashMap::<String, Vec<f64>>::new();
+            for &var in &data_types {
+                let corr_name = to_generic_name(var, options);
 
-            for var in data_types {
-                let corr_name = to_generic_name(var);
-
-                let var_opt = reader.variable(var);
-
-                if var_opt.is_none() {
+                let Some(field_var) = reader
+                    .variable(var)
+                    .filter(|_| options.wants_field(&corr_name))
+                else {
                     continue;
-                }
+                };
 
-                let scale = match var_opt
-                    .as_ref()
-                    .unwrap()
-                    .attribute("scale_factor")
-                    .map(|v| v.value().unwrap())
-                {
+                let scale = match attribute_value(field_var.attribute("scale_factor")) {
                     Some(AttrValue::Double(x)) => x,
                     Some(AttrValue::Float(x)) => x as f64,
                     _ => 1.0,
                 };
 
-                let offset = match var_opt
-                    .as_ref()
-                    .unwrap()
-                    .attribute("add_offset")
-                    .map(|v| v.value().unwrap())
-                {
+                let offset = match attribute_value(field_var.attribute("add_offset")) {
                     Some(AttrValue::Double(x)) => x,
                     Some(AttrValue::Float(x)) => x as f64,
                     _ => 0.0,
                 };
 
-                let mut var_data = var_opt
-                    .unwrap()
-                    .values::<f64>(Some(&[i, 0]), Some(&[1, ngates]))
-                    .unwrap();
+                // Gates equal to the fill value have no data
+                let fill = ["_FillValue", "missing_value"].iter().find_map(|name| {
+                    match attribute_value(field_var.attribute(name)) {
+                        Some(AttrValue::Double(x)) => Some(x),
+                        Some(AttrValue::Float(x)) => Some(x as f64),
+                        Some(AttrValue::Int(x)) => Some(x as f64),
+                        Some(AttrValue::Short(x)) => Some(x as f64),
+                        Some(AttrValue::Schar(x)) => Some(x as f64),
+                        _ => None,
+                    }
+                });
 
-                let mut var_data = var_data * scale + offset;
+                // Velocities positive toward the radar are flipped to positive away
+                let sign = match attribute_value(field_var.attribute("standard_name")) {
+                    Some(AttrValue::Str(s)) if s.contains("toward_instrument") => -1.0,
+                    _ => 1.0,
+                };
+
+                let var_data = field_var
+                    .values::<f64, _>([ray_idx..ray_idx + 1, 0..ngates])
+                    .map_err(read_error)?;
 
-                // if corr_name == "REF" {
-                //     // var_data = var_data * 1.5 + 15.0;
-                //     println!("{i} {:?}", &var_data.as_slice().unwrap());
-                // }
-                // println!("{}", var);
-                // println!("{}, {}", scale, offset);
-                // println!("{:?}", ((var_data.clone() + offset) * scale).into_raw_vec());
                 data.insert(
-                    corr_name.to_string(),
-                    var_data.into_raw_vec(),
+                    corr_name,
+                    var_data
+                        .into_iter()
+                        .map(|v| {
+                            if Some(v) == fill {
+                                MISSING
+                            } else {
+                                sign * (v * scale + offset)
+                            }
+                        })
+                        .collect(),
                 );
             }
 
-            // TODO: FIX
             let new_ray = Ray {
-                // time: start_time.clone() + Duration::seconds(time),
-                azimuth: azims[i],
-                data: data,
-                ..Default::default()
+                time: start_time + Duration::milliseconds(time),
+                azimuth: azims[j],
+                elevation: signed_elevation(elevs[j]),
+                raw_azimuth: raw_azims.as_ref().map(|azims| azims[j]),
+                platform: None,
+                transition: transitions
+                    .as_ref()
+                    .is_some_and(|transitions| transitions[j] != 0),
+                data,
             };
 
             sweep.rays.push(new_ray);
@@ -233,5 +585,440 @@ pub fn read_cfradial(path: impl AsRef<Path>) -> RadarFile {
         radar.sweeps.push(sweep)
     }
 
-    radar
+    Ok(radar)
+}
+
+/// Generates the name of a new file in the output directory
+fn output_file_name(path: &Path, radar: &RadarFile, options: &RadyOptions) -> PathBuf {
+    let sweep = &radar.sweeps[0];
+
+    let name = match &options.name_format {
+        Some(name_format) => sweep
+            .time()
+            .format(name_format)
+            .to_string()
+            .replace("[icao]", &radar.name.to_uppercase())
+            .replace("[vol]", &radar.volume_number.unwrap_or(0).to_string()),
+        None => {
+            let angle = match sweep.scan_mode {
+                ScanMode::RHI => format!("rhi_{}", elevation_label(sweep.scheduled_angle())),
+                _ => sweep.label(),
+            };
+
+            sweep
+                .time()
+                .format(&Format::CFRADIAL.format_str())
+                .to_string()
+                + format!("_{}.nc", angle).as_str()
+        }
+    };
+
+    path.join(name)
+}
+
+/// Writes a radar to a CfRadial file. Fields are written on the gates of the first field
+pub fn write_cfradial(
+    radar: &RadarFile,
+    path: impl AsRef<Path>,
+    options: &RadyOptions,
+) -> Result<(), Error> {
+    // CfRadial has a single range axis, so fields on other gates are resampled onto it
+    let mut resampled;
+    let radar =
+        if radar.sweeps.iter().any(|sweep| !sweep.gates.is_empty()) || distinct_geometry(radar) {
+            resampled = radar.clone();
+            resampled.resample_gates();
+            &resampled
+        } else {
+            radar
+        };
+
+    let Some(file_name) = resolve_conflict(
+        output_file_name(path.as_ref(), radar, options),
+        options.on_conflict,
+    ) else {
+        return Ok(());
+    };
+    // The name format can put files in subdirectories
+    std::fs::create_dir_all(file_name.parent().unwrap())?;
+
+    let mut file = netcdf::create(file_name)
+        .map_err(|e| Error::Format(format!("Can't create the netCDF file: {}", e)))?;
+
+    let rays: Vec<&Ray> = radar.sweeps.iter().flat_map(|sweep| &sweep.rays).collect();
+    let ngates = rays
+        .iter()
+        .flat_map(|ray| ray.data.values())
+        .map(|v| v.len())
+        .max()
+        .unwrap_or(0);
+    let start_time = rays[0].time;
+    let end_time = rays.iter().map(|ray| ray.time).max().unwrap();
+
+    let mut fields: Vec<_> = radar.params.keys().collect();
+    fields.sort();
+
+    let (first_gate, gate_spacing) = fields
+        .first()
+        .map(|field| {
+            (
+                radar.params[*field].meters_to_first_cell,
+                radar.params[*field].meters_between_cells,
+            )
+        })
+        .unwrap_or((0.0, 250.0));
+
+    file.add_attribute("Conventions", "CF/Radial").unwrap();
+    file.add_attribute("instrument_name", radar.name.as_str())
+        .unwrap();
+    file.add_attribute(
+        "time_coverage_start",
+        start_time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+    )
+    .unwrap();
+    file.add_attribute(
+        "time_coverage_end",
+        end_time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+    )
+    .unwrap();
+
+    file.add_dimension("time", rays.len()).unwrap();
+    file.add_dimension("range", ngates).unwrap();
+    file.add_dimension("sweep", radar.sweeps.len()).unwrap();
+
+    let times: Vec<f64> = rays
+        .iter()
+        .map(|ray| (ray.time - start_time).num_milliseconds() as f64 / 1000.0)
+        .collect();
+    let mut var = file.add_variable::<f64>("time", &["time"]).unwrap();
+    var.add_attribute(
+        "units",
+        format!("seconds since {}", start_time.format("%Y-%m-%dT%H:%M:%SZ")),
+    )
+    .unwrap();
+    var.put_values(&times, ..).unwrap();
+
+    let ranges: Vec<f32> = (0..ngates)
+        .map(|gate| first_gate + gate as f32 * gate_spacing)
+        .collect();
+    let mut var = file.add_variable::<f32>("range", &["range"]).unwrap();
+    var.add_attribute("units", "meters").unwrap();
+    var.add_attribute("meters_to_center_of_first_gate", first_gate)
+        .unwrap();
+    var.add_attribute("meters_between_gates", gate_spacing)
+        .unwrap();
+    var.put_values(&ranges, ..).unwrap();
+
+    let mut var = file.add_variable::<f64>("latitude", &[]).unwrap();
+    var.put_value(radar.sweeps[0].latitude as f64, ..).unwrap();
+    let mut var = file.add_variable::<f64>("longitude", &[]).unwrap();
+    var.put_value(radar.sweeps[0].longitude as f64, ..).unwrap();
+
+    if let Some(number) = radar.volume_number {
+        file.add_variable::<i32>("volume_number", &[])
+            .unwrap()
+            .put_value(number as i32, ..)
+            .unwrap();
+    }
+
+    write_instrument(&mut file, &radar.instrument, rays.len());
+
+    let mut starts = Vec::new();
+    let mut ends = Vec::new();
+    for sweep in &radar.sweeps {
+        let start = ends.last().map_or(0, |end| end + 1);
+        starts.push(start);
+        ends.push(start + sweep.rays.len() as i32 - 1);
+    }
+
+    file.add_variable::<i32>("sweep_start_ray_index", &["sweep"])
+        .unwrap()
+        .put_values(&starts, ..)
+        .unwrap();
+    file.add_variable::<i32>("sweep_end_ray_index", &["sweep"])
+        .unwrap()
+        .put_values(&ends, ..)
+        .unwrap();
+
+    let fixed_angles: Vec<f32> = radar
+        .sweeps
+        .iter()
+        .map(|sweep| sweep.scheduled_angle())
+        .collect();
+    file.add_variable::<f32>("fixed_angle", &["sweep"])
+        .unwrap()
+        .put_values(&fixed_angles, ..)
+        .unwrap();
+
+    let mut var = file.add_string_variable("sweep_mode", &["sweep"]).unwrap();
+    for (i, sweep) in radar.sweeps.iter().enumerate() {
+        var.put_string(to_sweep_mode(sweep.scan_mode), i).unwrap();
+    }
+
+    let per_ray = |value: fn(&Sweep, &Ray) -> f32| -> Vec<f32> {
+        radar
+            .sweeps
+            .iter()
+            .flat_map(|sweep| sweep.rays.iter().map(move |ray| value(sweep, ray)))
+            .collect()
+    };
+
+    let azimuths = per_ray(|_, ray| ray.azimuth);
+    let elevations = per_ray(|_, ray| ray.elevation);
+    let nyquist = per_ray(|sweep, _| sweep.nyquist_velocity);
+    file.add_variable::<f32>("azimuth", &["time"])
+        .unwrap()
+        .put_values(&azimuths, ..)
+        .unwrap();
+    file.add_variable::<f32>("elevation", &["time"])
+        .unwrap()
+        .put_values(&elevations, ..)
+        .unwrap();
+    file.add_variable::<f32>("nyquist_velocity", &["time"])
+        .unwrap()
+        .put_values(&nyquist, ..)
+        .unwrap();
+
+    // Recorded azimuths of smoothed sweeps
+    if rays.iter().any(|ray| ray.raw_azimuth.is_some()) {
+        let raw_azimuths = per_ray(|_, ray| ray.raw_azimuth.unwrap_or(ray.azimuth));
+        file.add_variable::<f32>("raw_azimuth", &["time"])
+            .unwrap()
+            .put_values(&raw_azimuths, ..)
+            .unwrap();
+    }
+
+    if rays.iter().any(|ray| ray.transition) {
+        let transitions: Vec<i8> = rays.iter().map(|ray| ray.transition as i8).collect();
+        file.add_variable::<i8>("antenna_transition", &["time"])
+            .unwrap()
+            .put_values(&transitions, ..)
+            .unwrap();
+    }
+
+    for field in fields {
+        let data = gate_values(rays.iter().map(|ray| ray.data.get(field)), ngates);
+
+        write_field(
+            &mut file,
+            field,
+            &radar.params[field],
+            &["time", "range"],
+            &data,
+            options.naming,
+        );
+    }
+
+    Ok(())
+}
+
+/// Writes the instrument parameters that are known, using the CfRadial names and units
+fn write_instrument(file: &mut netcdf::MutableFile, instrument: &InstrumentInfo, nrays: usize) {
+    if let Some(altitude) = instrument.altitude {
+        let mut var = file.add_variable::<f64>("altitude", &[]).unwrap();
+        var.add_attribute("units", "meters").unwrap();
+        var.put_value(altitude as f64, ..).unwrap();
+    }
+
+    for (name, value) in [
+        ("radar_beam_width_h", instrument.horizontal_beam_width),
+        ("radar_beam_width_v", instrument.vertical_beam_width),
+    ] {
+        if let Some(value) = value {
+            let mut var = file.add_variable::<f32>(name, &[]).unwrap();
+            var.add_attribute("units", "degrees").unwrap();
+            var.put_value(value, ..).unwrap();
+        }
+    }
+
+    if let Some(wavelength) = instrument.wavelength {
+        file.add_dimension("frequency", 1).unwrap();
+        let mut var = file
+            .add_variable::<f32>("frequency", &["frequency"])
+            .unwrap();
+        var.add_attribute("units", "s-1").unwrap();
+        var.put_values(&[SPEED_OF_LIGHT / wavelength], ..).unwrap();
+    }
+
+    // Stored per ray in seconds
+    for (name, value) in [
+        ("prt", instrument.prf.map(|prf| 1.0 / prf)),
+        (
+            "pulse_width",
+            instrument.pulse_width.map(|width| width * 1e-6),
+        ),
+    ] {
+        if let Some(value) = value {
+            let mut var = file.add_variable::<f32>(name, &["time"]).unwrap();
+            var.add_attribute("units", "seconds").unwrap();
+            var.put_values(&vec![value; nrays], ..).unwrap();
+        }
+    }
+
+    if let Some(radar_constant) = instrument.radar_constant {
+        file.add_dimension("r_calib", 1).unwrap();
+        let mut var = file
+            .add_variable::<f32>("r_calib_radar_constant_h", &["r_calib"])
+            .unwrap();
+        var.add_attribute("units", "dB").unwrap();
+        var.put_values(&[radar_constant], ..).unwrap();
+    }
+}
+
+/// Reads the instrument parameters that are present in the file
+fn read_instrument(reader: &netcdf::File) -> InstrumentInfo {
+    let first = |name: &str| -> Option<f32> {
+        reader
+            .variable(name)
+            .and_then(|var| var.values::<f32, _>(..).ok())
+            .and_then(|values| values.first().copied())
+            .filter(|value| value.is_finite())
+    };
+
+    InstrumentInfo {
+        altitude: first("altitude"),
+        horizontal_beam_width: first("radar_beam_width_h"),
+        vertical_beam_width: first("radar_beam_width_v"),
+        wavelength: first("frequency").map(|frequency| SPEED_OF_LIGHT / frequency),
+        prf: first("prt").map(|prt| 1.0 / prt),
+        pulse_width: first("pulse_width").map(|width| width * 1e6),
+        radar_constant: first("r_calib_radar_constant_h"),
+    }
+}
+
+/// Checks if the fields of a radar have different gate geometries
+fn distinct_geometry(radar: &RadarFile) -> bool {
+    let mut geometries = radar.params.values().map(|param| param.geometry());
+    let first = geometries.next();
+
+    geometries.any(|geometry| Some(geometry) != first)
+}
+
+/// Writes each field of each sweep to its own CfRadial file, in a directory named after the field
+pub fn write_cfradial_fields(
+    radar: &RadarFile,
+    path: impl AsRef<Path>,
+    options: &RadyOptions,
+) -> Result<(), Error> {
+    for (field, param) in &radar.params {
+        for sweep in &radar.sweeps {
+            let mut sweep = sweep.clone();
+            sweep
+                .rays
+                .iter_mut()
+                .for_each(|ray| ray.data.retain(|f, _| f == field));
+
+            let single = RadarFile {
+                name: radar.name.clone(),
+                sweeps: vec![sweep],
+                params: HashMap::from([(field.clone(), param.clone())]),
+                instrument: radar.instrument,
+                scan_strategy: radar.scan_strategy.clone(),
+                volume_number: radar.volume_number,
+                corrections: radar.corrections,
+            };
+
+            write_cfradial(&single, path.as_ref().join(field.as_str()), options)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Flattens rays of data into rows of `ngates`, padding short or missing rays with the fill value
+fn gate_values<'a>(rays: impl Iterator<Item = Option<&'a Vec<f64>>>, ngates: usize) -> Vec<f32> {
+    rays.flat_map(|data| {
+        (0..ngates).map(move |gate| match data.and_then(|data| data.get(gate)) {
+            Some(&value) if !is_missing(value) => value as f32,
+            _ => FILL_VALUE,
+        })
+    })
+    .collect()
+}
+
+/// Writes the vertically pointing sweeps of a radar as a time-height series, with each field
+/// on a (time, height) grid and heights measured above the radar
+pub fn write_time_height(radar: &RadarFile, path: impl AsRef<Path>, options: &RadyOptions) {
+    let sweeps: Vec<&Sweep> = radar
+        .sweeps
+        .iter()
+        .filter(|sweep| sweep.scan_mode == ScanMode::Vertical)
+        .collect();
+
+    if sweeps.is_empty() {
+        eprintln!("No vertically pointing sweeps to write as a time-height series");
+        return;
+    }
+
+    let rays: Vec<&Ray> = sweeps.iter().flat_map(|sweep| &sweep.rays).collect();
+    let ngates = rays
+        .iter()
+        .flat_map(|ray| ray.data.values())
+        .map(|v| v.len())
+        .max()
+        .unwrap_or(0);
+    let start_time = rays[0].time;
+
+    let mut fields: Vec<_> = radar.params.keys().collect();
+    fields.sort();
+
+    let (first_gate, gate_spacing) = fields
+        .first()
+        .map(|field| {
+            (
+                radar.params[*field].meters_to_first_cell,
+                radar.params[*field].meters_between_cells,
+            )
+        })
+        .unwrap_or((0.0, 250.0));
+
+    std::fs::create_dir_all(path.as_ref()).unwrap();
+    let file_name = path
+        .as_ref()
+        .join(start_time.format("TIMEHEIGHT.%Y%m%d_%H%M%S.nc").to_string());
+    let mut file = netcdf::create(file_name).unwrap();
+
+    file.add_attribute("instrument_name", radar.name.as_str())
+        .unwrap();
+    file.add_dimension("time", rays.len()).unwrap();
+    file.add_dimension("height", ngates).unwrap();
+
+    let times: Vec<f64> = rays
+        .iter()
+        .map(|ray| (ray.time - start_time).num_milliseconds() as f64 / 1000.0)
+        .collect();
+    let mut var = file.add_variable::<f64>("time", &["time"]).unwrap();
+    var.add_attribute(
+        "units",
+        format!("seconds since {}", start_time.format("%Y-%m-%dT%H:%M:%SZ")),
+    )
+    .unwrap();
+    var.put_values(&times, ..).unwrap();
+
+    // Birdbath scans are close enough to vertical that the beam stays over the radar
+    let elevation = sweeps[0].elevation.to_radians();
+    let heights: Vec<f32> = (0..ngates)
+        .map(|gate| (first_gate + gate as f32 * gate_spacing) * elevation.sin())
+        .collect();
+    let mut var = file.add_variable::<f32>("height", &["height"]).unwrap();
+    var.add_attribute("units", "meters").unwrap();
+    var.put_values(&heights, ..).unwrap();
+
+    let mut var = file.add_variable::<f64>("latitude", &[]).unwrap();
+    var.put_value(sweeps[0].latitude as f64, ..).unwrap();
+    let mut var = file.add_variable::<f64>("longitude", &[]).unwrap();
+    var.put_value(sweeps[0].longitude as f64, ..).unwrap();
+
+    for field in fields {
+        let data = gate_values(rays.iter().map(|ray| ray.data.get(field)), ngates);
+
+        write_field(
+            &mut file,
+            field,
+            &radar.params[field],
+            &["time", "height"],
+            &data,
+            options.naming,
+        );
+    }
 }
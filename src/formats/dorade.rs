@@ -1,16 +1,29 @@
-use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+// Block names mirror the DORADE specification
+#![allow(clippy::upper_case_acronyms)]
+
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Utc};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::mem::size_of;
 use std::path::Path;
 
-use crate::{ParamDescription, RadarFile, RadyOptions, Ray, ScanMode, Sweep};
+use bincode::{DefaultOptions, Options};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_big_array::BigArray;
+use static_assertions::assert_eq_size;
+
+use super::RadarRead;
+use crate::{
+    signed_elevation, Corrections, Error, Field, InstrumentInfo, ParamDescription, Platform,
+    RadarFile, RadyOptions, Ray, ScanMode, Sweep, MISSING,
+};
 
 impl ScanMode {
-    fn from_num(num: u16) -> ScanMode {
-        match num {
+    fn from_num(num: u16) -> Result<ScanMode, Error> {
+        Ok(match num {
             0 => ScanMode::Calibration,
             1 => ScanMode::PPI,
             2 => ScanMode::Coplane,
@@ -22,22 +35,24 @@ impl ScanMode {
             8 => ScanMode::Surveillance,
             9 => ScanMode::Airborne,
             10 => ScanMode::Horizontal,
-            x => panic!("Unkown scan mode format {x}"),
-        }
+            x => return Err(Error::Format(format!("Unknown scan mode {x}"))),
+        })
     }
 }
 
 // Comment block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 struct COMM {
     id: [u8; 4],
     nbytes: u32,
+    #[serde(with = "BigArray")]
     comment: [u8; 500],
 }
 
 // Super Sweep Identification Block
 #[repr(C, packed)]
+#[derive(Deserialize)]
 struct SSWB {
     id: [u8; 4],
     nbytes: u32,
@@ -83,7 +98,7 @@ struct SSWB {
 
 // Volume description block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 struct VOLD {
     id: [u8; 4],
     nbytes: u32,
@@ -166,6 +181,7 @@ struct VOLD {
 
 // Radar description
 #[repr(C, packed)]
+#[derive(Deserialize)]
 struct RADD {
     id: [u8; 4],
     nbytes: u32,
@@ -209,7 +225,7 @@ struct RADD {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 struct LIDR {
     id: [u8; 4],
     nbytes: u32,
@@ -245,7 +261,7 @@ struct LIDR {
 
 // Correction factor
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct CFAC {
     id: [u8; 4],
     nbytes: u32,
@@ -305,11 +321,12 @@ pub struct CFAC {
 
 // Parameter (data field) description
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct PARM {
     id: [u8; 4],
     nbytes: u32,
     parameter_name: [u8; 8],
+    #[serde(with = "BigArray")]
     param_description: [u8; 40],
     param_units: [u8; 8],
     interpulse_time: u16,
@@ -328,17 +345,18 @@ pub struct PARM {
 
 // Cell vector block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct CELV {
     id: [u8; 4],
     nbytes: u32,
     number_cells: u32,
+    #[serde(with = "BigArray")]
     dist_cells: [f32; 1500],
 }
 
 // Cell spacing table
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct CSFD {
     id: [u8; 4],
     nbytes: u32,
@@ -350,7 +368,7 @@ pub struct CSFD {
 
 // Sweep information table
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct SWIB {
     id: [u8; 4],
     nbytes: u32,
@@ -365,7 +383,7 @@ pub struct SWIB {
 
 // Platform geo-reference block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct ASIB {
     id: [u8; 4],
     nbytes: u32,
@@ -391,7 +409,7 @@ pub struct ASIB {
 
 // Ray information block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct RYIB {
     id: [u8; 4],
     nbytes: u32,
@@ -410,7 +428,7 @@ pub struct RYIB {
 
 // Field data block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct RDAT {
     id: [u8; 4],
     nbytes: u32,
@@ -419,7 +437,7 @@ pub struct RDAT {
 
 // Extended field data block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct QDAT {
     id: [u8; 4],
     nbytes: u32,
@@ -433,7 +451,7 @@ pub struct QDAT {
 
 // Extra stuff block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct XSTF {
     id: [u8; 4],
     nbytes: u32,
@@ -445,7 +463,7 @@ pub struct XSTF {
 
 // Null block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct _NULL {
     id: [u8; 4],
     nbytes: u32,
@@ -453,7 +471,7 @@ pub struct _NULL {
 
 // Rotation angle data block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct _RKTB {
     id: [u8; 4],
     nbytes: u32,
@@ -466,7 +484,7 @@ pub struct _RKTB {
 
 // Radar parameter block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 pub struct _FRAD {
     id: [u8; 4],
     nbytes: u32,
@@ -486,7 +504,7 @@ pub struct _FRAD {
 
 // Field radar block
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 struct _FRIB {
     id: [u8; 4],
     nbytes: u32,
@@ -519,6 +537,7 @@ struct _FRIB {
     indepf_times_flg: u16,
     time_series_gate: u16,
     num_base_params: u16,
+    #[serde(with = "BigArray")]
     file_name: [u8; 80],
 }
 
@@ -533,167 +552,363 @@ struct ParmDesc {
 
 struct DoradeDesc {
     start_time: DateTime<Utc>,
-    parm_desc: HashMap<String, ParmDesc>,
+    parm_desc: HashMap<Field, ParmDesc>,
     ngates: u16,
     compress: u16,
     scan_mode: ScanMode,
+    radar_type: u16,
+    corrections: Option<Corrections>,
+}
+
+// Radar types of airborne radars in RADD blocks
+const AIR_FORE: u16 = 1;
+const AIR_AFT: u16 = 2;
+const AIR_TAIL: u16 = 3;
+const AIR_LF: u16 = 4;
+
+fn is_airborne(radar_type: u16) -> bool {
+    matches!(radar_type, AIR_FORE | AIR_AFT | AIR_TAIL | AIR_LF)
 }
 
+/// Position and attitude of the platform from an ASIB block
+fn platform(asib: &ASIB) -> Platform {
+    Platform {
+        latitude: asib.latitude,
+        longitude: asib.longitude,
+        altitude: asib.altitude_msl * 1000.0,
+        velocity: [asib.ew_velocity, asib.ns_velocity, asib.vert_velocity],
+        heading: asib.heading,
+        roll: asib.roll,
+        pitch: asib.pitch,
+        drift: asib.drift_angle,
+        rotation: asib.rotation_angle,
+        tilt: asib.tilt,
+    }
+}
+
+/// Correction factors of a CFAC block, with altitudes in meters
+fn corrections(cfac: &CFAC) -> Corrections {
+    Corrections {
+        azimuth: cfac.azimuth_corr,
+        elevation: cfac.elevation_curr,
+        range_delay: cfac.range_delay_corr,
+        latitude: cfac.latitude_corr,
+        longitude: cfac.longitude_corr,
+        pressure_altitude: cfac.pressure_alt_corr * 1000.0,
+        altitude: cfac.radar_alt_corr * 1000.0,
+        velocity: [cfac.ew_gndspd_corr, cfac.ns_gndspd_corr, cfac.vert_vel_corr],
+        heading: cfac.heading_corr,
+        roll: cfac.roll_corr,
+        pitch: cfac.pitch_corr,
+        drift: cfac.drift_corr,
+        rotation: cfac.rot_angle_corr,
+        tilt: cfac.tilt_corr,
+    }
+}
+
+/// Reads a block, leaving the reader after it. RDAT and QDAT blocks are followed by their data,
+/// so the reader is left after their fixed part
 macro_rules! consume_block {
-    // Macro to convert bytes into a block
+    ($reader:expr, $struc:ty) => {
+        $reader.read_block::<$struc>()
+    };
+}
 
-    ($reader:expr, $struc:ty) => {{
-        const N: usize = size_of::<$struc>();
-        let mut new_struc: $struc = unsafe { std::mem::zeroed() };
+// Block sizes must match the specification, as the data of RDAT and QDAT blocks follows them
+assert_eq_size!(COMM, [u8; 508]);
+assert_eq_size!(SSWB, [u8; 196]);
+assert_eq_size!(VOLD, [u8; 72]);
+assert_eq_size!(RADD, [u8; 144]);
+assert_eq_size!(LIDR, [u8; 148]);
+assert_eq_size!(CFAC, [u8; 72]);
+assert_eq_size!(PARM, [u8; 104]);
+assert_eq_size!(CELV, [u8; 6012]);
+assert_eq_size!(CSFD, [u8; 64]);
+assert_eq_size!(SWIB, [u8; 40]);
+assert_eq_size!(ASIB, [u8; 80]);
+assert_eq_size!(RYIB, [u8; 44]);
+assert_eq_size!(RDAT, [u8; 16]);
+assert_eq_size!(QDAT, [u8; 56]);
+assert_eq_size!(XSTF, [u8; 24]);
+
+#[allow(clippy::wrong_self_convention)]
+trait AsString<'a> {
+    fn as_string(self) -> Result<String, Error>;
+    fn as_str(self) -> Result<&'a str, Error>;
+}
 
-        unsafe {
-            let slice = std::slice::from_raw_parts_mut(&mut new_struc as *mut _ as *mut u8, N);
-            $reader.read_exact(slice).unwrap();
-        }
+impl<'a> AsString<'a> for &'a [u8] {
+    fn as_string(self) -> Result<String, Error> {
+        Ok(self.as_str()?.to_string())
+    }
+    fn as_str(self) -> Result<&'a str, Error> {
+        std::str::from_utf8(self)
+            .map(|s| s.trim_matches(char::from(0)))
+            .map_err(|e| Error::Format(format!("Text in a block isn't valid UTF-8: {}", e)))
+    }
+}
 
-        if new_struc.id.as_str().unwrap() != "RDAT" && new_struc.id.as_str().unwrap() != "QDAT" {
-            let seek_bytes = (new_struc.nbytes - N as u32) as i64;
+/// Reader over the memory-mapped file
+type Reader<'a> = Cursor<&'a [u8]>;
 
-            $reader.seek(SeekFrom::Current(seek_bytes)).unwrap();
+/// Takes the next `len` bytes without copying them
+fn take<'a>(reader: &mut Reader<'a>, len: usize) -> Result<&'a [u8], Error> {
+    let pos = reader.position() as usize;
+    let bytes = pos
+        .checked_add(len)
+        .and_then(|end| reader.get_ref().get(pos..end))
+        .ok_or_else(|| Error::Format("A data block goes past the end of the file".to_string()))?;
 
-            // if new_struc.id.as_str() == "CFAC" {
-            //     println!("{:?}, {}", new_struc, size_of::<CFAC>());
-            // }
+    reader.set_position((pos + len) as u64);
+    Ok(bytes)
+}
 
-            // assert_eq!(new_struc.nbytes as usize, N, "Struct sizes do not match. Expected: {}, Found: {}", stringify!($struc), struc_name);
-        }
+/// Length of a block from its header, if the bytes are long enough to have one
+fn block_len(bytes: &[u8]) -> Option<usize> {
+    Some(u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?) as usize)
+}
 
-        new_struc
-    }};
+/// Blocks are little endian, with fixed size integers
+fn block_options() -> impl Options {
+    DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .allow_trailing_bytes()
 }
 
-trait AsString<'a> {
-    fn as_string(self) -> Result<String, core::str::Utf8Error>;
-    fn as_str(self) -> Result<&'a str, core::str::Utf8Error>;
+trait ReadBlock {
+    fn read_block<T: DeserializeOwned>(&mut self) -> Result<T, Error>;
 }
 
-impl<'a> AsString<'a> for &'a [u8] {
-    fn as_string(self) -> Result<String, core::str::Utf8Error> {
-        Ok(self.as_str()?.to_string())
+impl ReadBlock for Reader<'_> {
+    fn read_block<T: DeserializeOwned>(&mut self) -> Result<T, Error> {
+        let start = self.position() as usize;
+        let bytes = self.get_ref().get(start..).unwrap_or_default();
+        let id = String::from_utf8_lossy(bytes.get(..4).unwrap_or(bytes)).into_owned();
+
+        let block: T = block_options()
+            .deserialize(bytes)
+            .map_err(|e| Error::Format(format!("Could not read the {} block: {}", id, e)))?;
+
+        let len = if id == "RDAT" || id == "QDAT" {
+            size_of::<T>()
+        } else {
+            std::cmp::max(block_len(bytes).unwrap_or(0), size_of::<T>())
+        };
+
+        self.set_position((start + len) as u64);
+        Ok(block)
     }
-    fn as_str(self) -> Result<&'a str, core::str::Utf8Error> {
-        Ok(std::str::from_utf8(self)?.trim_matches(char::from(0)))
+}
+
+/// Skips blocks up to the next one with one of the wanted ids, and returns its id. Blocks the
+/// reader doesn't use, such as RKTB, FRIB, and SEDS, are skipped by their length. Returns None at
+/// the end of the data
+fn skip_to(reader: &mut Reader, wanted: &[&str]) -> Result<Option<String>, Error> {
+    loop {
+        let pos = reader.position() as usize;
+        let bytes = reader.get_ref().get(pos..).unwrap_or_default();
+
+        let Some(nbytes) = block_len(bytes) else {
+            return Ok(None);
+        };
+
+        let id = String::from_utf8_lossy(&bytes[..4]).into_owned();
+        if wanted.contains(&id.as_str()) {
+            return Ok(Some(id));
+        }
+
+        if nbytes < 8 {
+            return Err(Error::Format(format!(
+                "The {} block has an invalid length of {} bytes",
+                id, nbytes
+            )));
+        }
+
+        reader.set_position((pos + nbytes) as u64);
     }
 }
 
 trait NextString<'a> {
-    fn next_string(&mut self) -> Result<String, core::str::Utf8Error>;
+    /// Id of the next block, or None at the end of the data
+    fn next_string(&mut self) -> Option<String>;
 }
 
-impl<'a> NextString<'a> for File {
-    fn next_string(&mut self) -> Result<String, core::str::Utf8Error> {
-        let mut tmp = [0u8; 4];
-        self.read_exact(&mut tmp).unwrap();
-        let next = tmp.as_string()?;
-        self.seek(SeekFrom::Current(-4)).unwrap();
-        Ok(next)
+impl<'a> NextString<'a> for Cursor<&'a [u8]> {
+    fn next_string(&mut self) -> Option<String> {
+        let pos = self.position() as usize;
+        self.get_ref().get(pos..pos + 4)?.as_string().ok()
     }
 }
 
-fn dorade_to_generic_name(name: String) -> String {
+fn dorade_to_generic_name(name: String, options: &RadyOptions) -> Field {
     // Converts format-specific variable names to the generic names
 
+    if let Some(field) = options.field_map.get(&name) {
+        return field.clone();
+    }
+
     match name.as_str() {
-        "DBZ" | "DCZ" | "DBZHM" => "REF",
-        "VEL" | "VC" => "VEL",
-        "WIDTH" => "SW",
-        "ZDR" => "ZDR",
-        "PHI" => "PHI",
-        "KDP" => "KDP",
-        "RHOHV" => "RHO",
-        _ => name.as_str(),
+        "DBZ" | "DCZ" | "DBZHM" => Field::REF,
+        "VEL" | "VC" => Field::VEL,
+        "WIDTH" => Field::SW,
+        "RHOHV" => Field::RHO,
+        _ => name.parse().unwrap(),
+    }
+}
+
+/// DORADE sweep files
+pub struct Dorade;
+
+impl RadarRead for Dorade {
+    fn name(&self) -> &'static str {
+        "dorade"
+    }
+
+    fn sniff(&self, path: &Path) -> bool {
+        is_dorade(path)
+    }
+
+    fn read(&self, path: &Path, options: &RadyOptions) -> Result<RadarFile, Error> {
+        read_dorade(path, options)
+    }
+
+    fn decode(&self, bytes: &[u8], options: &RadyOptions) -> Option<Result<RadarFile, Error>> {
+        matches!(bytes.get(..4), Some(b"COMM") | Some(b"SSWB"))
+            .then(|| decode_dorade(bytes, options))
     }
-    .to_string()
 }
 
 pub fn is_dorade(path: impl AsRef<Path>) -> bool {
     // Checks if a file is in the dorade format
 
-    let mut file = File::open(path).unwrap();
-    let id = file.next_string();
+    let mut id = [0u8; 4];
 
-    if let Ok(result) = id {
-        if result == "COMM".to_string() || result == "SSWB".to_string() {
-            return true;
-        }
+    if File::open(path)
+        .and_then(|mut file| file.read_exact(&mut id))
+        .is_err()
+    {
+        return false;
     }
 
-    false
+    matches!(id.as_str(), Ok("COMM") | Ok("SSWB"))
 }
 
-pub fn read_dorade(path: impl AsRef<Path>, options: &RadyOptions) -> RadarFile {
-    // Reads a dorade file
+pub fn read_dorade(path: impl AsRef<Path>, options: &RadyOptions) -> Result<RadarFile, Error> {
+    decode_dorade(&super::map_file(path.as_ref())?, options)
+}
 
-    let mut reader = File::open(path).unwrap();
+/// Reads the contents of a dorade file
+pub fn decode_dorade(bytes: &[u8], options: &RadyOptions) -> Result<RadarFile, Error> {
+    let mut reader = Cursor::new(bytes);
 
-    // Load the first 3 blocks.
-    // TODO: Check if they all always present
-    if reader.next_string().unwrap().as_str() == "COMM" {
-        let _comm = consume_block!(reader, COMM);
+    // Comments and other blocks before the sweep and volume descriptions are skipped
+    if skip_to(&mut reader, &["SSWB"])?.is_none() {
+        return Err(Error::Format(
+            "The file ends before its sweep description".to_string(),
+        ));
     }
+    let sswb = consume_block!(reader, SSWB)?;
 
-    let sswb = consume_block!(reader, SSWB);
-    let vold = consume_block!(reader, VOLD);
-
-    assert_eq!(sswb.id.as_str().unwrap(), "SSWB");
-    assert_eq!(vold.id.as_str().unwrap(), "VOLD");
+    if skip_to(&mut reader, &["VOLD"])?.is_none() {
+        return Err(Error::Format(
+            "The file ends before its volume description".to_string(),
+        ));
+    }
+    let vold = consume_block!(reader, VOLD)?;
 
     let mut radar = RadarFile {
-        name: sswb.radar_name.as_string().unwrap(),
+        name: sswb.radar_name.as_string()?,
         sweeps: Vec::new(),
         params: HashMap::new(),
+        instrument: InstrumentInfo::default(),
+        scan_strategy: None,
+        volume_number: Some(vold.volume_num as u32),
+        corrections: None,
     };
 
     let mut desc = DoradeDesc {
-        start_time: Utc.timestamp(sswb.start_time as i64, 0),
+        start_time: Utc
+            .timestamp_opt(sswb.start_time as i64, 0)
+            .single()
+            .ok_or_else(|| Error::Format("The start time is out of range".to_string()))?,
         parm_desc: HashMap::new(),
         ngates: 0,
         compress: 0,
         scan_mode: ScanMode::PPI,
+        radar_type: 0,
+        corrections: None,
     };
 
-    load_sensor(&mut reader, &mut radar, &mut desc);
+    load_sensor(&mut reader, &mut radar, &mut desc, options)?;
+    radar.corrections = desc.corrections;
 
     if options.print_products {
         println!(
             "Products: {}",
-            radar.params.keys().cloned().collect::<Vec<_>>().join(", ")
+            radar
+                .params
+                .keys()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
         )
     }
 
-    load_sweep(&mut reader, &mut radar, &mut desc, options);
+    load_sweep(&mut reader, &mut radar, &mut desc, options)?;
+
+    radar.params.retain(|field, _| options.wants_field(field));
 
-    radar
+    Ok(radar)
 }
 
 /// Loads the sensor (header) part of the data
-fn load_sensor(reader: &mut File, radar: &mut RadarFile, desc: &mut DoradeDesc) {
+fn load_sensor(
+    reader: &mut Reader,
+    radar: &mut RadarFile,
+    desc: &mut DoradeDesc,
+    options: &RadyOptions,
+) -> Result<(), Error> {
     // Load cell correction block
-    // TODO: Look into
-    if reader.next_string().unwrap().as_str() == "CFAC" {
-        let _cfac = consume_block!(reader, CFAC);
+    if reader.next_string().as_deref() == Some("CFAC") {
+        desc.corrections = Some(corrections(&consume_block!(reader, CFAC)?));
     }
 
-    let radd = consume_block!(reader, RADD);
-    desc.scan_mode = ScanMode::from_num(radd.scan_mode);
+    skip_to(reader, &["RADD"])?;
+    let radd = consume_block!(reader, RADD)?;
+    desc.scan_mode = ScanMode::from_num(radd.scan_mode)?;
+    desc.radar_type = radd.radar_type;
 
     desc.compress = radd.data_compress;
 
+    // Unset values are left as zero
+    let known = |value: f32| Some(value).filter(|value| *value != 0.0 && value.is_finite());
+    radar.instrument = InstrumentInfo {
+        altitude: known(radd.radar_altitude).map(|km| km * 1000.0),
+        horizontal_beam_width: known(radd.horz_beam_width),
+        vertical_beam_width: known(radd.vert_beam_width),
+        wavelength: known(radd.freq1).map(|ghz| crate::SPEED_OF_LIGHT / (ghz * 1e9)),
+        prf: known(radd.interpulse_per1).map(|ms| 1000.0 / ms),
+        pulse_width: None,
+        radar_constant: known(radd.radar_const),
+    };
+
     // If LIDR exists read it
-    if reader.next_string().unwrap() == "LIDR" {
-        let _lidr = consume_block!(reader, LIDR);
+    if reader.next_string().as_deref() == Some("LIDR") {
+        let _lidr = consume_block!(reader, LIDR)?;
     }
 
-    // Read all of the PARM blocks
-    while reader.next_string().unwrap() == "PARM" {
-        let parm = consume_block!(reader, PARM);
+    // Read all of the PARM blocks, up to the cell descriptor
+    while skip_to(reader, &["PARM", "CELV", "CSFD"])?.as_deref() == Some("PARM") {
+        let parm = consume_block!(reader, PARM)?;
 
-        let new_name = dorade_to_generic_name(parm.parameter_name.as_string().unwrap());
+        let new_name = dorade_to_generic_name(parm.parameter_name.as_string()?, options);
+
+        // The pulse width is stored as a length in meters
+        if parm.pulse_width > 0 && radar.instrument.pulse_width.is_none() {
+            radar.instrument.pulse_width =
+                Some(parm.pulse_width as f32 / crate::SPEED_OF_LIGHT * 1e6);
+        }
 
         desc.parm_desc.insert(
             new_name.clone(),
@@ -710,8 +925,8 @@ fn load_sensor(reader: &mut File, radar: &mut RadarFile, desc: &mut DoradeDesc)
         radar.params.insert(
             new_name.clone(),
             ParamDescription {
-                description: parm.param_description.as_string().unwrap(),
-                units: parm.param_units.as_string().unwrap(),
+                description: parm.param_description.as_string()?,
+                units: parm.param_units.as_string()?,
                 meters_to_first_cell: 50.0,
                 meters_between_cells: 50.0,
             },
@@ -719,9 +934,9 @@ fn load_sensor(reader: &mut File, radar: &mut RadarFile, desc: &mut DoradeDesc)
     }
 
     // Load the cell descriptor
-    match reader.next_string().unwrap().as_str() {
-        "CELV" => {
-            let celv = consume_block!(reader, CELV);
+    match reader.next_string().as_deref() {
+        Some("CELV") => {
+            let celv = consume_block!(reader, CELV)?;
             desc.ngates = celv.number_cells as u16;
 
             let first_gate = if celv.dist_cells[0] < 0.0 {
@@ -733,13 +948,13 @@ fn load_sensor(reader: &mut File, radar: &mut RadarFile, desc: &mut DoradeDesc)
 
             // println!("{}, {}", first_gate, width);
 
-            for mut val in &mut radar.params.values_mut() {
+            for val in &mut radar.params.values_mut() {
                 val.meters_to_first_cell = first_gate;
                 val.meters_between_cells = width;
             }
         }
-        "CSFD" => {
-            let csfd = consume_block!(reader, CSFD);
+        Some("CSFD") => {
+            let csfd = consume_block!(reader, CSFD)?;
 
             let mut num_segs = csfd.num_segments;
             if num_segs > 8 {
@@ -753,76 +968,104 @@ fn load_sensor(reader: &mut File, radar: &mut RadarFile, desc: &mut DoradeDesc)
             let first_gate = csfd.dist_to_first;
             let width = csfd.spacing[0];
 
-            for mut val in &mut radar.params.values_mut() {
+            for val in &mut radar.params.values_mut() {
                 val.meters_to_first_cell = first_gate;
                 val.meters_between_cells = width;
             }
         }
-        _ => panic!("Unknown cell block format"),
+        _ => {
+            return Err(Error::Format(
+                "The file has no CELV or CSFD cell descriptor".to_string(),
+            ))
+        }
     };
 
     // Load cell correction block
-    // TODO: Look into
-    if reader.next_string().unwrap().as_str() == "CFAC" {
-        let _cfac = consume_block!(reader, CFAC);
+    if reader.next_string().as_deref() == Some("CFAC") {
+        desc.corrections = Some(corrections(&consume_block!(reader, CFAC)?));
     }
+
+    skip_to(reader, &["SWIB"])?;
+    Ok(())
 }
 
 /// Load a new sweep
 fn load_sweep(
-    reader: &mut File,
+    reader: &mut Reader,
     radar: &mut RadarFile,
     desc: &mut DoradeDesc,
     options: &RadyOptions,
-) {
-    let _swib = consume_block!(reader, SWIB);
-    let mut sweep = Sweep::default();
-    sweep.scan_mode = desc.scan_mode;
+) -> Result<(), Error> {
+    let swib = consume_block!(reader, SWIB)?;
+    let mut sweep = Sweep {
+        scan_mode: desc.scan_mode,
+        elevation: swib.fixed_angle,
+        ..Default::default()
+    };
 
     // sweep.sweep_num = radar.sweeps.len() as u32;
 
-    while reader.next_string().unwrap() != "NULL" {
-        load_ray(reader, &mut sweep, desc, options);
+    // Rotation angle tables, editing histories, and the like between the rays are skipped
+    while skip_to(reader, &["RYIB", "NULL"])?.as_deref() == Some("RYIB") {
+        load_ray(reader, &mut sweep, desc, options)?;
+    }
+
+    // Some writers leave the fixed angle unset, so fall back to the first ray. The fixed angle
+    // of an RHI is its azimuth
+    let rhi = sweep.scan_mode == ScanMode::RHI;
+    let recorded = (-90.0..=360.0).contains(&sweep.elevation);
+    if !recorded {
+        sweep.elevation =
+            sweep
+                .rays
+                .first()
+                .map_or(0.0, |ray| if rhi { ray.azimuth } else { ray.elevation });
     }
 
+    sweep.elevation = if rhi {
+        sweep.elevation.rem_euclid(360.0)
+    } else {
+        signed_elevation(sweep.elevation)
+    };
+    sweep.fixed_angle = recorded.then_some(sweep.elevation);
+
     radar.sweeps.push(sweep);
+    Ok(())
 }
 
 /// Function to load a single ray into the sweep
 fn load_ray(
-    reader: &mut File,
+    reader: &mut Reader,
     sweep: &mut Sweep,
     desc: &mut DoradeDesc,
     options: &RadyOptions,
-) {
+) -> Result<(), Error> {
     // Load the first two blocks
-    let ryib = consume_block!(reader, RYIB);
-    let asib = consume_block!(reader, ASIB);
+    let ryib = consume_block!(reader, RYIB)?;
+    let asib = consume_block!(reader, ASIB)?;
 
     // Calculate new time
     let new_time: DateTime<Utc> = {
-        let ymd = Utc.ymd(desc.start_time.year(), 1, 1).and_hms(0, 0, 0);
-        let julian_day = (desc.start_time - ymd).num_days() + 1;
+        let julian_day = desc.start_time.ordinal() as i64;
 
-        let (mut hour, mut min, mut sec, mut milli) = (ryib.hour as u32, ryib.minute as u32, ryib.second as u32, ryib.millisecond as u32);
+        let mut milli = ryib.hour as i64 * 60 * 60 * 1000
+            + ryib.minute as i64 * 60 * 1000
+            + ryib.second as i64 * 1000
+            + ryib.millisecond as i64;
 
-        if hour * 60 * 60 * 1000 + min * 60 * 1000 + sec * 1000 + milli > 24 * 60 * 60 * 1000 {
-            (hour, min, sec, milli) = (0, 0, 0, 0);
+        if milli > 24 * 60 * 60 * 1000 {
+            milli = 0;
         }
 
-        desc.start_time.date().and_hms_milli(hour, min, sec, milli)
-             + Duration::days(ryib.julian_day as i64 - julian_day)
+        Utc.from_utc_datetime(&desc.start_time.date_naive().and_time(NaiveTime::MIN))
+            + Duration::milliseconds(milli)
+            + Duration::days(ryib.julian_day as i64 - julian_day)
     };
 
     // If first ray in sweep
     if sweep.nrays() == 0 {
         sweep.latitude = asib.latitude;
         sweep.longitude = asib.longitude;
-        sweep.elevation = if ryib.elevation > 180.0 {
-            ryib.elevation - 360.0
-        } else {
-            ryib.elevation
-        };
         sweep.scan_rate = Some(ryib.true_scan_rate);
 
         if options.location {
@@ -830,8 +1073,8 @@ fn load_ray(
         }
 
         // Only load nyquist velocity if VEL is present
-        if desc.parm_desc.contains_key("VEL") {
-            sweep.nyquist_velocity = desc.parm_desc.get("VEL").unwrap().nyquist;
+        if let Some(vel) = desc.parm_desc.get(&Field::VEL) {
+            sweep.nyquist_velocity = vel.nyquist;
         }
     }
 
@@ -839,88 +1082,104 @@ fn load_ray(
     let mut new_ray = Ray {
         time: new_time,
         azimuth: ryib.azimuth,
-        data: HashMap::new(),
+        elevation: signed_elevation(ryib.elevation),
+        platform: is_airborne(desc.radar_type).then(|| platform(&asib)),
+        // A status of 1 is a transition, and 2 a bad ray
+        transition: ryib.ray_status == 1,
+        ..Default::default()
     };
 
-    // Loop through each gate
-    while !["RYIB", "NULL"].contains(&reader.next_string().unwrap().as_str()) {
+    // Lower fuselage radars scan in a different plane, so only the radars on the longitudinal
+    // axis are georeferenced
+    let longitudinal = matches!(desc.radar_type, AIR_FORE | AIR_AFT | AIR_TAIL);
+    if let Some(platform) = new_ray
+        .platform
+        .filter(|_| options.georeference && longitudinal)
+    {
+        let platform = desc
+            .corrections
+            .map_or(platform, |corrections| platform.corrected(&corrections));
+        (new_ray.azimuth, new_ray.elevation) = platform.earth_angles();
+    }
+
+    // Loop through each data block of the ray, skipping the blocks that aren't data, such as
+    // FRAD
+    while let Some(id) = skip_to(reader, &["RDAT", "QDAT", "XSTF", "RYIB", "NULL"])? {
         let min_offset: usize;
         let mut data_len: usize;
-        let data_type: String;
+        let data_type: Field;
 
         // Load each data block
-        match reader.next_string().unwrap().as_str() {
+        match id.as_str() {
             "RDAT" => {
-                let rdat = consume_block!(reader, RDAT);
+                let rdat = consume_block!(reader, RDAT)?;
                 min_offset = size_of::<RDAT>();
                 data_len = rdat.nbytes as usize;
-                data_type = dorade_to_generic_name(rdat.pdata_name.as_string().unwrap());
+                data_type = dorade_to_generic_name(rdat.pdata_name.as_string()?, options);
             }
             "QDAT" => {
-                let qdat = consume_block!(reader, QDAT);
+                let qdat = consume_block!(reader, QDAT)?;
                 min_offset = size_of::<QDAT>();
                 data_len = qdat.nbytes as usize;
-                data_type = dorade_to_generic_name(qdat.pdata_name.as_string().unwrap());
+                data_type = dorade_to_generic_name(qdat.pdata_name.as_string()?, options);
             }
             "XSTF" => {
-                consume_block!(reader, XSTF);
+                let xstf = consume_block!(reader, XSTF)?;
+                new_ray.transition |= xstf.transition_flag != 0;
                 continue;
             }
-            _ => panic!(
-                "Unexpected data block format: {}, bytes: {:x?}",
-                reader.next_string().unwrap(),
-                reader.next_string().unwrap().as_bytes()
-            ),
+            _ => break,
+        };
+
+        let Some(param_desc) = desc.parm_desc.get(&data_type) else {
+            return Err(Error::Format(format!(
+                "The {} data has no parameter description",
+                data_type
+            )));
         };
 
         // Find the data offset and where to start reading
-        let struct_size = min_offset.clone();
-        let mut data_offset = desc.parm_desc.get(&data_type).unwrap().offset as usize;
+        let struct_size = min_offset;
+        let mut data_offset = param_desc.offset as usize;
 
         if data_offset > min_offset || data_offset == 0 {
             data_offset = min_offset;
         }
 
-        data_len -= data_offset;
-
-        reader
-            .seek(SeekFrom::Current((data_offset - struct_size) as i64))
-            .unwrap();
+        data_len = data_len.checked_sub(data_offset).ok_or_else(|| {
+            Error::Format(format!(
+                "The {} data block is shorter than its header",
+                data_type
+            ))
+        })?;
 
-        let param_desc = desc.parm_desc.get_mut(&data_type).unwrap();
+        reader.seek(SeekFrom::Current(data_offset as i64 - struct_size as i64))?;
 
-        let mut data: Vec<f64>;
-
-        // Match the binary format and get the data
-        match param_desc.binary_format {
-            1 => data = get_data::<i8>(reader, data_len, param_desc),
-            2 => {
-                data = if desc.compress == 0 {
-                    get_data::<i16>(reader, data_len, param_desc)
-                } else {
-                    get_compressed_data(reader, &data_type, desc, data_len)
-                }
-            }
-            3 => data = get_data::<i32>(reader, data_len, param_desc),
-            4 => data = get_data::<f32>(reader, data_len, param_desc),
-            _ => panic!("Unknown binary format"),
+        if !options.wants_field(&data_type) {
+            reader.seek(SeekFrom::Current(data_len as i64))?;
+            continue;
         }
 
-        if data_type == "REF" {
-            for elem in &mut data {
-                let tmp = (*elem * options.scale) + options.offset;
-                if tmp < options.remove {
-                    *elem = -999.0;
-                } else {
-                    *elem = tmp;
-                }
+        // Match the binary format and get the data
+        let data = match param_desc.binary_format {
+            1 => get_data::<i8>(reader, data_len, param_desc)?,
+            2 if desc.compress == 0 => get_data::<i16>(reader, data_len, param_desc)?,
+            2 => get_compressed_data(reader, param_desc, desc.ngates as usize, data_len)?,
+            3 => get_data::<i32>(reader, data_len, param_desc)?,
+            4 => get_data::<f32>(reader, data_len, param_desc)?,
+            format => {
+                return Err(Error::Format(format!(
+                    "The {} data has an unknown binary format {}",
+                    data_type, format
+                )))
             }
-        }
+        };
 
         new_ray.data.insert(data_type, data);
     }
 
     sweep.rays.push(new_ray);
+    Ok(())
 }
 
 trait FromBytes {
@@ -952,99 +1211,101 @@ impl FromBytes for f32 {
 }
 
 /// Function to get non-compressed dorade data
-fn get_data<T: FromBytes + Copy>(reader: &mut File, data_len: usize, desc: &ParmDesc) -> Vec<f64>
+fn get_data<T: FromBytes + Copy>(
+    reader: &mut Reader,
+    data_len: usize,
+    desc: &ParmDesc,
+) -> Result<Vec<f64>, Error>
 where
     f64: From<T>,
 {
-    let mut slice: Vec<u8> = Vec::with_capacity(data_len);
-    slice.resize(data_len, 0);
-    reader.read_exact(&mut slice).unwrap();
+    let slice = take(reader, data_len)?;
 
-    let mut new_vec: Vec<T> = Vec::new();
-    for i in (0..data_len).step_by(size_of::<T>()) {
-        new_vec.push(T::from_le_bytes(&slice[i..i + size_of::<T>()]))
-    }
+    // Bad data flags are stored as a 32 bit integer for every binary format
+    let bad_data = desc.bad_data as i32 as f64;
 
-    new_vec
-        .iter()
-        .map(|&x| (f64::from(x) / desc.scale as f64) + desc.bias as f64)
-        .collect()
+    Ok(slice
+        .chunks_exact(size_of::<T>())
+        .map(|bytes| f64::from(T::from_le_bytes(bytes)))
+        .map(|x| {
+            if x == bad_data {
+                MISSING
+            } else {
+                (x / desc.scale as f64) + desc.bias as f64
+            }
+        })
+        .collect())
 }
 
-/// Function to decompress HRD data
-#[allow(non_snake_case)]
-fn decompress_HRD(raw: &Vec<u16>, bad_data: u16, ngates: u16) -> Vec<u16> {
-    // Decompressed output data
-    let mut decomp: Vec<u16> = Vec::with_capacity(ngates as usize);
-    decomp.resize(ngates as usize, 0);
-
-    // Variables
-    let mut raw_i: usize = 0;
-    let mut decomp_i: usize = 0;
-    let mut nn;
-    let mut wcount = 0u16;
-
-    while raw[raw_i] != 1 {
-        nn = raw[raw_i] & 0x7fff;
+/// Reads the little endian word at a byte offset, if the data is long enough
+fn word_at(raw: &[u8], offset: usize) -> Option<u16> {
+    raw.get(offset..offset + 2)
+        .map(|word| u16::from_le_bytes([word[0], word[1]]))
+}
 
-        if wcount + nn > ngates {
-            panic!("Could not decode {} {} {}", wcount, nn, ngates);
-        } else {
-            wcount += nn;
+/// Decompresses HRD run length encoded data straight from its bytes. Each run starts with a word
+/// of its length, with the high bit set if the values of the run follow, or clear for a run of bad
+/// data, and a word of 1 ends the data. Gates past the end of the data are bad data
+fn decompress_hrd(raw: &[u8], bad_data: u16, ngates: usize) -> Result<Vec<u16>, Error> {
+    let mut decomp = Vec::with_capacity(ngates);
+    let mut offset = 0;
+
+    while let Some(word) = word_at(raw, offset).filter(|&word| word != 1) {
+        let len = (word & 0x7fff) as usize;
+        offset += 2;
+
+        if decomp.len() + len > ngates {
+            return Err(Error::Format(format!(
+                "Could not decode a run of {} gates after {} of {}",
+                len,
+                decomp.len(),
+                ngates
+            )));
         }
 
-        if (raw[raw_i] & 0x8000) > 0 {
-            raw_i += 1;
+        if word & 0x8000 == 0 {
+            decomp.resize(decomp.len() + len, bad_data);
+            continue;
+        }
 
-            while nn > 0 {
-                decomp[decomp_i] = raw[raw_i];
-                raw_i += 1;
-                decomp_i += 1;
-                nn -= 1;
-            }
-        } else {
-            raw_i += 1;
+        let Some(run) = raw.get(offset..offset + 2 * len) else {
+            return Err(Error::Format(format!(
+                "A run of {} gates goes past the end of the data",
+                len
+            )));
+        };
 
-            while nn > 1 {
-                decomp[decomp_i] = bad_data;
-                decomp_i += 1;
-                nn -= 1;
-            }
-        }
+        decomp.extend(
+            run.chunks_exact(2)
+                .map(|word| u16::from_le_bytes([word[0], word[1]])),
+        );
+        offset += 2 * len;
     }
 
-    decomp
+    decomp.resize(ngates, bad_data);
+    Ok(decomp)
 }
 
+/// Function to decompress 16 bit dorade data
 fn get_compressed_data(
-    reader: &mut File,
-    field: &String,
-    desc: &DoradeDesc,
+    reader: &mut Reader,
+    parm_desc: &ParmDesc,
+    ngates: usize,
     data_len: usize,
-) -> Vec<f64> {
-    // Function to decompress 16 bit dorade data
-
-    let parm_desc = desc.parm_desc.get(field).unwrap();
-
-    let raw: Vec<u16> = {
-        let mut slice: Vec<u8> = Vec::with_capacity(data_len);
-        slice.resize(data_len, 0);
-        reader.read_exact(&mut slice).unwrap();
-        let mut new_vec: Vec<u16> = Vec::new();
-        for i in (0..data_len).step_by(2) {
-            new_vec.push(u16::from_le_bytes(slice[i..i + 2].try_into().unwrap()))
-        }
-
-        new_vec
-    };
+) -> Result<Vec<f64>, Error> {
+    let bad_data = parm_desc.bad_data as u16;
 
-    // Decompressed data
-    let decomp = decompress_HRD(&raw, parm_desc.bad_data as u16, desc.ngates);
+    let decomp = decompress_hrd(take(reader, data_len)?, bad_data, ngates)?;
 
     // Read the u16 data as i16 and apply scale/offset
-    decomp
-        .iter()
-        .map(|&x| i16::from_ne_bytes(x.to_ne_bytes()))
-        .map(|x| (f64::from(x) / parm_desc.scale as f64) + parm_desc.bias as f64)
-        .collect()
+    Ok(decomp
+        .into_iter()
+        .map(|x| {
+            if x == bad_data {
+                MISSING
+            } else {
+                (f64::from(x as i16) / parm_desc.scale as f64) + parm_desc.bias as f64
+            }
+        })
+        .collect())
 }
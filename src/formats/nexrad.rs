@@ -1,16 +1,89 @@
-use chrono::{DateTime, TimeZone, Utc};
-use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::File;
-use std::{io::{Read, Write, Seek, SeekFrom}, path::Path};
-use std::collections::HashMap;
-
-use crate::{Format, RadarFile, RadyOptions, Sweep, Ray, ParamDescription};
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{FileBytes, RadarRead, RadarWrite, SweepSink};
+use crate::{
+    is_missing, resolve_conflict, signed_elevation, Error, Field, Format, GateGeometry,
+    InstrumentInfo, ParamDescription, RadarFile, RadyOptions, Ray, ScanCut, ScanMode, ScanStrategy,
+    Sweep, Waveform, MISSING,
+};
 
 use bincode::{DefaultOptions, Options};
+use rayon::prelude::*;
+use static_assertions::assert_eq_size;
+
+impl ScanMode {
+    /// Cut sector number for the type 31 header. Scans that don't rotate in azimuth aren't
+    /// sectors of a cut and are written as 0
+    fn nexrad_cut_sector(&self) -> u8 {
+        match self {
+            ScanMode::PPI
+            | ScanMode::Surveillance
+            | ScanMode::Manual
+            | ScanMode::Sector
+            | ScanMode::Airborne => 1,
+            _ => 0,
+        }
+    }
+}
+
+impl Waveform {
+    /// Waveform of a type 5 message cut
+    fn from_nexrad(code: u8) -> Waveform {
+        match code {
+            1 => Waveform::ContiguousSurveillance,
+            2 => Waveform::ContiguousDoppler,
+            3 => Waveform::ContiguousDopplerNoResolution,
+            4 => Waveform::Batch,
+            5 => Waveform::StaggeredPulsePair,
+            _ => Waveform::Unknown,
+        }
+    }
+
+    /// Waveform code for a type 5 message cut
+    fn nexrad_code(&self) -> u8 {
+        match self {
+            Waveform::ContiguousSurveillance => 1,
+            Waveform::ContiguousDoppler => 2,
+            Waveform::ContiguousDopplerNoResolution => 3,
+            Waveform::Batch => 4,
+            Waveform::StaggeredPulsePair => 5,
+            Waveform::Unknown => 0,
+        }
+    }
+}
+
+/// Azimuth indexing mode of a sweep for the type 31 header: the ray spacing in hundredths of a
+/// degree if every ray lies on a fixed grid of azimuths, otherwise 0 for no indexing
+fn azimuth_indexing(sweep: &Sweep) -> u8 {
+    let spacing = sweep.azimuth_spacing().abs();
+
+    if sweep.scan_mode.nexrad_cut_sector() == 0 || !(0.01..=1.0).contains(&spacing) {
+        return 0;
+    }
+
+    let offset = sweep.rays[0].azimuth.rem_euclid(spacing);
+    let indexed = sweep.rays.iter().all(|ray| {
+        let error = (ray.azimuth - offset).rem_euclid(spacing);
+        error.min(spacing - error) < 0.1 * spacing
+    });
+
+    if indexed {
+        (spacing * 100.0).round() as u8
+    } else {
+        0
+    }
+}
 
 #[repr(C)]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 struct VolumeHeader {
     tape: [u8; 9],
     extension: [u8; 3],
@@ -20,7 +93,7 @@ struct VolumeHeader {
 }
 
 #[repr(C)]
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 struct MsgHeader {
     size: u16,
     channels: u8,
@@ -33,7 +106,7 @@ struct MsgHeader {
 }
 
 #[repr(C)]
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 struct Msg31Header {
     icao: [u8; 4],
     collect_ms: u32,
@@ -53,12 +126,6 @@ struct Msg31Header {
     block_count: u16,
 }
 
-#[repr(C)]
-#[derive(Serialize)]
-struct BlockPtrs {
-    ptrs: Vec<u32>,
-}
-
 #[repr(C)]
 #[derive(Serialize, Deserialize)]
 struct DataBlock {
@@ -77,7 +144,7 @@ struct DataBlock {
 }
 
 #[repr(C)]
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 struct VolumeDataBlock {
     block_type: [u8; 1],
     data_name: [u8; 3],
@@ -97,8 +164,38 @@ struct VolumeDataBlock {
     spare: u16,
 }
 
+/// Header of a type 5 (volume coverage pattern) message
 #[repr(C)]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct VcpHeader {
+    size: u16,
+    pattern_type: u16,
+    pattern_number: u16,
+    cut_count: u16,
+    version: u8,
+    clutter_map_group: u8,
+    velocity_resolution: u8,
+    pulse_width: u8,
+    spare: [u16; 5],
+}
+
+/// An elevation cut of a type 5 message
+#[repr(C)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct VcpCut {
+    elevation: u16,
+    channel_config: u8,
+    waveform: u8,
+    super_resolution: u8,
+    surveillance_prf: u8,
+    surveillance_pulses: u16,
+    azimuth_rate: u16,
+    thresholds: [i16; 6],
+    doppler_sectors: [u16; 12],
+}
+
+#[repr(C)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct ElevationDataBlock {
     block_name: [u8; 1],
     data_name: [u8; 3],
@@ -108,7 +205,7 @@ struct ElevationDataBlock {
 }
 
 #[repr(C)]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct RadialDataBlock {
     block_name: [u8; 1],
     data_name: [u8; 3],
@@ -120,110 +217,84 @@ struct RadialDataBlock {
     spare: u16,
 }
 
-fn scale_offset(data_type: &str) -> (f32, f32) {
+// Block pointers and message sizes are computed from these, so they must match the serialized sizes
+assert_eq_size!(VolumeHeader, [u8; 24]);
+assert_eq_size!(MsgHeader, [u8; 16]);
+assert_eq_size!(Msg31Header, [u8; 32]);
+assert_eq_size!(DataBlock, [u8; 28]);
+assert_eq_size!(VolumeDataBlock, [u8; 44]);
+assert_eq_size!(VcpHeader, [u8; 22]);
+assert_eq_size!(VcpCut, [u8; 46]);
+assert_eq_size!(ElevationDataBlock, [u8; 12]);
+assert_eq_size!(RadialDataBlock, [u8; 20]);
+
+fn scale_offset(data_type: &Field) -> (f32, f32) {
     match data_type {
-        "REF" => (2.0, 66.0),
-        "VEL" => (2.0, 129.0),
-        "SW" => (2.0, 129.9),
-        "ZDR" => (16.0, 128.0),
-        "PHI" => (2.8261, 2.0),
-        "RHO" => (300.0, -60.5),
-        "CFP" => (1.0, 8.0),
+        Field::REF => (2.0, 66.0),
+        Field::VEL => (2.0, 129.0),
+        Field::SW => (2.0, 129.9),
+        Field::ZDR => (16.0, 128.0),
+        Field::PHI => (2.8261, 2.0),
+        Field::RHO => (300.0, -60.5),
+        Field::CFP => (1.0, 8.0),
         _ => panic!("Unknown data type: {}", data_type),
     }
 }
 
-trait ToBytes {
-    fn to_be_bytes(self) -> Vec<u8>;
-}
-
-impl ToBytes for u8 {
-    fn to_be_bytes(self) -> Vec<u8> {
-        vec![self]
-    }
-}
-
-impl ToBytes for u16 {
-    fn to_be_bytes(self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-}
-
-trait F64ToInt<V> {
-    fn f64_to_int(self) -> V;
-}
-
-impl F64ToInt<u8> for f64 {
-    fn f64_to_int(self) -> u8 {
-        self as u8
-    }
-}
-
-impl F64ToInt<u16> for f64 {
-    fn f64_to_int(self) -> u16 {
-        self as u16
-    }
-}
-
 /// Attributes that are stored per ray, but we want them to be stored per sweep
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Default)]
 struct RayAttribs {
     elev: f32,
+    elevation_number: u8,
     nyq: f32,
     lat: f32,
     lon: f32,
+    icao: [u8; 4],
+    gates: HashMap<Field, GateGeometry>,
+    instrument: Option<InstrumentInfo>,
+    vcp: u16,
 }
 
 /// Converts to the date and time format NEXRAD uses
 fn to_day_ms(datetime: DateTime<Utc>) -> (u32, u32) {
     (
-        (datetime.date() - Utc.ymd(1970, 1, 1)).num_days() as u32 + 1,
-        (datetime - datetime.date().and_hms(0, 0, 0)).num_milliseconds() as u32,
+        (datetime.date_naive() - NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()).num_days() as u32
+            + 1,
+        (datetime.naive_utc() - datetime.date_naive().and_hms_opt(0, 0, 0).unwrap())
+            .num_milliseconds() as u32,
     )
 }
 
-macro_rules! consume_block {
-    ($reader:expr, BlockPtrs, $len:expr) => {{
-        const N: usize = std::mem::size_of::<u32>();
-        let mut ptrs = vec![0; N * $len];
-
-        unsafe {
-            let slice = std::slice::from_raw_parts_mut(&mut ptrs as *mut _ as *mut u8, N);
-            $reader.read_exact(slice).unwrap();
-        }
-
-        BlockPtrs {
-            ptrs
-        }
-    }};
-    
-    ($reader:expr, $struc:ty) => {{
-        const N: usize = std::mem::size_of::<$struc>();
-        let mut new_struc: $struc = unsafe { std::mem::zeroed() };
-
-        unsafe {
-            let slice = std::slice::from_raw_parts_mut(&mut new_struc as *mut _ as *mut u8, N);
-            $reader.read_exact(slice).unwrap();
-        }
-
-        new_struc
-    }};
+/// Converts from the date and time format NEXRAD uses
+fn from_day_ms(date: u32, ms: u32) -> DateTime<Utc> {
+    let date = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap() + Duration::days(date as i64 - 1);
+    Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()) + Duration::milliseconds(ms as i64)
 }
 
+/// Takes bytes from the front of a `&mut &[u8]`, borrowing them rather than copying, or fails if
+/// there aren't enough. With a type, the bytes are read as big endian values of it as they are
+/// iterated
 macro_rules! consume {
     ($reader:expr, $len:expr) => {{
-        let mut buf = vec![0; $len];
-        $reader.read_exact(&mut buf).unwrap();
+        let bytes: &[u8] = *$reader;
+        let len = $len;
+
+        if bytes.len() < len {
+            Err(Error::Format("A message ends early".to_string()))
+        } else {
+            let (taken, rest) = bytes.split_at(len);
+            *$reader = rest;
 
-        buf
+            Ok(taken)
+        }
     }};
 
     ($reader:expr, $len:expr, $ty:ty) => {{
-        let mut buf = vec![0; $len * std::mem::size_of::<$ty>()];
-        $reader.read_exact(&mut buf).unwrap();
-        buf.chunks_exact(std::mem::size_of::<$ty>())
-            .map(|v| <$ty>::from_be_bytes(v.try_into().unwrap()))
-            .collect::<Vec<_>>()
+        consume!($reader, $len * std::mem::size_of::<$ty>()).map(|bytes| {
+            bytes
+                .chunks_exact(std::mem::size_of::<$ty>())
+                .map(|v| <$ty>::from_be_bytes(v.try_into().unwrap()))
+        })
     }};
 }
 
@@ -235,190 +306,955 @@ pub fn serialize<S: Serialize>(t: &S) -> Vec<u8> {
         .unwrap()
 }
 
-pub fn deserialize<R: Read, S: DeserializeOwned>(t: R) -> S {
+pub fn deserialize<R: Read, S: DeserializeOwned>(t: R) -> Result<S, Error> {
     DefaultOptions::new()
         .with_fixint_encoding()
         .with_big_endian()
         .deserialize_from(t)
-        .unwrap()
+        .map_err(|e| Error::Format(format!("A message can't be read: {}", e)))
 }
 
-pub fn deserialize_block<S: DeserializeOwned>(mut t: &mut &[u8]) -> S {
-    let lrtup = u16::from_be_bytes(t[4..6].try_into().unwrap()) as usize;
+pub fn deserialize_block<S: DeserializeOwned>(mut t: &mut &[u8]) -> Result<S, Error> {
+    let lrtup = match t.get(4..6) {
+        Some(lrtup) => u16::from_be_bytes(lrtup.try_into().unwrap()) as usize,
+        None => return Err(Error::Format("A data block ends early".to_string())),
+    };
 
+    let s: S = deserialize(&mut t)?;
     if lrtup > std::mem::size_of::<S>() {
-        let s: S = deserialize(&mut t);
-        consume!(t, lrtup - std::mem::size_of::<S>());
-        s
-    } else {
-        deserialize(&mut t)
+        consume!(t, lrtup - std::mem::size_of::<S>())?;
+    }
+
+    Ok(s)
+}
+
+/// NEXRAD Level II archive files
+pub struct Nexrad;
+
+impl RadarRead for Nexrad {
+    fn name(&self) -> &'static str {
+        "nexrad"
+    }
+
+    fn sniff(&self, path: &Path) -> bool {
+        is_nexrad(path)
+    }
+
+    fn read(&self, path: &Path, options: &RadyOptions) -> Result<RadarFile, Error> {
+        read_nexrad(path, options)
+    }
+
+    fn decode(&self, bytes: &[u8], options: &RadyOptions) -> Option<Result<RadarFile, Error>> {
+        bytes.starts_with(b"AR2V").then(|| {
+            load_nexrad(
+                LazyNexrad::from_bytes(FileBytes::Read(bytes.to_vec()))?,
+                options,
+            )
+        })
+    }
+}
+
+impl RadarWrite for Nexrad {
+    fn name(&self) -> &'static str {
+        "nexrad"
+    }
+
+    fn write(&self, mut radar: RadarFile, path: &Path, options: &RadyOptions) -> Result<(), Error> {
+        radar.sweeps.iter_mut().for_each(even_gates);
+
+        report_clipped(write_nexrad(&radar, path, options)?);
+        Ok(())
+    }
+
+    fn encode(&self, mut radar: RadarFile, _options: &RadyOptions) -> Option<Vec<u8>> {
+        radar.sweeps.iter_mut().for_each(even_gates);
+
+        let mut buf = Vec::new();
+        let mut clipped = HashMap::new();
+
+        // Writing into memory can't fail
+        if let Some(first) = radar.sweeps.first() {
+            start_volume(&mut buf, &radar, first.time()).unwrap();
+        }
+
+        for (sweep_index, sweep) in radar.sweeps.iter().enumerate() {
+            write_sweep(
+                &radar,
+                sweep,
+                sweep_index,
+                sweep_index == radar.sweeps.len() - 1,
+                &mut buf,
+                &mut clipped,
+            )
+            .unwrap();
+        }
+
+        report_clipped(clipped);
+        Some(buf)
+    }
+
+    fn tolerance(&self, field: &Field) -> f64 {
+        match field {
+            Field::REF
+            | Field::VEL
+            | Field::SW
+            | Field::ZDR
+            | Field::PHI
+            | Field::RHO
+            | Field::CFP => 0.5 / scale_offset(field).0 as f64,
+            _ => 0.0,
+        }
+    }
+
+    fn stream(
+        &self,
+        radar: RadarFile,
+        path: &Path,
+        options: &RadyOptions,
+    ) -> Option<Box<dyn SweepSink>> {
+        Some(Box::new(NexradStream {
+            radar,
+            path: path.to_path_buf(),
+            options: options.clone(),
+            file: None,
+            skipped: false,
+            written: 0,
+            cuts: Vec::new(),
+            clipped: HashMap::new(),
+        }))
+    }
+}
+
+/// Drops the last gate of odd length rays, since moment arrays must have an even number of gates
+fn even_gates(sweep: &mut Sweep) {
+    sweep.rays.iter_mut().for_each(|ray| {
+        ray.data.values_mut().for_each(|val| {
+            while val.len() % 2 != 0 {
+                val.pop().unwrap();
+            }
+        })
+    })
+}
+
+/// Reports the fields that had gates outside of their encodable range
+fn report_clipped(clipped: HashMap<Field, ClipCounts>) {
+    let mut fields: Vec<_> = clipped
+        .into_iter()
+        .filter(|(_, c)| *c != ClipCounts::default())
+        .collect();
+    fields.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (field, counts) in fields {
+        eprintln!(
+            "{}: {} gates below and {} gates above the encodable range were written as missing",
+            field, counts.below, counts.above
+        );
+    }
+}
+
+/// Writes the sweeps of a volume to a NEXRAD file as they arrive. The latest sweep is held back
+/// until the next one comes, so the end of the volume can be marked on its last radial
+pub struct NexradStream {
+    /// Metadata of the volume, with the sweep that hasn't been written yet
+    radar: RadarFile,
+
+    /// Output directory
+    path: PathBuf,
+
+    options: RadyOptions,
+
+    /// Output file, created with the first sweep, and the time of that sweep
+    file: Option<(File, DateTime<Utc>)>,
+
+    /// The output file already existed and was kept
+    skipped: bool,
+
+    /// Number of sweeps written
+    written: usize,
+
+    /// Cuts of the sweeps written, for the type 5 message
+    cuts: Vec<ScanCut>,
+
+    clipped: HashMap<Field, ClipCounts>,
+}
+
+impl NexradStream {
+    /// Writes the held back sweep
+    fn write_pending(&mut self, last: bool) -> Result<(), Error> {
+        let Some(sweep) = self.radar.sweeps.first() else {
+            return Ok(());
+        };
+
+        if self.file.is_none() && !self.skipped {
+            match create_new_file(&self.path, &self.radar, 0, &self.options)? {
+                Some(file) => self.file = Some((file, sweep.time())),
+                None => self.skipped = true,
+            }
+        }
+
+        if let Some((file, _)) = &mut self.file {
+            write_sweep(
+                &self.radar,
+                sweep,
+                self.written,
+                last,
+                file,
+                &mut self.clipped,
+            )?;
+        }
+
+        self.cuts
+            .extend(ScanStrategy::from_sweeps(0, &self.radar.sweeps).cuts);
+        self.radar.sweeps.clear();
+        self.written += 1;
+
+        Ok(())
+    }
+}
+
+impl SweepSink for NexradStream {
+    fn push(
+        &mut self,
+        mut sweep: Sweep,
+        params: &HashMap<Field, ParamDescription>,
+    ) -> Result<(), Error> {
+        even_gates(&mut sweep);
+
+        for (field, param) in params {
+            self.radar
+                .params
+                .entry(field.clone())
+                .or_insert_with(|| param.clone());
+        }
+
+        self.write_pending(false)?;
+        self.radar.sweeps.push(sweep);
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        self.write_pending(true)?;
+
+        // The type 5 message was written before the cuts were known, so it is filled in now
+        if let (Some((file, start_time)), None) = (&mut self.file, &self.radar.scan_strategy) {
+            let strategy = ScanStrategy {
+                vcp: 0,
+                cuts: std::mem::take(&mut self.cuts),
+            };
+            let offset = std::mem::size_of::<VolumeHeader>() + CTM_SIZE;
+
+            file.seek(SeekFrom::Start(offset as u64))?;
+            file.write_all(&pack_vcp(&strategy, *start_time))?;
+        }
+
+        report_clipped(std::mem::take(&mut self.clipped));
+        Ok(())
     }
 }
 
 pub fn is_nexrad(path: impl AsRef<Path>) -> bool {
     // Checks if a file is in the nexrad format
 
-    let mut file = File::open(path).unwrap();
+    let mut magic = [0u8; 4];
+
+    if File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_err()
+    {
+        return false;
+    }
 
-    consume!(file, 4) == b"AR2V"
+    &magic == b"AR2V"
 }
 
-pub fn read_nexrad(path: impl AsRef<Path>, options: &RadyOptions) -> RadarFile {
-    let mut reader = File::open(path).unwrap();
+pub fn read_nexrad(path: impl AsRef<Path>, options: &RadyOptions) -> Result<RadarFile, Error> {
+    load_nexrad(LazyNexrad::open(path)?, options)
+}
 
-    let vol_header: VolumeHeader = deserialize(&mut reader);
-    let compression_record = consume!(reader, 12);
- 
-    let mut buf = Vec::new();
+/// Decodes the wanted fields of an opened file
+fn load_nexrad(mut lazy: LazyNexrad, options: &RadyOptions) -> Result<RadarFile, Error> {
+    let fields: Vec<Field> = lazy
+        .fields()
+        .into_iter()
+        .filter(|field| options.wants_field(field))
+        .collect();
 
-    match &compression_record[4..6] {
-        b"BZ" => buf = decompress_records(reader),
-        b"\x00\x00" | b"\t\x80" => reader.read_exact(&mut buf).unwrap(),
-        _ => panic!("Unknown compression record"),
+    if options.jobs > 1 {
+        crate::in_pool(options.jobs, || lazy.load_parallel(&fields))?;
+    } else {
+        fields.iter().try_for_each(|field| lazy.load(field))?;
     }
 
-    let mut reader = buf.as_slice();  
+    let mut radar = lazy.into_radar();
+    name_unknown(&mut radar, options);
+
+    Ok(radar)
+}
 
-    let mut params = HashMap::new();
-    let mut sweeps = Vec::new();
-    let mut sweep = Sweep::default();
-    let mut atts = RayAttribs::default();
+/// Names a radar without an ICAO with the one from the options, or UNKN
+fn name_unknown(radar: &mut RadarFile, options: &RadyOptions) {
+    if radar.name.is_empty() {
+        radar.name = options.override_radar.clone().unwrap_or_else(|| {
+            eprintln!("No ICAO found in the file, using UNKN. Use --radar to set one");
+            "UNKN".to_string()
+        });
+    }
+}
 
-    while reader.len() > 0 {
-        if let Some((ray, end)) = read_ray(&mut reader, &mut atts, &mut params) {
-            sweep.rays.push(ray);
+/// A volume of the real-time Level II feed, assembled from its chunks as they arrive. The first
+/// chunk has the volume header and the metadata records, and the rest have compressed records of
+/// radials, so the chunks joined in order are an archive file that grows until the volume ends
+#[derive(Default)]
+pub struct ChunkedVolume {
+    /// Volume header of the first chunk
+    header: Option<VolumeHeader>,
 
-            if end {
-                sweep.latitude = atts.lat / sweep.rays.len() as f32;
-                sweep.longitude = atts.lon / sweep.rays.len() as f32;
-                sweep.nyquist_velocity = atts.nyq / sweep.rays.len() as f32;
-                sweep.elevation = atts.elev / sweep.rays.len() as f32;
+    /// Headers read from the chunks so far, with the decoded rays of the sweep that hasn't ended
+    headers: Headers,
+}
 
-                sweeps.push(sweep);
+impl ChunkedVolume {
+    /// Adds the next chunk, returning the sweeps it completed along with the metadata of the
+    /// volume. Radials of a sweep that hasn't ended yet are held until a later chunk ends it.
+    /// Only the new chunk is decompressed and decoded
+    pub fn push(&mut self, chunk: &[u8], options: &RadyOptions) -> Result<RadarFile, Error> {
+        let (header, records) = match self.header.take() {
+            Some(header) => (header, chunk),
+            None if !chunk.starts_with(b"AR2V") => {
+                return Err(Error::Format(
+                    "The first chunk of a volume doesn't have a volume header".to_string(),
+                ))
+            }
+            None => {
+                let header_size = std::mem::size_of::<VolumeHeader>();
+                let header = chunk.get(..header_size).ok_or_else(|| {
+                    Error::Format("The file ends before the end of its volume header".to_string())
+                })?;
+                (deserialize(header)?, &chunk[header_size..])
+            }
+        };
+        let header = &*self.header.insert(header);
 
-                atts = RayAttribs::default();
-                sweep = Sweep::default();
+        let messages = match records.get(4..6) {
+            Some(b"BZ") => decompress_records(records)?,
+            Some(b"\x00\x00" | b"\t\x80") => records.get(CTM_SIZE..).unwrap_or_default().to_vec(),
+            _ => return Err(Error::Format("Unknown compression record".to_string())),
+        };
+        let buf = reassemble_segments(Messages::Owned(messages))?;
+        self.headers.read(&buf)?;
+
+        // The moments of the rays in this chunk are decoded now, so the chunk isn't kept. Rays
+        // of earlier chunks have no blocks left
+        let fields: Vec<Field> = self
+            .headers
+            .params
+            .keys()
+            .filter(|field| options.wants_field(field))
+            .cloned()
+            .collect();
+        let headers = &mut self.headers;
+        let sweeps = headers.sweeps.iter_mut().chain([&mut headers.sweep]);
+        let blocks = headers.blocks.iter_mut().chain([&mut headers.sweep_blocks]);
+
+        for (sweep, sweep_blocks) in sweeps.zip(blocks) {
+            for (ray, ray_blocks) in sweep.rays.iter_mut().zip(sweep_blocks) {
+                for field in &fields {
+                    if let Some(offset) = ray_blocks.remove(field) {
+                        ray.data
+                            .insert(field.clone(), decode_moment(&buf[offset..])?);
+                    }
+                }
+
+                ray_blocks.clear();
             }
         }
+
+        let (sweeps, _) = self.headers.take_sweeps();
+        let mut radar = self.headers.radar(header, sweeps);
+        radar.params.retain(|field, _| fields.contains(field));
+        name_unknown(&mut radar, options);
+
+        Ok(radar)
     }
+}
 
-    RadarFile {
-        name: String::from_utf8(vol_header.icao.to_vec()).unwrap(),
-        sweeps,
-        params
+impl std::fmt::Debug for ChunkedVolume {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ChunkedVolume")
+            .field("header", &self.header)
+            .field("held_rays", &self.headers.sweep.rays.len())
+            .finish_non_exhaustive()
     }
 }
 
-fn read_ray(mut reader: &mut &[u8], atts: &mut RayAttribs, params: &mut HashMap<String, ParamDescription>) -> Option<(Ray, bool)> {
-    let header: MsgHeader = deserialize(&mut reader);
+/// Reads an ICAO, if it is made of printable characters
+fn parse_icao(icao: &[u8; 4]) -> Option<String> {
+    if icao.iter().all(|c| c.is_ascii_alphanumeric()) {
+        Some(String::from_utf8(icao.to_vec()).unwrap())
+    } else {
+        None
+    }
+}
 
-    if header.f_type != 31 {
-        consume!(reader, 2432 - std::mem::size_of::<MsgHeader>());
-        return None;
+/// Messages of a file, read in place from the file contents if uncompressed
+enum Messages {
+    InPlace(FileBytes),
+    Owned(Vec<u8>),
+}
+
+impl std::ops::Deref for Messages {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            // Skip the volume header and the first CTM record
+            Messages::InPlace(bytes) => &bytes[std::mem::size_of::<VolumeHeader>() + CTM_SIZE..],
+            Messages::Owned(buf) => buf,
+        }
+    }
+}
+
+/// A NEXRAD file whose moment data is decoded on request
+///
+/// Opening the file reads every message header and records where each moment block is,
+/// so converting a few fields from a file with many moments skips decoding the rest.
+pub struct LazyNexrad {
+    /// Decompressed messages
+    buf: Messages,
+
+    /// Radar with the sweep and ray headers, and the fields loaded so far
+    radar: RadarFile,
+
+    /// Offsets of the undecoded moment blocks into the buffer, for each sweep and ray
+    blocks: Vec<Vec<HashMap<Field, usize>>>,
+}
+
+impl LazyNexrad {
+    /// Reads the headers of a file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_bytes(super::map_file(path.as_ref())?)
+    }
+
+    /// Reads the headers of a file's contents
+    pub(crate) fn from_bytes(bytes: FileBytes) -> Result<Self, Error> {
+        let header_size = std::mem::size_of::<VolumeHeader>();
+
+        if bytes.len() < header_size + CTM_SIZE {
+            return Err(Error::Format(
+                "The file ends before the end of its volume header".to_string(),
+            ));
+        }
+
+        let vol_header: VolumeHeader = deserialize(&bytes[..header_size])?;
+        let compression_record = &bytes[header_size..header_size + CTM_SIZE];
+
+        let buf = match &compression_record[4..6] {
+            b"BZ" => Messages::Owned(decompress_records(&bytes[header_size..])?),
+            b"\x00\x00" | b"\t\x80" => Messages::InPlace(bytes),
+            _ => return Err(Error::Format("Unknown compression record".to_string())),
+        };
+
+        let buf = reassemble_segments(buf)?;
+        let mut headers = Headers::default();
+        headers.read(&buf)?;
+
+        let (sweeps, blocks) = headers.take_sweeps();
+        let radar = headers.radar(&vol_header, sweeps);
+
+        Ok(LazyNexrad { buf, radar, blocks })
+    }
+
+    /// All of the fields in the file
+    pub fn fields(&self) -> Vec<Field> {
+        self.radar.params.keys().cloned().collect()
+    }
+
+    /// Decodes a field into every ray, if it hasn't been already
+    pub fn load(&mut self, field: &Field) -> Result<(), Error> {
+        for (sweep, sweep_blocks) in self.radar.sweeps.iter_mut().zip(&mut self.blocks) {
+            for (ray, ray_blocks) in sweep.rays.iter_mut().zip(sweep_blocks) {
+                if let Some(offset) = ray_blocks.remove(field) {
+                    ray.data
+                        .insert(field.clone(), decode_moment(&self.buf[offset..])?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decodes fields into every ray, with the sweeps decoded in parallel on the current thread
+    /// pool. Each ray's moments are at their own offsets, so sweeps don't depend on each other
+    pub fn load_parallel(&mut self, fields: &[Field]) -> Result<(), Error> {
+        let buf = &self.buf;
+
+        self.radar
+            .sweeps
+            .par_iter_mut()
+            .zip(self.blocks.par_iter_mut())
+            .try_for_each(|(sweep, sweep_blocks)| {
+                for (ray, ray_blocks) in sweep.rays.iter_mut().zip(sweep_blocks) {
+                    for field in fields {
+                        if let Some(offset) = ray_blocks.remove(field) {
+                            ray.data
+                                .insert(field.clone(), decode_moment(&buf[offset..])?);
+                        }
+                    }
+                }
+
+                Ok(())
+            })
+    }
+
+    /// Radar with the fields loaded so far
+    pub fn radar(&self) -> &RadarFile {
+        &self.radar
+    }
+
+    /// Returns the radar with the fields loaded so far. Fields that were never loaded are
+    /// removed from the params
+    pub fn into_radar(mut self) -> RadarFile {
+        let sweeps = &self.radar.sweeps;
+
+        self.radar.params.retain(|field, _| {
+            sweeps
+                .iter()
+                .any(|sweep| sweep.rays.iter().any(|ray| ray.data.contains_key(field)))
+        });
+
+        self.radar
+    }
+}
+
+/// Sweep and ray headers read from messages, with the offsets of the moment blocks of each ray.
+/// The real-time feed reads each chunk into the same headers, so a sweep can span chunks
+#[derive(Default)]
+struct Headers {
+    params: HashMap<Field, ParamDescription>,
+
+    /// Sweeps that have ended, with the moment blocks and the cut number of each
+    sweeps: Vec<Sweep>,
+    blocks: Vec<Vec<HashMap<Field, usize>>>,
+    elevation_numbers: Vec<u8>,
+
+    /// Sweep being read, until a ray ends it
+    sweep: Sweep,
+    sweep_blocks: Vec<HashMap<Field, usize>>,
+    atts: RayAttribs,
+
+    msg_icao: Option<String>,
+    instrument: Option<InstrumentInfo>,
+    vcp: u16,
+
+    /// Cuts of the type 5 message
+    scan_strategy: Option<ScanStrategy>,
+}
+
+impl Headers {
+    /// Reads the headers of the messages in a buffer. Moment blocks are recorded at their
+    /// offsets into the buffer
+    fn read(&mut self, buf: &[u8]) -> Result<(), Error> {
+        let mut reader = buf;
+
+        while !reader.is_empty() {
+            let offset = buf.len() - reader.len();
+
+            // The volume coverage pattern comes in the metadata record, before the radials
+            if self.scan_strategy.is_none() && reader.len() >= VCP_MSG_SIZE && reader[3] == 5 {
+                self.scan_strategy = Some(read_vcp(&reader[std::mem::size_of::<MsgHeader>()..])?);
+            }
+
+            if let Some((ray, ray_blocks, end)) =
+                read_ray(&mut reader, offset, &mut self.atts, &mut self.params)?
+            {
+                self.sweep.rays.push(ray);
+                self.sweep_blocks.push(ray_blocks);
+
+                if end {
+                    self.end_sweep();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finishes the sweep being read, with the attributes averaged over its rays
+    fn end_sweep(&mut self) {
+        let atts = std::mem::take(&mut self.atts);
+        let mut sweep = std::mem::take(&mut self.sweep);
+
+        sweep.latitude = atts.lat / sweep.rays.len() as f32;
+        sweep.longitude = atts.lon / sweep.rays.len() as f32;
+        sweep.nyquist_velocity = atts.nyq / sweep.rays.len() as f32;
+        sweep.elevation = atts.elev / sweep.rays.len() as f32;
+
+        // Only keep the geometry of fields that differ from the volume
+        let params = &self.params;
+        sweep.gates = atts.gates;
+        sweep.gates.retain(|field, geometry| {
+            params.get(field).map(ParamDescription::geometry) != Some(*geometry)
+        });
+
+        if self.msg_icao.is_none() {
+            self.msg_icao = parse_icao(&atts.icao);
+        }
+
+        if self.instrument.is_none() {
+            self.instrument = atts.instrument;
+        }
+
+        if self.vcp == 0 {
+            self.vcp = atts.vcp;
+        }
+
+        self.sweeps.push(sweep);
+        self.blocks.push(std::mem::take(&mut self.sweep_blocks));
+        self.elevation_numbers.push(atts.elevation_number);
     }
 
-    let msg_31_header: Msg31Header = deserialize(&mut reader);
-    let ptrs = consume!(reader, msg_31_header.block_count as usize, u32);
+    /// Takes the sweeps that have ended, along with the moment blocks of their rays
+    fn take_sweeps(&mut self) -> (Vec<Sweep>, Vec<Vec<HashMap<Field, usize>>>) {
+        let mut sweeps = std::mem::take(&mut self.sweeps);
+        let numbers = std::mem::take(&mut self.elevation_numbers);
+
+        // The fixed angles are those of the cuts in the type 5 message. Radials don't flag the
+        // antenna moving between cuts, so rays far from the fixed angle are taken as transitions
+        if let Some(strategy) = &self.scan_strategy {
+            for (sweep, number) in sweeps.iter_mut().zip(numbers) {
+                let cut = (number as usize)
+                    .checked_sub(1)
+                    .and_then(|i| strategy.cuts.get(i));
+                sweep.fixed_angle = cut.map(|cut| cut.elevation);
+
+                if let Some(fixed_angle) = sweep.fixed_angle {
+                    for ray in &mut sweep.rays {
+                        ray.transition = (ray.elevation - fixed_angle).abs() > TRANSITION_ELEVATION;
+                    }
+                }
+            }
+        }
 
-    let mut ray = Ray::default();
-    ray.azimuth = msg_31_header.azimuth_angle;
-    atts.elev += msg_31_header.elevation_angle;
+        (sweeps, std::mem::take(&mut self.blocks))
+    }
 
-    // let mut skip = 0;
+    /// Radar of sweeps, with the metadata of the volume
+    fn radar(&self, vol_header: &VolumeHeader, sweeps: Vec<Sweep>) -> RadarFile {
+        // Without a type 5 message, the cuts are taken from the sweeps
+        let scan_strategy = match &self.scan_strategy {
+            None if self.vcp != 0 => Some(ScanStrategy::from_sweeps(self.vcp, &sweeps)),
+            strategy => strategy.clone(),
+        };
 
-    for ptr in ptrs.into_iter().filter(|&p| p > 0) {
-        let ptr = ptr as usize - std::mem::size_of::<Msg31Header>() - msg_31_header.block_count as usize * std::mem::size_of::<u32>();
-        let _ = read_data_block(&mut reader.split_at(ptr).1, atts, &mut ray, params);
+        // Some archives have a blank ICAO in the volume header, but still have it in each radial
+        RadarFile {
+            name: parse_icao(&vol_header.icao)
+                .or_else(|| self.msg_icao.clone())
+                .unwrap_or_default(),
+            sweeps,
+            params: self.params.clone(),
+            instrument: self.instrument.unwrap_or_default(),
+            scan_strategy,
+            volume_number: std::str::from_utf8(&vol_header.extension)
+                .ok()
+                .and_then(|s| s.parse().ok()),
+            corrections: None,
+        }
     }
+}
+
+/// Length of a message starting at the front of the buffer, including the CTM record of the next one
+fn message_len(header: &MsgHeader, remaining: usize) -> usize {
+    let len = if header.f_type == 31 {
+        header.size as usize * 2 + CTM_SIZE
+    } else {
+        2432
+    };
 
-    let skip = header.size as usize * 2 - 4 - std::mem::size_of::<Msg31Header>() - msg_31_header.block_count as usize * 4;
+    std::cmp::min(len, remaining)
+}
+
+/// Joins type 31 messages that were split into several segments back into single messages
+fn reassemble_segments(buf: Messages) -> Result<Messages, Error> {
+    let header_size = std::mem::size_of::<MsgHeader>();
+
+    let mut out: Option<Vec<u8>> = None;
+    let mut pending: Option<(MsgHeader, Vec<u8>)> = None;
+    let mut reader = &buf[..];
+
+    while reader.len() >= header_size {
+        let header: MsgHeader = deserialize(&reader[..header_size])?;
+        let (msg, rest) = reader.split_at(message_len(&header, reader.len()));
+
+        if header.f_type != 31 || header.segments <= 1 {
+            if let Some(out) = &mut out {
+                out.extend_from_slice(msg);
+            }
+
+            reader = rest;
+            continue;
+        }
+
+        // Copy everything before the first segmented message
+        let out = out.get_or_insert_with(|| buf[..buf.len() - reader.len()].to_vec());
+        let payload = msg
+            .get(header_size..std::cmp::min(header.size as usize * 2, msg.len()))
+            .unwrap_or_default();
+
+        if header.seg_num == 1 {
+            pending = Some((header, payload.to_vec()));
+        } else if let Some((_, data)) = &mut pending {
+            data.extend_from_slice(payload);
+        }
+
+        if header.seg_num >= header.segments {
+            if let Some((first, data)) = pending.take() {
+                let joined = MsgHeader {
+                    size: ((header_size + data.len()) / 2) as u16,
+                    segments: 1,
+                    seg_num: 1,
+                    ..first
+                };
+
+                out.extend(serialize(&joined));
+                out.extend(data);
+                out.extend([0u8; CTM_SIZE]);
+            }
+        }
+
+        reader = rest;
+    }
+
+    Ok(match out {
+        Some(mut out) => {
+            out.extend_from_slice(reader);
+            Messages::Owned(out)
+        }
+        None => buf,
+    })
+}
+
+/// A radial, the offsets of its moment blocks, and whether it ends its sweep
+type RayHeaders = (Ray, HashMap<Field, usize>, bool);
+
+/// Reads the headers of a message. `offset` is the position of the message in the buffer
+fn read_ray(
+    mut reader: &mut &[u8],
+    offset: usize,
+    atts: &mut RayAttribs,
+    params: &mut HashMap<Field, ParamDescription>,
+) -> Result<Option<RayHeaders>, Error> {
+    let header: MsgHeader = deserialize(&mut reader)?;
+
+    if header.f_type != 31 {
+        consume!(reader, 2432 - std::mem::size_of::<MsgHeader>())?;
+        return Ok(None);
+    }
+
+    let msg_31_header: Msg31Header = deserialize(&mut reader)?;
+    let ptrs = consume!(reader, msg_31_header.block_count as usize, u32)?;
+    let headers_size = std::mem::size_of::<Msg31Header>() + msg_31_header.block_count as usize * 4;
+
+    let ray = Ray {
+        time: from_day_ms(msg_31_header.collect_date as u32, msg_31_header.collect_ms),
+        azimuth: msg_31_header.azimuth_angle,
+        elevation: signed_elevation(msg_31_header.elevation_angle),
+        ..Default::default()
+    };
+    let mut blocks = HashMap::new();
+    atts.elev += ray.elevation;
+    atts.elevation_number = msg_31_header.elevation_number;
+    atts.icao = msg_31_header.icao;
+
+    for ptr in ptrs.filter(|&p| p > 0) {
+        let ptr = (ptr as usize).checked_sub(headers_size).ok_or_else(|| {
+            Error::Format("A data block starts inside the radial header".to_string())
+        })?;
+        let block_offset = offset + std::mem::size_of::<MsgHeader>() + headers_size + ptr;
+
+        let Some(mut block) = reader.get(ptr..) else {
+            return Err(Error::Format(
+                "A data block starts past the end of the data".to_string(),
+            ));
+        };
+
+        if let Some(name) = read_data_block(&mut block, atts, params)? {
+            blocks.insert(name, block_offset);
+        }
+    }
+
+    let skip = (header.size as usize * 2)
+        .checked_sub(4 + headers_size)
+        .ok_or_else(|| Error::Format("A radial is shorter than its header".to_string()))?;
 
     *reader = reader.split_at(std::cmp::min(skip, reader.len())).1;
 
-    Some((ray, msg_31_header.radial_status == 2 || msg_31_header.radial_status == 4))
+    Ok(Some((
+        ray,
+        blocks,
+        msg_31_header.radial_status == 2 || msg_31_header.radial_status == 4,
+    )))
 }
 
-fn read_data_block(mut reader: &mut &[u8], atts: &mut RayAttribs, ray: &mut Ray, params: &mut HashMap<String, ParamDescription>) -> usize {
-    match std::str::from_utf8(&consume!(reader.clone(), 4)[1..4]).unwrap() {
+/// Reads a data block, returning the name of the moment if it is a moment block
+fn read_data_block(
+    mut reader: &mut &[u8],
+    atts: &mut RayAttribs,
+    params: &mut HashMap<Field, ParamDescription>,
+) -> Result<Option<Field>, Error> {
+    let name = reader
+        .get(1..4)
+        .and_then(|name| std::str::from_utf8(name).ok())
+        .unwrap_or_default();
+
+    match name {
         "VOL" => {
-            let vol: VolumeDataBlock = deserialize_block(reader);
+            let vol: VolumeDataBlock = deserialize_block(reader)?;
             atts.lat += vol.lat;
             atts.lon += vol.lon;
-            return std::mem::size_of::<VolumeDataBlock>();
+            atts.vcp = vol.vcp;
+
+            // The antenna sits on the feedhorn above the site
+            atts.instrument.get_or_insert(InstrumentInfo {
+                altitude: Some(vol.height as f32 + vol.feedhorn_height as f32)
+                    .filter(|height| *height > 0.0),
+                radar_constant: Some(vol.refl_calib).filter(|calib| *calib != 0.0),
+                ..Default::default()
+            });
+            Ok(None)
         }
         "ELV" => {
-            let elv: ElevationDataBlock = deserialize_block(reader);
-            return std::mem::size_of::<ElevationDataBlock>();
+            let _elv: ElevationDataBlock = deserialize_block(reader)?;
+            Ok(None)
         }
         "RAD" => {
-            let rad: RadialDataBlock = deserialize_block(reader);
+            let rad: RadialDataBlock = deserialize_block(reader)?;
             atts.nyq += rad.nyquist_vel as f32 / 100.0;
-            return std::mem::size_of::<RadialDataBlock>();
+            Ok(None)
         }
         name if ["REF", "VEL", "SW ", "ZDR", "PHI", "RHO", "CFP"].contains(&name) => {
-            let name = name.trim().to_string();
-            
-            let data_block: DataBlock = deserialize(&mut reader);
+            let name: Field = name.trim().parse().unwrap();
 
-            if !params.contains_key(&name) {
-                params.insert(name.clone(), ParamDescription {
-                    meters_to_first_cell: data_block.first_gate as f32,
-                    meters_between_cells: data_block.gate_spacing as f32,
-                    ..Default::default()
-                });
-            }
+            let data_block: DataBlock = deserialize(&mut reader)?;
 
-            let (scale, offset) = scale_offset(&name);
+            atts.gates.entry(name.clone()).or_insert(GateGeometry {
+                meters_to_first_cell: data_block.first_gate as f32,
+                meters_between_cells: data_block.gate_spacing as f32,
+            });
 
-            let data = match data_block.word_size {
-                16 => consume!(reader, data_block.ngates as usize, u16).into_iter().map(|v| if v < 2 { f64::MIN } else { ((v as f32 - offset) / scale) as f64 }).collect(),
-                8 => consume!(reader, data_block.ngates as usize, u8).into_iter().map(|v| if v < 2 { f64::MIN } else { ((v as f32 - offset) / scale) as f64 }).collect(),
-                size => panic!("Unknown word size {size}"),
-            };
+            if !params.contains_key(&name) {
+                params.insert(
+                    name.clone(),
+                    ParamDescription {
+                        meters_to_first_cell: data_block.first_gate as f32,
+                        meters_between_cells: data_block.gate_spacing as f32,
+                        ..Default::default()
+                    },
+                );
+            }
 
-            ray.data.insert(name, data);
-            return std::mem::size_of::<DataBlock>() + data_block.ngates as usize * data_block.word_size as usize / 8;
+            Ok(Some(name))
         }
-        name => panic!("Unknown product {name}"),
+        name => Err(Error::Format(format!("Unknown data block {:?}", name))),
     }
 }
 
-fn decompress_records(mut reader: File) -> Vec<u8> {
-    reader.seek(SeekFrom::Current(-12)).unwrap();
-
-    let mut buf = Vec::new();
-    reader.read_to_end(&mut buf).unwrap();
-    let mut reader = buf.as_slice();
+/// Decodes the gates of a moment block
+fn decode_moment(mut reader: &[u8]) -> Result<Vec<f64>, Error> {
+    let data_block: DataBlock = deserialize(&mut reader)?;
+    let name: Field = std::str::from_utf8(&data_block.data_name)
+        .map_err(|_| Error::Format("A moment name isn't valid UTF-8".to_string()))?
+        .trim()
+        .parse()
+        .unwrap();
+    let (scale, offset) = scale_offset(&name);
+
+    Ok(match data_block.word_size {
+        16 => consume!(&mut reader, data_block.ngates as usize, u16)?
+            .map(|v| {
+                if v < 2 {
+                    MISSING
+                } else {
+                    ((v as f32 - offset) / scale) as f64
+                }
+            })
+            .collect(),
+        8 => consume!(&mut reader, data_block.ngates as usize, u8)?
+            .map(|v| {
+                if v < 2 {
+                    MISSING
+                } else {
+                    ((v as f32 - offset) / scale) as f64
+                }
+            })
+            .collect(),
+        size => {
+            return Err(Error::Format(format!(
+                "The {} moment has an unknown word size {}",
+                name, size
+            )))
+        }
+    })
+}
 
+/// Decompresses the bzip2 records that follow the volume header
+fn decompress_records(mut reader: &[u8]) -> Result<Vec<u8>, Error> {
     let mut decompressed_buf = Vec::new();
 
     loop {
-        reader = reader.split_at(4).1;
+        reader = reader
+            .get(4..)
+            .ok_or_else(|| Error::Format("A compressed record ends early".to_string()))?;
 
-        let mut new_buf = Vec::new();
+        // Each record is decompressed onto the end of the buffer
         let mut decoder = bzip2::read::BzDecoder::new(reader);
-
-        decoder.read_to_end(&mut new_buf).unwrap();
+        decoder.read_to_end(&mut decompressed_buf).map_err(|e| {
+            Error::Format(format!("A compressed record can't be decompressed: {}", e))
+        })?;
 
         reader = reader.split_at(decoder.total_in() as usize).1;
 
-        decompressed_buf.extend(new_buf);
-
-        if reader.len() == 0 {
+        if reader.is_empty() {
             break;
         }
     }
 
-    decompressed_buf[12..].to_vec()
+    if decompressed_buf.len() < CTM_SIZE {
+        return Err(Error::Format(
+            "The compressed records are empty".to_string(),
+        ));
+    }
+
+    // Drop the CTM record of the first message, in place
+    decompressed_buf.drain(..CTM_SIZE);
+    Ok(decompressed_buf)
 }
 
-/// Function to write a nexrad file
-pub fn write_nexrad(radar: &RadarFile, path: impl AsRef<Path>, options: &RadyOptions) {
-    let mut writer = create_new_file(path, radar, 0, options);
+/// Function to write a nexrad file. Returns the number of gates of each field that were
+/// written as missing because they were outside the encodable range
+pub fn write_nexrad(
+    radar: &RadarFile,
+    path: impl AsRef<Path>,
+    options: &RadyOptions,
+) -> std::io::Result<HashMap<Field, ClipCounts>> {
+    let mut clipped = HashMap::new();
+    let Some(mut writer) = create_new_file(path, radar, 0, options)? else {
+        return Ok(clipped);
+    };
 
-    for sweep_index in 0..radar.nsweeps() as usize {
-        write_sweep(radar, sweep_index, &mut writer);
+    for (sweep_index, sweep) in radar.sweeps.iter().enumerate() {
+        write_sweep(
+            radar,
+            sweep,
+            sweep_index,
+            sweep_index == radar.sweeps.len() - 1,
+            &mut writer,
+            &mut clipped,
+        )?;
     }
+
+    Ok(clipped)
 }
 
 fn string_to_bytes(string: &str) -> [u8; 4] {
@@ -428,16 +1264,16 @@ fn string_to_bytes(string: &str) -> [u8; 4] {
 }
 
 fn to_padded_string(mut bytes: &mut [u8], string: &str) {
-    bytes.write(string.to_uppercase().as_bytes()).unwrap();
+    bytes.write_all(string.to_uppercase().as_bytes()).unwrap();
 }
 
-/// Creates and initializes a new nexrad file
+/// Creates and initializes a new nexrad file, unless one exists and is to be kept
 fn create_new_file(
     path: impl AsRef<Path>,
     radar: &RadarFile,
     sweep_index: usize,
     options: &RadyOptions,
-) -> File {
+) -> std::io::Result<Option<File>> {
     let sweep = &radar.sweeps[sweep_index];
     let mut file_name = path.as_ref().to_path_buf();
 
@@ -449,106 +1285,188 @@ fn create_new_file(
             time.format(name_format)
                 .to_string()
                 .replace("[icao]", &radar.name.as_str()[0..4].to_uppercase())
+                .replace("[vol]", &radar.volume_number.unwrap_or(0).to_string())
                 .as_str(),
         );
     } else {
         file_name.push(
-            sweep.time().format(Format::NEXRAD.format_str()).to_string()
-                + format!("_{:.1}", sweep.elevation).as_str(),
+            sweep
+                .time()
+                .format(&Format::NEXRAD.format_str())
+                .to_string()
+                + "_"
+                + sweep.label().as_str(),
         );
     }
 
     // Creates the directory if it doesnt exist
-    std::fs::create_dir_all(file_name.parent().unwrap()).unwrap();
+    std::fs::create_dir_all(file_name.parent().unwrap())?;
 
     // Open the new file
-    let mut writer = File::create(file_name).unwrap();
-    let (date, time) = to_day_ms(sweep.time());
-    let icao = string_to_bytes(&radar.name);
-
-    // Write the volume header
-    let volume = VolumeHeader {
-        tape: *b"AR2V0006.",
-        extension: *b"001",
-        date,
-        time,
-        icao,
+    let Some(file_name) = resolve_conflict(file_name, options.on_conflict) else {
+        return Ok(None);
     };
+    let mut writer = File::create(file_name)?;
+    start_volume(&mut writer, radar, sweep.time())?;
+
+    Ok(Some(writer))
+}
 
-    let bytes = serialize(&volume);
-    writer.write_all(&bytes).unwrap();
-    writer.write_all(&[0u8; 12]).unwrap();
+/// Writes the volume header and the type 5 message that start a file
+fn start_volume(
+    writer: &mut impl Write,
+    radar: &RadarFile,
+    time: DateTime<Utc>,
+) -> std::io::Result<()> {
+    let mut header = pack_volume_header(&radar.name, time);
+    if let Some(number) = radar.volume_number {
+        // The extension of the volume header holds the last three digits of the volume number
+        header[9..12].copy_from_slice(format!("{:03}", number % 1000).as_bytes());
+    }
 
-    writer
+    writer.write_all(&header)?;
+    write_message(writer, &pack_vcp(&radar.strategy(), time))
 }
 
 /// Writes a sweep to the file
-fn write_sweep(radar: &RadarFile, sweep_index: usize, writer: &mut File) {
-    let sweep = &radar.sweeps[sweep_index];
+fn write_sweep(
+    radar: &RadarFile,
+    sweep: &Sweep,
+    sweep_index: usize,
+    last_sweep: bool,
+    writer: &mut impl Write,
+    clipped: &mut HashMap<Field, ClipCounts>,
+) -> std::io::Result<()> {
+    let azimuth_mode = azimuth_indexing(sweep);
+    let strategy = radar.strategy();
+
+    for (index, ray) in sweep.rays.iter().enumerate() {
+        let radial_status = match (index, sweep_index) {
+            (0, 0) => RadialStatus::StartOfVolume,
+            (0, _) => RadialStatus::StartOfElevation,
+            _ if index == sweep.rays.len() - 1 && last_sweep => RadialStatus::EndOfVolume,
+            _ if index == sweep.rays.len() - 1 => RadialStatus::EndOfElevation,
+            _ => RadialStatus::Intermediate,
+        };
 
-    for index in 0..sweep.nrays() as usize {
-        let (data, ptrs) = pack_data(radar, sweep_index, index);
-        let msg_header = pack_msg_header(sweep, data.len());
-        let msg_31_header = pack_msg_31_header(radar, sweep_index, index as u16, &ptrs);
+        let mut msg =
+            Msg31Builder::new(&radar.name, ray.time, ray.azimuth, sweep.scheduled_angle())
+                .azimuth_number(index as u16 + 1)
+                .elevation_number(sweep_index as u8 + 1)
+                .cut_sector(sweep.scan_mode.nexrad_cut_sector())
+                .azimuth_resolution(sweep.azimuth_spacing().abs())
+                .azimuth_mode(azimuth_mode)
+                .radial_status(radial_status)
+                .location(sweep.latitude, sweep.longitude)
+                .instrument(&radar.instrument)
+                .vcp(strategy.vcp)
+                .nyquist_velocity(sweep.nyquist_velocity);
+
+        for field in [
+            Field::REF,
+            Field::VEL,
+            Field::SW,
+            Field::RHO,
+            Field::PHI,
+            Field::ZDR,
+        ] {
+            if let Some(data) = ray.data.get(&field) {
+                let geometry = radar.geometry(sweep, &field);
+
+                let moment = MomentBlock::new(
+                    field.clone(),
+                    geometry.meters_to_first_cell as u16,
+                    geometry.meters_between_cells as u16,
+                    data,
+                );
+
+                *clipped.entry(field).or_default() += moment.clipped;
+                msg = msg.moment(moment);
+            }
+        }
 
-        writer.write_all(&msg_header).unwrap();
-        writer.write_all(&msg_31_header).unwrap();
-        writer.write_all(&data).unwrap();
+        write_message(writer, &msg.build())?;
     }
+
+    Ok(())
 }
 
-/// Packs a MSG31 header block
-fn pack_msg_31_header(radar: &RadarFile, sweep_index: usize, index: u16, ptrs: &[u32]) -> Vec<u8> {
-    let sweep = &radar.sweeps[sweep_index];
+/// Size of the compression/CTM record that precedes every message in an archive file
+pub const CTM_SIZE: usize = 12;
 
-    let (date, ms) = to_day_ms(sweep.time());
-    let radial_status = {
-        if index == 0 && sweep_index == 0 {
-            3
-        } else if index == sweep.nrays() - 1 && sweep_index as u16 == radar.nsweeps() - 1 {
-            4
-        } else if index == 0 {
-            0
-        } else if index == sweep.nrays() - 1 {
-            2
-        } else {
-            1
-        }
-    } as u8;
-
-    let block = Msg31Header {
-        icao: string_to_bytes(&radar.name),
-        collect_ms: ms,
-        collect_date: date as u16,
-        azimuth_number: index + 1,
-        azimuth_angle: sweep.rays[index as usize].azimuth,
-        compress_flag: 0,
-        spare_0: 0,
-        radial_length: 0,
-        azimuth_resolution: 1,
-        radial_status,
-        elevation_number: sweep_index as u8 + 1,
-        cut_sector: 1, // Check
-        elevation_angle: sweep.elevation,
-        radial_blanking: 0, // Check
-        azimuth_mode: 0,
-        block_count: 9,
+/// Packs the volume header that starts an archive file, followed by the first (uncompressed) CTM record
+pub fn pack_volume_header(icao: &str, time: DateTime<Utc>) -> Vec<u8> {
+    let (date, time) = to_day_ms(time);
+
+    let volume = VolumeHeader {
+        tape: *b"AR2V0006.",
+        extension: *b"001",
+        date,
+        time,
+        icao: string_to_bytes(icao),
     };
 
-    let mut bytes = serialize(&block);
+    serialize(&volume)
+}
 
-    bytes.extend(ptrs.iter().flat_map(|ptr| ptr.to_be_bytes().into_iter()));
-    bytes
+/// Size of a fixed length message, including its header but not its CTM record
+const VCP_MSG_SIZE: usize = 2432 - CTM_SIZE;
+
+/// Degrees from the fixed angle of the cut that a radial is taken as an antenna transition,
+/// about half the beam width
+const TRANSITION_ELEVATION: f32 = 0.5;
+
+/// Converts an angle coded in units of 180/32768 degrees
+fn from_angle_code(code: u16) -> f32 {
+    signed_elevation(code as f32 * 180.0 / 32768.0)
+}
+
+/// Codes an angle in units of 180/32768 degrees
+fn to_angle_code(angle: f32) -> u16 {
+    ((angle.rem_euclid(360.0) * 32768.0 / 180.0).round() as u32 % 65536) as u16
+}
+
+/// Reads the scan strategy from the body of a type 5 message
+fn read_vcp(mut reader: &[u8]) -> Result<ScanStrategy, Error> {
+    let header: VcpHeader = deserialize(&mut reader)?;
+
+    // Guard against counts that would run past the message
+    let max_cuts =
+        (VCP_MSG_SIZE - std::mem::size_of::<MsgHeader>() - std::mem::size_of::<VcpHeader>())
+            / std::mem::size_of::<VcpCut>();
+
+    let cuts = (0..std::cmp::min(header.cut_count as usize, max_cuts))
+        .map(|_| {
+            let cut: VcpCut = deserialize(&mut reader)?;
+
+            Ok(ScanCut {
+                elevation: from_angle_code(cut.elevation),
+                waveform: Waveform::from_nexrad(cut.waveform),
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    Ok(ScanStrategy {
+        vcp: header.pattern_number,
+        cuts,
+    })
 }
 
-/// Packs a msg header block
-fn pack_msg_header(sweep: &Sweep, data_len: usize) -> Vec<u8> {
-    let (date, ms) = to_day_ms(sweep.time());
+/// Packs a type 5 message describing the scan strategy, padded to the fixed message size.
+/// Cuts that don't fit in the message are left out
+pub fn pack_vcp(strategy: &ScanStrategy, time: DateTime<Utc>) -> Vec<u8> {
+    let max_cuts =
+        (VCP_MSG_SIZE - std::mem::size_of::<MsgHeader>() - std::mem::size_of::<VcpHeader>())
+            / std::mem::size_of::<VcpCut>();
+    let cuts = &strategy.cuts[..std::cmp::min(strategy.cuts.len(), max_cuts)];
 
-    let block = MsgHeader {
-        size: (std::mem::size_of::<Msg31Header>() + 9 * 4 + data_len + 4) as u16 / 2,
+    let vcp_size = std::mem::size_of::<VcpHeader>() + cuts.len() * std::mem::size_of::<VcpCut>();
+    let (date, ms) = to_day_ms(time);
+
+    let msg_header = MsgHeader {
+        size: ((std::mem::size_of::<MsgHeader>() + vcp_size) / 2) as u16,
         channels: 0,
-        f_type: 31,
+        f_type: 5,
         seq_id: 0,
         date: date as u16,
         ms,
@@ -556,157 +1474,369 @@ fn pack_msg_header(sweep: &Sweep, data_len: usize) -> Vec<u8> {
         seg_num: 1,
     };
 
-    serialize(&block)
+    // Constant elevation cuts, 0.5 m/s velocities and short pulses
+    let header = VcpHeader {
+        size: (vcp_size / 2) as u16,
+        pattern_type: 2,
+        pattern_number: strategy.vcp,
+        cut_count: cuts.len() as u16,
+        velocity_resolution: 2,
+        pulse_width: 2,
+        ..Default::default()
+    };
+
+    let mut bytes = serialize(&msg_header);
+    bytes.extend(serialize(&header));
+    for cut in cuts {
+        bytes.extend(serialize(&VcpCut {
+            elevation: to_angle_code(cut.elevation),
+            waveform: cut.waveform.nexrad_code(),
+            ..Default::default()
+        }));
+    }
+    bytes.resize(VCP_MSG_SIZE, 0);
+
+    bytes
 }
 
-/// Packs the data blocks
-fn pack_data(radar: &RadarFile, sweep_index: usize, index: usize) -> (Vec<u8>, Vec<u32>) {
-    let mut ptrs = Vec::new();;
-    // let mut next_ptr: u32 = 0x90;
-    let mut next_ptr = std::mem::size_of::<Msg31Header>() as u32 + 9 * 4;
-    let mut data: Vec<u8> = Vec::new();
-    let sweep = &radar.sweeps[sweep_index];
+/// Writes a message built with `Msg31Builder`, preceded by its CTM record
+pub fn write_message(writer: &mut impl Write, message: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&[0u8; CTM_SIZE])?;
+    writer.write_all(message)
+}
 
-    for data_name in ["VOL", "ELV", "RAD"] {
-        ptrs.push(next_ptr);
+/// Status of a radial within the volume
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RadialStatus {
+    StartOfElevation = 0,
+    Intermediate = 1,
+    EndOfElevation = 2,
+    StartOfVolume = 3,
+    EndOfVolume = 4,
+}
 
-        let mut new_data = match data_name {
-            "VOL" => { next_ptr += std::mem::size_of::<VolumeDataBlock>() as u32; pack_volume_block(sweep) },
-            "ELV" => { next_ptr += std::mem::size_of::<ElevationDataBlock>() as u32; pack_elevation_block() },
-            "RAD" => { next_ptr += std::mem::size_of::<RadialDataBlock>() as u32; pack_radial_block(sweep) },
-            _ => unreachable!(),
-        };
+/// A moment ("D") data block of a type 31 message
+#[derive(Clone, Debug)]
+pub struct MomentBlock {
+    /// The moment
+    pub name: Field,
+
+    /// Range to the center of the first gate in meters
+    pub first_gate: u16,
+
+    /// Distance between gates in meters
+    pub gate_spacing: u16,
+
+    /// Bits per gate, either 8 or 16
+    pub word_size: u8,
+
+    /// Scale used to encode the gates
+    pub scale: f32,
 
-        data.append(&mut new_data);
+    /// Offset used to encode the gates
+    pub offset: f32,
+
+    /// Encoded gates. 0 is below threshold and 1 is range folded
+    pub gates: Vec<u16>,
+
+    /// Valid values that did not fit in the word and were stored as below threshold
+    pub clipped: ClipCounts,
+}
+
+/// Number of valid gates that were outside the encodable range of a moment
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClipCounts {
+    /// Gates below the lowest encodable value
+    pub below: usize,
+
+    /// Gates above the highest encodable value
+    pub above: usize,
+}
+
+impl std::ops::AddAssign for ClipCounts {
+    fn add_assign(&mut self, other: ClipCounts) {
+        self.below += other.below;
+        self.above += other.above;
     }
+}
 
-    for field in ["REF", "VEL", "SW", "RHO", "PHI", "ZDR"] {
-        if !sweep.rays[index].data.contains_key(field) {
-            continue;
+impl MomentBlock {
+    /// Encodes a moment with the scale, offset, and word size the writer uses for it.
+    /// Panics if the moment is not one of REF, VEL, SW, ZDR, PHI, RHO, or CFP
+    pub fn new(name: Field, first_gate: u16, gate_spacing: u16, values: &[f64]) -> Self {
+        let (scale, offset) = scale_offset(&name);
+        let word_size = if name == Field::PHI { 16 } else { 8 };
+
+        Self::with_encoding(
+            name,
+            first_gate,
+            gate_spacing,
+            word_size,
+            scale,
+            offset,
+            values,
+        )
+    }
+
+    /// Encodes a moment with a custom scale, offset, and word size. Values that do not
+    /// fit in the word are stored as below threshold
+    pub fn with_encoding(
+        name: Field,
+        first_gate: u16,
+        gate_spacing: u16,
+        word_size: u8,
+        scale: f32,
+        offset: f32,
+        values: &[f64],
+    ) -> Self {
+        let max_val = if word_size == 16 { 65535.0 } else { 255.0 };
+        let mut clipped = ClipCounts::default();
+
+        let gates = values
+            .iter()
+            .map(|value| {
+                let val = (value * scale as f64) + offset as f64;
+                let missing = is_missing(*value);
+
+                if val > max_val || val < 2.0 {
+                    if !missing && val > max_val {
+                        clipped.above += 1;
+                    } else if !missing {
+                        clipped.below += 1;
+                    }
+
+                    0
+                } else {
+                    val as u16
+                }
+            })
+            .collect();
+
+        MomentBlock {
+            name,
+            first_gate,
+            gate_spacing,
+            word_size,
+            scale,
+            offset,
+            gates,
+            clipped,
         }
+    }
 
-        let mut new_data = pack_data_block(sweep, field, radar);
-        let mut array_data: Vec<u8>;
+    /// Packs the block header and gates
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data_name = self.name.as_str().as_bytes().to_vec();
+        data_name.resize(3, b' ');
+
+        let block = DataBlock {
+            block_type: *b"D",
+            data_name: data_name.as_slice().try_into().unwrap(),
+            reserved: 0,
+            ngates: self.gates.len() as u16,
+            first_gate: self.first_gate,
+            gate_spacing: self.gate_spacing,
+            thresh: 0,
+            snr_thresh: 0,
+            flags: 0,
+            word_size: self.word_size,
+            scale: self.scale,
+            offset: self.offset,
+        };
 
-        if field == "PHI" {
-            array_data = pack_data_array::<u16>(sweep, index, 65535.0, field);
-        } else {
-            array_data = pack_data_array::<u8>(sweep, index, 255.0, field);
+        let mut bytes = serialize(&block);
+
+        match self.word_size {
+            16 => bytes.extend(self.gates.iter().flat_map(|v| v.to_be_bytes())),
+            8 => bytes.extend(self.gates.iter().map(|&v| v as u8)),
+            size => panic!("Unknown word size {size}"),
         }
 
-        ptrs.push(next_ptr);
-        next_ptr += new_data.len() as u32 + array_data.len() as u32 + 12;
+        bytes
+    }
+}
+
+/// Builds a type 31 (generic digital radar data) message
+///
+/// The volume, elevation, and radial blocks are always written, followed by the
+/// moment blocks in the order they were added.
+#[derive(Clone, Debug)]
+pub struct Msg31Builder {
+    header: Msg31Header,
+    time: DateTime<Utc>,
+    volume: VolumeDataBlock,
+    elevation: ElevationDataBlock,
+    radial: RadialDataBlock,
+    moments: Vec<MomentBlock>,
+}
 
-        data.append(&mut new_data);
-        data.append(&mut array_data);
-        data.append(&mut vec![0u8; 12]);
+impl Msg31Builder {
+    /// Starts a message for a single radial
+    pub fn new(icao: &str, time: DateTime<Utc>, azimuth: f32, elevation: f32) -> Self {
+        let (date, ms) = to_day_ms(time);
+
+        Msg31Builder {
+            header: Msg31Header {
+                icao: string_to_bytes(icao),
+                collect_ms: ms,
+                collect_date: date as u16,
+                azimuth_number: 1,
+                azimuth_angle: azimuth,
+                compress_flag: 0,
+                spare_0: 0,
+                radial_length: 0,
+                azimuth_resolution: 1,
+                radial_status: RadialStatus::Intermediate as u8,
+                elevation_number: 1,
+                cut_sector: 1,
+                elevation_angle: elevation,
+                radial_blanking: 0,
+                azimuth_mode: 0,
+                block_count: 0,
+            },
+            time,
+            volume: VolumeDataBlock {
+                block_type: *b"R",
+                data_name: *b"VOL",
+                lrtup: std::mem::size_of::<VolumeDataBlock>() as u16,
+                ..Default::default()
+            },
+            elevation: ElevationDataBlock {
+                block_name: *b"R",
+                data_name: *b"ELV",
+                lrtup: std::mem::size_of::<ElevationDataBlock>() as u16,
+                atmos: 0,
+                refl_calib: 0.0,
+            },
+            radial: RadialDataBlock {
+                block_name: *b"R",
+                data_name: *b"RAD",
+                lrtup: std::mem::size_of::<RadialDataBlock>() as u16,
+                unambig_range: 0,
+                noise_h: 0.0,
+                noise_v: 0.0,
+                nyquist_vel: 0,
+                spare: 0,
+            },
+            moments: Vec::new(),
+        }
     }
 
-    ptrs.resize(9, 0);
+    /// Sets the 1-based index of the radial in the elevation
+    pub fn azimuth_number(mut self, number: u16) -> Self {
+        self.header.azimuth_number = number;
+        self
+    }
 
-    (data, ptrs)
-}
+    /// Sets the 1-based index of the elevation in the volume
+    pub fn elevation_number(mut self, number: u8) -> Self {
+        self.header.elevation_number = number;
+        self
+    }
 
-/// Packs a generic data block
-fn pack_data_block(sweep: &Sweep, field: &str, radar: &RadarFile) -> Vec<u8> {
-    let (scale, offset) = scale_offset(field);
-    let param = radar.params.get(&field.to_string()).unwrap();
-    let mut field_name = field.as_bytes().to_vec();
-    field_name.resize(3, 0);
+    /// Sets the sector number within the cut
+    pub fn cut_sector(mut self, sector: u8) -> Self {
+        self.header.cut_sector = sector;
+        self
+    }
 
-    let block = DataBlock {
-        block_type: *b"D",
-        data_name: field_name.as_slice().try_into().unwrap(),
-        reserved: 0,
-        ngates: sweep.ngates(),
-        first_gate: param.meters_to_first_cell as u16, // Already in meters
-        gate_spacing: param.meters_between_cells as u16, // Already in meters
-        thresh: 0,
-        snr_thresh: 0,
-        flags: 0,
-        word_size: if field == "PHI" { 16 } else { 8 },
-        scale,
-        offset,
-    };
+    /// Sets the azimuthal spacing of the radials in degrees. Spacings up to 0.75 degrees are
+    /// written as super resolution (0.5 degree) radials, and coarser ones as 1 degree radials
+    pub fn azimuth_resolution(mut self, spacing: f32) -> Self {
+        self.header.azimuth_resolution = if spacing <= 0.75 { 1 } else { 2 };
+        self
+    }
 
-    serialize(&block)
-}
+    /// Sets the azimuth indexing mode: 0 for no indexing, or the indexing angle in hundredths of a degree
+    pub fn azimuth_mode(mut self, mode: u8) -> Self {
+        self.header.azimuth_mode = mode;
+        self
+    }
 
-/// Packs a generic data array
-fn pack_data_array<T: From<u8> + ToBytes>(
-    sweep: &Sweep,
-    index: usize,
-    max_val: f64,
-    field: &str,
-) -> Vec<u8>
-where
-    f64: F64ToInt<T>,
-{
-    let data = sweep.rays[index].data.get(&field.to_string()).unwrap();
+    /// Sets the position of the radial within the volume
+    pub fn radial_status(mut self, status: RadialStatus) -> Self {
+        self.header.radial_status = status as u8;
+        self
+    }
 
-    let mut new_data: Vec<T> = Vec::with_capacity(data.len());
+    /// Sets the location of the radar
+    pub fn location(mut self, latitude: f32, longitude: f32) -> Self {
+        self.volume.lat = latitude;
+        self.volume.lon = longitude;
+        self
+    }
 
-    let (scale, offset) = scale_offset(field);
+    /// Sets the antenna height and reflectivity calibration constant, where known
+    pub fn instrument(mut self, instrument: &InstrumentInfo) -> Self {
+        self.volume.height = instrument.altitude.unwrap_or_default().round() as u16;
+        self.volume.feedhorn_height = 0;
+        self.volume.refl_calib = instrument.radar_constant.unwrap_or_default();
+        self
+    }
 
-    for i in 0..data.len() {
-        let val = (data[i] * scale as f64) + offset as f64;
+    /// Sets the volume coverage pattern
+    pub fn vcp(mut self, vcp: u16) -> Self {
+        self.volume.vcp = vcp;
+        self
+    }
 
-        if val > max_val || val < 2.0 {
-            new_data.push(<f64 as F64ToInt<T>>::f64_to_int(0.0f64));
-        } else {
-            new_data.push(<f64 as F64ToInt<T>>::f64_to_int(val));
-        }
-    }
-
-    new_data.into_iter().flat_map(|x| x.to_be_bytes()).collect()
-}
-
-fn pack_volume_block(sweep: &Sweep) -> Vec<u8> {
-    let block = VolumeDataBlock {
-        block_type: *b"R",
-        data_name: *b"VOL",
-        lrtup: std::mem::size_of::<VolumeDataBlock>() as u16,
-        version_major: 0,
-        version_minor: 0,
-        lat: sweep.latitude,
-        lon: sweep.longitude,
-        height: 0,
-        feedhorn_height: 0,
-        refl_calib: 0.0,
-        power_h: 0.0,
-        power_v: 0.0,
-        diff_refl_calib: 0.0,
-        init_phase: 0.0,
-        vcp: 0,
-        ..Default::default()
-    };
+    /// Sets the nyquist velocity in m/s
+    pub fn nyquist_velocity(mut self, nyquist_velocity: f32) -> Self {
+        self.radial.nyquist_vel = (nyquist_velocity * 100.0) as u16;
+        self
+    }
 
-    serialize(&block)
-}
+    /// Adds a moment block
+    pub fn moment(mut self, moment: MomentBlock) -> Self {
+        self.moments.push(moment);
+        self
+    }
 
-fn pack_elevation_block() -> Vec<u8> {
-    let block = ElevationDataBlock {
-        block_name: *b"R",
-        data_name: *b"ELV",
-        lrtup: std::mem::size_of::<ElevationDataBlock>() as u16,
-        atmos: 0,
-        refl_calib: 0.0,
-    };
+    /// Packs the message header, type 31 header, block pointers, and data blocks.
+    /// The CTM record is not included, see `write_message`
+    pub fn build(&self) -> Vec<u8> {
+        let block_count = 3 + self.moments.len();
+        let mut blocks = vec![
+            serialize(&self.volume),
+            serialize(&self.elevation),
+            serialize(&self.radial),
+        ];
+        blocks.extend(self.moments.iter().map(|moment| moment.pack()));
+
+        // Block pointers are relative to the start of the type 31 header
+        let mut ptrs = Vec::with_capacity(block_count);
+        let mut next_ptr = std::mem::size_of::<Msg31Header>() + block_count * 4;
+        for block in &blocks {
+            ptrs.push(next_ptr as u32);
+            next_ptr += block.len();
+        }
 
-    serialize(&block)
-}
+        // Messages are measured in halfwords
+        let radial_length = next_ptr + next_ptr % 2;
+
+        let msg_header = MsgHeader {
+            size: ((std::mem::size_of::<MsgHeader>() + radial_length) / 2) as u16,
+            channels: 0,
+            f_type: 31,
+            seq_id: 0,
+            date: self.header.collect_date,
+            ms: to_day_ms(self.time).1,
+            segments: 1,
+            seg_num: 1,
+        };
 
-fn pack_radial_block(sweep: &Sweep) -> Vec<u8> {
-    let block = RadialDataBlock {
-        block_name: *b"R",
-        data_name: *b"RAD",
-        lrtup: std::mem::size_of::<RadialDataBlock>() as u16,
-        unambig_range: 0,
-        noise_h: 0.0,
-        noise_v: 0.0,
-        nyquist_vel: (sweep.nyquist_velocity * 100.0) as u16,
-        spare: 0,
-    };
+        let header = Msg31Header {
+            radial_length: radial_length as u16,
+            block_count: block_count as u16,
+            ..self.header
+        };
 
-    serialize(&block)
+        let mut bytes = serialize(&msg_header);
+        bytes.extend(serialize(&header));
+        bytes.extend(ptrs.iter().flat_map(|ptr| ptr.to_be_bytes()));
+        blocks.into_iter().for_each(|block| bytes.extend(block));
+        bytes.resize(std::mem::size_of::<MsgHeader>() + radial_length, 0);
+
+        bytes
+    }
 }
@@ -0,0 +1,281 @@
+//! Interpolation of radar fields from polar gates onto a Cartesian grid
+
+#[cfg(feature = "gpu")]
+mod gpu;
+
+use crate::processing::{beam_height, EARTH_RADIUS};
+use crate::{is_missing, Field, RadarFile, MISSING};
+use std::collections::HashMap;
+use std::str::FromStr;
+use tracing::instrument;
+
+/// Evenly spaced grid coordinates in meters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Axis {
+    /// Coordinate of the first point
+    pub start: f32,
+
+    /// Distance between points
+    pub spacing: f32,
+
+    /// Number of points
+    pub count: usize,
+}
+
+impl Axis {
+    /// Axis of `count` points centered on the radar
+    pub fn centered(spacing: f32, count: usize) -> Axis {
+        Axis {
+            start: -spacing * (count as f32 - 1.0) / 2.0,
+            spacing,
+            count,
+        }
+    }
+
+    /// Coordinate of a point
+    pub fn value(&self, index: usize) -> f32 {
+        self.start + index as f32 * self.spacing
+    }
+}
+
+/// Distance weighting of the gates around a grid point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Weighting {
+    /// (R² - d²) / (R² + d²)
+    #[default]
+    Cressman,
+
+    /// exp(-d² / κ), with κ = R² / 4
+    Barnes,
+}
+
+impl Weighting {
+    /// Weight of a gate `distance` meters from a grid point, with a radius of influence
+    pub fn weight(&self, distance: f32, radius: f32) -> f32 {
+        let (d2, r2) = (distance * distance, radius * radius);
+
+        match self {
+            Weighting::Cressman => (r2 - d2) / (r2 + d2),
+            Weighting::Barnes => (-4.0 * d2 / r2).exp(),
+        }
+    }
+}
+
+impl FromStr for Weighting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Weighting, String> {
+        match s.to_lowercase().as_str() {
+            "cressman" => Ok(Weighting::Cressman),
+            "barnes" => Ok(Weighting::Barnes),
+            _ => Err(format!(
+                "Unknown weighting {}, expected cressman or barnes",
+                s
+            )),
+        }
+    }
+}
+
+/// Distance from a grid point within which gates are weighted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RadiusOfInfluence {
+    /// The same radius in meters everywhere
+    Constant(f32),
+
+    /// Grows with distance from the radar to match the beam width in degrees, and is at least
+    /// `min` meters
+    Beam { min: f32, beam_width: f32 },
+}
+
+impl RadiusOfInfluence {
+    /// Radius at a point `distance` meters from the radar
+    pub fn at(&self, distance: f32) -> f32 {
+        match *self {
+            RadiusOfInfluence::Constant(radius) => radius,
+            RadiusOfInfluence::Beam { min, beam_width } => {
+                min.max(distance * beam_width.to_radians())
+            }
+        }
+    }
+
+    /// Smallest radius anywhere
+    fn min(&self) -> f32 {
+        match *self {
+            RadiusOfInfluence::Constant(radius) => radius,
+            RadiusOfInfluence::Beam { min, .. } => min,
+        }
+    }
+}
+
+/// Layout of a grid centered on the radar
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridSpec {
+    /// Eastward coordinates
+    pub x: Axis,
+
+    /// Northward coordinates
+    pub y: Axis,
+
+    /// Heights above the radar. A 2-D grid weights gates by horizontal distance only
+    pub z: Option<Axis>,
+
+    pub weighting: Weighting,
+    pub radius: RadiusOfInfluence,
+}
+
+/// A field interpolated onto a grid
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    pub spec: GridSpec,
+    pub field: Field,
+
+    /// Values indexed by height, then y, then x. Points without gates in range are missing
+    pub values: Vec<f64>,
+}
+
+impl Grid {
+    /// Value at a point, using height 0 for a 2-D grid
+    pub fn get(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[(z * self.spec.y.count + y) * self.spec.x.count + x]
+    }
+}
+
+/// Position of a gate relative to the radar as east, north, and height in meters, using the 4/3
+/// effective earth radius model
+pub fn gate_position(range: f32, elevation: f32, azimuth: f32) -> (f32, f32, f32) {
+    let radius = EARTH_RADIUS * 4.0 / 3.0;
+    let height = beam_height(range, elevation);
+    let distance = radius * (range * elevation.to_radians().cos() / (radius + height)).asin();
+    let azimuth = azimuth.to_radians();
+
+    (distance * azimuth.sin(), distance * azimuth.cos(), height)
+}
+
+/// Where a grid is interpolated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// On the GPU when the build has the `gpu` feature and one is available, and on the CPU
+    /// otherwise
+    #[default]
+    Auto,
+
+    Cpu,
+}
+
+/// Gate with data, as its east, north, and height in meters and its value
+pub type Gate = (f32, f32, f32, f64);
+
+/// Interpolates a field from every sweep onto a grid, weighting the gates within the radius of
+/// influence of each point
+pub fn grid(radar: &RadarFile, field: &Field, spec: &GridSpec) -> Grid {
+    grid_with(radar, field, spec, Backend::Auto)
+}
+
+/// Interpolates a field onto a grid like `grid`, on a chosen backend
+#[instrument(skip_all, fields(field = field.as_str()))]
+pub fn grid_with(radar: &RadarFile, field: &Field, spec: &GridSpec, backend: Backend) -> Grid {
+    let gates: Vec<Gate> = radar
+        .sweeps
+        .iter()
+        .flat_map(|sweep| {
+            let geometry = radar.geometry(sweep, field);
+
+            sweep
+                .rays
+                .iter()
+                .filter_map(|ray| ray.data.get(field).map(|data| (ray, data)))
+                .flat_map(move |(ray, data)| {
+                    data.iter()
+                        .enumerate()
+                        .filter(|(_, value)| !is_missing(**value))
+                        .map(move |(gate, &value)| {
+                            let (x, y, z) =
+                                gate_position(geometry.range(gate), ray.elevation, ray.azimuth);
+                            (x, y, z, value)
+                        })
+                })
+        })
+        .collect();
+
+    Grid {
+        spec: spec.clone(),
+        field: field.clone(),
+        values: interpolate(&gates, spec, backend),
+    }
+}
+
+/// Weighted average of the gates within the radius of influence of each point of a grid, indexed
+/// by height, then y, then x. Points without gates in range are MISSING
+pub fn interpolate(gates: &[Gate], spec: &GridSpec, backend: Backend) -> Vec<f64> {
+    // Buckets of gates in squares as wide as the smallest radius, so a point only checks nearby gates
+    let size = spec.radius.min().max(1.0);
+
+    match backend {
+        #[cfg(feature = "gpu")]
+        Backend::Auto => gpu::interpolate(gates, spec, size)
+            .unwrap_or_else(|| interpolate_cpu(gates, spec, size)),
+        _ => interpolate_cpu(gates, spec, size),
+    }
+}
+
+/// Bucket of the square `size` meters wide that a point is in
+fn bucket(x: f32, y: f32, size: f32) -> (i32, i32) {
+    ((x / size).floor() as i32, (y / size).floor() as i32)
+}
+
+/// Weighted average of the gates around each point of a grid, on the CPU
+fn interpolate_cpu(gates: &[Gate], spec: &GridSpec, size: f32) -> Vec<f64> {
+    let mut buckets: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+
+    for (i, &(x, y, _, _)) in gates.iter().enumerate() {
+        buckets.entry(bucket(x, y, size)).or_default().push(i);
+    }
+
+    let levels: Vec<Option<f32>> = match spec.z {
+        Some(z) => (0..z.count).map(|k| Some(z.value(k))).collect(),
+        None => vec![None],
+    };
+
+    let mut values = Vec::with_capacity(levels.len() * spec.y.count * spec.x.count);
+
+    for level in &levels {
+        for j in 0..spec.y.count {
+            for i in 0..spec.x.count {
+                let (x, y) = (spec.x.value(i), spec.y.value(j));
+                let radius = spec.radius.at(x.hypot(y));
+                let reach = (radius / size).ceil() as i32;
+                let (bx, by) = bucket(x, y, size);
+
+                let (mut total, mut weights) = (0.0, 0.0);
+
+                for cx in bx - reach..=bx + reach {
+                    for cy in by - reach..=by + reach {
+                        for &g in buckets.get(&(cx, cy)).into_iter().flatten() {
+                            let (gx, gy, gz, value) = gates[g];
+                            let distance = match level {
+                                Some(z) => {
+                                    ((gx - x).powi(2) + (gy - y).powi(2) + (gz - z).powi(2)).sqrt()
+                                }
+                                None => (gx - x).hypot(gy - y),
+                            };
+
+                            if distance < radius {
+                                let weight = spec.weighting.weight(distance, radius) as f64;
+                                total += weight * value;
+                                weights += weight;
+                            }
+                        }
+                    }
+                }
+
+                values.push(if weights > 0.0 {
+                    total / weights
+                } else {
+                    MISSING
+                });
+            }
+        }
+    }
+
+    values
+}
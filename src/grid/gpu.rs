@@ -0,0 +1,264 @@
+//! Gridding on the GPU with wgpu, for grids too large to interpolate quickly on the CPU
+
+use super::{bucket, Gate, GridSpec, RadiusOfInfluence, Weighting};
+use crate::MISSING;
+use std::sync::OnceLock;
+use wgpu::util::DeviceExt;
+
+/// Points interpolated by each workgroup, as in the shader
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Device and pipeline, set up once for every grid
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+/// The GPU, or None if there isn't one that can run the shader
+fn gpu() -> Option<&'static Gpu> {
+    static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+
+    GPU.get_or_init(|| {
+        let instance = wgpu::Instance::default();
+        let options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        };
+        let adapter = pollster::block_on(instance.request_adapter(&options))?;
+
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("grid"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None)).ok()?;
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("grid"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("grid.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("grid"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(e) => {
+                eprintln!("Gridding on the CPU, the GPU can't run the shader: {}", e);
+                None
+            }
+            None => Some(Gpu {
+                device,
+                queue,
+                pipeline,
+            }),
+        }
+    })
+    .as_ref()
+}
+
+/// Bytes of 32-bit words, in the GPU's byte order
+fn bytes(words: impl IntoIterator<Item = u32>) -> Vec<u8> {
+    words.into_iter().flat_map(u32::to_le_bytes).collect()
+}
+
+/// Weighted average of the gates around each point of a grid, on the GPU. Sums are in single
+/// precision, so values are summed relative to their mean. Returns None if there is no GPU or the
+/// grid doesn't fit on it
+pub(super) fn interpolate(gates: &[Gate], spec: &GridSpec, size: f32) -> Option<Vec<f64>> {
+    let gpu = gpu()?;
+    let limits = gpu.device.limits();
+
+    // Gates are sorted by bucket, over the buckets from the first to the last that has gates
+    let cells: Vec<(i32, i32)> = gates
+        .iter()
+        .map(|&(x, y, _, _)| bucket(x, y, size))
+        .collect();
+    let first_x = cells.iter().map(|cell| cell.0).min().unwrap_or(0);
+    let first_y = cells.iter().map(|cell| cell.1).min().unwrap_or(0);
+    let cells_x = cells
+        .iter()
+        .map(|cell| cell.0 - first_x + 1)
+        .max()
+        .unwrap_or(1) as usize;
+    let cells_y = cells
+        .iter()
+        .map(|cell| cell.1 - first_y + 1)
+        .max()
+        .unwrap_or(1) as usize;
+    let cell_index =
+        |cell: (i32, i32)| (cell.1 - first_y) as usize * cells_x + (cell.0 - first_x) as usize;
+
+    let mut cell_start = vec![0u32; cells_x.checked_mul(cells_y)? + 1];
+    for &cell in &cells {
+        cell_start[cell_index(cell) + 1] += 1;
+    }
+    for i in 1..cell_start.len() {
+        cell_start[i] += cell_start[i - 1];
+    }
+
+    let mean = gates.iter().map(|gate| gate.3).sum::<f64>() / gates.len().max(1) as f64;
+    let mut next = cell_start.clone();
+    let mut sorted = vec![[0.0f32; 4]; gates.len().max(1)];
+    for (&(x, y, z, value), &cell) in gates.iter().zip(&cells) {
+        let slot = &mut next[cell_index(cell)];
+        sorted[*slot as usize] = [x, y, z, (value - mean) as f32];
+        *slot += 1;
+    }
+
+    let z = spec.z.unwrap_or(super::Axis {
+        start: 0.0,
+        spacing: 0.0,
+        count: 1,
+    });
+    let points = z
+        .count
+        .checked_mul(spec.y.count)?
+        .checked_mul(spec.x.count)?;
+    let groups = u32::try_from(points).ok()?.div_ceil(WORKGROUP_SIZE);
+    let rows = groups
+        .div_ceil(limits.max_compute_workgroups_per_dimension)
+        .max(1);
+    let row = groups.div_ceil(rows);
+
+    let sums_size = points as u64 * 8;
+    let gates_size = sorted.len() as u64 * 16;
+    let largest = sums_size.max(gates_size).max(cell_start.len() as u64 * 4);
+    if points == 0
+        || rows > limits.max_compute_workgroups_per_dimension
+        || largest > limits.max_storage_buffer_binding_size as u64
+        || largest > limits.max_buffer_size
+    {
+        return None;
+    }
+
+    let (radius, beam, beam_width) = match spec.radius {
+        RadiusOfInfluence::Constant(radius) => (radius, 0, 0.0),
+        RadiusOfInfluence::Beam { min, beam_width } => (min, 1, beam_width.to_radians()),
+    };
+
+    let params = bytes([
+        spec.x.start.to_bits(),
+        spec.x.spacing.to_bits(),
+        spec.x.count as u32,
+        spec.y.start.to_bits(),
+        spec.y.spacing.to_bits(),
+        spec.y.count as u32,
+        z.start.to_bits(),
+        z.spacing.to_bits(),
+        z.count as u32,
+        spec.z.is_some() as u32,
+        (spec.weighting == Weighting::Barnes) as u32,
+        beam,
+        radius.to_bits(),
+        beam_width.to_bits(),
+        size.to_bits(),
+        first_x as u32,
+        first_y as u32,
+        cells_x as u32,
+        cells_y as u32,
+        row * WORKGROUP_SIZE,
+    ]);
+
+    let device = &gpu.device;
+    device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let storage = |label, contents: &[u8]| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    };
+    let params = storage("params", &params);
+    let gates = storage(
+        "gates",
+        &bytes(sorted.iter().flatten().map(|v| v.to_bits())),
+    );
+    let cell_start = storage("cell start", &bytes(cell_start));
+
+    let sums = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("sums"),
+        size: sums_size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: sums_size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("grid"),
+        layout: &gpu.pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: gates.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: cell_start.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: sums.as_entire_binding(),
+            },
+        ],
+    });
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&gpu.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(row, rows, 1);
+    }
+    encoder.copy_buffer_to_buffer(&sums, 0, &readback, 0, sums_size);
+    gpu.queue.submit([encoder.finish()]);
+
+    let validation = pollster::block_on(device.pop_error_scope());
+    let memory = pollster::block_on(device.pop_error_scope());
+    if let Some(e) = validation.or(memory) {
+        eprintln!("Gridding on the CPU, the GPU failed: {}", e);
+        return None;
+    }
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    readback
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap()
+        });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv().ok()?.ok()?;
+
+    let data = readback.slice(..).get_mapped_range();
+    let values = data
+        .chunks_exact(8)
+        .map(|sum| {
+            let total = f32::from_le_bytes(sum[..4].try_into().unwrap()) as f64;
+            let weights = f32::from_le_bytes(sum[4..].try_into().unwrap()) as f64;
+            if weights > 0.0 {
+                mean + total / weights
+            } else {
+                MISSING
+            }
+        })
+        .collect();
+
+    Some(values)
+}
@@ -0,0 +1,87 @@
+// Weighted average of the gates around each point of a grid, one point per invocation. Gates are
+// sorted into square buckets, and `cell_start` has the first gate of each bucket and the end of
+// the last one
+
+struct Params {
+    x_start: f32,
+    x_spacing: f32,
+    x_count: u32,
+    y_start: f32,
+    y_spacing: f32,
+    y_count: u32,
+    z_start: f32,
+    z_spacing: f32,
+    z_count: u32,
+    has_z: u32,
+    barnes: u32,
+    beam: u32,
+    radius: f32,
+    beam_width: f32,
+    size: f32,
+    first_x: i32,
+    first_y: i32,
+    cells_x: u32,
+    cells_y: u32,
+    row: u32,
+}
+
+@group(0) @binding(0) var<storage, read> params: Params;
+@group(0) @binding(1) var<storage, read> gates: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read> cell_start: array<u32>;
+@group(0) @binding(3) var<storage, read_write> sums: array<vec2<f32>>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x + id.y * params.row;
+    let per_level = params.x_count * params.y_count;
+    if (index >= per_level * params.z_count) {
+        return;
+    }
+
+    let level = index / per_level;
+    let point = vec2<f32>(
+        params.x_start + f32(index % params.x_count) * params.x_spacing,
+        params.y_start + f32((index % per_level) / params.x_count) * params.y_spacing,
+    );
+    let z = params.z_start + f32(level) * params.z_spacing;
+
+    var radius = params.radius;
+    if (params.beam == 1u) {
+        radius = max(radius, length(point) * params.beam_width);
+    }
+    let r2 = radius * radius;
+
+    let reach = i32(ceil(radius / params.size));
+    let bx = i32(floor(point.x / params.size)) - params.first_x;
+    let by = i32(floor(point.y / params.size)) - params.first_y;
+
+    var total = 0.0;
+    var weights = 0.0;
+
+    for (var cy = max(by - reach, 0); cy <= min(by + reach, i32(params.cells_y) - 1); cy++) {
+        for (var cx = max(bx - reach, 0); cx <= min(bx + reach, i32(params.cells_x) - 1); cx++) {
+            let cell = u32(cy) * params.cells_x + u32(cx);
+
+            for (var g = cell_start[cell]; g < cell_start[cell + 1u]; g++) {
+                let gate = gates[g];
+                let offset = gate.xy - point;
+                var d2 = dot(offset, offset);
+                if (params.has_z == 1u) {
+                    d2 += (gate.z - z) * (gate.z - z);
+                }
+
+                if (d2 < r2) {
+                    var weight = (r2 - d2) / (r2 + d2);
+                    if (params.barnes == 1u) {
+                        weight = exp(-4.0 * d2 / r2);
+                    }
+
+                    total += weight * gate.w;
+                    weights += weight;
+                }
+            }
+        }
+    }
+
+    sums[index] = vec2<f32>(total, weights);
+}
@@ -1,28 +1,136 @@
 use chrono::{DateTime, Utc};
-use clap::{App, AppSettings, Arg};
+use clap::{App, AppSettings, Arg, ErrorKind};
 use glob::glob;
-use std::collections::HashMap;
-use std::path::Path;
+#[cfg(feature = "watch")]
+use notify::RecursiveMode;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::sync_channel;
+use std::sync::Mutex;
+use tracing::{info_span, instrument};
 
 mod formats;
-use formats::*;
+pub use formats::nexrad;
+pub use formats::{
+    detect, reader, register_reader, register_writer, writer, RadarRead, RadarWrite, SweepSink,
+};
+
+pub mod expr;
+pub mod grid;
+pub mod processing;
+pub mod radx;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod selftest;
+#[cfg(feature = "watch")]
+pub mod serve;
+pub mod testdata;
 
 /// Radar format to conver to
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
 pub enum Format {
     NEXRAD,
     DORADE,
+    CFRADIAL,
+
+    /// A format added with `register_writer`, by name
+    Custom(&'static str),
 }
 
 impl Format {
-    fn format_str(&self) -> &str {
+    /// Name of the format in the registry
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::NEXRAD => "nexrad",
+            Format::DORADE => "dorade",
+            Format::CFRADIAL => "cfradial",
+            Format::Custom(name) => name,
+        }
+    }
+
+    fn format_str(&self) -> String {
+        format!("{}.%Y%m%d_%H%M%S", self.name().to_uppercase())
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    /// Parses a format name, ignoring case. Names of registered writers become `Format::Custom`.
+    /// CfRadial is an error in builds without the `netcdf` feature, which can't write it
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s.to_lowercase().as_str() {
+            "nexrad" => Ok(Format::NEXRAD),
+            "dorade" => Ok(Format::DORADE),
+            #[cfg(feature = "netcdf")]
+            "cfradial" => Ok(Format::CFRADIAL),
+            #[cfg(not(feature = "netcdf"))]
+            "cfradial" => Err("This build can't write CfRadial".to_string()),
+            name => match writer(name) {
+                Some(writer) => Ok(Format::Custom(writer.name())),
+                None => Err(format!("Unknown format {}", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// Why a file couldn't be read or written
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The file couldn't be opened, read, or written
+    Io(std::io::Error),
+
+    /// The file isn't valid in its format, such as a file that ends early
+    Format(String),
+
+    /// The files that were written don't match the radar written into them
+    Verify(Vec<PathBuf>, Vec<String>),
+
+    /// The format can't be read or written this way, such as a format that needs a path
+    Unsupported(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Format::DORADE => "DORADE.%Y%m%d_%H%M%S",
-            Format::NEXRAD => "NEXRAD.%Y%m%d_%H%M%S",
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Format(message) | Error::Unsupported(message) => f.write_str(message),
+            Error::Verify(files, differences) => {
+                let names: Vec<String> = files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect();
+                write!(f, "{} differs from what was written:", names.join(", "))?;
+                differences
+                    .iter()
+                    .try_for_each(|difference| write!(f, "\n  {}", difference))
+            }
         }
     }
 }
 
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
 // macro_rules! value_enum {
 //     ($name:ident,
 //         $(
@@ -49,7 +157,7 @@ impl Format {
 // pub static radar_abreivations: HashMap<&str, &str> = HashMap::from([])
 
 /// Scan mode of a radar
-#[derive(Copy, Clone, PartialEq, Eq, Default)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
 pub enum ScanMode {
     Calibration,
     #[default]
@@ -63,10 +171,352 @@ pub enum ScanMode {
     Surveillance,
     Airborne,
     Horizontal,
+
+    /// Scans back and forth in azimuth over part of a rotation
+    Sector,
+}
+
+impl ScanMode {
+    /// Fewest rays for a piece of a split sweep to be kept. Rotating scans leave short pieces
+    /// of the next rotation, while sector scans can be narrow
+    pub fn min_rays(&self) -> usize {
+        if self.is_rotating() {
+            20
+        } else {
+            2
+        }
+    }
+
+    /// Checks if the antenna turns through full rotations, so a sweep can run into the next one
+    pub fn is_rotating(&self) -> bool {
+        matches!(
+            self,
+            ScanMode::PPI | ScanMode::Surveillance | ScanMode::Horizontal | ScanMode::Airborne
+        )
+    }
+
+    /// Checks if the antenna covers part of a rotation, which can include north
+    pub fn is_sector(&self) -> bool {
+        matches!(self, ScanMode::Sector | ScanMode::Manual)
+    }
+}
+
+/// Pulse waveform used for a cut
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Waveform {
+    /// Long PRT, for reflectivity
+    ContiguousSurveillance,
+
+    /// Short PRT, for velocity, with range ambiguity resolution
+    ContiguousDoppler,
+
+    /// Short PRT, for velocity, without range ambiguity resolution
+    ContiguousDopplerNoResolution,
+
+    /// Alternating long and short PRT
+    Batch,
+
+    /// Staggered PRT
+    StaggeredPulsePair,
+
+    #[default]
+    Unknown,
+}
+
+/// A scheduled cut of a scan strategy
+#[derive(Clone, Copy, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ScanCut {
+    /// Fixed elevation angle in degrees
+    pub elevation: f32,
+
+    /// Waveform of the cut
+    pub waveform: Waveform,
+}
+
+/// Scan strategy (volume coverage pattern) of a volume
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ScanStrategy {
+    /// Volume coverage pattern number, 0 if unknown
+    pub vcp: u16,
+
+    /// Cuts in the order they are collected
+    pub cuts: Vec<ScanCut>,
+}
+
+impl ScanStrategy {
+    /// Builds a strategy from the elevations of the sweeps, guessing the waveforms from their fields
+    pub fn from_sweeps(vcp: u16, sweeps: &[Sweep]) -> Self {
+        let cuts = sweeps
+            .iter()
+            .map(|sweep| {
+                let has = |field| {
+                    sweep
+                        .rays
+                        .first()
+                        .is_some_and(|ray| ray.data.contains_key(field))
+                };
+
+                ScanCut {
+                    elevation: sweep.scheduled_angle(),
+                    waveform: match (has(&Field::REF), has(&Field::VEL)) {
+                        (true, true) => Waveform::Batch,
+                        (true, false) => Waveform::ContiguousSurveillance,
+                        (false, true) => Waveform::ContiguousDoppler,
+                        (false, false) => Waveform::Unknown,
+                    },
+                }
+            })
+            .collect();
+
+        ScanStrategy { vcp, cuts }
+    }
+}
+
+/// A radar moment. Serialized as its generic name
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum Field {
+    /// Reflectivity
+    REF,
+
+    /// Radial velocity
+    VEL,
+
+    /// Spectrum width
+    SW,
+
+    /// Differential reflectivity
+    ZDR,
+
+    /// Differential phase
+    PHI,
+
+    /// Correlation coefficient
+    RHO,
+
+    /// Specific differential phase
+    KDP,
+
+    /// Clutter filter power removed
+    CFP,
+
+    /// Rain rate derived from reflectivity
+    RATE,
+
+    /// Storm-relative radial velocity
+    SRV,
+
+    /// Echo top height
+    ETH,
+
+    /// Any other field, by its name in the file
+    Other(String),
+}
+
+impl Field {
+    /// Generic name of the field
+    pub fn as_str(&self) -> &str {
+        match self {
+            Field::REF => "REF",
+            Field::VEL => "VEL",
+            Field::SW => "SW",
+            Field::ZDR => "ZDR",
+            Field::PHI => "PHI",
+            Field::RHO => "RHO",
+            Field::KDP => "KDP",
+            Field::CFP => "CFP",
+            Field::RATE => "RATE",
+            Field::SRV => "SRV",
+            Field::ETH => "ETH",
+            Field::Other(name) => name,
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = Infallible;
+
+    /// Parses a generic field name, ignoring case. Unknown names become `Field::Other`
+    fn from_str(s: &str) -> Result<Field, Infallible> {
+        Ok(match s.to_uppercase().as_str() {
+            "REF" => Field::REF,
+            "VEL" => Field::VEL,
+            "SW" => Field::SW,
+            "ZDR" => Field::ZDR,
+            "PHI" => Field::PHI,
+            "RHO" => Field::RHO,
+            "KDP" => Field::KDP,
+            "CFP" => Field::CFP,
+            "RATE" => Field::RATE,
+            "SRV" => Field::SRV,
+            "ETH" => Field::ETH,
+            _ => Field::Other(s.to_string()),
+        })
+    }
+}
+
+impl From<String> for Field {
+    fn from(s: String) -> Field {
+        s.parse().unwrap()
+    }
+}
+
+impl From<Field> for String {
+    fn from(field: Field) -> String {
+        field.as_str().to_string()
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// Value of a gate with no valid data. Readers translate their format's bad data flags to it,
+/// and writers translate it back
+pub const MISSING: f64 = f64::NAN;
+
+/// Speed of light in m/s, for converting between frequencies and wavelengths
+pub(crate) const SPEED_OF_LIGHT: f32 = 299_792_458.0;
+
+/// Checks if a gate has no valid data
+pub fn is_missing(value: f64) -> bool {
+    !value.is_finite()
+}
+
+/// Where in the beam a ray's azimuth is measured
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum AzimuthRef {
+    /// Center of the beam, as native 88D data uses
+    #[default]
+    Center,
+
+    /// Edge of the beam in the direction of rotation
+    LeadingEdge,
+}
+
+/// What happens when an output file already exists
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum OnConflict {
+    /// Replaces the existing file
+    Overwrite,
+
+    /// Keeps the existing file, and the new one isn't written
+    Skip,
+
+    /// Writes the new file with a numbered suffix, such as `_1`
+    #[default]
+    Suffix,
+}
+
+/// Convention for the names of fields in CfRadial files
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Naming {
+    /// Short names such as DBZ and WIDTH, as LROSE writes them
+    #[default]
+    Short,
+
+    /// Py-ART names such as reflectivity
+    PyArt,
+
+    /// CF standard names such as equivalent_reflectivity_factor
+    Cf,
+
+    /// OPERA (ODIM) quantities such as DBZH
+    Opera,
+}
+
+impl FromStr for Naming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Naming, String> {
+        match s.to_lowercase().as_str() {
+            "short" => Ok(Naming::Short),
+            "pyart" => Ok(Naming::PyArt),
+            "cf" => Ok(Naming::Cf),
+            "opera" => Ok(Naming::Opera),
+            _ => Err(format!(
+                "Unknown naming {}, expected short, pyart, cf, or opera",
+                s
+            )),
+        }
+    }
+}
+
+/// Path to write an output file to under a conflict policy, or None if it shouldn't be written
+pub(crate) fn resolve_conflict(path: PathBuf, on_conflict: OnConflict) -> Option<PathBuf> {
+    if !path.exists() {
+        return Some(path);
+    }
+
+    match on_conflict {
+        OnConflict::Overwrite => Some(path),
+        OnConflict::Skip => {
+            eprintln!("Skipping {}: it already exists", path.display());
+            None
+        }
+        OnConflict::Suffix => {
+            // Names can end in an elevation such as 0.5, so only letters are kept as an extension
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let (stem, extension) = match name.rsplit_once('.') {
+                Some((stem, extension)) if extension.chars().all(|c| c.is_ascii_alphabetic()) => {
+                    (stem, format!(".{}", extension))
+                }
+                _ => (name.as_str(), String::new()),
+            };
+
+            let renamed = (1..)
+                .map(|n| path.with_file_name(format!("{}_{}{}", stem, n, extension)))
+                .find(|path| !path.exists())
+                .unwrap();
+            eprintln!(
+                "{} already exists, writing {}",
+                path.display(),
+                renamed.display()
+            );
+            Some(renamed)
+        }
+    }
+}
+
+/// How rays are resampled onto a regular grid of azimuths
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum AzimuthInterpolation {
+    /// Takes the nearest ray
+    #[default]
+    Nearest,
+
+    /// Interpolates between the rays on either side
+    Linear,
+}
+
+/// Units of the reflectivity in the input files
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum RefUnits {
+    /// Detected from the units of the field and the range of its values
+    #[default]
+    Auto,
+
+    /// Already in dBZ
+    Decibels,
+
+    /// Linear Z in mm^6/m^3, converted to dBZ
+    Linear,
+}
+
+/// Method used to unfold aliased velocities
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Dealias {
+    /// Unfolds regions of smoothly varying velocity to match their neighbors
+    Region,
+
+    /// Unfolds each gate to the velocity closest to the wind in a sounding
+    Sounding,
 }
 
 /// An individual ray in a sweep
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Ray {
     /// Ray scan time
     pub time: DateTime<Utc>,
@@ -74,8 +524,24 @@ pub struct Ray {
     /// Azimuth angle for the ray
     pub azimuth: f32,
 
+    /// Measured elevation angle for the ray
+    pub elevation: f32,
+
+    /// Azimuth as recorded by the pedestal, kept when `azimuth` has been smoothed
+    #[serde(default)]
+    pub raw_azimuth: Option<f32>,
+
+    /// Position and attitude of a moving platform when the ray was taken
+    #[serde(default)]
+    pub platform: Option<Platform>,
+
+    /// Taken while the antenna was moving between sweeps
+    #[serde(default)]
+    pub transition: bool,
+
     /// Data hashmap
-    pub data: HashMap<String, Vec<f64>>,
+    #[serde(with = "gate_data")]
+    pub data: HashMap<Field, Vec<f64>>,
 }
 
 impl Default for Ray {
@@ -83,20 +549,169 @@ impl Default for Ray {
         Ray {
             time: chrono::Utc::now(),
             azimuth: 0.0,
+            elevation: 0.0,
+            raw_azimuth: None,
+            platform: None,
+            transition: false,
             data: std::collections::HashMap::new(),
         }
     }
 }
 
+/// Position and attitude of a moving platform, such as an aircraft, with angles in degrees
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Platform {
+    pub latitude: f32,
+    pub longitude: f32,
+
+    /// Meters above mean sea level
+    pub altitude: f32,
+
+    /// Velocity toward the east, north, and up in m/s
+    pub velocity: [f32; 3],
+
+    /// Direction of the nose clockwise from north
+    pub heading: f32,
+
+    /// Positive with the right wing down
+    pub roll: f32,
+
+    /// Positive with the nose up
+    pub pitch: f32,
+
+    /// Angle from the heading to the track, positive clockwise
+    pub drift: f32,
+
+    /// Angle of the antenna around the longitudinal axis, clockwise looking forward from zero
+    /// straight up
+    pub rotation: f32,
+
+    /// Angle of the beam forward of the plane normal to the longitudinal axis
+    pub tilt: f32,
+}
+
+impl Platform {
+    /// Position and attitude with correction factors added
+    pub fn corrected(&self, corrections: &Corrections) -> Platform {
+        let velocity = [0, 1, 2].map(|i| self.velocity[i] + corrections.velocity[i]);
+
+        Platform {
+            latitude: self.latitude + corrections.latitude,
+            longitude: self.longitude + corrections.longitude,
+            altitude: self.altitude + corrections.altitude,
+            velocity,
+            heading: self.heading + corrections.heading,
+            roll: self.roll + corrections.roll,
+            pitch: self.pitch + corrections.pitch,
+            drift: self.drift + corrections.drift,
+            rotation: self.rotation + corrections.rotation,
+            tilt: self.tilt + corrections.tilt,
+        }
+    }
+
+    /// Earth-relative azimuth and elevation of a beam from a rotating antenna on the platform's
+    /// longitudinal axis, such as a tail radar, following Lee et al. (1994)
+    pub fn earth_angles(&self) -> (f32, f32) {
+        let [roll, pitch, drift, rotation, tilt] =
+            [self.roll, self.pitch, self.drift, self.rotation, self.tilt]
+                .map(|angle| (angle as f64).to_radians());
+        let track = (self.heading + self.drift) as f64;
+
+        let (sin_p, cos_p) = pitch.sin_cos();
+        let (sin_d, cos_d) = drift.sin_cos();
+        let (sin_t, cos_t) = tilt.sin_cos();
+        let (sin_r, cos_r) = (rotation + roll).sin_cos();
+
+        // Beam direction across the track, along it, and up
+        let x = cos_r * sin_d * cos_t * sin_p + cos_d * sin_r * cos_t - sin_d * cos_p * sin_t;
+        let y = -cos_r * cos_d * cos_t * sin_p + sin_d * sin_r * cos_t + cos_p * cos_d * sin_t;
+        let z = cos_p * cos_t * cos_r + sin_p * sin_t;
+
+        let azimuth = (x.atan2(y).to_degrees() + track).rem_euclid(360.0);
+        let elevation = z.clamp(-1.0, 1.0).asin().to_degrees();
+
+        (azimuth as f32, elevation as f32)
+    }
+}
+
+/// Serializes gate data with missing gates as nulls, since JSON has no NaN
+mod gate_data {
+    use super::{is_missing, Field, MISSING};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S: Serializer>(
+        data: &HashMap<Field, Vec<f64>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let data: HashMap<&Field, Vec<Option<f64>>> = data
+            .iter()
+            .map(|(field, values)| {
+                (
+                    field,
+                    values
+                        .iter()
+                        .map(|&v| (!is_missing(v)).then_some(v))
+                        .collect(),
+                )
+            })
+            .collect();
+
+        data.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<Field, Vec<f64>>, D::Error> {
+        let data = HashMap::<Field, Vec<Option<f64>>>::deserialize(deserializer)?;
+
+        Ok(data
+            .into_iter()
+            .map(|(field, values)| {
+                (
+                    field,
+                    values.into_iter().map(|v| v.unwrap_or(MISSING)).collect(),
+                )
+            })
+            .collect())
+    }
+}
+
+/// Elevation wrapped to (-180, 180], for sources that store negative tilts as angles near 360
+pub(crate) fn signed_elevation(elevation: f32) -> f32 {
+    if elevation > 180.0 {
+        elevation - 360.0
+    } else {
+        elevation
+    }
+}
+
+/// Elevation as written in file names, to a tenth of a degree without a sign on zero
+pub(crate) fn elevation_label(elevation: f32) -> String {
+    let rounded = (elevation * 10.0).round() / 10.0;
+    format!("{:.1}", if rounded == 0.0 { 0.0 } else { rounded })
+}
+
+/// Signed difference between two azimuths, wrapped to [-180, 180)
+fn azimuth_diff(a: f32, b: f32) -> f32 {
+    (a - b + 180.0).rem_euclid(360.0) - 180.0
+}
+
 /// An individual sweep
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Sweep {
     /// Vector of rays in the sweep
     pub rays: Vec<Ray>,
 
-    /// Sweep elevation
+    /// Elevation of the sweep, or the azimuth of an RHI. Some formats give the fixed angle here,
+    /// and others the mean of the rays
     pub elevation: f32,
 
+    /// Angle the sweep was scheduled at, as recorded in the file's sweep descriptions or scan
+    /// strategy. It is the azimuth of an RHI
+    #[serde(default)]
+    pub fixed_angle: Option<f32>,
+
     /// Latitude of the radar
     pub latitude: f32,
 
@@ -111,9 +726,33 @@ pub struct Sweep {
 
     /// Scanning mode
     pub scan_mode: ScanMode,
+
+    /// Gate geometry of fields that differ from the radar's params in this sweep
+    #[serde(default)]
+    pub gates: HashMap<Field, GateGeometry>,
+
+    /// Height in meters above the radar of a constant altitude pseudo-sweep, which has no fixed
+    /// angle of its own
+    #[serde(default)]
+    pub height: Option<f32>,
 }
 
 impl Sweep {
+    /// Fixed angle of the sweep, or its elevation if the file doesn't record one. Files are named
+    /// and grouped into volumes by this angle
+    pub fn scheduled_angle(&self) -> f32 {
+        self.fixed_angle.unwrap_or(self.elevation)
+    }
+
+    /// Angle of the sweep as written in file names, or its height for a constant altitude
+    /// pseudo-sweep
+    pub fn label(&self) -> String {
+        match self.height {
+            Some(height) => format!("cappi_{}m", height),
+            None => elevation_label(self.scheduled_angle()),
+        }
+    }
+
     pub fn time(&self) -> DateTime<Utc> {
         self.rays[0].time
     }
@@ -122,34 +761,144 @@ impl Sweep {
         self.rays.len() as u16
     }
 
-    pub fn ngates(&self) -> u16 {
-        self.rays[0].data.values().next().unwrap().len() as u16
+    /// Most gates of a field in any ray. Fields can have different numbers of gates, such as
+    /// dual-pol moments that end before reflectivity
+    pub fn ngates(&self, field: &Field) -> usize {
+        self.rays
+            .iter()
+            .filter_map(|ray| ray.data.get(field))
+            .map(|data| data.len())
+            .max()
+            .unwrap_or(0)
     }
 
     pub fn azimuths(&self) -> Vec<f32> {
         self.rays.iter().map(|x| x.azimuth).collect::<Vec<_>>()
     }
 
+    /// Median change in azimuth between consecutive rays. Negative for counterclockwise sweeps
+    pub fn azimuth_spacing(&self) -> f32 {
+        let mut deltas: Vec<f32> = self
+            .rays
+            .windows(2)
+            .map(|pair| azimuth_diff(pair[1].azimuth, pair[0].azimuth))
+            .collect();
+
+        if deltas.is_empty() {
+            return 0.0;
+        }
+
+        deltas.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        deltas[deltas.len() / 2]
+    }
+
+    /// Moves every azimuth by a fraction of the ray spacing, in the direction of rotation
+    pub fn shift_azimuths(&mut self, fraction: f32) {
+        let shift = fraction * self.azimuth_spacing();
+
+        for ray in &mut self.rays {
+            ray.azimuth = (ray.azimuth + shift).rem_euclid(360.0);
+        }
+    }
+
+    /// Replaces the azimuths with a fit to a constant scan rate, keeping the recorded azimuths
+    /// in `raw_azimuth`. Removes wobble from noisy pedestal encoders
+    pub fn smooth_azimuths(&mut self) {
+        if self.is_vertical() || self.rays.len() < 3 {
+            return;
+        }
+
+        // Unwrap the azimuths so they increase or decrease continuously through north
+        let mut unwrapped = vec![self.rays[0].raw_azimuth.unwrap_or(self.rays[0].azimuth)];
+        for pair in self.rays.windows(2) {
+            let (a, b) = (
+                pair[0].raw_azimuth.unwrap_or(pair[0].azimuth),
+                pair[1].raw_azimuth.unwrap_or(pair[1].azimuth),
+            );
+            unwrapped.push(unwrapped.last().unwrap() + azimuth_diff(b, a));
+        }
+
+        // Fit against time, or against ray number if every ray has the same time
+        let start = self.time();
+        let mut x: Vec<f64> = self
+            .rays
+            .iter()
+            .map(|ray| (ray.time - start).num_milliseconds() as f64)
+            .collect();
+        if x.iter().all(|&t| t == x[0]) {
+            x = (0..self.rays.len()).map(|i| i as f64).collect();
+        }
+
+        let n = x.len() as f64;
+        let mean_x = x.iter().sum::<f64>() / n;
+        let mean_y = unwrapped.iter().map(|&y| y as f64).sum::<f64>() / n;
+        let slope = x
+            .iter()
+            .zip(&unwrapped)
+            .map(|(x, &y)| (x - mean_x) * (y as f64 - mean_y))
+            .sum::<f64>()
+            / x.iter().map(|x| (x - mean_x).powi(2)).sum::<f64>();
+
+        for (ray, x) in self.rays.iter_mut().zip(x) {
+            ray.raw_azimuth.get_or_insert(ray.azimuth);
+            ray.azimuth = ((mean_y + slope * (x - mean_x)) as f32).rem_euclid(360.0);
+        }
+    }
+
+    /// Checks if the sweep points vertically, where azimuths don't describe a rotation
+    pub fn is_vertical(&self) -> bool {
+        self.scan_mode == ScanMode::Vertical
+    }
+
     pub fn correct_azimuth(&mut self) {
         for ray in &mut self.rays {
             ray.azimuth = ray.azimuth.rem_euclid(360.0);
         }
     }
 
+    /// Sorts the rays along the scan. RHIs are sorted by elevation, and sectors by azimuth from
+    /// their edge, so a sector across north isn't split in two
     pub fn sort_rays_by_azimuth(&mut self) {
+        if self.is_vertical() {
+            return;
+        }
+
+        if self.scan_mode == ScanMode::RHI {
+            self.rays
+                .sort_by(|a, b| a.elevation.total_cmp(&b.elevation));
+            return;
+        }
+
         self.correct_azimuth();
 
-        self.rays.sort_by(|a, b| a.azimuth.partial_cmp(&b.azimuth).unwrap());
+        self.rays
+            .sort_by(|a, b| a.azimuth.partial_cmp(&b.azimuth).unwrap());
+
+        // The widest gap between rays is outside the sector
+        let len = self.rays.len();
+        if self.scan_mode.is_sector() && len > 1 {
+            let gap = |i: usize| {
+                (self.rays[(i + 1) % len].azimuth - self.rays[i].azimuth).rem_euclid(360.0)
+            };
+            let widest = (0..len).max_by(|&a, &b| gap(a).total_cmp(&gap(b))).unwrap();
+            self.rays.rotate_left((widest + 1) % len);
+        }
     }
 
+    /// Drops the rays past a full rotation. Only rotating scans are trimmed
     pub fn trim_rays(&mut self) {
+        // A single ray can't make a full rotation
+        if !self.scan_mode.is_rotating() || self.rays.len() < 2 {
+            return;
+        }
+
         self.correct_azimuth();
 
         let mut change: f32 = 0.0;
         let mut last_change: f32 = 0.0;
         let direction: f32 = {
-            let dir: f32 = self.azimuths()[0..5].iter().sum();
-            if dir > 0.0 || dir < -300.0 {
+            let dir: f32 = self.rays.iter().take(5).map(|ray| ray.azimuth).sum();
+            if !(-300.0..=0.0).contains(&dir) {
                 1.0
             } else {
                 -1.0
@@ -179,42 +928,512 @@ impl Sweep {
         }
     }
 
-    pub fn get_data(&self, field: &str) -> Vec<Vec<f64>> {
-        self.rays
+    /// Validity of each gate of a field, true where the gate has data. Rays without the field
+    /// are all false
+    pub fn mask(&self, field: &Field) -> Vec<Vec<bool>> {
+        self.get_data(field)
             .iter()
-            .map(|x| x.data.get(field).unwrap().clone())
+            .map(|data| data.iter().map(|&v| !is_missing(v)).collect())
             .collect()
     }
-}
 
-/// Description of a parameter
-#[derive(Debug, Clone, Default)]
-pub struct ParamDescription {
-    /// Description on the parameter
-    pub description: String,
+    /// Checks if two sweeps hold data from the same scan, starting within a second of each
+    /// other at the same fixed angle
+    pub fn same_scan(&self, other: &Sweep) -> bool {
+        !self.rays.is_empty()
+            && !other.rays.is_empty()
+            && (self.scheduled_angle() - other.scheduled_angle()).abs() < 0.1
+            && (self.time() - other.time()).num_milliseconds().abs() < 1000
+    }
 
-    /// Units
-    pub units: String,
+    /// Removes rays within a quarter of a ray of the azimuth of another, keeping the one with the
+    /// most gates with data, or else the latest. Returns the number of rays removed
+    pub fn dedup_rays(&mut self) -> usize {
+        if self.is_vertical() || self.scan_mode == ScanMode::RHI || self.rays.len() < 2 {
+            return 0;
+        }
 
-    /// Meters to the first cell in the ray
-    pub meters_to_first_cell: f32,
+        let tolerance = self.azimuth_spacing().abs().max(0.1) / 4.0;
+        let rank = |ray: &Ray| {
+            (
+                ray.data
+                    .values()
+                    .flatten()
+                    .filter(|v| !is_missing(**v))
+                    .count(),
+                ray.time,
+            )
+        };
 
-    /// Meters between each cell
-    pub meters_between_cells: f32,
-}
+        // Rays in azimuth order from the widest gap, so duplicates are next to each other
+        let mut order: Vec<usize> = (0..self.rays.len()).collect();
+        order.sort_by(|&a, &b| {
+            self.rays[a]
+                .azimuth
+                .rem_euclid(360.0)
+                .total_cmp(&self.rays[b].azimuth.rem_euclid(360.0))
+        });
+        let gap = |i: usize| {
+            (self.rays[order[(i + 1) % order.len()]].azimuth - self.rays[order[i]].azimuth)
+                .rem_euclid(360.0)
+        };
+        let widest = (0..order.len())
+            .max_by(|&a, &b| gap(a).total_cmp(&gap(b)))
+            .unwrap();
+        let start = (widest + 1) % order.len();
+        order.rotate_left(start);
 
-// An entire file, containing multiple sweeps
-#[derive(Clone)]
-pub struct RadarFile {
-    /// Name of the radar
-    pub name: String,
+        let mut keep = vec![true; self.rays.len()];
+        let mut kept = order[0];
+        for &i in &order[1..] {
+            if azimuth_diff(self.rays[i].azimuth, self.rays[kept].azimuth).abs() > tolerance {
+                kept = i;
+            } else if rank(&self.rays[i]) > rank(&self.rays[kept]) {
+                keep[kept] = false;
+                kept = i;
+            } else {
+                keep[i] = false;
+            }
+        }
 
-    /// Vector of the sweeps
-    pub sweeps: Vec<Sweep>,
+        let mut i = 0;
+        self.rays.retain(|_| {
+            i += 1;
+            keep[i - 1]
+        });
 
-    /// Hashmap of the field names and the description of the field
-    pub params: HashMap<String, ParamDescription>,
-}
+        keep.iter().filter(|keep| !**keep).count()
+    }
+
+    /// Adds the fields of another sweep of the same scan to each ray, matching rays by azimuth.
+    /// Rays of the other sweep without a match within half a ray are dropped
+    pub fn merge_rays(&mut self, other: Sweep) {
+        let tolerance = self.azimuth_spacing().abs().max(0.1) / 2.0;
+
+        for (field, geometry) in other.gates {
+            if !self.rays.iter().any(|ray| ray.data.contains_key(&field)) {
+                self.gates.insert(field, geometry);
+            }
+        }
+
+        for ray in &mut self.rays {
+            let nearest = other.rays.iter().min_by(|a, b| {
+                let da = azimuth_diff(a.azimuth, ray.azimuth).abs();
+                let db = azimuth_diff(b.azimuth, ray.azimuth).abs();
+                da.partial_cmp(&db).unwrap()
+            });
+
+            if let Some(nearest) = nearest {
+                if azimuth_diff(nearest.azimuth, ray.azimuth).abs() <= tolerance {
+                    for (field, data) in &nearest.data {
+                        ray.data
+                            .entry(field.clone())
+                            .or_insert_with(|| data.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves a field from one set of gates onto another, taking the nearest gate.
+    /// Gates outside of the original range are missing
+    pub fn resample(&mut self, field: &Field, from: GateGeometry, to: GateGeometry) {
+        for ray in &mut self.rays {
+            if let Some(data) = ray.data.get_mut(field) {
+                let last_range = from.range(data.len().saturating_sub(1));
+                let ngates = ((last_range - to.meters_to_first_cell) / to.meters_between_cells)
+                    .floor() as isize
+                    + 1;
+
+                *data = (0..ngates.max(0) as usize)
+                    .map(|gate| {
+                        let index = ((to.range(gate) - from.meters_to_first_cell)
+                            / from.meters_between_cells)
+                            .round();
+
+                        if index >= 0.0 {
+                            data.get(index as usize).copied().unwrap_or(MISSING)
+                        } else {
+                            MISSING
+                        }
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    /// Moves a field onto coarser gates, averaging the gates centered in each new gate.
+    /// New gates without any gates that have data are missing
+    pub fn average_gates(&mut self, field: &Field, from: GateGeometry, to: GateGeometry) {
+        for ray in &mut self.rays {
+            if let Some(data) = ray.data.get_mut(field) {
+                let mut sums: Vec<(f64, usize)> = Vec::new();
+
+                for (gate, &value) in data.iter().enumerate() {
+                    let index = ((from.range(gate) - to.meters_to_first_cell)
+                        / to.meters_between_cells)
+                        .round();
+
+                    if index < 0.0 {
+                        continue;
+                    }
+
+                    let index = index as usize;
+                    if sums.len() <= index {
+                        sums.resize(index + 1, (0.0, 0));
+                    }
+
+                    if !is_missing(value) {
+                        sums[index].0 += value;
+                        sums[index].1 += 1;
+                    }
+                }
+
+                *data = sums
+                    .into_iter()
+                    .map(|(sum, count)| {
+                        if count > 0 {
+                            sum / count as f64
+                        } else {
+                            MISSING
+                        }
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    /// Resamples the rays onto a regular grid of azimuths centered between multiples of
+    /// `spacing`. Sector scans only cover the sector, and grid azimuths more than `spacing`
+    /// from any ray are filled with missing data
+    pub fn interpolate_to_regular_azimuths(&mut self, spacing: f32, method: AzimuthInterpolation) {
+        if self.is_vertical() || self.rays.len() < 2 {
+            return;
+        }
+
+        // Unwrap the azimuths to find the extent of the scan
+        let mut unwrapped = vec![self.rays[0].azimuth];
+        for pair in self.rays.windows(2) {
+            unwrapped
+                .push(unwrapped.last().unwrap() + azimuth_diff(pair[1].azimuth, pair[0].azimuth));
+        }
+
+        let low = unwrapped.iter().copied().fold(f32::INFINITY, f32::min);
+        let high = unwrapped.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+        let grid: Vec<f32> = if high - low >= 360.0 - spacing {
+            (0..(360.0 / spacing).round() as usize)
+                .map(|i| (i as f32 + 0.5) * spacing)
+                .collect()
+        } else {
+            let first = ((low - spacing / 2.0) / spacing).ceil() as i32;
+            let last = ((high - spacing / 2.0) / spacing).floor() as i32;
+            let mut grid: Vec<f32> = (first..=last)
+                .map(|i| ((i as f32 + 0.5) * spacing).rem_euclid(360.0))
+                .collect();
+
+            // Keep the direction of rotation
+            if unwrapped.last().unwrap() < &unwrapped[0] {
+                grid.reverse();
+            }
+
+            grid
+        };
+
+        let rays = std::mem::take(&mut self.rays);
+        let closest = |azimuth: f32, forward: bool| {
+            rays.iter()
+                .map(|ray| {
+                    let diff = azimuth_diff(ray.azimuth, azimuth);
+                    (if forward { diff } else { -diff }, ray)
+                })
+                .filter(|(diff, _)| *diff >= 0.0)
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+        };
+
+        self.rays = grid
+            .into_iter()
+            .map(|azimuth| {
+                let before = closest(azimuth, false);
+                let after = closest(azimuth, true);
+                let (distance, nearest) = [before, after]
+                    .into_iter()
+                    .flatten()
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .unwrap();
+
+                let data = match (method, before, after) {
+                    (AzimuthInterpolation::Linear, Some((db, b)), Some((da, a)))
+                        if db + da <= 2.0 * spacing && db + da > 0.0 =>
+                    {
+                        let weight = (db / (db + da)) as f64;
+
+                        nearest
+                            .data
+                            .keys()
+                            .map(|field| {
+                                let from = b.data.get(field).or(a.data.get(field)).unwrap();
+                                let to = a.data.get(field).unwrap_or(from);
+                                let values = (0..from.len().max(to.len()))
+                                    .map(|gate| {
+                                        let (x, y) = (
+                                            from.get(gate).copied().unwrap_or(MISSING),
+                                            to.get(gate).copied().unwrap_or(MISSING),
+                                        );
+
+                                        match (is_missing(x), is_missing(y)) {
+                                            (false, false) => x + (y - x) * weight,
+                                            _ if weight < 0.5 => x,
+                                            _ => y,
+                                        }
+                                    })
+                                    .collect();
+
+                                (field.clone(), values)
+                            })
+                            .collect()
+                    }
+                    _ if distance <= spacing => nearest.data.clone(),
+                    _ => nearest
+                        .data
+                        .iter()
+                        .map(|(field, data)| (field.clone(), vec![MISSING; data.len()]))
+                        .collect(),
+                };
+
+                Ray {
+                    time: nearest.time,
+                    azimuth,
+                    elevation: nearest.elevation,
+                    data,
+                    ..Default::default()
+                }
+            })
+            .collect();
+    }
+
+    /// Gates of a field in each ray. Rays without the field are MISSING, with as many gates as
+    /// the longest ray
+    pub fn get_data(&self, field: &Field) -> Vec<Vec<f64>> {
+        let ngates = self.ngates(field);
+
+        self.rays
+            .iter()
+            .map(|x| match x.data.get(field) {
+                Some(data) => data.clone(),
+                None => vec![MISSING; ngates],
+            })
+            .collect()
+    }
+}
+
+/// Antenna and transmitter characteristics. Values the input format doesn't record are `None`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct InstrumentInfo {
+    /// Altitude of the antenna above mean sea level in meters
+    pub altitude: Option<f32>,
+
+    /// Horizontal beam width in degrees
+    pub horizontal_beam_width: Option<f32>,
+
+    /// Vertical beam width in degrees
+    pub vertical_beam_width: Option<f32>,
+
+    /// Transmitted wavelength in meters
+    pub wavelength: Option<f32>,
+
+    /// Pulse repetition frequency in Hz
+    pub prf: Option<f32>,
+
+    /// Pulse width in microseconds
+    pub pulse_width: Option<f32>,
+
+    /// Radar constant in dB
+    pub radar_constant: Option<f32>,
+}
+
+/// Correction factors recorded with the data, which are added to the angles, ranges, and
+/// positions when applied
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Corrections {
+    /// Degrees added to the azimuth and elevation of rays from a fixed radar
+    pub azimuth: f32,
+    pub elevation: f32,
+
+    /// Meters added to the range of every gate
+    pub range_delay: f32,
+
+    /// Degrees added to the position of the radar
+    pub latitude: f32,
+    pub longitude: f32,
+
+    /// Meters added to the pressure altitude and the altitude of the radar
+    pub pressure_altitude: f32,
+    pub altitude: f32,
+
+    /// Meters per second added to the velocity of a moving platform toward the east, north, and up
+    pub velocity: [f32; 3],
+
+    /// Degrees added to the attitude of a moving platform
+    pub heading: f32,
+    pub roll: f32,
+    pub pitch: f32,
+    pub drift: f32,
+    pub rotation: f32,
+    pub tilt: f32,
+}
+
+impl Corrections {
+    /// Names and values of the corrections that aren't zero
+    pub fn nonzero(&self) -> Vec<(&'static str, f32)> {
+        let [east, north, up] = self.velocity;
+
+        [
+            ("azimuth", self.azimuth),
+            ("elevation", self.elevation),
+            ("range delay", self.range_delay),
+            ("latitude", self.latitude),
+            ("longitude", self.longitude),
+            ("pressure altitude", self.pressure_altitude),
+            ("altitude", self.altitude),
+            ("east velocity", east),
+            ("north velocity", north),
+            ("vertical velocity", up),
+            ("heading", self.heading),
+            ("roll", self.roll),
+            ("pitch", self.pitch),
+            ("drift", self.drift),
+            ("rotation", self.rotation),
+            ("tilt", self.tilt),
+        ]
+        .into_iter()
+        .filter(|(_, value)| *value != 0.0)
+        .collect()
+    }
+}
+
+/// Ranges of the gates of a field
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct GateGeometry {
+    /// Meters to the first cell in the ray
+    pub meters_to_first_cell: f32,
+
+    /// Meters between each cell
+    pub meters_between_cells: f32,
+}
+
+impl GateGeometry {
+    /// Range to the center of a gate in meters
+    pub fn range(&self, gate: usize) -> f32 {
+        self.meters_to_first_cell + gate as f32 * self.meters_between_cells
+    }
+}
+
+/// Description of a parameter
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParamDescription {
+    /// Description on the parameter
+    pub description: String,
+
+    /// Units
+    pub units: String,
+
+    /// Meters to the first cell in the ray
+    pub meters_to_first_cell: f32,
+
+    /// Meters between each cell
+    pub meters_between_cells: f32,
+}
+
+impl ParamDescription {
+    /// Gate geometry of the field
+    pub fn geometry(&self) -> GateGeometry {
+        GateGeometry {
+            meters_to_first_cell: self.meters_to_first_cell,
+            meters_between_cells: self.meters_between_cells,
+        }
+    }
+}
+
+/// A problem found by `RadarFile::validate`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Issue {
+    /// The file has no sweeps
+    NoSweeps,
+
+    /// A sweep has no rays
+    EmptySweep { sweep: usize },
+
+    /// A ray has a different number of gates for a field than the first ray of the sweep
+    MismatchedGates {
+        sweep: usize,
+        ray: usize,
+        field: Field,
+    },
+
+    /// A ray was collected before the ray preceding it
+    NonMonotonicTime { sweep: usize, ray: usize },
+
+    /// A ray has no valid azimuth
+    NanAzimuth { sweep: usize, ray: usize },
+
+    /// A field has data but no description in the params
+    MissingParam { field: Field },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::NoSweeps => write!(f, "no sweeps"),
+            Issue::EmptySweep { sweep } => write!(f, "sweep {} has no rays", sweep),
+            Issue::MismatchedGates { sweep, ray, field } => {
+                write!(
+                    f,
+                    "sweep {} ray {} has a different number of {} gates than the rest of the sweep",
+                    sweep, ray, field
+                )
+            }
+            Issue::NonMonotonicTime { sweep, ray } => write!(
+                f,
+                "sweep {} ray {} is earlier than the ray before it",
+                sweep, ray
+            ),
+            Issue::NanAzimuth { sweep, ray } => {
+                write!(f, "sweep {} ray {} has no azimuth", sweep, ray)
+            }
+            Issue::MissingParam { field } => write!(f, "{} has data but no description", field),
+        }
+    }
+}
+
+// An entire file, containing multiple sweeps
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RadarFile {
+    /// Name of the radar
+    pub name: String,
+
+    /// Vector of the sweeps
+    pub sweeps: Vec<Sweep>,
+
+    /// Hashmap of the field names and the description of the field
+    pub params: HashMap<Field, ParamDescription>,
+
+    /// Antenna and transmitter characteristics
+    #[serde(default)]
+    pub instrument: InstrumentInfo,
+
+    /// Scan strategy recorded in the file
+    #[serde(default)]
+    pub scan_strategy: Option<ScanStrategy>,
+
+    /// Number of the volume in the radar's sequence, if the file records it
+    #[serde(default)]
+    pub volume_number: Option<u32>,
+
+    /// Correction factors recorded in the file, which aren't applied unless asked for
+    #[serde(default)]
+    pub corrections: Option<Corrections>,
+}
 
 impl RadarFile {
     /// Returns the number of sweeps in the the file
@@ -223,440 +1442,3843 @@ impl RadarFile {
     }
 
     /// Sorts all the rays in each sweep by azimuth
+    #[instrument(skip_all)]
     pub fn sort_rays_by_azimuth(&mut self) {
         for sweep in &mut self.sweeps {
             sweep.sort_rays_by_azimuth();
         }
     }
 
-    /// Sorts all sweeps by time
-    pub fn sort_sweeps_by_time(&mut self) {
-        self.sweeps.sort_by_key(|sweep| sweep.time());
+    /// Sorts all sweeps by time, with sweeps without rays last
+    pub fn sort_sweeps_by_time(&mut self) {
+        self.sweeps.sort_by_key(|sweep| match sweep.rays.first() {
+            Some(ray) => (false, Some(ray.time)),
+            None => (true, None),
+        });
+    }
+
+    /// Sorts all sweeps by their fixed angle
+    pub fn sort_sweeps_by_elevation(&mut self) {
+        self.sweeps
+            .sort_by(|s1, s2| s1.scheduled_angle().total_cmp(&s2.scheduled_angle()));
+    }
+
+    /// Drops the gates of every field beyond a range in meters
+    #[instrument(skip_all)]
+    pub fn crop_range(&mut self, max_range: f32) {
+        for i in 0..self.sweeps.len() {
+            let fields: BTreeSet<Field> = self.sweeps[i]
+                .rays
+                .iter()
+                .flat_map(|ray| ray.data.keys())
+                .cloned()
+                .collect();
+
+            for field in fields {
+                let geometry = self.geometry(&self.sweeps[i], &field);
+                let ngates = ((max_range - geometry.meters_to_first_cell)
+                    / geometry.meters_between_cells)
+                    .floor()
+                    + 1.0;
+
+                for ray in &mut self.sweeps[i].rays {
+                    if let Some(data) = ray.data.get_mut(&field) {
+                        data.truncate(ngates.max(0.0) as usize);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Deletes excess rays in each sweep
+    #[instrument(skip_all)]
+    pub fn trim_rays(&mut self) {
+        for sweep in &mut self.sweeps {
+            sweep.trim_rays();
+        }
+    }
+
+    /// Negates the velocities, switching between positive toward and positive away from the radar
+    pub fn flip_velocity(&mut self) {
+        for ray in self.sweeps.iter_mut().flat_map(|sweep| &mut sweep.rays) {
+            if let Some(data) = ray.data.get_mut(&Field::VEL) {
+                data.iter_mut().for_each(|v| *v = -*v);
+            }
+        }
+    }
+
+    /// Scan strategy recorded in the file, or one synthesized from the sweeps
+    pub fn strategy(&self) -> ScanStrategy {
+        self.scan_strategy
+            .clone()
+            .unwrap_or_else(|| ScanStrategy::from_sweeps(0, &self.sweeps))
+    }
+
+    /// Checks if the reflectivity is in linear units, either by its units or by values far
+    /// above anything a dBZ field holds
+    pub fn linear_reflectivity(&self) -> bool {
+        let Some(param) = self.params.get(&Field::REF) else {
+            return false;
+        };
+
+        let units = param.units.to_lowercase().replace(['^', ' '], "");
+        if units == "z" || units.starts_with("mm6") {
+            return true;
+        }
+
+        let values = || {
+            self.sweeps
+                .iter()
+                .flat_map(|sweep| &sweep.rays)
+                .filter_map(|ray| ray.data.get(&Field::REF))
+                .flatten()
+                .filter(|v| !is_missing(**v))
+        };
+
+        values().all(|&v| v >= 0.0) && values().any(|&v| v > 100.0)
+    }
+
+    /// Converts linear reflectivity to dBZ. Gates without any power are missing
+    pub fn reflectivity_to_dbz(&mut self) {
+        for ray in self.sweeps.iter_mut().flat_map(|sweep| &mut sweep.rays) {
+            if let Some(data) = ray.data.get_mut(&Field::REF) {
+                data.iter_mut()
+                    .for_each(|v| *v = if *v > 0.0 { 10.0 * v.log10() } else { MISSING });
+            }
+        }
+
+        if let Some(param) = self.params.get_mut(&Field::REF) {
+            param.units = "dBZ".to_string();
+        }
+    }
+
+    /// Differences between the radar and a copy of it that was written and read back, such as
+    /// missing sweeps or fields, moved rays, and values that changed by more than the tolerance
+    /// of their field. Sweeps are matched in order of time
+    pub fn differences(
+        &self,
+        written: &RadarFile,
+        tolerance: impl Fn(&Field) -> f64,
+    ) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        if written.sweeps.len() != self.sweeps.len() {
+            differences.push(format!(
+                "{} sweeps were written instead of {}",
+                written.sweeps.len(),
+                self.sweeps.len()
+            ));
+        }
+
+        let mut fields: Vec<&Field> = self.params.keys().collect();
+        fields.sort();
+
+        for field in &fields {
+            if !written.params.contains_key(field) {
+                differences.push(format!("{} wasn't written", field));
+            }
+        }
+
+        fn by_time(radar: &RadarFile) -> Vec<&Sweep> {
+            let mut sweeps: Vec<&Sweep> = radar
+                .sweeps
+                .iter()
+                .filter(|sweep| !sweep.rays.is_empty())
+                .collect();
+            sweeps.sort_by_key(|sweep| sweep.time());
+            sweeps
+        }
+
+        for (i, (source, copy)) in by_time(self).into_iter().zip(by_time(written)).enumerate() {
+            if copy.rays.len() != source.rays.len() {
+                differences.push(format!(
+                    "sweep {} has {} rays instead of {}",
+                    i,
+                    copy.rays.len(),
+                    source.rays.len()
+                ));
+            }
+
+            let moved = source
+                .rays
+                .iter()
+                .zip(&copy.rays)
+                .filter(|(a, b)| azimuth_diff(a.azimuth, b.azimuth).abs() > 0.01)
+                .count();
+            if moved > 0 {
+                differences.push(format!(
+                    "sweep {} has {} rays at different azimuths",
+                    i, moved
+                ));
+            }
+
+            for field in fields
+                .iter()
+                .filter(|field| written.params.contains_key(field))
+            {
+                let tolerance = tolerance(field);
+                let (mut changed, mut largest) = (0, 0.0f64);
+
+                for (a, b) in source.rays.iter().zip(&copy.rays) {
+                    let (Some(a), Some(b)) = (a.data.get(field), b.data.get(field)) else {
+                        continue;
+                    };
+
+                    for (&expected, &value) in a.iter().zip(b) {
+                        let error = match (is_missing(expected), is_missing(value)) {
+                            (true, true) => 0.0,
+                            (false, false) => (value - expected).abs(),
+                            _ => f64::INFINITY,
+                        };
+
+                        // Values are allowed to lose the precision of a 32 bit float
+                        if error > tolerance + 1e-6 * expected.abs().max(1.0) {
+                            changed += 1;
+                            largest = largest.max(error);
+                        }
+                    }
+                }
+
+                if changed > 0 {
+                    differences.push(format!(
+                        "sweep {} has {} {} gates that changed, by up to {}",
+                        i, changed, field, largest
+                    ));
+                }
+            }
+        }
+
+        differences
+    }
+
+    /// Checks the radar for problems that would make it fail or write garbage
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let mut unknown = BTreeSet::new();
+
+        if self.sweeps.is_empty() {
+            issues.push(Issue::NoSweeps);
+        }
+
+        for (s, sweep) in self.sweeps.iter().enumerate() {
+            if sweep.rays.is_empty() {
+                issues.push(Issue::EmptySweep { sweep: s });
+            }
+
+            let mut ngates = HashMap::new();
+
+            for (r, ray) in sweep.rays.iter().enumerate() {
+                if ray.azimuth.is_nan() {
+                    issues.push(Issue::NanAzimuth { sweep: s, ray: r });
+                }
+
+                if r > 0 && ray.time < sweep.rays[r - 1].time {
+                    issues.push(Issue::NonMonotonicTime { sweep: s, ray: r });
+                }
+
+                let mut fields: Vec<_> = ray.data.iter().collect();
+                fields.sort_by(|a, b| a.0.cmp(b.0));
+
+                for (field, data) in fields {
+                    if *ngates.entry(field).or_insert(data.len()) != data.len() {
+                        issues.push(Issue::MismatchedGates {
+                            sweep: s,
+                            ray: r,
+                            field: field.clone(),
+                        });
+                    }
+
+                    if !self.params.contains_key(field) {
+                        unknown.insert(field.clone());
+                    }
+                }
+            }
+        }
+
+        issues.extend(
+            unknown
+                .into_iter()
+                .map(|field| Issue::MissingParam { field }),
+        );
+        issues
+    }
+
+    /// Adds a calibration correction to every value of each field, such as a negative ZDR bias
+    pub fn add_bias(&mut self, biases: &HashMap<Field, f64>) {
+        let biases: Vec<(&Field, f64)> = biases
+            .iter()
+            .map(|(field, &bias)| (field, bias))
+            .filter(|&(_, bias)| bias != 0.0)
+            .collect();
+
+        for ray in self.sweeps.iter_mut().flat_map(|sweep| &mut sweep.rays) {
+            for &(field, bias) in &biases {
+                if let Some(data) = ray.data.get_mut(field) {
+                    data.iter_mut()
+                        .filter(|value| !is_missing(**value))
+                        .for_each(|value| *value += bias);
+                }
+            }
+        }
+    }
+
+    /// Adds the recorded correction factors to the angles of rays from a fixed radar, the range of
+    /// every gate, and the position of the radar or platform
+    pub fn apply_corrections(&mut self) {
+        let Some(corrections) = self.corrections else {
+            return;
+        };
+
+        for param in self.params.values_mut() {
+            param.meters_to_first_cell += corrections.range_delay;
+        }
+
+        if let Some(altitude) = &mut self.instrument.altitude {
+            *altitude += corrections.altitude;
+        }
+
+        for sweep in &mut self.sweeps {
+            sweep.latitude += corrections.latitude;
+            sweep.longitude += corrections.longitude;
+
+            for gates in sweep.gates.values_mut() {
+                gates.meters_to_first_cell += corrections.range_delay;
+            }
+
+            for ray in &mut sweep.rays {
+                match &mut ray.platform {
+                    Some(platform) => *platform = platform.corrected(&corrections),
+                    None => {
+                        ray.azimuth = (ray.azimuth + corrections.azimuth).rem_euclid(360.0);
+                        ray.elevation += corrections.elevation;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes rays that repeat the azimuth of another ray in their sweep, keeping the better one
+    #[instrument(skip_all)]
+    pub fn dedup_rays(&mut self) {
+        let removed: usize = self.sweeps.iter_mut().map(|sweep| sweep.dedup_rays()).sum();
+
+        if removed > 0 {
+            eprintln!("Dropped {} rays with duplicate azimuths", removed);
+        }
+    }
+
+    /// Removes rays taken while the antenna was moving between sweeps, and sweeps left without
+    /// rays
+    pub fn drop_transition_rays(&mut self) {
+        let nrays = |radar: &RadarFile| {
+            radar
+                .sweeps
+                .iter()
+                .map(|sweep| sweep.rays.len())
+                .sum::<usize>()
+        };
+        let before = nrays(self);
+
+        self.sweeps
+            .iter_mut()
+            .for_each(|sweep| sweep.rays.retain(|ray| !ray.transition));
+        self.sweeps.retain(|sweep| !sweep.rays.is_empty());
+
+        if nrays(self) < before {
+            eprintln!("Dropped {} antenna transition rays", before - nrays(self));
+        }
+    }
+
+    /// Removes sweeps collected while the radar was idle or calibrating
+    pub fn drop_calibration_sweeps(&mut self) {
+        let nsweeps = self.sweeps.len();
+        self.sweeps
+            .retain(|sweep| !matches!(sweep.scan_mode, ScanMode::Idle | ScanMode::Calibration));
+
+        if self.sweeps.len() < nsweeps {
+            eprintln!(
+                "Dropped {} idle or calibration sweeps",
+                nsweeps - self.sweeps.len()
+            );
+        }
+    }
+
+    /// Combines the split cuts of NEXRAD low tilts, where a surveillance sweep with reflectivity
+    /// is followed by a Doppler sweep with velocity at the same elevation. Each surveillance ray
+    /// gets the Doppler fields of the nearest Doppler ray, and the Doppler sweep is removed
+    #[instrument(skip_all)]
+    pub fn merge_split_cuts(&mut self) {
+        let has = |sweep: &Sweep, field: &Field| {
+            sweep.rays.iter().any(|ray| ray.data.contains_key(field))
+        };
+        let mut merged = 0;
+        let mut i = 0;
+
+        while i + 1 < self.sweeps.len() {
+            let (surveillance, doppler) = (&self.sweeps[i], &self.sweeps[i + 1]);
+
+            let split = (surveillance.scheduled_angle() - doppler.scheduled_angle()).abs() < 0.2
+                && has(surveillance, &Field::REF)
+                && !has(surveillance, &Field::VEL)
+                && has(doppler, &Field::VEL);
+
+            if !split {
+                i += 1;
+                continue;
+            }
+
+            let doppler = self.sweeps.remove(i + 1);
+            let surveillance = &mut self.sweeps[i];
+            let width = doppler.azimuth_spacing().abs().max(0.5);
+
+            for ray in &mut surveillance.rays {
+                let nearest = doppler
+                    .rays
+                    .iter()
+                    .map(|other| (azimuth_diff(other.azimuth, ray.azimuth).abs(), other))
+                    .filter(|&(diff, _)| diff <= width)
+                    .min_by(|a, b| a.0.total_cmp(&b.0));
+
+                if let Some((_, other)) = nearest {
+                    for (field, data) in &other.data {
+                        ray.data
+                            .entry(field.clone())
+                            .or_insert_with(|| data.clone());
+                    }
+                }
+            }
+
+            for (field, geometry) in doppler.gates {
+                surveillance.gates.entry(field).or_insert(geometry);
+            }
+
+            if doppler.nyquist_velocity > 0.0 {
+                surveillance.nyquist_velocity = doppler.nyquist_velocity;
+            }
+
+            merged += 1;
+            i += 1;
+        }
+
+        if merged > 0 {
+            eprintln!("Merged {} split cuts", merged);
+        }
+    }
+
+    /// Splits overlapping rays into new sweeps, dropping pieces with fewer than `min_rays` rays,
+    /// or the scan mode's minimum if it isn't given. Only rotating scans are split
+    #[instrument(skip_all)]
+    pub fn split_overlap_rays(&mut self, min_rays: Option<usize>) {
+        let mut new_sweeps: Vec<Sweep> = Vec::new();
+
+        for sweep in &mut self.sweeps {
+            if !sweep.scan_mode.is_rotating() {
+                new_sweeps.push(sweep.clone());
+                continue;
+            }
+
+            // A single ray can't overlap, so the sweep is only checked against the minimum
+            if sweep.rays.len() < 2 {
+                if sweep.rays.len() >= min_rays.unwrap_or(sweep.scan_mode.min_rays()) {
+                    new_sweeps.push(sweep.clone());
+                }
+                continue;
+            }
+
+            sweep.correct_azimuth();
+
+            let mut change: f32 = 0.0;
+            let mut last_change: f32 = 0.0;
+            let direction: f32 = {
+                let dir: f32 = sweep.rays.iter().take(5).map(|ray| ray.azimuth).sum();
+                if !(-300.0..=0.0).contains(&dir) {
+                    1.0
+                } else {
+                    -1.0
+                }
+            };
+
+            let mut ray_idx: usize = 0;
+
+            for i in 0..sweep.nrays() as usize {
+                if i == 0 {
+                    change = 0.0;
+                } else {
+                    let mut small_change = sweep.rays[i].azimuth - last_change;
+
+                    if small_change.abs() > 300.0 {
+                        small_change = 360.0 - small_change.abs()
+                    }
+
+                    change += direction * small_change;
+                }
+
+                last_change = sweep.rays[i].azimuth;
+
+                if change >= 360.0 {
+                    let mut new_sweep = sweep.clone();
+                    new_sweep.rays = new_sweep.rays[ray_idx..i].to_vec();
+
+                    ray_idx = i;
+                    change -= 360.0;
+
+                    new_sweeps.push(new_sweep)
+                }
+            }
+
+            // If last sweep
+            let remaining = sweep.rays.len() - ray_idx;
+            if remaining < min_rays.unwrap_or(sweep.scan_mode.min_rays()) {
+                if remaining > 0 {
+                    eprintln!(
+                        "Dropped {} rays left over from the {:.1} degree sweep",
+                        remaining, sweep.elevation
+                    );
+                }
+            } else {
+                let mut new_sweep = sweep.clone();
+                new_sweep.rays = new_sweep.rays[ray_idx..].to_vec();
+
+                new_sweeps.push(new_sweep)
+            }
+        }
+
+        self.sweeps = new_sweeps;
+    }
+
+    /// Gate geometry of a field in a sweep
+    pub fn geometry(&self, sweep: &Sweep, field: &Field) -> GateGeometry {
+        match sweep.gates.get(field) {
+            Some(&geometry) => geometry,
+            None => self
+                .params
+                .get(field)
+                .map(|param| param.geometry())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Resamples every field in every sweep onto a common range axis, for formats with a single
+    /// set of gates. The axis starts at the nearest first gate and uses the finest spacing
+    #[instrument(skip_all)]
+    pub fn resample_gates(&mut self) -> GateGeometry {
+        let fields: Vec<Field> = self.params.keys().cloned().collect();
+        let geometries: Vec<GateGeometry> = self
+            .sweeps
+            .iter()
+            .flat_map(|sweep| fields.iter().map(move |field| (sweep, field)))
+            .map(|(sweep, field)| self.geometry(sweep, field))
+            .collect();
+
+        if geometries.is_empty() {
+            return GateGeometry::default();
+        }
+
+        let common = GateGeometry {
+            meters_to_first_cell: geometries
+                .iter()
+                .map(|g| g.meters_to_first_cell)
+                .fold(f32::INFINITY, f32::min),
+            meters_between_cells: geometries
+                .iter()
+                .map(|g| g.meters_between_cells)
+                .fold(f32::INFINITY, f32::min),
+        };
+
+        for i in 0..self.sweeps.len() {
+            for field in &fields {
+                let from = self.geometry(&self.sweeps[i], field);
+
+                if from != common {
+                    self.sweeps[i].resample(field, from, common);
+                }
+            }
+
+            self.sweeps[i].gates.clear();
+        }
+
+        for param in self.params.values_mut() {
+            param.meters_to_first_cell = common.meters_to_first_cell;
+            param.meters_between_cells = common.meters_between_cells;
+        }
+
+        common
+    }
+
+    /// Changes the gate spacing of every field to `spacing` meters, keeping where the first gate
+    /// starts. Coarser gates average the gates in them, and finer gates take the nearest gate
+    #[instrument(skip_all)]
+    pub fn set_gate_spacing(&mut self, spacing: f32) {
+        let respaced = |from: GateGeometry| GateGeometry {
+            meters_to_first_cell: from.meters_to_first_cell
+                + (spacing - from.meters_between_cells) / 2.0,
+            meters_between_cells: spacing,
+        };
+
+        let fields: Vec<Field> = self.params.keys().cloned().collect();
+
+        for i in 0..self.sweeps.len() {
+            for field in &fields {
+                let from = self.geometry(&self.sweeps[i], field);
+                let to = respaced(from);
+                let sweep = &mut self.sweeps[i];
+
+                if spacing > from.meters_between_cells {
+                    sweep.average_gates(field, from, to);
+                } else if spacing < from.meters_between_cells {
+                    sweep.resample(field, from, to);
+                }
+
+                if let Some(geometry) = sweep.gates.get_mut(field) {
+                    *geometry = to;
+                }
+            }
+        }
+
+        for param in self.params.values_mut() {
+            let to = respaced(param.geometry());
+            param.meters_to_first_cell = to.meters_to_first_cell;
+            param.meters_between_cells = to.meters_between_cells;
+        }
+    }
+
+    /// Merges another radar into this one, such as a file holding the other fields of the same
+    /// scan or the next sweeps of the volume. Sweeps taken at the same time and elevation are
+    /// combined ray by ray, adding the fields this radar doesn't already have, and the
+    /// remaining sweeps are added as new sweeps
+    pub fn merge(&mut self, other: RadarFile) {
+        for mut sweep in other.sweeps {
+            // Fields on different gates than this radar's keep their geometry in the sweep
+            for (field, param) in &other.params {
+                let geometry = sweep
+                    .gates
+                    .get(field)
+                    .copied()
+                    .unwrap_or_else(|| param.geometry());
+
+                match self.params.get(field) {
+                    Some(own) if own.geometry() != geometry => {
+                        sweep.gates.insert(field.clone(), geometry)
+                    }
+                    Some(_) => sweep.gates.remove(field),
+                    None => None,
+                };
+            }
+
+            match self.sweeps.iter_mut().find(|s| s.same_scan(&sweep)) {
+                Some(existing) => existing.merge_rays(sweep),
+                None => self.sweeps.push(sweep),
+            }
+        }
+
+        for (field, param) in other.params {
+            self.params.entry(field).or_insert(param);
+        }
+
+        self.sort_sweeps_by_time();
+    }
+
+    /// Time of first sweep
+    pub fn start_time(&self) -> DateTime<Utc> {
+        self.sweeps[0].time()
+    }
+}
+
+/// Options for conversion
+#[derive(Clone)]
+pub struct RadyOptions {
+    /// Overrides the output radar
+    pub override_radar: Option<String>,
+
+    /// Deletes overlapping rays
+    pub trim_rays: bool,
+
+    /// Splits overlapping rays into new sweeps
+    pub split_overlap_rays: bool,
+
+    /// Removes rays that repeat the azimuth of another ray in their sweep
+    pub dedup_rays: bool,
+
+    /// Sorts rays by azimuth
+    pub sort_rays_by_azimuth: bool,
+
+    /// Converts to the specified format
+    pub format: Format,
+
+    /// Aggregates and writes all volumes
+    pub write_volumes: bool,
+
+    /// Degrees that a sweep must tilt past the last one to continue its volume
+    pub volume_step: f32,
+
+    /// Most seconds between the starts of consecutive sweeps of a volume. Sweeps further apart
+    /// start a new volume
+    pub volume_gap: Option<f64>,
+
+    /// Writes each sweep to its own file
+    pub write_separate: bool,
+
+    /// Writes an index grouping the files of separately written sweeps into volumes
+    pub index: bool,
+
+    /// Processes and writes one sweep at a time when sweeps aren't grouped into volumes
+    pub low_memory: bool,
+
+    /// Prints all of the file products and exit
+    pub print_products: bool,
+
+    /// Adds a file path to read. To select all files in a directory, use the * wildcard at the end.
+    /// `-` reads a file from stdin, and an http or https URL downloads a file
+    pub files: String,
+
+    /// Path of a file listing files to read, one per line, which are read along with `files`. `-`
+    /// reads the list from stdin
+    pub file_list: Option<String>,
+
+    /// More file paths to read along with `files`
+    pub extra_files: Vec<String>,
+
+    /// Reads every file below the directories that `files` matches
+    pub recursive: bool,
+
+    /// Only reads files whose names or paths match one of these patterns, if there are any
+    pub include: Vec<glob::Pattern>,
+
+    /// Skips files whose names or paths match any of these patterns
+    pub exclude: Vec<glob::Pattern>,
+
+    /// Converts files as they appear in this directory instead of reading `files`
+    pub watch: Option<String>,
+
+    /// Runs as a service with the configuration in this TOML file, instead of converting
+    pub serve: Option<String>,
+
+    /// Prints the status of the running service instead of starting it
+    pub serve_status: bool,
+
+    /// Reads the files as chunks of the real-time Level II feed, writing each sweep to its own
+    /// file once it completes
+    pub realtime: bool,
+
+    /// Prints the location in lat, long for each sweep
+    pub location: bool,
+
+    /// Computes earth-relative azimuths and elevations of airborne rays from the platform
+    /// attitude and the correction factors in the file
+    pub georeference: bool,
+
+    /// Adds the correction factors recorded in the file to the angles, ranges, and positions
+    pub apply_corrections: bool,
+
+    /// Sets the directory to make the output folder in. Default is the same as the input. `-`
+    /// writes a single file to stdout
+    pub outdir: Option<String>,
+
+    /// Creates files with a given name. Available codes are from the "chrono" library
+    pub name_format: Option<String>,
+
+    /// Only decodes these fields when reading. Decodes every field if None
+    pub fields: Option<Vec<Field>>,
+
+    /// Fields that aren't decoded when reading
+    pub exclude_fields: Vec<Field>,
+
+    /// Only keeps the sweeps at these positions in each file, counting from 0
+    pub sweeps: Option<Vec<usize>>,
+
+    /// Only keeps sweeps at these fixed angles, within `elevation_tolerance` degrees
+    pub elevations: Option<Vec<f32>>,
+
+    /// Degrees a sweep can be from a selected elevation
+    pub elevation_tolerance: f32,
+
+    /// Skips files that end before this time
+    pub start: Option<DateTime<Utc>>,
+
+    /// Skips files that start after this time
+    pub end: Option<DateTime<Utc>>,
+
+    /// NEXRAD site, such as KTLX, whose archive files that start between `start` and `end` are
+    /// downloaded from the NOAA bucket on S3 and read
+    pub site: Option<String>,
+
+    /// Where the azimuths of the input files are measured
+    pub azimuth_in: AzimuthRef,
+
+    /// Where the azimuths of the output files are measured
+    pub azimuth_out: AzimuthRef,
+
+    /// What happens when an output file already exists
+    pub on_conflict: OnConflict,
+
+    /// Convention for the names of fields in CfRadial files
+    pub naming: Naming,
+
+    /// Reads back each file after writing it, and reports values that changed by more than the
+    /// precision of the format
+    pub verify: bool,
+
+    /// Also writes vertically pointing sweeps as a time-height series netCDF
+    pub time_height: bool,
+
+    /// Keeps idle and calibration sweeps, which are dropped by default
+    pub keep_calibration: bool,
+
+    /// Drops rays taken while the antenna was moving between sweeps
+    pub drop_transitions: bool,
+
+    /// Combines the surveillance and Doppler sweeps of NEXRAD split cuts
+    pub merge_split_cuts: bool,
+
+    /// Appends every volume into a single CfRadial file along time
+    pub aggregate: bool,
+
+    /// Checks each codec on built-in samples instead of converting
+    pub selftest: bool,
+
+    /// Prints a summary of each file instead of converting
+    pub info: bool,
+
+    /// Prints the summaries as JSON
+    pub info_json: bool,
+
+    /// Generic fields for moment names in the input files, checked before the built in names
+    pub field_map: HashMap<String, Field>,
+
+    /// Corrections added to every value of each field as it is read
+    pub bias: HashMap<Field, f64>,
+
+    /// Fits azimuths to a constant scan rate, keeping the recorded azimuths alongside
+    pub smooth_azimuths: bool,
+
+    /// Negates velocities, for sources that use positive toward the radar
+    pub flip_velocity: bool,
+
+    /// Units of the input reflectivity
+    pub ref_units: RefUnits,
+
+    /// Unfolds aliased velocities using the sweep's Nyquist velocity
+    pub dealias: Option<Dealias>,
+
+    /// Environmental wind profile for sounding dealiasing
+    pub sounding: Option<processing::Sounding>,
+
+    /// Removes runs of echo shorter than this many gates along each ray
+    pub despeckle: Option<usize>,
+
+    /// Removes connected areas of echo with fewer than this many gates
+    pub despeckle_area: Option<usize>,
+
+    /// Fewest rays for a piece of a split sweep to be kept, by default depending on the scan mode
+    pub min_rays: Option<usize>,
+
+    /// Thresholds that gates must pass, or every field is censored at the gate
+    pub qc: Vec<processing::Threshold>,
+
+    /// Expressions that censor every field at gates where they are true
+    pub filter: Vec<expr::Expr>,
+
+    /// Corrects REF and ZDR for rain attenuation using differential phase
+    pub correct_attenuation: bool,
+
+    /// Adds a rain rate field from reflectivity with this Z-R relation
+    pub rain_rate: Option<processing::ZR>,
+
+    /// Estimates the rain rate from KDP and ZDR where they are reliable, using the Z-R relation elsewhere
+    pub polarimetric_rain: bool,
+
+    /// Unfolds differential phase and removes the system phase, then smooths it with a median
+    /// of this many gates
+    pub process_phidp: Option<usize>,
+
+    /// Median or boxcar smoothing of fields, applied before derived fields are computed
+    pub smooth: Vec<processing::Smoothing>,
+
+    /// Adds the standard deviation of fields over a window as new fields, such as VEL_TEXTURE
+    pub texture: Vec<processing::Texture>,
+
+    /// Adds a storm-relative velocity field for a storm moving with this eastward and northward
+    /// velocity in m/s
+    pub storm_motion: Option<(f32, f32)>,
+
+    /// Fields computed from expressions over the others, in order, after the rest of the
+    /// processing
+    pub derive: Vec<expr::Derived>,
+
+    /// Replaces the sweeps with constant altitude PPIs at these heights in meters above the radar
+    pub cappi: Vec<f32>,
+
+    /// Fields interpolated to the constant altitude PPIs
+    pub cappi_fields: Vec<Field>,
+
+    /// Replaces the sweeps with the echo top heights of this reflectivity in dBZ
+    pub echo_tops: Option<f64>,
+
+    /// Blanks gates closer than this range in meters
+    pub min_range: Option<f32>,
+
+    /// Blanks gates higher than this height above the radar in meters
+    pub max_height: Option<f32>,
+
+    /// Drops gates farther than this range in meters
+    pub max_range: Option<f32>,
+
+    /// Changes the gate spacing of every field to this many meters before writing
+    pub gate_spacing: Option<f32>,
+
+    /// Validates each radar before writing it, reporting and skipping radars with problems
+    pub check: bool,
+
+    /// Number of files converted at once when each is written on its own, and of threads that
+    /// decode the sweeps of a NEXRAD file
+    pub jobs: usize,
+
+    /// Writes a Chrome trace of where the conversion spends its time to this file
+    pub trace_output: Option<String>,
+}
+
+impl Default for RadyOptions {
+    fn default() -> RadyOptions {
+        RadyOptions {
+            override_radar: None,
+            trim_rays: false,
+            split_overlap_rays: false,
+            dedup_rays: false,
+            sort_rays_by_azimuth: false,
+            format: Format::NEXRAD,
+            write_volumes: false,
+            volume_step: 0.1,
+            volume_gap: None,
+            write_separate: false,
+            index: false,
+            low_memory: false,
+            print_products: false,
+            files: String::new(),
+            file_list: None,
+            extra_files: Vec::new(),
+            recursive: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            watch: None,
+            serve: None,
+            serve_status: false,
+            realtime: false,
+            location: false,
+            georeference: false,
+            apply_corrections: false,
+            outdir: None,
+            name_format: None,
+            fields: None,
+            exclude_fields: Vec::new(),
+            sweeps: None,
+            elevations: None,
+            elevation_tolerance: 0.2,
+            start: None,
+            end: None,
+            site: None,
+            azimuth_in: AzimuthRef::Center,
+            azimuth_out: AzimuthRef::Center,
+            on_conflict: OnConflict::default(),
+            naming: Naming::default(),
+            verify: false,
+            time_height: false,
+            keep_calibration: false,
+            drop_transitions: false,
+            merge_split_cuts: false,
+            aggregate: false,
+            selftest: false,
+            info: false,
+            info_json: false,
+            field_map: HashMap::new(),
+            bias: HashMap::new(),
+            smooth_azimuths: false,
+            flip_velocity: false,
+            ref_units: RefUnits::Auto,
+            dealias: None,
+            sounding: None,
+            despeckle: None,
+            despeckle_area: None,
+            min_rays: None,
+            qc: Vec::new(),
+            filter: Vec::new(),
+            correct_attenuation: false,
+            rain_rate: None,
+            polarimetric_rain: false,
+            process_phidp: None,
+            smooth: Vec::new(),
+            texture: Vec::new(),
+            storm_motion: None,
+            derive: Vec::new(),
+            cappi: Vec::new(),
+            cappi_fields: vec![Field::REF],
+            echo_tops: None,
+            min_range: None,
+            max_height: None,
+            max_range: None,
+            gate_spacing: None,
+            check: false,
+            trace_output: None,
+            jobs: 1,
+        }
+    }
+}
+
+impl RadyOptions {
+    /// Checks if a field should be decoded
+    pub fn wants_field(&self, field: &Field) -> bool {
+        let listed = match &self.fields {
+            Some(fields) => fields.iter().any(|f| f == field),
+            None => true,
+        };
+
+        listed && !self.exclude_fields.contains(field)
+    }
+
+    /// Checks if any ray of a radar is between the start and end times
+    pub fn in_time_range(&self, radar: &RadarFile) -> bool {
+        let times = || {
+            radar
+                .sweeps
+                .iter()
+                .flat_map(|sweep| &sweep.rays)
+                .map(|ray| ray.time)
+        };
+
+        let (Some(first), Some(last)) = (times().min(), times().max()) else {
+            return true;
+        };
+
+        self.start.is_none_or(|start| last >= start) && self.end.is_none_or(|end| first <= end)
+    }
+
+    /// Whether the files are downloaded, from an `s3://` path or a site
+    pub fn is_remote(&self) -> bool {
+        self.site.is_some() || self.files.starts_with("s3://")
+    }
+
+    /// Keeps the sweeps selected by position and elevation, as a file is read
+    pub fn select_sweeps(&self, radar: &mut RadarFile) {
+        if let Some(indices) = &self.sweeps {
+            let mut i = 0;
+            radar.sweeps.retain(|_| {
+                i += 1;
+                indices.contains(&(i - 1))
+            });
+        }
+
+        if let Some(elevations) = &self.elevations {
+            radar.sweeps.retain(|sweep| {
+                elevations.iter().any(|elevation| {
+                    (sweep.scheduled_angle() - elevation).abs() <= self.elevation_tolerance
+                })
+            });
+        }
+    }
+
+    /// Applies the processing steps of the options to a radar. Returns an error if a step can't
+    /// be done on it, such as a derived field whose fields the radar doesn't have
+    #[instrument(skip_all, fields(radar = %radar.name))]
+    pub fn apply_options(&self, radar: &mut RadarFile) -> Result<(), Error> {
+        if self.override_radar.is_some() {
+            radar.name = self.override_radar.clone().unwrap();
+        }
+
+        if self.apply_corrections {
+            radar.apply_corrections();
+        }
+
+        if !self.keep_calibration {
+            radar.drop_calibration_sweeps();
+        }
+
+        if self.drop_transitions {
+            radar.drop_transition_rays();
+        }
+
+        if self.merge_split_cuts {
+            radar.merge_split_cuts();
+        }
+
+        // Rays are kept as beam centers, shifted back half a ray when written
+        if self.azimuth_in == AzimuthRef::LeadingEdge {
+            radar
+                .sweeps
+                .iter_mut()
+                .filter(|sweep| !sweep.is_vertical())
+                .for_each(|sweep| sweep.shift_azimuths(-0.5));
+        }
+
+        if self.flip_velocity {
+            radar.flip_velocity();
+        }
+
+        let linear = match self.ref_units {
+            RefUnits::Auto => radar.linear_reflectivity(),
+            RefUnits::Decibels => false,
+            RefUnits::Linear => true,
+        };
+
+        if linear {
+            eprintln!("Converting linear reflectivity to dBZ");
+            radar.reflectivity_to_dbz();
+        }
+
+        if !self.qc.is_empty() {
+            radar
+                .sweeps
+                .iter_mut()
+                .for_each(|sweep| processing::censor(sweep, &self.qc));
+        }
+
+        for filter in &self.filter {
+            radar
+                .sweeps
+                .iter_mut()
+                .for_each(|sweep| expr::filter(sweep, filter));
+        }
+
+        if let Some(window) = self.process_phidp {
+            radar
+                .sweeps
+                .iter_mut()
+                .for_each(|sweep| processing::process_phidp(sweep, window));
+        }
+
+        for smoothing in &self.smooth {
+            radar
+                .sweeps
+                .iter_mut()
+                .for_each(|sweep| processing::smooth(sweep, smoothing));
+        }
+
+        if self.correct_attenuation {
+            processing::correct_attenuation(radar);
+        }
+
+        if self.min_range.is_some() || self.max_height.is_some() {
+            processing::mask_by_geometry(radar, self.min_range, self.max_height);
+        }
+
+        if let Some(max_range) = self.max_range {
+            radar.crop_range(max_range);
+        }
+
+        if let Some(min_gates) = self.despeckle {
+            radar
+                .sweeps
+                .iter_mut()
+                .for_each(|sweep| processing::despeckle(sweep, min_gates));
+        }
+
+        if let Some(min_area) = self.despeckle_area {
+            radar
+                .sweeps
+                .iter_mut()
+                .for_each(|sweep| processing::despeckle_area(sweep, min_area));
+        }
+
+        if self.polarimetric_rain {
+            processing::rain_rate_polarimetric(radar, self.rain_rate.unwrap_or_default());
+        } else if let Some(zr) = self.rain_rate {
+            processing::rain_rate(radar, zr);
+        }
+
+        match self.dealias {
+            Some(Dealias::Region) => radar
+                .sweeps
+                .iter_mut()
+                .for_each(processing::dealias_region_based),
+            Some(Dealias::Sounding) => {
+                let sounding = self
+                    .sounding
+                    .as_ref()
+                    .expect("Sounding dealiasing needs a sounding file");
+                let altitude = radar.instrument.altitude.unwrap_or(0.0);
+
+                for i in 0..radar.sweeps.len() {
+                    let geometry = radar.geometry(&radar.sweeps[i], &Field::VEL);
+                    processing::dealias_sounding(
+                        &mut radar.sweeps[i],
+                        geometry,
+                        sounding,
+                        altitude,
+                    );
+                }
+            }
+            None => (),
+        }
+
+        for texture in &self.texture {
+            processing::texture(radar, texture);
+        }
+
+        if let Some((u, v)) = self.storm_motion {
+            processing::storm_relative_velocity(radar, u, v);
+        }
+
+        for derived in &self.derive {
+            expr::derive(radar, derived)?;
+        }
+
+        if !self.cappi.is_empty() {
+            processing::cappi(radar, &self.cappi, &self.cappi_fields);
+        }
+
+        if let Some(threshold) = self.echo_tops {
+            processing::echo_tops(radar, threshold);
+        }
+
+        if let Some(spacing) = self.gate_spacing {
+            radar.set_gate_spacing(spacing);
+        }
+
+        if self.smooth_azimuths {
+            radar
+                .sweeps
+                .iter_mut()
+                .for_each(|sweep| sweep.smooth_azimuths());
+        }
+
+        if self.trim_rays {
+            radar.trim_rays();
+        }
+
+        if self.split_overlap_rays {
+            radar.split_overlap_rays(self.min_rays);
+        }
+
+        if self.dedup_rays {
+            radar.dedup_rays();
+        }
+
+        if self.sort_rays_by_azimuth {
+            radar.sort_rays_by_azimuth();
+        }
+
+        if self.location && !radar.sweeps.is_empty() {
+            println!(
+                "{}: {}, {}",
+                radar.name, radar.sweeps[0].latitude, radar.sweeps[0].longitude
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a field map from a TOML or JSON (by extension) file of file names to generic names,
+/// e.g. `DBZHC_V = "REF"`
+pub fn read_field_map(path: impl AsRef<Path>) -> HashMap<String, Field> {
+    let contents = std::fs::read_to_string(path.as_ref()).unwrap();
+
+    let map: HashMap<String, String> = match path.as_ref().extension().and_then(|ext| ext.to_str())
+    {
+        Some("json") => serde_json::from_str(&contents).unwrap(),
+        _ => toml::from_str(&contents).unwrap(),
+    };
+
+    map.into_iter()
+        .map(|(name, field)| (name, field.parse().unwrap()))
+        .collect()
+}
+
+/// Reads a file, panicking if it can't be read or isn't in a known format
+pub fn read(path: impl AsRef<Path>, options: &RadyOptions) -> RadarFile {
+    match try_read(path, options) {
+        Ok(Some(radar)) => radar,
+        Ok(None) => panic!("Unknown file format"),
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Reads a file if it is in a known format, or returns None for files that aren't radar data,
+/// such as logs, notes, and empty placeholders
+pub fn try_read(path: impl AsRef<Path>, options: &RadyOptions) -> Result<Option<RadarFile>, Error> {
+    // Sniffing treats files that can't be opened as unknown, so they are opened first to report why
+    std::fs::File::open(path.as_ref())?;
+
+    let Some(reader) = detect(path.as_ref()) else {
+        return Ok(None);
+    };
+
+    let _span =
+        info_span!("read", format = reader.name(), path = %path.as_ref().display()).entered();
+    let mut radar = reader.read(path.as_ref(), options)?;
+    radar.add_bias(&options.bias);
+    options.select_sweeps(&mut radar);
+
+    Ok(Some(radar))
+}
+
+/// Reads a file held in memory if it is in a format that can be read without a path, applying
+/// the same options as `try_read`. Nothing is read from or written to the file system
+pub fn read_from_bytes(bytes: &[u8], options: &RadyOptions) -> Result<Option<RadarFile>, Error> {
+    let Some(radar) = formats::decode(bytes, options) else {
+        return Ok(None);
+    };
+
+    let mut radar = radar?;
+    radar.add_bias(&options.bias);
+    options.select_sweeps(&mut radar);
+
+    Ok(Some(radar))
+}
+
+/// Writes a radar into a single file held in memory, or returns None if the format can only be
+/// written to a directory
+pub fn write_to_bytes(
+    mut radar: RadarFile,
+    format: Format,
+    options: &RadyOptions,
+) -> Option<Vec<u8>> {
+    radar.sort_sweeps_by_elevation();
+    writer(format.name())?.encode(radar, options)
+}
+
+fn vol_mode(radar: &RadarFile) -> f32 {
+    match radar.sweeps.len() {
+        0 | 1 => return 1.0,
+        2 => {
+            return (radar.sweeps[1].scheduled_angle() - radar.sweeps[0].scheduled_angle()).signum()
+        }
+        _ => (),
+    }
+
+    let min_elev = radar
+        .sweeps
+        .iter()
+        .map(|sweep| sweep.scheduled_angle())
+        .reduce(|elev1, elev2| if elev1 < elev2 { elev1 } else { elev2 })
+        .unwrap();
+
+    let ii = radar
+        .sweeps
+        .iter()
+        .map(|sweep| sweep.scheduled_angle())
+        .enumerate()
+        .find(|elev| (elev.1 - min_elev).abs() < 0.05)
+        .unwrap()
+        .0;
+
+    if ii > radar.sweeps.len() - 2 {
+        return -1.0;
+    }
+
+    if (radar.sweeps[ii + 2].scheduled_angle() - radar.sweeps[ii].scheduled_angle()).abs() < 0.05 {
+        return 1.0;
+    }
+
+    (radar.sweeps[ii + 2].scheduled_angle() - radar.sweeps[ii + 1].scheduled_angle()).signum()
+}
+
+/// Writes a radar into the output directory in the output format
+pub fn write(
+    mut radar: RadarFile,
+    path: impl AsRef<Path>,
+    options: &RadyOptions,
+) -> Result<(), Error> {
+    radar.sort_sweeps_by_time();
+
+    if options.write_volumes {
+        let mut stream = VolumeStream::new(path, options);
+        stream.direction = Some(vol_mode(&radar));
+        stream.push(radar)?;
+        stream.finish()
+    } else if options.write_separate {
+        write_tilts(radar, path.as_ref(), options, None)
+    } else {
+        if options.azimuth_out == AzimuthRef::LeadingEdge {
+            radar
+                .sweeps
+                .iter_mut()
+                .filter(|sweep| !sweep.is_vertical())
+                .for_each(|sweep| sweep.shift_azimuths(0.5));
+        }
+
+        write_volume(radar, path.as_ref(), options)
+    }
+}
+
+/// Writes each sweep to its own file, adding the files written for each sweep to the index if
+/// one is given. Writers pick their own file names, so the new files are found by listing the
+/// output directory around each write
+fn write_tilts(
+    mut radar: RadarFile,
+    path: &Path,
+    options: &RadyOptions,
+    mut index: Option<&mut VolumeIndex>,
+) -> Result<(), Error> {
+    radar.sort_sweeps_by_time();
+
+    let mut new_ops = options.clone();
+    new_ops.write_volumes = false;
+    new_ops.write_separate = false;
+
+    for sweep in std::mem::take(&mut radar.sweeps) {
+        if sweep.rays.is_empty() {
+            continue;
+        }
+
+        let before: BTreeSet<PathBuf> = match index {
+            Some(_) => files_in(path).unwrap_or_default().into_iter().collect(),
+            None => BTreeSet::new(),
+        };
+
+        let (time, elevation) = (sweep.time(), sweep.scheduled_angle());
+        write(
+            RadarFile {
+                sweeps: vec![sweep],
+                ..radar.clone()
+            },
+            path,
+            &new_ops,
+        )?;
+
+        if let Some(index) = index.as_deref_mut() {
+            let files = files_in(path)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|file| !before.contains(file))
+                .map(|file| {
+                    file.strip_prefix(path)
+                        .unwrap_or(&file)
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+
+            index.add(files, time, elevation);
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the files in a directory and its subdirectories, sorted by path
+fn files_in(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Writes a single volume, with the sweeps in increasing elevation, in the output format
+fn write_volume(mut radar: RadarFile, path: &Path, options: &RadyOptions) -> Result<(), Error> {
+    radar.sort_sweeps_by_elevation();
+
+    if options.time_height {
+        let _span = info_span!("write_time_height").entered();
+
+        #[cfg(feature = "netcdf")]
+        formats::cfradial::write_time_height(&radar, path, options);
+
+        #[cfg(not(feature = "netcdf"))]
+        return Err(Error::Unsupported(
+            "Time-height files are CfRadial, which this build can't write".to_string(),
+        ));
+    }
+
+    match writer(options.format.name()) {
+        Some(writer) => {
+            let _span =
+                info_span!("write", format = writer.name(), sweeps = radar.sweeps.len()).entered();
+            let verify = options
+                .verify
+                .then(|| (radar.clone(), modification_times(path)));
+
+            writer.write(radar, path, options)?;
+
+            match verify {
+                Some((source, before)) => verify_written(&source, &before, path, writer.as_ref()),
+                None => Ok(()),
+            }
+        }
+        None => Err(unsupported_writer(options)),
+    }
+}
+
+/// Times that the files in a directory were last modified
+fn modification_times(dir: &Path) -> HashMap<PathBuf, Option<std::time::SystemTime>> {
+    let files = files_in(dir).unwrap_or_default();
+    files
+        .into_iter()
+        .map(|file| {
+            (
+                file.clone(),
+                file.metadata().and_then(|m| m.modified()).ok(),
+            )
+        })
+        .collect()
+}
+
+/// Reads back the files that a writer made or changed in a directory and compares them to the
+/// radar that was written, returning the differences as an error
+fn verify_written(
+    source: &RadarFile,
+    before: &HashMap<PathBuf, Option<std::time::SystemTime>>,
+    dir: &Path,
+    writer: &dyn RadarWrite,
+) -> Result<(), Error> {
+    let after = modification_times(dir);
+    let mut written: Vec<PathBuf> = after
+        .into_iter()
+        .filter(|(file, time)| before.get(file) != Some(time))
+        .map(|(file, _)| file)
+        .collect();
+    written.sort();
+
+    let mut copy: Option<RadarFile> = None;
+    for file in &written {
+        let piece = match try_read(file, &RadyOptions::default()) {
+            Ok(Some(piece)) => piece,
+            Ok(None) => continue,
+            Err(e) => {
+                return Err(Error::Verify(
+                    vec![file.clone()],
+                    vec![format!("it can't be read back: {}", e)],
+                ))
+            }
+        };
+
+        match &mut copy {
+            Some(copy) => {
+                copy.sweeps.extend(piece.sweeps);
+                piece.params.into_iter().for_each(|(field, param)| {
+                    copy.params.entry(field).or_insert(param);
+                });
+            }
+            None => copy = Some(piece),
+        }
+    }
+
+    let Some(copy) = copy else {
+        return Err(Error::Verify(
+            vec![dir.to_path_buf()],
+            vec!["nothing was written to it to verify".to_string()],
+        ));
+    };
+
+    let differences = source.differences(&copy, |field| writer.tolerance(field));
+
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Verify(written, differences))
+    }
+}
+
+/// Holds the sweeps of a volume until it is finished, for formats that can't append sweeps
+struct BufferedVolume {
+    radar: RadarFile,
+    path: PathBuf,
+    options: RadyOptions,
+}
+
+impl SweepSink for BufferedVolume {
+    fn push(
+        &mut self,
+        sweep: Sweep,
+        params: &HashMap<Field, ParamDescription>,
+    ) -> Result<(), Error> {
+        for (field, param) in params {
+            self.radar
+                .params
+                .entry(field.clone())
+                .or_insert_with(|| param.clone());
+        }
+
+        self.radar.sweeps.push(sweep);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        write_volume(self.radar, &self.path, &self.options)
+    }
+}
+
+/// Splits sweeps into volumes as they arrive, and writes each volume as it is assembled.
+/// Formats that can append to an open file get each sweep as soon as it arrives, so only a
+/// sweep is held in memory, while other formats hold one volume at a time
+pub struct VolumeStream {
+    path: PathBuf,
+    options: RadyOptions,
+
+    /// Volume being written, and the elevation and time of its latest sweep
+    volume: Option<(Box<dyn SweepSink>, f32, DateTime<Utc>)>,
+
+    /// Whether elevations increase (1) or decrease (-1) through a volume, once known
+    direction: Option<f32>,
+}
+
+impl VolumeStream {
+    /// Starts a stream of volumes written to the output directory
+    pub fn new(path: impl AsRef<Path>, options: &RadyOptions) -> Self {
+        let mut options = options.clone();
+        options.write_volumes = false;
+        options.write_separate = false;
+
+        VolumeStream {
+            path: path.as_ref().to_path_buf(),
+            options,
+            volume: None,
+            direction: None,
+        }
+    }
+
+    /// Adds the sweeps of a radar, in time order. A sweep that doesn't continue the volume
+    /// in its direction of tilt starts a new one
+    pub fn push(&mut self, mut radar: RadarFile) -> Result<(), Error> {
+        radar.sort_sweeps_by_time();
+
+        for mut sweep in std::mem::take(&mut radar.sweeps) {
+            if sweep.rays.is_empty() {
+                continue;
+            }
+
+            let (elevation, time) = (sweep.scheduled_angle(), sweep.time());
+
+            if let Some(&(_, last, last_time)) = self.volume.as_ref() {
+                if !continues_volume(
+                    &mut self.direction,
+                    (last, last_time),
+                    (elevation, time),
+                    (self.options.volume_step, self.options.volume_gap),
+                ) {
+                    self.finish_volume()?;
+                }
+            }
+
+            if self.options.azimuth_out == AzimuthRef::LeadingEdge && !sweep.is_vertical() {
+                sweep.shift_azimuths(0.5);
+            }
+
+            let volume = match self.volume.take() {
+                Some(volume) => volume,
+                None => {
+                    let header = RadarFile {
+                        sweeps: Vec::new(),
+                        ..radar.clone()
+                    };
+                    (
+                        open_volume(header, &self.path, &self.options)?,
+                        elevation,
+                        time,
+                    )
+                }
+            };
+            let (sink, last, last_time) = self.volume.insert(volume);
+
+            sink.push(sweep, &radar.params)?;
+            (*last, *last_time) = (elevation, time);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the end of the volume being assembled
+    fn finish_volume(&mut self) -> Result<(), Error> {
+        match self.volume.take() {
+            Some((sink, _, _)) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the last volume
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.finish_volume()
+    }
+}
+
+/// Checks if a sweep at an elevation and time continues a volume whose latest sweep is `last`,
+/// tilting by more than `step` degrees in the volume's direction at most `gap` seconds later.
+/// The first change in elevation sets the direction
+fn continues_volume(
+    direction: &mut Option<f32>,
+    last: (f32, DateTime<Utc>),
+    sweep: (f32, DateTime<Utc>),
+    (step, gap): (f32, Option<f64>),
+) -> bool {
+    let change = sweep.0 - last.0;
+    let seconds = (sweep.1 - last.1).num_milliseconds() as f64 / 1000.0;
+
+    if gap.is_some_and(|gap| seconds > gap) {
+        return false;
+    }
+
+    match *direction {
+        Some(direction) => change * direction > step,
+        None if change.abs() > step => {
+            *direction = Some(change.signum());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Opens a volume in the output format, streaming it if the format supports it. Time-height
+/// output needs the whole volume, so it is always buffered
+fn open_volume(
+    radar: RadarFile,
+    path: &Path,
+    options: &RadyOptions,
+) -> Result<Box<dyn SweepSink>, Error> {
+    let writer = writer(options.format.name()).ok_or_else(|| unsupported_writer(options))?;
+
+    let stream = if options.time_height {
+        None
+    } else {
+        writer.stream(radar.clone(), path, options)
+    };
+
+    Ok(stream.unwrap_or_else(|| {
+        Box::new(BufferedVolume {
+            radar,
+            path: path.to_path_buf(),
+            options: options.clone(),
+        })
+    }))
+}
+
+/// Error for an output format that this build has no writer for
+fn unsupported_writer(options: &RadyOptions) -> Error {
+    Error::Unsupported(format!("This build can't write {}", options.format.name()))
+}
+
+/// Number of sweeps written at a fixed angle
+#[derive(Debug, Clone, Serialize)]
+pub struct TiltCount {
+    /// Fixed angle in degrees, to a tenth of a degree
+    pub elevation: f32,
+
+    /// Height in meters above the radar of constant altitude pseudo-sweeps
+    pub height: Option<f32>,
+
+    /// Number of sweeps at the angle
+    pub sweeps: usize,
+}
+
+/// Summary of the volumes written to an output directory, saved as `INVENTORY.json`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Inventory {
+    /// Names of the radars
+    pub radars: BTreeSet<String>,
+
+    /// Time of the first ray written
+    pub start_time: Option<DateTime<Utc>>,
+
+    /// Time of the last ray written
+    pub end_time: Option<DateTime<Utc>>,
+
+    /// Number of volumes written
+    pub volumes: usize,
+
+    /// Number of sweeps written at each fixed angle, in increasing elevation
+    pub tilts: Vec<TiltCount>,
+
+    /// Fields written across all of the volumes
+    pub fields: BTreeSet<Field>,
+
+    /// Number of input files that weren't radar data
+    pub skipped_files: usize,
+
+    /// Number of input files that couldn't be converted
+    pub failed_files: usize,
+}
+
+impl Inventory {
+    /// Adds a volume to the summary
+    pub fn add(&mut self, radar: &RadarFile) {
+        self.volumes += 1;
+        self.add_sweeps(radar);
+    }
+
+    /// Adds more sweeps of the latest volume to the summary
+    pub fn add_sweeps(&mut self, radar: &RadarFile) {
+        self.radars.insert(radar.name.clone());
+        self.fields.extend(radar.params.keys().cloned());
+
+        for sweep in &radar.sweeps {
+            let start = sweep.rays.iter().map(|ray| ray.time).min();
+            let end = sweep.rays.iter().map(|ray| ray.time).max();
+            self.start_time = self.start_time.into_iter().chain(start).min();
+            self.end_time = self.end_time.into_iter().chain(end).max();
+
+            let (elevation, height) = (
+                (sweep.scheduled_angle() * 10.0).round() / 10.0,
+                sweep.height,
+            );
+            match self
+                .tilts
+                .iter_mut()
+                .find(|tilt| tilt.elevation == elevation && tilt.height == height)
+            {
+                Some(tilt) => tilt.sweeps += 1,
+                None => self.tilts.push(TiltCount {
+                    elevation,
+                    height,
+                    sweeps: 1,
+                }),
+            }
+        }
+
+        self.tilts.sort_by(|a, b| {
+            a.elevation
+                .total_cmp(&b.elevation)
+                .then(a.height.partial_cmp(&b.height).unwrap())
+        });
+    }
+
+    /// Writes the summary to `INVENTORY.json` in a directory
+    pub fn write(&self, dir: impl AsRef<Path>) {
+        std::fs::create_dir_all(dir.as_ref()).unwrap();
+        std::fs::write(
+            dir.as_ref().join("INVENTORY.json"),
+            serde_json::to_string_pretty(self).unwrap(),
+        )
+        .unwrap();
+    }
+}
+
+/// Summary of a radar file, as printed by the info subcommand
+#[derive(Debug, Clone, Serialize)]
+pub struct FileInfo {
+    /// Name of the radar
+    pub name: String,
+
+    /// Location of the radar at the first sweep
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+
+    /// Scan mode of the first sweep
+    pub scan_mode: Option<ScanMode>,
+
+    /// Correction factors recorded in the file
+    pub corrections: Option<Corrections>,
+
+    pub sweeps: Vec<SweepInfo>,
+
+    /// Description of each field, by name
+    pub fields: BTreeMap<String, FieldInfo>,
+}
+
+/// Summary of a sweep
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepInfo {
+    /// Fixed angle, or the measured elevation if the file doesn't record one
+    pub elevation: f32,
+    pub scan_mode: ScanMode,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub rays: usize,
+    pub nyquist_velocity: f32,
+
+    /// Most gates of each field in a ray, by name
+    pub gates: BTreeMap<String, usize>,
+}
+
+/// Summary of a field
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldInfo {
+    pub description: String,
+    pub units: String,
+    pub meters_to_first_cell: f32,
+    pub meters_between_cells: f32,
+}
+
+impl FileInfo {
+    /// Summarizes a radar
+    pub fn new(radar: &RadarFile) -> FileInfo {
+        let first = radar.sweeps.first();
+
+        let sweeps = radar
+            .sweeps
+            .iter()
+            .filter(|sweep| !sweep.rays.is_empty())
+            .map(|sweep| {
+                let fields: BTreeSet<&Field> =
+                    sweep.rays.iter().flat_map(|ray| ray.data.keys()).collect();
+
+                SweepInfo {
+                    elevation: sweep.scheduled_angle(),
+                    scan_mode: sweep.scan_mode,
+                    start_time: sweep.rays.iter().map(|ray| ray.time).min().unwrap(),
+                    end_time: sweep.rays.iter().map(|ray| ray.time).max().unwrap(),
+                    rays: sweep.rays.len(),
+                    nyquist_velocity: sweep.nyquist_velocity,
+                    gates: fields
+                        .into_iter()
+                        .map(|field| (field.to_string(), sweep.ngates(field)))
+                        .collect(),
+                }
+            })
+            .collect();
+
+        let fields = radar
+            .params
+            .iter()
+            .map(|(field, param)| {
+                let info = FieldInfo {
+                    description: param.description.clone(),
+                    units: param.units.clone(),
+                    meters_to_first_cell: param.meters_to_first_cell,
+                    meters_between_cells: param.meters_between_cells,
+                };
+
+                (field.to_string(), info)
+            })
+            .collect();
+
+        FileInfo {
+            name: radar.name.clone(),
+            latitude: first.map(|sweep| sweep.latitude),
+            longitude: first.map(|sweep| sweep.longitude),
+            scan_mode: first.map(|sweep| sweep.scan_mode),
+            corrections: radar.corrections,
+            sweeps,
+            fields,
+        }
+    }
+}
+
+impl fmt::Display for FileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Radar: {}", self.name)?;
+        if let (Some(latitude), Some(longitude), Some(scan_mode)) =
+            (self.latitude, self.longitude, self.scan_mode)
+        {
+            write!(f, " at {}, {} ({:?})", latitude, longitude, scan_mode)?;
+        }
+        writeln!(f)?;
+
+        if let Some(corrections) = &self.corrections {
+            let nonzero: Vec<String> = corrections
+                .nonzero()
+                .iter()
+                .map(|(name, value)| format!("{} {}", name, value))
+                .collect();
+            let nonzero = if nonzero.is_empty() {
+                "none".to_string()
+            } else {
+                nonzero.join(", ")
+            };
+            writeln!(f, "Corrections: {}", nonzero)?;
+        }
+
+        writeln!(f, "Fields:")?;
+        for (name, field) in &self.fields {
+            writeln!(
+                f,
+                "  {}: {} ({}), first gate at {} m, {} m apart",
+                name,
+                field.description,
+                field.units,
+                field.meters_to_first_cell,
+                field.meters_between_cells
+            )?;
+        }
+
+        writeln!(f, "Sweeps: {}", self.sweeps.len())?;
+        for (i, sweep) in self.sweeps.iter().enumerate() {
+            let gates: Vec<String> = sweep
+                .gates
+                .iter()
+                .map(|(name, gates)| format!("{} {}", name, gates))
+                .collect();
+            writeln!(
+                f,
+                "  {}: {:.2} degrees {:?} at {}, {} rays, gates {}",
+                i,
+                sweep.elevation,
+                sweep.scan_mode,
+                sweep.start_time.format("%Y-%m-%d %H:%M:%S"),
+                sweep.rays,
+                gates.join(", ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints a summary of each input file, as text or JSON, without writing anything. Returns how
+/// many files failed, like `convert`
+pub fn print_info(options: &RadyOptions) -> usize {
+    #[derive(Serialize)]
+    struct Entry {
+        file: String,
+
+        #[serde(flatten)]
+        info: FileInfo,
+    }
+
+    let files = match input_files(options, Path::new("")) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let mut entries = Vec::new();
+    let failures = Failures::default();
+
+    for file in files {
+        let Some(read) = guard(&file, &failures, || try_read(&file, options)) else {
+            continue;
+        };
+
+        let Some(radar) = read else {
+            eprintln!("Skipping {}: not radar data", file.display());
+            continue;
+        };
+
+        let info = FileInfo::new(&radar);
+
+        if options.info_json {
+            entries.push(Entry {
+                file: file.display().to_string(),
+                info,
+            });
+        } else {
+            println!("{}\n{}", file.display(), info);
+        }
+    }
+
+    if options.info_json {
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+    }
+
+    let failed = failures.into_inner().unwrap().len();
+    if failed > 0 {
+        eprintln!("{} files failed to read", failed);
+    }
+
+    failed
+}
+
+/// Files written for a sweep, in the volume index
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexedSweep {
+    /// Paths of the files, relative to the output directory
+    pub files: Vec<String>,
+
+    pub time: DateTime<Utc>,
+    pub elevation: f32,
+}
+
+/// Sweeps of a volume, in the volume index
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexedVolume {
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub sweeps: Vec<IndexedSweep>,
+}
+
+/// Index grouping the files of sweeps written separately into volumes
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeIndex {
+    pub volumes: Vec<IndexedVolume>,
+
+    /// Whether elevations increase (1) or decrease (-1) through a volume, once known
+    #[serde(skip)]
+    direction: Option<f32>,
+
+    /// Degrees and seconds between sweeps that split them into volumes
+    #[serde(skip)]
+    tolerances: (f32, Option<f64>),
+}
+
+impl VolumeIndex {
+    /// Starts an empty index, splitting volumes with the tolerances of the options
+    pub fn new(options: &RadyOptions) -> Self {
+        VolumeIndex {
+            volumes: Vec::new(),
+            direction: None,
+            tolerances: (options.volume_step, options.volume_gap),
+        }
+    }
+
+    /// Adds the files of a sweep, in time order. A sweep that doesn't continue the latest volume
+    /// in its direction of tilt starts a new one
+    pub fn add(&mut self, files: Vec<String>, time: DateTime<Utc>, elevation: f32) {
+        let last = self
+            .volumes
+            .last()
+            .and_then(|volume| volume.sweeps.last())
+            .map(|sweep| (sweep.elevation, sweep.time));
+        let sweep = IndexedSweep {
+            files,
+            time,
+            elevation,
+        };
+
+        match (self.volumes.last_mut(), last) {
+            (Some(volume), Some(last))
+                if continues_volume(
+                    &mut self.direction,
+                    last,
+                    (elevation, time),
+                    self.tolerances,
+                ) =>
+            {
+                volume.end_time = volume.end_time.max(time);
+                volume.sweeps.push(sweep);
+            }
+            _ => self.volumes.push(IndexedVolume {
+                start_time: time,
+                end_time: time,
+                sweeps: vec![sweep],
+            }),
+        }
+    }
+
+    /// Writes the index to `INDEX.json` in a directory
+    pub fn write(&self, dir: impl AsRef<Path>) {
+        std::fs::create_dir_all(dir.as_ref()).unwrap();
+        std::fs::write(
+            dir.as_ref().join("INDEX.json"),
+            serde_json::to_string_pretty(self).unwrap(),
+        )
+        .unwrap();
+    }
+}
+
+/// Appends radars into a single CfRadial file, with the sweeps in time order
+pub fn write_aggregate(
+    radars: Vec<RadarFile>,
+    path: impl AsRef<Path>,
+    options: &RadyOptions,
+) -> Result<(), Error> {
+    let mut radars = radars.into_iter().filter(|radar| !radar.sweeps.is_empty());
+    let mut aggregate = radars
+        .next()
+        .ok_or_else(|| Error::Format("No sweeps to aggregate".to_string()))?;
+
+    for radar in radars {
+        if radar.name != aggregate.name {
+            eprintln!(
+                "Aggregating {} into a file for {}",
+                radar.name, aggregate.name
+            );
+        }
+
+        aggregate.merge(radar);
+    }
+
+    if options.azimuth_out == AzimuthRef::LeadingEdge {
+        aggregate
+            .sweeps
+            .iter_mut()
+            .filter(|sweep| !sweep.is_vertical())
+            .for_each(|sweep| sweep.shift_azimuths(0.5));
+    }
+
+    #[cfg(feature = "netcdf")]
+    return formats::cfradial::write_cfradial(&aggregate, path, options);
+
+    #[cfg(not(feature = "netcdf"))]
+    {
+        let _ = (path, options);
+        Err(Error::Unsupported(
+            "Aggregates are CfRadial, which this build can't write".to_string(),
+        ))
+    }
+}
+
+/// Converts a file held in memory into a file held in memory, detecting the input format from
+/// the contents unless `from` is given. The whole volume goes into one output, so
+/// `write_volumes` and `write_separate` are ignored. Formats that can only be read from or written
+/// to a path return `Error::Unsupported`
+pub fn convert_bytes(
+    input: &[u8],
+    from: Option<Format>,
+    to: Format,
+    options: &RadyOptions,
+) -> Result<Vec<u8>, Error> {
+    let mut options = options.clone();
+    options.format = to;
+
+    let decoded = match from {
+        Some(format) => reader(format.name()).and_then(|reader| reader.decode(input, &options)),
+        None => formats::decode(input, &options),
+    };
+
+    let mut radar = decoded.ok_or_else(|| match from {
+        Some(format) => Error::Unsupported(format!("{} can't be read from memory", format)),
+        None => {
+            Error::Format("The input isn't in a format that can be read from memory".to_string())
+        }
+    })??;
+
+    radar.add_bias(&options.bias);
+    options.select_sweeps(&mut radar);
+    options.apply_options(&mut radar)?;
+
+    if options.azimuth_out == AzimuthRef::LeadingEdge {
+        radar
+            .sweeps
+            .iter_mut()
+            .filter(|sweep| !sweep.is_vertical())
+            .for_each(|sweep| sweep.shift_azimuths(0.5));
+    }
+
+    write_to_bytes(radar, to, &options)
+        .ok_or_else(|| Error::Unsupported(format!("{} can't be written to memory", to)))
+}
+
+/// Whether a file path is an http or https URL
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Time to wait for a download to connect, or for more of it to arrive, before giving up
+#[cfg(feature = "http")]
+const DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Largest file that is downloaded into memory, in bytes
+#[cfg(feature = "http")]
+const MAX_DOWNLOAD_SIZE: u64 = 1 << 30;
+
+/// Downloads a file given as a URL into memory
+#[cfg(feature = "http")]
+fn fetch(url: &str) -> Result<Vec<u8>, Error> {
+    eprintln!("Downloading {}", url);
+
+    let failed = |e: &dyn fmt::Display| {
+        Error::Io(std::io::Error::other(format!(
+            "Can't download {}: {}",
+            url, e
+        )))
+    };
+    let too_large = || failed(&format!("it is larger than {} bytes", MAX_DOWNLOAD_SIZE));
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(DOWNLOAD_TIMEOUT)
+        .timeout_read(DOWNLOAD_TIMEOUT)
+        .build();
+    let response = agent.get(url).call().map_err(|e| failed(&e))?;
+
+    if response
+        .header("Content-Length")
+        .and_then(|len| len.parse::<u64>().ok())
+        .is_some_and(|len| len > MAX_DOWNLOAD_SIZE)
+    {
+        return Err(too_large());
+    }
+
+    // The length can be missing or wrong, so the body is cut off just past the limit
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_DOWNLOAD_SIZE + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| failed(&e))?;
+
+    if bytes.len() as u64 > MAX_DOWNLOAD_SIZE {
+        return Err(too_large());
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch(url: &str) -> Result<Vec<u8>, Error> {
+    Err(Error::Io(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("This build can't download {}", url),
+    )))
+}
+
+/// Converts a file given as an http or https URL into the output folder of the working directory
+/// by default. Returns how many files failed, like `convert`
+fn convert_url(options: &RadyOptions) -> usize {
+    convert_from_memory(Path::new(&options.files), || fetch(&options.files), options)
+}
+
+/// Converts a single file read from stdin when `files` is `-`, and writes the converted bytes to
+/// stdout when `outdir` is `-`. Messages go to stderr, so stdout only has the output. Returns
+/// how many files failed, like `convert`
+fn convert_stream(options: &RadyOptions) -> usize {
+    let name = Path::new(&options.files);
+    let failures = Failures::default();
+
+    let input = || -> Result<Vec<u8>, Error> {
+        Ok(if options.files == "-" {
+            let mut input = Vec::new();
+            std::io::stdin().read_to_end(&mut input)?;
+            input
+        } else if is_url(&options.files) {
+            fetch(&options.files)?
+        } else {
+            std::fs::read(&options.files)?
+        })
+    };
+
+    // Input from stdin is named like any other file, into the output folder of the working
+    // directory by default
+    if options.outdir.as_deref() != Some("-") {
+        return convert_from_memory(name, input, options);
+    }
+
+    guard(name, &failures, || {
+        let written = convert_bytes(&input()?, None, options.format, options)?;
+
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&written)?;
+        stdout.flush()?;
+
+        Ok(())
+    });
+
+    failures.into_inner().unwrap().len()
+}
+
+/// Converts a file that is only held in memory, such as a download or stdin, into the output
+/// directory. Its format has to be readable from memory. Returns how many files failed, like
+/// `convert`
+fn convert_from_memory(
+    name: &Path,
+    input: impl FnOnce() -> Result<Vec<u8>, Error>,
+    options: &RadyOptions,
+) -> usize {
+    let out_path = PathBuf::from(options.outdir.as_deref().unwrap_or("output"));
+    let mut inventory = Inventory::default();
+    let failures = Failures::default();
+
+    guard(name, &failures, || {
+        let Some(mut radar) = read_from_bytes(&input()?, options)? else {
+            return Err(Error::Unsupported(format!(
+                "{} isn't in a format that can be read from memory",
+                name.display()
+            )));
+        };
+
+        if !options.in_time_range(&radar) {
+            return Ok(());
+        }
+
+        options.apply_options(&mut radar)?;
+
+        if (options.check && !passes_check(name, &radar)) || radar.sweeps.is_empty() {
+            return Ok(());
+        }
+
+        inventory.add(&radar);
+        write(radar, &out_path, options)
+    });
+
+    inventory.failed_files = failures.into_inner().unwrap().len();
+    finish_inventory(&inventory, &out_path);
+    inventory.failed_files
+}
+
+/// Records the spans of the readers, processing steps, and writers to a Chrome trace file, which
+/// can be opened in chrome://tracing or Perfetto. The trace is written when the guard is dropped
+pub fn trace_to(path: impl AsRef<Path>) -> tracing_chrome::FlushGuard {
+    use tracing_subscriber::prelude::*;
+
+    let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+        .file(path.as_ref())
+        .include_args(true)
+        .build();
+    tracing_subscriber::registry().with(layer).init();
+
+    guard
+}
+
+/// Files to convert: those matching `options.files` and those in the file list, or every file
+/// below the directories they match when recursive, other than the output directory. Files are
+/// then filtered by the include and exclude patterns
+fn input_files(options: &RadyOptions, out_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let in_path = Path::new(&options.files);
+
+    let mut matched: Vec<PathBuf> = if options.files.is_empty() || options.is_remote() {
+        remote_files(options)?
+    } else if in_path.is_file() {
+        vec![in_path.to_path_buf()]
+    } else {
+        glob(in_path.to_str().unwrap())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    };
+
+    if let Some(list) = &options.file_list {
+        matched.extend(listed_files(list)?);
+    }
+
+    matched.extend(options.extra_files.iter().map(PathBuf::from));
+
+    let mut files = Vec::new();
+    for path in matched {
+        if path.is_dir() {
+            if options.recursive {
+                files_below(&path, out_path, &mut files);
+            }
+        } else {
+            files.push(path);
+        }
+    }
+
+    files.retain(|file| is_included(file, options));
+    Ok(files)
+}
+
+/// Downloads the files of an `s3://` path, or of a site between the start and end times.
+/// Archive files named by the time of their volume are skipped without downloading them if they
+/// start outside the time range
+#[cfg(feature = "s3")]
+fn remote_files(options: &RadyOptions) -> Result<Vec<PathBuf>, Error> {
+    let (bucket, keys) = match (&options.site, s3::S3Path::parse(&options.files)) {
+        (Some(site), _) => {
+            let start = options.start.ok_or_else(|| {
+                Error::Unsupported(format!(
+                    "Downloading the files of {} needs a start time",
+                    site
+                ))
+            })?;
+            (
+                s3::NEXRAD_BUCKET.to_string(),
+                s3::site_keys(site, start, options.end.unwrap_or_else(Utc::now))?,
+            )
+        }
+        (None, Some(path)) => {
+            let in_range = |time: DateTime<Utc>| {
+                options.start.is_none_or(|start| time >= start)
+                    && options.end.is_none_or(|end| time <= end)
+            };
+            let keys = s3::list(&path.bucket, &path.prefix)?
+                .into_iter()
+                .filter(|key| !key.ends_with('/') && s3::key_time(key).is_none_or(in_range))
+                .collect();
+            (path.bucket, keys)
+        }
+        (None, None) => return Ok(Vec::new()),
+    };
+
+    keys.iter()
+        .map(|key| s3::download(&bucket, key, &s3::download_dir()))
+        .collect()
+}
+
+#[cfg(not(feature = "s3"))]
+fn remote_files(options: &RadyOptions) -> Result<Vec<PathBuf>, Error> {
+    if options.is_remote() {
+        return Err(Error::Unsupported(
+            "This build can't download files from S3".to_string(),
+        ));
+    }
+
+    Ok(Vec::new())
+}
+
+/// Paths in a file list, one per line, skipping blank lines. `-` reads the list from stdin
+fn listed_files(list: &str) -> Result<Vec<PathBuf>, Error> {
+    let text = if list == "-" {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        std::fs::read_to_string(list)
+    }
+    .map_err(|e| {
+        Error::Io(std::io::Error::other(format!(
+            "Can't read file list {}: {}",
+            list, e
+        )))
+    })?;
+
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Whether a file passes the include and exclude patterns, by its name or its path
+fn is_included(file: &Path, options: &RadyOptions) -> bool {
+    let name = file.file_name().map(Path::new).unwrap_or(file);
+    let matches = |patterns: &[glob::Pattern]| {
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches_path(name) || pattern.matches_path(file))
+    };
+
+    (options.include.is_empty() || matches(&options.include)) && !matches(&options.exclude)
+}
+
+/// Adds every file below a directory in sorted order, skipping the output directory
+fn files_below(dir: &Path, out_path: &Path, files: &mut Vec<PathBuf>) {
+    if dir == out_path {
+        return;
+    }
+
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            files_below(&entry, out_path, files);
+        } else {
+            files.push(entry);
+        }
+    }
+}
+
+/// Validates a radar, reporting its problems if it has any
+fn passes_check(file: &Path, radar: &RadarFile) -> bool {
+    let issues = radar.validate();
+
+    if !issues.is_empty() {
+        eprintln!("Skipping {}:", file.display());
+        issues.iter().for_each(|issue| eprintln!("  {}", issue));
+    }
+
+    issues.is_empty()
+}
+
+/// Input files that failed to convert
+type Failures = Mutex<BTreeSet<PathBuf>>;
+
+/// Runs a step of converting a file, reporting an error as a failure of the file so that the rest
+/// of the files are still converted. Panics are bugs rather than problems with the file, but are
+/// caught as a last resort after the panic hook has reported them, where unwinding is available
+fn guard<T>(
+    file: &Path,
+    failures: &Failures,
+    step: impl FnOnce() -> Result<T, Error>,
+) -> Option<T> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(step)) {
+        Ok(Ok(value)) => return Some(value),
+        Ok(Err(e)) => eprintln!("Error: {}: {}", file.display(), e),
+        Err(_) => (),
+    }
+
+    eprintln!("Failed to convert {}", file.display());
+    failures.lock().unwrap().insert(file.to_path_buf());
+    None
+}
+
+/// Reports the skipped and failed files and writes the inventory of a conversion
+fn finish_inventory(inventory: &Inventory, out_path: &Path) {
+    if inventory.skipped_files > 0 {
+        eprintln!(
+            "Skipped {} files that aren't radar data",
+            inventory.skipped_files
+        );
+    }
+
+    if inventory.failed_files > 0 {
+        eprintln!("{} files failed to convert", inventory.failed_files);
+    }
+
+    if inventory.volumes > 0 {
+        inventory.write(out_path);
+    }
+}
+
+/// Runs work that uses rayon on a pool of `jobs` threads, or on the pool it is already running on
+/// when files are converted in parallel
+fn in_pool<T: Send>(jobs: usize, work: impl FnOnce() -> T + Send) -> T {
+    if rayon::current_thread_index().is_some() {
+        return work();
+    }
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .unwrap()
+        .install(work)
+}
+
+/// Reads, processes, and writes files that are each written on their own, `options.jobs` at a
+/// time
+fn convert_parallel(files: &[PathBuf], out_path: &Path, options: &RadyOptions) -> Inventory {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs)
+        .build()
+        .unwrap();
+    let inventory = Mutex::new(Inventory::default());
+    let failures = Failures::default();
+
+    pool.install(|| {
+        files.par_iter().for_each(|file| {
+            guard(file, &failures, || {
+                let Some(mut radar) = try_read(file, options)? else {
+                    eprintln!("Skipping {}: not radar data", file.display());
+                    inventory.lock().unwrap().skipped_files += 1;
+                    return Ok(());
+                };
+
+                if !options.in_time_range(&radar) {
+                    return Ok(());
+                }
+
+                options.apply_options(&mut radar)?;
+
+                if (options.check && !passes_check(file, &radar)) || radar.sweeps.is_empty() {
+                    return Ok(());
+                }
+
+                inventory.lock().unwrap().add(&radar);
+                write(radar, out_path, options)
+            });
+        })
+    });
+
+    let mut inventory = inventory.into_inner().unwrap();
+    inventory.failed_files = failures.into_inner().unwrap().len();
+    inventory
+}
+
+/// Volumes of the real-time Level II feed being assembled, by the directory and start time in
+/// the names of their chunks
+#[derive(Default)]
+struct ChunkFeed {
+    volumes: HashMap<PathBuf, FeedVolume>,
+}
+
+/// A volume being assembled, with the chunks that arrived before the ones that precede them
+#[derive(Default)]
+struct FeedVolume {
+    volume: nexrad::ChunkedVolume,
+
+    /// Number of the next chunk to add. Chunks are numbered from 1
+    next: u32,
+
+    /// Chunks waiting for the ones before them, and whether each ends the volume
+    waiting: BTreeMap<u32, (PathBuf, bool)>,
+}
+
+/// Volume, number, and whether it ends the volume, of a chunk of the real-time feed named like
+/// `20220504-233000-001-S`. The volume is the directory joined with the start time
+fn parse_chunk_name(file: &Path) -> Option<(PathBuf, u32, bool)> {
+    let name = file.file_name()?.to_str()?;
+    let parts: Vec<&str> = name.split('-').collect();
+
+    let [date, time, number, kind] = parts[..] else {
+        return None;
+    };
+
+    if !matches!(kind, "S" | "I" | "E") {
+        return None;
+    }
+
+    let volume = file
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(format!("{}-{}", date, time));
+    Some((volume, number.parse().ok()?, kind == "E"))
+}
+
+impl ChunkFeed {
+    /// Adds a chunk file, along with any waiting chunks that it lets through, and returns the
+    /// sweeps that they completed
+    fn add(&mut self, file: &Path, options: &RadyOptions) -> Result<Vec<RadarFile>, Error> {
+        let Some((key, number, last)) = parse_chunk_name(file) else {
+            return Err(Error::Format(format!(
+                "{} isn't named like a chunk of the real-time feed",
+                file.display()
+            )));
+        };
+
+        let feed = self
+            .volumes
+            .entry(key.clone())
+            .or_insert_with(|| FeedVolume {
+                next: 1,
+                ..Default::default()
+            });
+        feed.waiting.insert(number, (file.to_path_buf(), last));
+
+        let mut completed = Vec::new();
+        while let Some((file, last)) = feed.waiting.remove(&feed.next) {
+            completed.push(feed.volume.push(&std::fs::read(&file)?, options)?);
+            feed.next += 1;
+
+            if last {
+                self.volumes.remove(&key);
+                break;
+            }
+        }
+
+        Ok(completed)
+    }
+}
+
+/// Adds a chunk of the real-time feed, writing each sweep that it completes to its own file
+fn convert_chunk(
+    feed: &mut ChunkFeed,
+    file: &Path,
+    out_path: &Path,
+    options: &RadyOptions,
+    inventory: &mut Inventory,
+) -> Result<(), Error> {
+    if parse_chunk_name(file).is_some_and(|(_, number, _)| number == 1) {
+        inventory.volumes += 1;
+    }
+
+    for mut radar in feed.add(file, options)? {
+        if !options.in_time_range(&radar) {
+            continue;
+        }
+
+        options.apply_options(&mut radar)?;
+        inventory.add_sweeps(&radar);
+
+        for sweep in std::mem::take(&mut radar.sweeps) {
+            eprintln!(
+                "Writing the {} degree sweep of {}",
+                sweep.elevation, radar.name
+            );
+            write(
+                RadarFile {
+                    sweeps: vec![sweep],
+                    ..radar.clone()
+                },
+                out_path,
+                options,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Time a file in a watched directory must go without changing before it is converted, so files
+/// that are still being written aren't read
+#[cfg(feature = "watch")]
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Converts files as they are created or changed in a directory, each on its own, until the
+/// process is stopped. The inventory is rewritten after every file
+#[cfg(feature = "watch")]
+pub fn watch(dir: &Path, options: &RadyOptions) {
+    let out_path = options
+        .outdir
+        .as_ref()
+        .map_or_else(|| dir.join("output"), PathBuf::from);
+    std::fs::create_dir_all(&out_path).unwrap();
+    let out_path = out_path.canonicalize().unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut debouncer = notify_debouncer_mini::new_debouncer(WATCH_DEBOUNCE, tx).unwrap();
+    let mode = if options.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    debouncer.watcher().watch(dir, mode).unwrap();
+
+    eprintln!("Watching {} for new files", dir.display());
+    let mut inventory = Inventory::default();
+    let mut feed = ChunkFeed::default();
+    let failures = Failures::default();
+
+    for events in rx {
+        let mut events = match events {
+            Ok(events) => events,
+            Err(error) => {
+                eprintln!("Error watching {}: {}", dir.display(), error);
+                continue;
+            }
+        };
+
+        // Chunks of the real-time feed that arrive together are added in order
+        events.sort_by(|a, b| a.path.cmp(&b.path));
+
+        for event in events {
+            let Ok(file) = event.path.canonicalize() else {
+                continue;
+            };
+
+            if !file.is_file() || file.starts_with(&out_path) || !is_included(&file, options) {
+                continue;
+            }
+
+            if options.realtime {
+                if guard(&file, &failures, || {
+                    convert_chunk(&mut feed, &file, &out_path, options, &mut inventory)
+                })
+                .is_some()
+                {
+                    inventory.write(&out_path);
+                }
+
+                continue;
+            }
+
+            let read = guard(&file, &failures, || {
+                let Some(mut radar) = try_read(&file, options)? else {
+                    eprintln!("Skipping {}: not radar data", file.display());
+                    return Ok(None);
+                };
+
+                if !options.in_time_range(&radar) {
+                    return Ok(None);
+                }
+
+                options.apply_options(&mut radar)?;
+                Ok(Some(radar))
+            });
+
+            let Some(radar) = read.flatten() else {
+                continue;
+            };
+
+            if (options.check && !passes_check(&file, &radar)) || radar.sweeps.is_empty() {
+                continue;
+            }
+
+            eprintln!("Converting {}", file.display());
+            inventory.add(&radar);
+
+            if guard(&file, &failures, || write(radar, &out_path, options)).is_some() {
+                inventory.write(&out_path);
+            }
+        }
+    }
+}
+
+/// Number of radars that can wait between each stage of a conversion
+const PIPELINE_DEPTH: usize = 2;
+
+/// Writes sweeps as they arrive in low-memory mode. Formats that can append to an open file get
+/// one file per input file, and other formats a file per sweep
+struct SweepWriter<'a> {
+    path: &'a Path,
+    options: &'a RadyOptions,
+
+    /// Input file whose volume is open, and the volume
+    open: Option<(PathBuf, Box<dyn SweepSink>)>,
+}
+
+impl<'a> SweepWriter<'a> {
+    fn new(path: &'a Path, options: &'a RadyOptions) -> Self {
+        SweepWriter {
+            path,
+            options,
+            open: None,
+        }
+    }
+
+    /// Writes the sweeps of a piece of an input file
+    fn push(
+        &mut self,
+        file: PathBuf,
+        mut radar: RadarFile,
+        index: Option<&mut VolumeIndex>,
+    ) -> Result<(), Error> {
+        if self.open.as_ref().is_some_and(|(open, _)| *open != file) {
+            self.finish()?;
+        }
+
+        if self.open.is_none() && !self.options.write_separate {
+            let header = RadarFile {
+                sweeps: Vec::new(),
+                ..radar.clone()
+            };
+            self.open = writer(self.options.format.name())
+                .and_then(|writer| writer.stream(header, self.path, self.options))
+                .map(|sink| (file, sink));
+        }
+
+        let Some((_, sink)) = self.open.as_mut() else {
+            return write_tilts(radar, self.path, self.options, index);
+        };
+
+        radar.sort_sweeps_by_time();
+
+        for mut sweep in std::mem::take(&mut radar.sweeps) {
+            if self.options.azimuth_out == AzimuthRef::LeadingEdge && !sweep.is_vertical() {
+                sweep.shift_azimuths(0.5);
+            }
+
+            sink.push(sweep, &radar.params)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes the open volume
+    fn finish(&mut self) -> Result<(), Error> {
+        match self.open.take() {
+            Some((_, sink)) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Converts the input files, returning how many failed. A file that fails is reported and the
+/// rest are still converted. A run whose files can't be found, listed, or downloaded is reported
+/// as a single failure
+pub fn convert(options: &RadyOptions) -> usize {
+    if options.files == "-" || options.outdir.as_deref() == Some("-") {
+        return convert_stream(options);
+    }
+
+    if is_url(&options.files) {
+        return convert_url(options);
+    }
+
+    // Without a local file path, the output goes next to the file list, or into the working
+    // directory for downloaded files
+    let in_path = match &options.file_list {
+        _ if options.is_remote() => Path::new("./"),
+        Some(list) if options.files.is_empty() => Path::new(if list == "-" { "./" } else { list }),
+        _ => Path::new(&options.files),
+    };
+
+    let mut out_path = match &options.outdir {
+        Some(outdir) => Path::new(outdir),
+        None => in_path.parent().unwrap(),
+    }
+    .to_path_buf();
+
+    if options.outdir.is_none() {
+        out_path.push("output");
+    }
+
+    if !out_path.is_dir() && out_path.exists() {
+        eprintln!("Output path {} is not a directory", out_path.display());
+        return 1;
+    }
+
+    let files = match input_files(options, &out_path) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    if files.is_empty() {
+        match &options.file_list {
+            _ if options.is_remote() => eprintln!("No files were found to download"),
+            Some(list) if options.files.is_empty() => {
+                eprintln!("File list {} doesn't have any files", list)
+            }
+            _ => eprintln!("Path: {:?} does not exist or have any files", in_path),
+        }
+
+        return 1;
+    }
+
+    if options.realtime {
+        let mut inventory = Inventory::default();
+        let mut feed = ChunkFeed::default();
+        let failures = Failures::default();
+
+        for file in &files {
+            guard(file, &failures, || {
+                convert_chunk(&mut feed, file, &out_path, options, &mut inventory)
+            });
+        }
+
+        for (volume, waiting) in &feed.volumes {
+            eprintln!(
+                "{} didn't end, and is missing chunk {}",
+                volume.display(),
+                waiting.next
+            );
+        }
+
+        inventory.failed_files = failures.into_inner().unwrap().len();
+        finish_inventory(&inventory, &out_path);
+        return inventory.failed_files;
+    }
+
+    // Files that are written on their own don't depend on each other, so they can be converted
+    // in any order
+    let independent = !(options.write_volumes
+        || options.aggregate
+        || options.low_memory
+        || (options.write_separate && options.index));
+
+    // Verification finds what was written by listing the output directory, which other files
+    // written at the same time would confuse
+    if options.jobs > 1 && independent && !options.verify {
+        let inventory = convert_parallel(&files, &out_path, options);
+        finish_inventory(&inventory, &out_path);
+        return inventory.failed_files;
+    }
+
+    if options.jobs > 1 {
+        eprintln!("Converting one file at a time, since files are combined in the output");
+    }
+
+    let mut radars = Vec::new();
+    let mut inventory = Inventory::default();
+    let failures = Failures::default();
+    let failures = &failures;
+
+    // Files are decoded, checked, and written on separate threads, so writing a volume overlaps
+    // decoding the next files. The channels are bounded to keep a few volumes in memory at most.
+    // In low-memory mode, files are passed along a sweep at a time with nothing waiting
+    let low_memory = options.low_memory && !options.write_volumes && !options.aggregate;
+    let depth = if low_memory { 0 } else { PIPELINE_DEPTH };
+    let (read_tx, read_rx) = sync_channel::<(PathBuf, RadarFile)>(depth);
+    let (write_tx, write_rx) = sync_channel::<(PathBuf, RadarFile)>(depth);
+
+    std::thread::scope(|scope| {
+        let reader = scope.spawn(move || {
+            let mut skipped = 0;
+
+            for file in files {
+                let Some(read) = guard(&file, failures, || try_read(&file, options)) else {
+                    continue;
+                };
+
+                let Some(mut radar) = read else {
+                    eprintln!("Skipping {}: not radar data", file.display());
+                    skipped += 1;
+                    continue;
+                };
+
+                if !options.in_time_range(&radar) {
+                    continue;
+                }
+
+                if low_memory {
+                    for sweep in std::mem::take(&mut radar.sweeps) {
+                        let mut piece = RadarFile {
+                            sweeps: vec![sweep],
+                            ..radar.clone()
+                        };
+
+                        let applied = guard(&file, failures, || options.apply_options(&mut piece));
+
+                        if applied.is_none() {
+                            continue;
+                        }
+
+                        if read_tx.send((file.clone(), piece)).is_err() {
+                            return skipped;
+                        }
+                    }
+
+                    continue;
+                }
+
+                let applied = guard(&file, failures, || options.apply_options(&mut radar));
+
+                if applied.is_none() {
+                    continue;
+                }
+
+                if read_tx.send((file, radar)).is_err() {
+                    break;
+                }
+            }
+
+            skipped
+        });
+
+        let out_path = &out_path;
+        scope.spawn(move || {
+            // Volumes can span several input files, so they are assembled across the whole run
+            let mut volumes = (options.write_volumes && !options.aggregate)
+                .then(|| VolumeStream::new(out_path, options));
+            let mut index =
+                (options.write_separate && options.index).then(|| VolumeIndex::new(options));
+            let mut sweeps = SweepWriter::new(out_path, options);
+
+            let mut last = None;
+
+            for (file, radar) in write_rx {
+                let path = file.clone();
+
+                guard(&path, failures, || match (&mut volumes, &mut index) {
+                    (Some(volumes), _) => volumes.push(radar),
+                    (None, index) if low_memory => sweeps.push(file, radar, index.as_mut()),
+                    (None, Some(index)) => write_tilts(radar, out_path, options, Some(index)),
+                    (None, None) => write(radar, out_path, options),
+                });
+
+                last = Some(path);
+            }
+
+            // The volume still open is finished after the last file written into it
+            if let Some(last) = last {
+                guard(&last, failures, || {
+                    sweeps.finish()?;
+                    volumes.map_or(Ok(()), VolumeStream::finish)
+                });
+            }
+
+            if let Some(index) = index {
+                index.write(out_path);
+            }
+        });
+
+        // Pieces of the same file are sweeps of one volume
+        let mut last_file = None;
+
+        for (file, radar) in read_rx {
+            if options.check && !passes_check(&file, &radar) {
+                continue;
+            }
+
+            if radar.sweeps.is_empty() {
+                continue;
+            }
+
+            if last_file.as_ref() == Some(&file) {
+                inventory.add_sweeps(&radar);
+            } else {
+                inventory.add(&radar);
+                last_file = Some(file.clone());
+            }
+
+            if options.aggregate {
+                radars.push(radar);
+            } else if write_tx.send((file, radar)).is_err() {
+                // The writer stopped, and its panic is raised when the scope ends
+                break;
+            }
+        }
+
+        drop(write_tx);
+
+        inventory.skipped_files = reader.join().unwrap();
+    });
+
+    // The aggregate combines every file, so a failure to write it is reported for the output
+    if options.aggregate {
+        guard(&out_path, failures, || {
+            write_aggregate(radars, &out_path, options)
+        });
+    }
+
+    inventory.failed_files = failures.lock().unwrap().len();
+    finish_inventory(&inventory, &out_path);
+
+    inventory.failed_files
+}
+
+/// Parses a UTC time given as RFC 3339, or as a date with an optional time such as
+/// `2013-05-31 23:10`
+fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    for format in [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ] {
+        if let Ok(time) = chrono::NaiveDateTime::parse_from_str(s, format) {
+            return Ok(time.and_utc());
+        }
+    }
+
+    chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| {
+            format!(
+                "Invalid time {}, expected a time such as 2013-05-31 23:10",
+                s
+            )
+        })
+}
+
+/// Escapes text for a roff man page
+fn roff_escape(s: &str) -> String {
+    let escaped = s.replace('\\', "\\e").replace('-', "\\-");
+
+    // Lines starting with these would be read as requests
+    if escaped.starts_with(['.', '\'']) {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// Man page of the command line, in roff
+pub fn man_page() -> String {
+    let mut app = app();
+    app.build();
+
+    let mut page = format!(
+        ".TH SILV 1 \"\" \"silv {}\"\n",
+        app.get_version().unwrap_or_default()
+    );
+
+    page += ".SH NAME\nsilv \\- converts and processes weather radar files\n";
+    page += ".SH SYNOPSIS\n\\fBsilv\\fR [\\fIOPTIONS\\fR] \\fB\\-\\-file\\fR \\fIPATH\\fR\n.br\n\\fBsilv\\fR \\fISUBCOMMAND\\fR [\\fIOPTIONS\\fR]\n";
+    page += ".SH OPTIONS\n";
+
+    for arg in app.get_arguments().filter(|arg| !arg.is_hide_set()) {
+        let mut names: Vec<String> = arg
+            .get_short()
+            .map(|short| format!("\\fB\\-{}\\fR", short))
+            .into_iter()
+            .collect();
+        names.extend(
+            arg.get_long()
+                .map(|long| format!("\\fB\\-\\-{}\\fR", roff_escape(long))),
+        );
+
+        let value = if arg.is_takes_value_set() {
+            format!(
+                " \\fI{}\\fR",
+                roff_escape(&arg.get_id().to_uppercase().replace(' ', "_"))
+            )
+        } else {
+            String::new()
+        };
+        page += &format!(
+            ".TP\n{}{}\n{}\n",
+            names.join(", "),
+            value,
+            roff_escape(arg.get_help().unwrap_or_default())
+        );
+    }
+
+    page += ".SH SUBCOMMANDS\n";
+    for subcommand in app
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+    {
+        page += &format!(
+            ".TP\n\\fB{}\\fR\n{}\n",
+            roff_escape(subcommand.get_name()),
+            roff_escape(subcommand.get_about().unwrap_or_default())
+        );
     }
 
-    /// Sorts all sweeps by elevation
-    pub fn sort_sweeps_by_elevation(&mut self) {
-        self.sweeps.sort_by(|s1, s2| s1.elevation.partial_cmp(&s2.elevation).unwrap());
-    }
+    page
+}
 
-    /// Deletes excess rays in each sweep
-    pub fn trim_rays(&mut self) {
-        for sweep in &mut self.sweeps {
-            sweep.trim_rays();
-        }
-    }
+/// Command line interface
+pub fn app() -> App<'static> {
+    App::new("RadyConvert")
+        .version("0.0.1")
+        .setting(AppSettings::AllowNegativeNumbers)
+        .arg(Arg::new("format").global(true).short('F').long("format").takes_value(true).help("Converts to the specified format")
+            .possible_values(["nexrad", "cfradial", "cfradial_fields"]).ignore_case(true))
+        .arg(Arg::new("override radar").global(true).short('R').long("radar").takes_value(true).help("Overrides the output radar"))
+        .arg(Arg::new("write volumes").global(true).long("vols").help("Aggregates sweeps into volumes and writes them separately."))
+        .arg(Arg::new("volume step").global(true).long("volume-step").takes_value(true).help("Degrees that a sweep must tilt past the last one to continue its volume, with --vols or --index. Default is 0.1"))
+        .arg(Arg::new("volume gap").global(true).long("volume-gap").takes_value(true).help("Starts a new volume when a sweep starts more than this many seconds after the last one, with --vols or --index"))
+        .arg(Arg::new("check").global(true).long("check").help("Validates each file before writing it, reporting and skipping files with problems"))
+        .arg(Arg::new("print products").global(true).short('P').long("print_p").help("Prints all of the file products and exit"))
+        .arg(Arg::new("files").global(true).short('f').long("file").takes_value(true).help("Adds a file path to read. To select all files in a directory, use the * wildcard at the end. Use - to read a file from stdin, an http or https URL to download a file, or s3://bucket/prefix to download every file under a prefix"))
+        .arg(Arg::new("recursive").global(true).short('r').long("recursive").help("Reads every file below the directories given with --file"))
+        .arg(Arg::new("include").global(true).long("include").takes_value(true).multiple_occurrences(true).help("Only reads files whose name or path matches a glob pattern, such as \"*.nc\". Can be given more than once"))
+        .arg(Arg::new("file list").global(true).long("file-list").takes_value(true).help("Reads files listed in a file, one path per line, along with those from --file. Use - to read the list from stdin"))
+        .arg(Arg::new("watch").global(true).short('w').long("watch").takes_value(true).help("Converts files as they appear in a directory, once they stop changing, until stopped. Use --recursive to watch its subdirectories too"))
+        .arg(Arg::new("realtime").global(true).long("realtime").help("Reads the files as chunks of the real-time NEXRAD Level II feed, named like 20220504-233000-001-S, and writes each sweep once it completes"))
+        .arg(Arg::new("exclude").global(true).long("exclude").takes_value(true).multiple_occurrences(true).help("Skips files whose name or path matches a glob pattern, such as \"*.txt\". Can be given more than once"))
+        .arg(Arg::new("location").global(true).short('l').long("location").help("Prints the location in lat, long for each sweep"))
+        .arg(Arg::new("georeference").global(true).long("georeference").help("Computes earth-relative azimuths and elevations of airborne DORADE rays, such as from ELDORA or the P-3 tail radar, from the aircraft attitude and correction factors"))
+        .arg(Arg::new("apply corrections").global(true).long("apply-corrections").help("Adds the correction factors recorded in DORADE files to the azimuths, elevations, gate ranges, and radar positions. The info subcommand lists them"))
+        .arg(Arg::new("outdir").global(true).short('o').long("outdir").takes_value(true).help("Sets the directory to make the output folder in. Default is the same as the input. Use - to write a single file to stdout"))
+        .arg(Arg::new("azimuth in").global(true).long("azimuth_in").takes_value(true).help("Where the input azimuths are measured in the beam")
+            .possible_values(["center", "leading"]).ignore_case(true))
+        .arg(Arg::new("azimuth out").global(true).long("azimuth_out").takes_value(true).help("Where the output azimuths are measured in the beam")
+            .possible_values(["center", "leading"]).ignore_case(true))
+        .arg(Arg::new("time height").global(true).long("time_height").help("Also writes vertically pointing sweeps as a time-height series"))
+        .arg(Arg::new("ref units").global(true).long("ref-units").takes_value(true).help("Units of the input reflectivity, detected by default")
+            .possible_values(["auto", "dbz", "linear"]).ignore_case(true))
+        .arg(Arg::new("flip velocity").global(true).long("flip-velocity").help("Negates velocities from sources that use positive toward the radar"))
+        .arg(Arg::new("dealias").global(true).long("dealias").takes_value(true).help("Unfolds aliased velocities with the given method")
+            .possible_values(["region", "sounding"]).ignore_case(true))
+        .arg(Arg::new("despeckle").global(true).long("despeckle").takes_value(true).help("Removes runs of echo shorter than this many gates along each ray"))
+        .arg(Arg::new("despeckle area").global(true).long("despeckle-area").takes_value(true).help("Removes connected areas of echo with fewer than this many gates"))
+        .arg(Arg::new("trim").global(true).long("trim").help("Trims rays past a full rotation from the end of each sweep"))
+        .arg(Arg::new("split overlap").global(true).long("split-overlap").help("Splits sweeps with more than a full rotation into separate sweeps"))
+        .arg(Arg::new("dedup rays").global(true).long("dedup-rays").help("Removes rays at nearly the same azimuth as another in their sweep, keeping the one with the most gates with data, or else the latest"))
+        .arg(Arg::new("sort").global(true).long("sort").help("Sorts the rays of each sweep by azimuth. This is the default"))
+        .arg(Arg::new("split by").global(true).long("split-by").takes_value(true).possible_values(["tilt", "volume"]).conflicts_with("write volumes").help("Writes each tilt or each volume to its own file"))
+        .arg(Arg::new("index").global(true).long("index").requires("split by").help("Writes INDEX.json grouping the files of each tilt into volumes, with their times and angles"))
+        .arg(Arg::new("no sort").global(true).long("no-sort").conflicts_with("sort").help("Keeps the rays of each sweep in the order they were recorded"))
+        .arg(Arg::new("min rays").global(true).long("min-rays").takes_value(true).help("Drops pieces of split sweeps with fewer rays than this"))
+        .arg(Arg::new("qc").global(true).long("qc").takes_value(true).help("Censors every field at gates that fail a comma separated list of thresholds, such as rho>0.85,snr>3"))
+        .arg(Arg::new("filter").global(true).long("filter").takes_value(true).multiple_occurrences(true).allow_hyphen_values(true).help("Censors every field at gates where an expression is true, such as \"REF < 5 || RHO < 0.8\". Can be given more than once"))
+        .arg(Arg::new("correct attenuation").global(true).long("correct-attenuation").help("Corrects REF and ZDR for rain attenuation using differential phase"))
+        .arg(Arg::new("rain rate").global(true).long("rain-rate").takes_value(true).min_values(0).help("Adds a RATE field from reflectivity with a Z-R relation: marshall-palmer (default), convective, tropical, or a,b"))
+        .arg(Arg::new("process phidp").global(true).long("process-phidp").takes_value(true).min_values(0).help("Unfolds PHI and removes the system phase, then smooths it with a median of this many gates. Default is no smoothing"))
+        .arg(Arg::new("smooth").global(true).long("smooth").takes_value(true).help("Smooths fields before deriving others, with a comma separated list of field:median|boxcar:gates[xrays], such as phi:median:5,vel:boxcar:3x3"))
+        .arg(Arg::new("texture").global(true).long("texture").takes_value(true).help("Adds the standard deviation of fields over a window, such as vel or ref:5x3 for 5 gates by 3 rays. Default window is 3x3"))
+        .arg(Arg::new("storm motion").global(true).long("storm-motion").takes_value(true).allow_hyphen_values(true).help("Adds an SRV field relative to a storm moving with velocity u,v in m/s toward the east and north"))
+        .arg(Arg::new("derive").global(true).long("derive").takes_value(true).multiple_occurrences(true).allow_hyphen_values(true).help("Adds a field computed at each gate from the others, such as \"VEL_KTS = VEL * 1.944\" or \"DBZ_LIN = 10^(REF/10)\". Can be given more than once"))
+        .arg(Arg::new("low memory").global(true).long("low-memory").conflicts_with_all(&["write volumes", "cappi", "time height"]).help("Processes and writes one sweep at a time, for devices with little memory. Formats that can't append to a file get a file per sweep"))
+        .arg(Arg::new("cappi").global(true).long("cappi").takes_value(true).help("Replaces the sweeps with constant altitude PPIs at a comma separated list of heights in meters above the radar"))
+        .arg(Arg::new("cappi fields").global(true).long("cappi-fields").takes_value(true).requires("cappi").help("Comma separated fields to interpolate to the constant altitude PPIs. Default is REF"))
+        .arg(Arg::new("echo tops").global(true).long("echo-tops").takes_value(true).min_values(0).conflicts_with("cappi").help("Replaces the sweeps with an ETH pseudo-sweep of the highest altitude reflectivity reaches, 18 dBZ by default"))
+        .arg(Arg::new("polarimetric rain").global(true).long("polarimetric-rain").help("Adds a RATE field from KDP and ZDR where they are reliable, and the Z-R relation elsewhere"))
+        .arg(Arg::new("min range").global(true).long("min-range").takes_value(true).help("Blanks gates closer than this range in meters, such as the blind zone"))
+        .arg(Arg::new("max range").global(true).long("max-range").takes_value(true).help("Drops gates farther than this range in meters, such as unreliable far gates"))
+        .arg(Arg::new("gate spacing").global(true).long("gate-spacing").takes_value(true).help("Changes the gate spacing to this many meters, averaging gates to coarsen them or taking the nearest gate to refine them"))
+        .arg(Arg::new("max height").global(true).long("max-height").takes_value(true).help("Blanks gates higher than this many meters above the radar"))
+        .arg(Arg::new("sounding").global(true).long("sounding").takes_value(true).help("Dealiases with a wind profile from a text or CSV file of height (m), direction and speed (m/s)"))
+        .arg(Arg::new("keep calibration").global(true).long("keep-calibration").help("Keeps idle and calibration sweeps instead of dropping them"))
+        .arg(Arg::new("drop transitions").global(true).long("drop-transitions").help("Drops rays taken while the antenna moves between sweeps, as flagged by the file or, for NEXRAD, far from the fixed angle of the cut"))
+        .arg(Arg::new("merge split cuts").global(true).long("merge-split-cuts").help("Combines the surveillance and Doppler sweeps of NEXRAD split cuts into single sweeps with all moments"))
+        .arg(Arg::new("bias").global(true).long("bias").takes_value(true).allow_hyphen_values(true).help("Adds a correction to every value of fields as they are read, as a comma separated list such as zdr=-0.3,ref=1.5"))
+        .arg(Arg::new("zdr offset").global(true).long("zdr-offset").takes_value(true).allow_hyphen_values(true).help("Removes a known ZDR bias in dB, subtracting it from every ZDR value"))
+        .arg(Arg::new("fields").global(true).long("fields").takes_value(true).help("Only reads and writes a comma separated list of fields, such as REF,VEL,ZDR. Fields used by processing options must be listed too"))
+        .arg(Arg::new("exclude fields").global(true).long("exclude-fields").takes_value(true).help("Skips a comma separated list of fields when reading, so they aren't written"))
+        .arg(Arg::new("sweeps").global(true).long("sweeps").takes_value(true).help("Only converts the sweeps at a comma separated list of positions in each file, counting from 0"))
+        .arg(Arg::new("elevations").global(true).long("elevations").takes_value(true).allow_hyphen_values(true).help("Only converts sweeps at a comma separated list of fixed angles in degrees"))
+        .arg(Arg::new("elevation tolerance").global(true).long("elevation-tolerance").takes_value(true).help("Degrees a sweep can be from a selected elevation. Default is 0.2"))
+        .arg(Arg::new("start").global(true).long("start").takes_value(true).help("Skips files that end before a UTC time, such as \"2013-05-31 23:00\" or 2013-05-31T23:00:00Z"))
+        .arg(Arg::new("site").global(true).long("site").takes_value(true).requires("start").help("Downloads the NEXRAD archive files of a site, such as KTLX, that start between --start and --end (or now) from the NOAA bucket on S3"))
+        .arg(Arg::new("end").global(true).long("end").takes_value(true).help("Skips files that start after a UTC time, such as \"2013-06-01 00:00\""))
+        .arg(Arg::new("verify").global(true).long("verify").conflicts_with("jobs").help("Reads back each file after writing it, reporting sweeps, rays, and values that changed by more than the precision of the format"))
+        .arg(Arg::new("naming").global(true).long("naming").takes_value(true).possible_values(["short", "pyart", "cf", "opera"]).ignore_case(true).help("Names fields in CfRadial files with short names such as DBZ (the default), Py-ART names such as reflectivity, CF standard names, or OPERA quantities such as DBZH"))
+        .arg(Arg::new("overwrite").global(true).long("overwrite").conflicts_with_all(&["skip existing", "suffix on conflict"]).help("Replaces output files that already exist"))
+        .arg(Arg::new("skip existing").global(true).long("skip-existing").conflicts_with("suffix on conflict").help("Doesn't write output files that already exist"))
+        .arg(Arg::new("suffix on conflict").global(true).long("suffix-on-conflict").help("Adds a numbered suffix to output files that already exist. This is the default"))
+        .arg(Arg::new("field map").global(true).long("field-map").takes_value(true).help("Maps moment names to generic fields, from a TOML or JSON file of name = \"FIELD\" pairs"))
+        .arg(Arg::new("smooth azimuths").global(true).long("smooth_azimuths").help("Fits azimuths to a constant scan rate, writing the recorded azimuths alongside"))
+        .arg(Arg::new("jobs").global(true).short('j').long("jobs").takes_value(true).help("Converts this many files at once when each is written on its own, rather than into volumes, an aggregate, an index, or a sweep at a time. The sweeps of each NEXRAD file are also decoded on this many threads"))
+        .arg(Arg::new("trace output").global(true).long("trace-output").takes_value(true).help("Writes a Chrome trace of where the conversion spends its time to this file"))
+        .arg(Arg::new("name format").global(true).long("name").takes_value(true).help("Creates files with a given name, which can include directories. Available codes are from the \"chrono\" library, along with [icao] for the radar and [vol] for the volume number"))
+        .subcommand(App::new("aggregate").about("Appends every volume into a single CfRadial file along time"))
+        .subcommand(App::new("radx").about("Converts with LROSE RadxConvert arguments, such as -f files... -outdir dir -ag, into yyyymmdd/ directories with _v<volume> names")
+            .arg(Arg::new("args").multiple_values(true).allow_hyphen_values(true)))
+        .subcommand(App::new("serve").about("Runs as an ingest service that converts files as they appear in the input directories of a TOML configuration, writes them to each of its outputs, and answers status and stop commands on its control address")
+            .arg(Arg::new("config").long("config").takes_value(true).required(true).help("TOML file with the control address and the [[input]] and [[output]] directories"))
+            .arg(Arg::new("status").long("status").help("Prints the status of the running service instead of starting it")))
+        .subcommand(App::new("selftest").about("Decodes and round-trips built-in samples of each format, to check that this build works"))
+        .subcommand(App::new("info").about("Prints the radar, sweeps, and fields of each file without converting it").arg(Arg::new("json").long("json").help("Prints the summaries as a JSON array")))
+        .subcommand(App::new("completions").hide(true).about("Prints a completion script for a shell").arg(Arg::new("shell").required(true).possible_values(["bash", "zsh", "fish", "elvish", "powershell"])))
+        .arg(Arg::new("man").long("man").help("Prints the man page"))
+}
 
-    /// Splits overlapping rays into new sweeps
-    pub fn split_overlap_rays(&mut self) {
-        let mut new_sweeps: Vec<Sweep> = Vec::new();
+pub fn arg_parse() -> RadyOptions {
+    let mut options = RadyOptions::default();
 
-        for sweep in &mut self.sweeps {
-            sweep.correct_azimuth();
+    // RadxConvert arguments aren't in the form clap takes, so they're parsed on their own
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("radx") {
+        return radx::parse(&args[2..]).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        });
+    }
 
-            let mut change: f32 = 0.0;
-            let mut last_change: f32 = 0.0;
-            let direction: f32 = {
-                let dir: f32 = sweep.azimuths()[0..5].iter().sum();
-                if dir > 0.0 || dir < -300.0 {
-                    1.0
-                } else {
-                    -1.0
-                }
-            };
+    let mut app = app();
+    let matches = app.get_matches_mut();
 
-            let mut ray_idx: usize = 0;
+    if matches.is_present("man") {
+        print!("{}", man_page());
+        std::process::exit(0);
+    }
 
-            for i in 0..sweep.nrays() as usize {
-                if i == 0 {
-                    change = 0.0;
-                } else {
-                    let mut small_change = sweep.rays[i].azimuth - last_change;
+    // Arguments are global so they can follow a subcommand, and are read from its matches
+    let (subcommand, matches) = match matches.subcommand() {
+        Some((name, sub_matches)) => (Some(name.to_string()), sub_matches.clone()),
+        None => (None, matches),
+    };
 
-                    if small_change.abs() > 300.0 {
-                        small_change = 360.0 - small_change.abs()
-                    }
+    if subcommand.as_deref() == Some("selftest") {
+        options.selftest = true;
+        return options;
+    }
 
-                    change += direction * small_change;
-                }
+    if subcommand.as_deref() == Some("serve") {
+        options.serve = Some(matches.value_of("config").unwrap().to_string());
+        options.serve_status = matches.is_present("status");
+    }
 
-                last_change = sweep.rays[i].azimuth;
+    if subcommand.as_deref() == Some("info") {
+        options.info = true;
+        options.info_json = matches.is_present("json");
+    }
 
-                if change >= 360.0 {
-                    let mut new_sweep = sweep.clone();
-                    new_sweep.rays = new_sweep.rays[ray_idx..i].to_vec();
+    if subcommand.as_deref() == Some("completions") {
+        let shell: clap_complete::Shell = matches.value_of("shell").unwrap().parse().unwrap();
+        clap_complete::generate(shell, &mut app, "silv", &mut std::io::stdout());
+        std::process::exit(0);
+    }
 
-                    ray_idx = i;
-                    change -= 360.0;
+    // Global arguments can't be marked as required, so the file is checked here
+    if !matches.is_present("files")
+        && !matches.is_present("file list")
+        && !matches.is_present("watch")
+        && !matches.is_present("site")
+        && options.serve.is_none()
+    {
+        app.error(
+            ErrorKind::MissingRequiredArgument,
+            "The --file, --file-list, --site, or --watch argument is required",
+        )
+        .exit();
+    }
 
-                    new_sweeps.push(new_sweep)
-                }
-            }
+    if matches.value_of("files") == Some("-") && matches.value_of("file list") == Some("-") {
+        app.error(
+            ErrorKind::ArgumentConflict,
+            "Only one of --file and --file-list can read stdin",
+        )
+        .exit();
+    }
 
-            // If last sweep
-            if sweep.nrays() - ray_idx as u16 > 20 {
-                let mut new_sweep = sweep.clone();
-                new_sweep.rays = new_sweep.rays[ray_idx..].to_vec();
+    if matches.is_present("format") {
+        options.format = match matches.value_of("format").unwrap().parse() {
+            Ok(format) => format,
+            Err(e) => app.error(ErrorKind::InvalidValue, e).exit(),
+        };
+    }
 
-                new_sweeps.push(new_sweep)
-            }
-        }
+    options.files = matches.value_of("files").unwrap_or_default().to_string();
 
-        self.sweeps = new_sweeps;
+    if matches.is_present("file list") {
+        options.file_list = Some(matches.value_of("file list").unwrap().to_string());
     }
 
-    /// Time of first sweep
-    pub fn start_time(&self) -> DateTime<Utc> {
-        self.sweeps[0].time()
+    if matches.is_present("watch") {
+        options.watch = Some(matches.value_of("watch").unwrap().to_string());
     }
-}
 
-/// Options for conversion
-#[derive(Clone)]
-pub struct RadyOptions {
-    /// Overrides the output radar
-    pub override_radar: Option<String>,
+    if matches.is_present("realtime") {
+        options.realtime = true;
+    }
 
-    /// Deletes overlapping rays
-    pub trim_rays: bool,
+    if matches.is_present("recursive") {
+        options.recursive = true;
+    }
 
-    /// Splits overlapping rays into new sweeps
-    pub split_overlap_rays: bool,
+    if let Some(patterns) = matches.values_of("include") {
+        options.include = patterns
+            .map(|pattern| glob::Pattern::new(pattern).unwrap())
+            .collect();
+    }
 
-    /// Sorts rays by azimuth
-    pub sort_rays_by_azimuth: bool,
+    if let Some(patterns) = matches.values_of("exclude") {
+        options.exclude = patterns
+            .map(|pattern| glob::Pattern::new(pattern).unwrap())
+            .collect();
+    }
 
-    /// Converts to the specified format
-    pub format: Format,
+    if matches.is_present("print products") {
+        options.print_products = true;
+    }
 
-    /// Aggregates and writes all volumes
-    pub write_volumes: bool,
+    if matches.is_present("check") {
+        options.check = true;
+    }
 
-    /// Aggregates and writes all volumes
-    pub write_separate: bool,
+    if matches.is_present("trace output") {
+        options.trace_output = Some(matches.value_of("trace output").unwrap().to_string());
+    }
 
-    /// Prints all of the file products and exit
-    pub print_products: bool,
+    if matches.is_present("jobs") {
+        options.jobs = matches.value_of("jobs").unwrap().parse().unwrap();
+    }
 
-    /// Adds a file path to read. To select all files in a directory, use the * wildcard at the end
-    pub files: String,
+    if matches.is_present("override radar") {
+        options.override_radar = Some(matches.value_of("override radar").unwrap().to_string());
+    }
 
-    /// Scales reflectivity
-    pub scale: f64,
+    if matches.is_present("write volumes") {
+        options.write_volumes = true;
+    }
 
-    /// Offsets reflectivity
-    pub offset: f64,
+    if matches.is_present("volume step") {
+        options.volume_step = matches.value_of("volume step").unwrap().parse().unwrap();
+    }
 
-    /// Removes all reflectivity values after scale/offset under this number
-    pub remove: f64,
+    if matches.is_present("volume gap") {
+        options.volume_gap = Some(matches.value_of("volume gap").unwrap().parse().unwrap());
+    }
 
-    /// Prints the location in lat, long for each sweep
-    pub location: bool,
+    match matches.value_of("split by") {
+        Some("tilt") => options.write_separate = true,
+        Some("volume") => options.write_volumes = true,
+        _ => (),
+    }
 
-    /// Sets the directory to make the output folder in. Default is the same as the input
-    pub outdir: Option<String>,
+    if matches.is_present("index") {
+        options.index = true;
+    }
 
-    /// Creates files with a given name. Available codes are from the "chrono" library
-    pub name_format: Option<String>,
-}
+    if matches.is_present("low memory") {
+        options.low_memory = true;
+    }
 
-impl Default for RadyOptions {
-    fn default() -> RadyOptions {
-        RadyOptions {
-            override_radar: None,
-            trim_rays: false,
-            split_overlap_rays: false,
-            sort_rays_by_azimuth: false,
-            format: Format::NEXRAD,
-            write_volumes: false,
-            write_separate: false,
-            print_products: false,
-            files: String::new(),
-            scale: 1.0,
-            offset: 0.0,
-            remove: -999.0,
-            location: false,
-            outdir: None,
-            name_format: None,
-        }
+    if matches.is_present("outdir") {
+        options.outdir = Some(matches.value_of("outdir").unwrap().to_string());
     }
-}
 
-impl RadyOptions {
-    pub fn apply_options(&self, radar: &mut RadarFile) {
-        if self.override_radar.is_some() {
-            radar.name = self.override_radar.clone().unwrap();
-        }
+    if matches.is_present("location") {
+        options.location = true;
+    }
 
-        if self.trim_rays {
-            radar.trim_rays();
-        }
+    if matches.is_present("georeference") {
+        options.georeference = true;
+    }
 
-        if self.split_overlap_rays {
-            radar.split_overlap_rays();
-        }
+    if matches.is_present("apply corrections") {
+        options.apply_corrections = true;
+    }
 
-        if self.sort_rays_by_azimuth {
-            radar.sort_rays_by_azimuth();
-        }
+    if matches.is_present("ref units") {
+        options.ref_units = match matches
+            .value_of("ref units")
+            .unwrap()
+            .to_lowercase()
+            .as_str()
+        {
+            "dbz" => RefUnits::Decibels,
+            "linear" => RefUnits::Linear,
+            _ => RefUnits::Auto,
+        };
+    }
 
-        if self.location {
-            println!(
-                "{}: {}, {}",
-                radar.name, radar.sweeps[0].latitude, radar.sweeps[0].longitude
-            );
-        }
+    if matches.is_present("dealias") {
+        options.dealias = match matches.value_of("dealias").unwrap().to_lowercase().as_str() {
+            "region" => Some(Dealias::Region),
+            "sounding" => Some(Dealias::Sounding),
+            _ => None,
+        };
     }
-}
 
-pub fn read(path: impl AsRef<Path>, options: &RadyOptions) -> RadarFile {
-    if dorade::is_dorade(path.as_ref()) {
-        dorade::read_dorade(path, options)
-    // } else if cfradial::is_cfradial() {
-    //     cfradial::read_cfradial(path)
-    } else if nexrad::is_nexrad(path.as_ref()) {
-        nexrad::read_nexrad(path, options)
-    } else {
-        panic!("Unknown file format");
+    if matches.is_present("trim") {
+        options.trim_rays = true;
     }
-}
 
-fn vol_mode(radar: &RadarFile) -> f32 {
-    match radar.sweeps.len() {
-        0 | 1 => return 1.0,
-        2 => return (radar.sweeps[1].elevation - radar.sweeps[0].elevation).signum(),
-        _ => (),
+    if matches.is_present("split overlap") {
+        options.split_overlap_rays = true;
     }
 
-    let min_elev = radar.sweeps.iter()
-        .map(|sweep| sweep.elevation)
-        .reduce(|elev1, elev2| if elev1 < elev2 { elev1 } else { elev2 })
-        .unwrap();
+    if matches.is_present("dedup rays") {
+        options.dedup_rays = true;
+    }
 
-    let ii = radar.sweeps.iter()
-        .map(|sweep| sweep.elevation)
-        .enumerate()
-        .find(|elev| (elev.1 - min_elev).abs() < 0.05)
-        .unwrap().0;
-        
-    if ii > radar.sweeps.len() - 2 {
-        return -1.0;
+    // Rays are sorted by default on the command line
+    options.sort_rays_by_azimuth = !matches.is_present("no sort");
+
+    if matches.is_present("despeckle") {
+        options.despeckle = Some(
+            matches
+                .value_of("despeckle")
+                .unwrap()
+                .parse::<usize>()
+                .unwrap(),
+        );
     }
-    
-    if (radar.sweeps[ii + 2].elevation - radar.sweeps[ii].elevation).abs() < 0.05 {
-        return 1.0;
+
+    if matches.is_present("despeckle area") {
+        options.despeckle_area = Some(
+            matches
+                .value_of("despeckle area")
+                .unwrap()
+                .parse::<usize>()
+                .unwrap(),
+        );
     }
-    
-    (radar.sweeps[ii + 2].elevation - radar.sweeps[ii + 1].elevation).signum()
-}
 
-pub fn write(mut radar: RadarFile, path: impl AsRef<Path>, options: &RadyOptions) {
-    radar.sort_sweeps_by_time();
+    if matches.is_present("min rays") {
+        options.min_rays = Some(
+            matches
+                .value_of("min rays")
+                .unwrap()
+                .parse::<usize>()
+                .unwrap(),
+        );
+    }
 
-    if options.write_volumes {
-        let mut vol = RadarFile {
-            name: radar.name.clone(),
-            sweeps: Vec::new(),
-            params: radar.params.clone(),
-        };
+    if matches.is_present("qc") {
+        options.qc = matches
+            .value_of("qc")
+            .unwrap()
+            .split(',')
+            .map(|threshold| threshold.parse().unwrap())
+            .collect();
+    }
 
-        let mut new_ops = (*options).clone();
-        new_ops.write_volumes = false;
-        new_ops.write_separate = false;
+    if matches.is_present("correct attenuation") {
+        options.correct_attenuation = true;
+    }
 
-        let vol_mode = vol_mode(&radar);
+    if matches.is_present("rain rate") {
+        options.rain_rate = Some(
+            matches
+                .value_of("rain rate")
+                .map_or(Ok(processing::ZR::default()), str::parse)
+                .unwrap(),
+        );
+    }
 
-        let mut last = radar.sweeps[0].elevation;
-        for sweep in radar.sweeps {
-            let elev = sweep.elevation;
+    if let Some(filters) = matches.values_of("filter") {
+        options.filter = filters.map(|filter| filter.parse().unwrap()).collect();
+    }
 
-            // if (elev - last).abs() < 0.1 {
-            //     write(vol.clone(), path.as_ref(), &new_ops);
-            //     vol.sweeps = vec![sweep];
-            // }
+    if matches.is_present("process phidp") {
+        options.process_phidp = Some(
+            matches
+                .value_of("process phidp")
+                .map_or(1, |window| window.parse().unwrap()),
+        );
+    }
 
-            let change = if vol_mode == 1.0 { elev - last } else { last - elev };
+    if matches.is_present("smooth") {
+        options.smooth = matches
+            .value_of("smooth")
+            .unwrap()
+            .split(',')
+            .map(|smoothing| smoothing.parse().unwrap())
+            .collect();
+    }
 
-            // TODO: Double check how to handle this
-            if vol.sweeps.is_empty() || change > 0.1 {
-                vol.sweeps.push(sweep);
-            } else {
-                write(vol.clone(), path.as_ref(), &new_ops);
-                vol.sweeps = vec![sweep];
-            }
+    if matches.is_present("texture") {
+        options.texture = matches
+            .value_of("texture")
+            .unwrap()
+            .split(',')
+            .map(|texture| texture.parse().unwrap())
+            .collect();
+    }
 
-            last = elev;
-        }
-    } else if options.write_separate {
-        for sweep in radar.sweeps {
-            let new_radar = RadarFile {
-                name: radar.name.clone(),
-                sweeps: vec![sweep],
-                params: radar.params.clone(),
-            };
+    if matches.is_present("storm motion") {
+        let motion: Vec<f32> = matches
+            .value_of("storm motion")
+            .unwrap()
+            .split(',')
+            .map(|value| value.trim().parse().unwrap())
+            .collect();
 
-            let mut new_ops = (*options).clone();
-            new_ops.write_volumes = false;
-            new_ops.write_separate = false;
-            write(new_radar, path.as_ref(), &new_ops);
+        match motion[..] {
+            [u, v] => options.storm_motion = Some((u, v)),
+            _ => panic!("Storm motion should be u,v"),
         }
-    } else {
-        radar.sort_sweeps_by_elevation();
-        match options.format {
-            Format::NEXRAD => {
-                for sweep in &mut radar.sweeps {
-                    // println!("{}", sweep.elevation);
-                    sweep.rays.iter_mut().for_each(|ray| {
-                        ray.data.values_mut().for_each(|val| {
-                            while val.len() % 2 != 0 {
-                                val.pop().unwrap();
-                            }
-                        })
-                    })
-                }
+    }
 
-                nexrad::write_nexrad(&radar, path, options);
-            }
-            _ => panic!("Write format not supported"),
-        }
+    if let Some(derived) = matches.values_of("derive") {
+        options.derive = derived.map(|derived| derived.parse().unwrap()).collect();
     }
-}
 
-pub fn convert(options: &RadyOptions) {
-    let in_path = Path::new(&options.files);
+    if matches.is_present("cappi") {
+        options.cappi = matches
+            .value_of("cappi")
+            .unwrap()
+            .split(',')
+            .map(|height| height.trim().parse().unwrap())
+            .collect();
+    }
 
-    let mut out_path = {
-        if options.outdir.is_none() {
-            in_path.parent().unwrap()
-        } else {
-            Path::new(options.outdir.as_ref().unwrap())
-        }
+    if matches.is_present("cappi fields") {
+        options.cappi_fields = matches
+            .value_of("cappi fields")
+            .unwrap()
+            .split(',')
+            .map(|field| field.trim().parse().unwrap())
+            .collect();
     }
-    .to_path_buf();
 
-    if options.outdir.is_none() {
-        out_path.push("output");
+    if matches.is_present("fields") {
+        options.fields = Some(
+            matches
+                .value_of("fields")
+                .unwrap()
+                .split(',')
+                .map(|field| field.trim().parse().unwrap())
+                .collect(),
+        );
     }
 
-    if !out_path.is_dir() && out_path.exists() {
-        panic!("Output file path is not a directory")
+    if matches.is_present("exclude fields") {
+        options.exclude_fields = matches
+            .value_of("exclude fields")
+            .unwrap()
+            .split(',')
+            .map(|field| field.trim().parse().unwrap())
+            .collect();
     }
 
-    let files;
+    if matches.is_present("sweeps") {
+        options.sweeps = Some(
+            matches
+                .value_of("sweeps")
+                .unwrap()
+                .split(',')
+                .map(|index| index.trim().parse().unwrap())
+                .collect(),
+        );
+    }
 
-    if Path::new(in_path).is_file() {
-        files = vec![Ok(in_path.to_path_buf())];
-    } else {
-        files = glob(in_path.to_str().unwrap()).unwrap().collect();
+    if matches.is_present("elevations") {
+        options.elevations = Some(
+            matches
+                .value_of("elevations")
+                .unwrap()
+                .split(',')
+                .map(|elevation| elevation.trim().parse().unwrap())
+                .collect(),
+        );
     }
 
-    if files.is_empty() {
-        panic!("Path: {:?} does not exist or have any files", in_path);
+    if matches.is_present("elevation tolerance") {
+        options.elevation_tolerance = matches
+            .value_of("elevation tolerance")
+            .unwrap()
+            .parse()
+            .unwrap();
     }
 
-    for file in files {
-        if file.as_ref().unwrap().is_dir() {
-            continue;
-        }
+    if matches.is_present("start") {
+        options.start = Some(parse_time(matches.value_of("start").unwrap()).unwrap());
+    }
+
+    if matches.is_present("end") {
+        options.end = Some(parse_time(matches.value_of("end").unwrap()).unwrap());
+    }
+
+    if matches.is_present("site") {
+        options.site = Some(matches.value_of("site").unwrap().to_string());
+    }
 
-        let mut radar = read(file.unwrap(), options);
-        options.apply_options(&mut radar);
-        write(radar, out_path.clone(), options);
+    if matches.is_present("verify") {
+        options.verify = true;
     }
 
-    // if options.aggregate_volumes {
-    //     let mut volume = read(files[0].as_ref().unwrap(), options);
+    if matches.is_present("naming") {
+        options.naming = matches.value_of("naming").unwrap().parse().unwrap();
+    }
 
-    //     for file in files.iter() {
-    //         if file.as_ref().unwrap().is_dir() {
-    //             continue;
-    //         }
-    //         let new = read(file.as_ref().unwrap(), options);
+    if matches.is_present("overwrite") {
+        options.on_conflict = OnConflict::Overwrite;
+    } else if matches.is_present("skip existing") {
+        options.on_conflict = OnConflict::Skip;
+    } else if matches.is_present("suffix on conflict") {
+        options.on_conflict = OnConflict::Suffix;
+    }
 
-    //         println!("{:?} {:?}", volume.start_time(), volume.sweeps.len());
+    if matches.is_present("echo tops") {
+        options.echo_tops = Some(
+            matches
+                .value_of("echo tops")
+                .map_or(18.0, |threshold| threshold.parse().unwrap()),
+        );
+    }
 
-    //         // If new sweep has a higher elevation by atleast 0.2 deg
-    //         if new.sweeps[0].elevation - volume.sweeps.last().unwrap().elevation > 0.5 {
-    //             volume.sweeps.extend(new.sweeps);
-    //         } else { // Write volume
-    //             options.apply_options(&mut volume);
-    //             write(&volume, &out_path, options);
+    if matches.is_present("polarimetric rain") {
+        options.polarimetric_rain = true;
+    }
 
-    //             volume = new;
-    //         }
-    //     }
+    if matches.is_present("min range") {
+        options.min_range = Some(
+            matches
+                .value_of("min range")
+                .unwrap()
+                .parse::<f32>()
+                .unwrap(),
+        );
+    }
 
-    // } else {
-    //     for file in files {
-    //         if file.as_ref().unwrap().is_dir() {
-    //             continue;
-    //         }
+    if matches.is_present("max range") {
+        options.max_range = Some(
+            matches
+                .value_of("max range")
+                .unwrap()
+                .parse::<f32>()
+                .unwrap(),
+        );
+    }
 
-    //         let mut radar = read(file.unwrap(), options);
-    //         options.apply_options(&mut radar);
-    //         write(&radar, out_path.clone(), options);
-    //     }
-    // }
-}
+    if matches.is_present("gate spacing") {
+        options.gate_spacing = Some(
+            matches
+                .value_of("gate spacing")
+                .unwrap()
+                .parse::<f32>()
+                .unwrap(),
+        );
+    }
 
-pub fn arg_parse() -> RadyOptions {
-    let mut options = RadyOptions::default();
+    if matches.is_present("max height") {
+        options.max_height = Some(
+            matches
+                .value_of("max height")
+                .unwrap()
+                .parse::<f32>()
+                .unwrap(),
+        );
+    }
 
-    let matches = App::new("RadyConvert")
-        .version("0.0.1")
-        .setting(AppSettings::AllowNegativeNumbers)
-        .arg(Arg::new("format").short('F').long("format").takes_value(true).help("Converts to the specified format")
-            .possible_values(["nexrad"]).ignore_case(true))
-        .arg(Arg::new("override radar").short('R').long("radar").takes_value(true).help("Overrides the output radar"))
-        .arg(Arg::new("write volumes").long("vols").help("Aggregates sweeps into volumes and writes them separately."))
-        .arg(Arg::new("print products").short('P').long("print_p").help("Prints all of the file products and exit"))
-        .arg(Arg::new("files").short('f').long("file").takes_value(true).required(true).help("Adds a file path to read. To select all files in a directory, use the * wildcard at the end"))
-        .arg(Arg::new("scale").long("scale").takes_value(true).help("Scales reflectivity"))
-        .arg(Arg::new("offset").long("offset").takes_value(true).help("Offsets reflectivity"))
-        .arg(Arg::new("remove").long("remove").takes_value(true).help("Removes all reflectivity values after scale/offset under this number"))
-        .arg(Arg::new("location").short('l').long("location").help("Prints the location in lat, long for each sweep"))
-        .arg(Arg::new("outdir").short('o').long("outdir").takes_value(true).help("Sets the directory to make the output folder in. Default is the same as the input"))
-        .arg(Arg::new("name format").long("name").takes_value(true).help("Creates files with a given name. Available codes are from the \"chrono\" library"))
-        .get_matches();
+    if matches.is_present("sounding") {
+        options.sounding = Some(processing::Sounding::read(
+            matches.value_of("sounding").unwrap(),
+        ));
+        options.dealias.get_or_insert(Dealias::Sounding);
+    }
 
-    if matches.is_present("format") {
-        options.format = match matches.value_of("format").unwrap().to_lowercase().as_str() {
-            "nexrad" => Format::NEXRAD,
-            _ => panic!("Unknown output format"),
-        };
+    for (arg, azimuth) in [
+        ("azimuth in", &mut options.azimuth_in),
+        ("azimuth out", &mut options.azimuth_out),
+    ] {
+        if matches.is_present(arg) {
+            *azimuth = match matches.value_of(arg).unwrap().to_lowercase().as_str() {
+                "leading" => AzimuthRef::LeadingEdge,
+                _ => AzimuthRef::Center,
+            };
+        }
     }
 
-    options.files = matches.value_of("files").unwrap().to_string();
+    if matches.is_present("time height") {
+        options.time_height = true;
+    }
 
-    if matches.is_present("print products") {
-        options.print_products = true;
+    if matches.is_present("keep calibration") {
+        options.keep_calibration = true;
     }
 
-    if matches.is_present("override radar") {
-        options.override_radar = Some(matches.value_of("override radar").unwrap().to_string());
+    if matches.is_present("drop transitions") {
+        options.drop_transitions = true;
     }
 
-    if matches.is_present("write volumes") {
-        options.write_volumes = true;
+    if matches.is_present("bias") {
+        for bias in matches.value_of("bias").unwrap().split(',') {
+            let (field, value) = bias.split_once('=').expect("Biases should be field=value");
+            options
+                .bias
+                .insert(field.trim().parse().unwrap(), value.trim().parse().unwrap());
+        }
     }
 
-    if matches.is_present("outdir") {
-        options.outdir = Some(matches.value_of("outdir").unwrap().to_string());
+    if matches.is_present("zdr offset") {
+        let offset: f64 = matches.value_of("zdr offset").unwrap().parse().unwrap();
+        *options.bias.entry(Field::ZDR).or_default() -= offset;
     }
 
-    if matches.is_present("scale") {
-        options.scale = matches.value_of("scale").unwrap().parse::<f64>().unwrap();
+    if matches.is_present("merge split cuts") {
+        options.merge_split_cuts = true;
     }
 
-    if matches.is_present("offset") {
-        options.offset = matches.value_of("offset").unwrap().parse::<f64>().unwrap();
+    if matches.is_present("flip velocity") {
+        options.flip_velocity = true;
     }
 
-    if matches.is_present("location") {
-        options.location = true;
+    if subcommand.as_deref() == Some("aggregate") {
+        options.aggregate = true;
+    }
+
+    if matches.is_present("field map") {
+        options.field_map = read_field_map(matches.value_of("field map").unwrap());
     }
 
-    if matches.is_present("remove") {
-        options.remove = matches.value_of("remove").unwrap().parse::<f64>().unwrap();
+    if matches.is_present("smooth azimuths") {
+        options.smooth_azimuths = true;
     }
 
     if matches.is_present("name format") {
@@ -1,6 +1,65 @@
 fn main() {
-    let mut args = silv::arg_parse();
-    args.sort_rays_by_azimuth = true;
+    let args = silv::arg_parse();
 
-    silv::convert(&args);
+    if args.selftest {
+        let failed = silv::selftest::run()
+            .iter()
+            .any(|check| check.result.is_err());
+        std::process::exit(failed as i32);
+    }
+
+    if args.info {
+        let failed = silv::print_info(&args) > 0;
+        std::process::exit(failed as i32);
+    }
+
+    // Held until the end of the run, when the trace is written
+    let trace = args.trace_output.as_ref().map(silv::trace_to);
+
+    #[cfg(feature = "watch")]
+    if let Some(config) = &args.serve {
+        let config = silv::serve::ServeConfig::read(config).unwrap_or_else(|e| fail(e));
+
+        if args.serve_status {
+            let Some(address) = config.control else {
+                fail("The configuration doesn't have a control address");
+            };
+            println!(
+                "{}",
+                silv::serve::request(&address, "status")
+                    .unwrap_or_else(|e| fail(format!("Can't reach {}: {}", address, e)))
+            );
+        } else if let Err(e) = silv::serve::serve(&config, &args) {
+            fail(e);
+        }
+
+        return;
+    }
+
+    #[cfg(not(feature = "watch"))]
+    if args.serve.is_some() {
+        fail("This build can't run as a service");
+    }
+
+    let failed = match &args.watch {
+        #[cfg(feature = "watch")]
+        Some(dir) => {
+            silv::watch(std::path::Path::new(dir), &args);
+            0
+        }
+        #[cfg(not(feature = "watch"))]
+        Some(_) => fail("This build can't watch directories"),
+        None => silv::convert(&args),
+    };
+
+    if failed > 0 {
+        drop(trace);
+        std::process::exit(1);
+    }
+}
+
+/// Reports an error that stops the run, and exits with a failure
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
@@ -0,0 +1,1404 @@
+//! Processing steps applied to the radar data between reading and writing
+
+use crate::{
+    azimuth_diff, is_missing, Field, GateGeometry, ParamDescription, RadarFile, Ray, ScanMode,
+    Sweep, MISSING,
+};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use tracing::instrument;
+
+/// Earth radius in meters
+pub(crate) const EARTH_RADIUS: f32 = 6_371_000.0;
+
+/// Height of the beam center above the radar in meters, using the 4/3 effective earth radius model
+pub fn beam_height(range: f32, elevation: f32) -> f32 {
+    let radius = EARTH_RADIUS * 4.0 / 3.0;
+    (range.powi(2) + radius.powi(2) + 2.0 * range * radius * elevation.to_radians().sin()).sqrt()
+        - radius
+}
+
+/// Wind at a height in a sounding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoundingLevel {
+    /// Height above sea level in meters
+    pub height: f32,
+
+    /// Eastward wind in m/s
+    pub u: f32,
+
+    /// Northward wind in m/s
+    pub v: f32,
+}
+
+/// Environmental wind profile, used as a first guess for dealiasing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sounding {
+    /// Levels in increasing height
+    pub levels: Vec<SoundingLevel>,
+}
+
+impl Sounding {
+    /// Reads a sounding from a text or CSV file with a height in meters, wind direction in degrees
+    /// and wind speed in m/s on each line. Lines that don't start with a number are skipped
+    pub fn read(path: impl AsRef<Path>) -> Sounding {
+        let contents = std::fs::read_to_string(path.as_ref()).unwrap();
+
+        let mut levels: Vec<SoundingLevel> = contents
+            .lines()
+            .filter_map(|line| {
+                let values: Vec<f32> = line
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|s| !s.is_empty())
+                    .map_while(|s| s.parse().ok())
+                    .collect();
+
+                match values[..] {
+                    [height, direction, speed, ..] => {
+                        let direction = direction.to_radians();
+                        Some(SoundingLevel {
+                            height,
+                            u: -speed * direction.sin(),
+                            v: -speed * direction.cos(),
+                        })
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+
+        if levels.is_empty() {
+            panic!("No levels in sounding {}", path.as_ref().display());
+        }
+
+        levels.sort_by(|a, b| a.height.total_cmp(&b.height));
+        Sounding { levels }
+    }
+
+    /// Wind at a height, interpolated between levels and held constant past the ends
+    pub fn wind(&self, height: f32) -> (f32, f32) {
+        let above = self.levels.partition_point(|level| level.height < height);
+
+        if above == 0 {
+            let level = self.levels[0];
+            return (level.u, level.v);
+        }
+
+        if above == self.levels.len() {
+            let level = self.levels[above - 1];
+            return (level.u, level.v);
+        }
+
+        let (lower, upper) = (self.levels[above - 1], self.levels[above]);
+        let t = (height - lower.height) / (upper.height - lower.height);
+        (
+            lower.u + t * (upper.u - lower.u),
+            lower.v + t * (upper.v - lower.v),
+        )
+    }
+}
+
+/// Gates of a field in a sweep, indexed by ray then gate
+type Gates = Vec<Vec<f64>>;
+
+/// Checks if the last ray of a sweep is next to the first
+fn full_circle(sweep: &Sweep) -> bool {
+    let nrays = sweep.rays.len();
+    nrays > 2
+        && azimuth_diff(sweep.rays[0].azimuth, sweep.rays[nrays - 1].azimuth).abs()
+            <= 2.0 * sweep.azimuth_spacing().abs()
+}
+
+/// Neighbors of a gate along its ray and in the rays on either side. The first and last rays
+/// are neighbors if the sweep covers the full circle
+fn neighbors(
+    data: &Gates,
+    wrap: bool,
+    ray: usize,
+    gate: usize,
+) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let nrays = data.len();
+    let prev_ray = if ray > 0 {
+        Some(ray - 1)
+    } else {
+        wrap.then(|| nrays - 1)
+    };
+    let next_ray = if ray + 1 < nrays {
+        Some(ray + 1)
+    } else {
+        wrap.then_some(0)
+    };
+
+    let along = [gate.checked_sub(1), Some(gate + 1)]
+        .into_iter()
+        .flatten()
+        .map(move |g| (ray, g));
+    let across = [prev_ray, next_ray]
+        .into_iter()
+        .flatten()
+        .map(move |r| (r, gate));
+
+    along.chain(across).filter(move |&(r, g)| g < data[r].len())
+}
+
+/// Unfolds aliased velocities by splitting the sweep into regions of smoothly varying velocity
+/// and shifting each region by multiples of twice the Nyquist velocity to match its neighbors,
+/// starting from the largest region
+#[instrument(skip_all)]
+pub fn dealias_region_based(sweep: &mut Sweep) {
+    let nyquist = sweep.nyquist_velocity as f64;
+
+    if nyquist <= 0.0 {
+        eprintln!(
+            "Skipping dealiasing of the {:.1} degree sweep without a Nyquist velocity",
+            sweep.elevation
+        );
+        return;
+    }
+
+    if !sweep
+        .rays
+        .iter()
+        .any(|ray| ray.data.contains_key(&Field::VEL))
+    {
+        return;
+    }
+
+    let data: Gates = sweep
+        .rays
+        .iter()
+        .map(|ray| ray.data.get(&Field::VEL).cloned().unwrap_or_default())
+        .collect();
+    let wrap = full_circle(sweep);
+
+    // Flood fill regions of gates that differ by less than half the Nyquist velocity
+    let mut region = data
+        .iter()
+        .map(|ray| vec![usize::MAX; ray.len()])
+        .collect::<Vec<_>>();
+    let mut sizes = Vec::new();
+
+    for ray in 0..data.len() {
+        for gate in 0..data[ray].len() {
+            if region[ray][gate] != usize::MAX || is_missing(data[ray][gate]) {
+                continue;
+            }
+
+            let id = sizes.len();
+            let mut size = 0;
+            let mut stack = vec![(ray, gate)];
+            region[ray][gate] = id;
+
+            while let Some((r, g)) = stack.pop() {
+                size += 1;
+
+                for (nr, ng) in neighbors(&data, wrap, r, g) {
+                    let value = data[nr][ng];
+
+                    if region[nr][ng] == usize::MAX
+                        && !is_missing(value)
+                        && (value - data[r][g]).abs() < nyquist / 2.0
+                    {
+                        region[nr][ng] = id;
+                        stack.push((nr, ng));
+                    }
+                }
+            }
+
+            sizes.push(size);
+        }
+    }
+
+    // Sum of the differences across the boundary between each pair of regions
+    let mut edges: HashMap<(usize, usize), (f64, usize)> = HashMap::new();
+    for ray in 0..data.len() {
+        for gate in 0..data[ray].len() {
+            let a = region[ray][gate];
+            if a == usize::MAX {
+                continue;
+            }
+
+            for (nr, ng) in neighbors(&data, wrap, ray, gate) {
+                let b = region[nr][ng];
+                if b == usize::MAX || b == a {
+                    continue;
+                }
+
+                let edge = edges.entry((a, b)).or_default();
+                edge.0 += data[ray][gate] - data[nr][ng];
+                edge.1 += 1;
+            }
+        }
+    }
+
+    let mut adjacent: Vec<Vec<usize>> = vec![Vec::new(); sizes.len()];
+    for &(a, b) in edges.keys() {
+        adjacent[a].push(b);
+    }
+
+    // Unfold from the largest region outward, starting again from the largest remaining region
+    // when a group of regions doesn't touch the ones already unfolded
+    let mut shifts: Vec<Option<f64>> = vec![None; sizes.len()];
+    let mut by_size: Vec<usize> = (0..sizes.len()).collect();
+    by_size.sort_by_key(|&id| std::cmp::Reverse(sizes[id]));
+
+    for start in by_size {
+        if shifts[start].is_some() {
+            continue;
+        }
+
+        shifts[start] = Some(0.0);
+        let mut queue: BinaryHeap<(usize, usize)> =
+            adjacent[start].iter().map(|&id| (sizes[id], id)).collect();
+
+        while let Some((_, id)) = queue.pop() {
+            if shifts[id].is_some() {
+                continue;
+            }
+
+            let (sum, count) = adjacent[id]
+                .iter()
+                .filter_map(|&other| shifts[other].map(|shift| (other, shift)))
+                .map(|(other, shift)| {
+                    let (sum, count) = edges[&(other, id)];
+                    (sum + count as f64 * shift, count)
+                })
+                .fold((0.0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+
+            let folds = (sum / count as f64 / (2.0 * nyquist)).round();
+            shifts[id] = Some(folds * 2.0 * nyquist);
+
+            queue.extend(
+                adjacent[id]
+                    .iter()
+                    .filter(|&&other| shifts[other].is_none())
+                    .map(|&other| (sizes[other], other)),
+            );
+        }
+    }
+
+    for (ray, regions) in sweep.rays.iter_mut().zip(region) {
+        if let Some(values) = ray.data.get_mut(&Field::VEL) {
+            for (value, id) in values.iter_mut().zip(regions) {
+                if id != usize::MAX {
+                    *value += shifts[id].unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// Unfolds aliased velocities to the radial velocity closest to the wind in a sounding, in the
+/// style of the 4DD algorithm's first guess. `altitude` is the height of the radar above sea level
+#[instrument(skip_all)]
+pub fn dealias_sounding(
+    sweep: &mut Sweep,
+    geometry: GateGeometry,
+    sounding: &Sounding,
+    altitude: f32,
+) {
+    let nyquist = sweep.nyquist_velocity as f64;
+
+    if nyquist <= 0.0 {
+        eprintln!(
+            "Skipping dealiasing of the {:.1} degree sweep without a Nyquist velocity",
+            sweep.elevation
+        );
+        return;
+    }
+
+    let elevation = sweep.elevation;
+
+    for ray in &mut sweep.rays {
+        let azimuth = ray.azimuth.to_radians();
+
+        if let Some(values) = ray.data.get_mut(&Field::VEL) {
+            for (gate, value) in values
+                .iter_mut()
+                .enumerate()
+                .filter(|(_, value)| !is_missing(**value))
+            {
+                let height = altitude + beam_height(geometry.range(gate), elevation);
+                let (u, v) = sounding.wind(height);
+                let expected =
+                    ((u * azimuth.sin() + v * azimuth.cos()) * elevation.to_radians().cos()) as f64;
+
+                *value += ((expected - *value) / (2.0 * nyquist)).round() * 2.0 * nyquist;
+            }
+        }
+    }
+}
+
+/// Adds a storm-relative velocity field on the gates of velocity, removing the radial component
+/// of a storm moving with eastward velocity `u` and northward velocity `v` in m/s
+#[instrument(skip_all)]
+pub fn storm_relative_velocity(radar: &mut RadarFile, u: f32, v: f32) {
+    let Some(velocity) = radar.params.get(&Field::VEL) else {
+        return;
+    };
+
+    let param = ParamDescription {
+        description: format!(
+            "Radial velocity relative to a storm moving at u = {} m/s, v = {} m/s",
+            u, v
+        ),
+        ..velocity.clone()
+    };
+    radar.params.insert(Field::SRV, param);
+
+    for sweep in &mut radar.sweeps {
+        if let Some(&geometry) = sweep.gates.get(&Field::VEL) {
+            sweep.gates.insert(Field::SRV, geometry);
+        }
+
+        for ray in &mut sweep.rays {
+            let azimuth = ray.azimuth.to_radians();
+            let motion =
+                ((u * azimuth.sin() + v * azimuth.cos()) * ray.elevation.to_radians().cos()) as f64;
+
+            if let Some(data) = ray.data.get(&Field::VEL) {
+                let values = data
+                    .iter()
+                    .map(|&value| {
+                        if is_missing(value) {
+                            MISSING
+                        } else {
+                            value - motion
+                        }
+                    })
+                    .collect();
+                ray.data.insert(Field::SRV, values);
+            }
+        }
+    }
+}
+
+/// Blanks gates closer than `min_range` meters, such as the transmitter blind zone, and gates
+/// with a beam center more than `max_height` meters above the radar
+#[instrument(skip_all)]
+pub fn mask_by_geometry(radar: &mut RadarFile, min_range: Option<f32>, max_height: Option<f32>) {
+    for i in 0..radar.sweeps.len() {
+        let fields: Vec<Field> = radar.sweeps[i]
+            .rays
+            .iter()
+            .flat_map(|ray| ray.data.keys())
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let geometries: HashMap<Field, GateGeometry> = fields
+            .into_iter()
+            .map(|field| (field.clone(), radar.geometry(&radar.sweeps[i], &field)))
+            .collect();
+
+        let sweep = &mut radar.sweeps[i];
+        let elevation = sweep.elevation;
+
+        for ray in &mut sweep.rays {
+            for (field, values) in &mut ray.data {
+                let geometry = geometries[field];
+
+                for (gate, value) in values.iter_mut().enumerate() {
+                    let range = geometry.range(gate);
+
+                    if min_range.is_some_and(|min| range < min)
+                        || max_height.is_some_and(|max| beam_height(range, elevation) > max)
+                    {
+                        *value = MISSING;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Consecutive gates at the start of the echo used to find the system phase
+const SYSTEM_PHASE_GATES: usize = 10;
+
+/// Median of some values, sorting them
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    })
+}
+
+/// Cleans up differential phase: unfolds wraps along each ray, removes the system phase so the
+/// phase starts near zero, and smooths it along range with a median of `window` gates. Phase
+/// within a 180 degree span is taken to wrap every 180 degrees, and every 360 degrees otherwise.
+/// The system phase is the median of the first gates of echo in each ray, where the correlation
+/// coefficient is at least 0.9 if it was measured
+#[instrument(skip_all)]
+pub fn process_phidp(sweep: &mut Sweep, window: usize) {
+    let valid: Vec<f64> = sweep
+        .rays
+        .iter()
+        .filter_map(|ray| ray.data.get(&Field::PHI))
+        .flatten()
+        .copied()
+        .filter(|v| !is_missing(*v))
+        .collect();
+
+    if valid.is_empty() {
+        return;
+    }
+
+    let span = valid.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b))
+        - valid.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+    let period = if span <= 180.0 { 180.0 } else { 360.0 };
+
+    let mut starts = Vec::new();
+
+    for ray in &mut sweep.rays {
+        let rho = ray.data.get(&Field::RHO).cloned();
+
+        let Some(phase) = ray.data.get_mut(&Field::PHI) else {
+            continue;
+        };
+
+        let mut reference: Option<f64> = None;
+        for value in phase.iter_mut().filter(|value| !is_missing(**value)) {
+            if let Some(reference) = reference {
+                *value += ((reference - *value) / period).round() * period;
+            }
+
+            reference = Some(*value);
+        }
+
+        let meteorological = |gate: usize| {
+            !is_missing(phase[gate])
+                && rho
+                    .as_ref()
+                    .and_then(|rho| rho.get(gate))
+                    .is_none_or(|&rho| is_missing(rho) || rho >= 0.9)
+        };
+        let first = (0..phase.len().saturating_sub(SYSTEM_PHASE_GATES - 1))
+            .find(|&gate| (gate..gate + SYSTEM_PHASE_GATES).all(meteorological));
+
+        if let Some(first) = first {
+            starts.extend(median(
+                &mut phase[first..first + SYSTEM_PHASE_GATES].to_vec(),
+            ));
+        }
+    }
+
+    let Some(system_phase) = median(&mut starts) else {
+        eprintln!(
+            "No echo to find the system phase of the {:.1} degree sweep",
+            sweep.elevation
+        );
+        return;
+    };
+
+    for phase in sweep
+        .rays
+        .iter_mut()
+        .filter_map(|ray| ray.data.get_mut(&Field::PHI))
+    {
+        phase
+            .iter_mut()
+            .filter(|value| !is_missing(**value))
+            .for_each(|value| *value -= system_phase);
+    }
+
+    if window > 1 {
+        smooth(
+            sweep,
+            &Smoothing {
+                field: Field::PHI,
+                filter: Filter::Median,
+                gates: window,
+                rays: 1,
+            },
+        );
+    }
+}
+
+/// Exponent of the power law between attenuation and reflectivity, A = aZ^b
+const ZPHI_B: f64 = 0.78;
+
+/// Ratio of differential to horizontal attenuation, used to correct ZDR
+const ZPHI_DIFFERENTIAL_RATIO: f64 = 0.14;
+
+/// Ratio of horizontal attenuation to specific differential phase in dB/degree, by wavelength
+fn attenuation_alpha(wavelength: Option<f32>) -> f64 {
+    match wavelength {
+        Some(wavelength) if wavelength < 0.04 => 0.28,
+        Some(wavelength) if wavelength < 0.08 => 0.08,
+        _ => 0.04,
+    }
+}
+
+/// Corrects REF and ZDR for rain attenuation using the ZPHI method, which constrains the
+/// attenuation along each ray to the total change in differential phase
+#[instrument(skip_all)]
+pub fn correct_attenuation(radar: &mut RadarFile) {
+    let alpha = attenuation_alpha(radar.instrument.wavelength);
+
+    for i in 0..radar.sweeps.len() {
+        let spacing = radar
+            .geometry(&radar.sweeps[i], &Field::REF)
+            .meters_between_cells as f64
+            / 1000.0;
+
+        for ray in &mut radar.sweeps[i].rays {
+            let (Some(reflectivity), Some(phase)) =
+                (ray.data.get(&Field::REF), ray.data.get(&Field::PHI))
+            else {
+                continue;
+            };
+
+            let valid: Vec<usize> = (0..reflectivity.len().min(phase.len()))
+                .filter(|&gate| !is_missing(reflectivity[gate]) && !is_missing(phase[gate]))
+                .collect();
+
+            let (first, last) = match (valid.first(), valid.last()) {
+                (Some(&first), Some(&last)) if last > first => (first, last),
+                _ => continue,
+            };
+
+            let delta_phase = phase[last] - phase[first];
+            if delta_phase <= 0.0 {
+                continue;
+            }
+
+            // Integral of Z^b from each gate to the end of the path
+            let zb: Vec<f64> = (0..reflectivity.len())
+                .map(|gate| {
+                    if gate >= first && gate <= last && !is_missing(reflectivity[gate]) {
+                        10f64.powf(0.1 * ZPHI_B * reflectivity[gate])
+                    } else {
+                        0.0
+                    }
+                })
+                .collect();
+
+            let mut integral = vec![0.0; zb.len() + 1];
+            for gate in (0..zb.len()).rev() {
+                integral[gate] = integral[gate + 1] + 0.46 * ZPHI_B * zb[gate] * spacing;
+            }
+
+            let c = 10f64.powf(0.1 * ZPHI_B * alpha * delta_phase) - 1.0;
+
+            // Two way path integrated attenuation, up to the center of each gate
+            let mut total = 0.0;
+            let pia: Vec<f64> = zb
+                .iter()
+                .enumerate()
+                .map(|(gate, &zb)| {
+                    let attenuation = zb * c / (integral[first] + c * integral[gate]);
+                    let pia = total + attenuation * spacing;
+                    total += 2.0 * attenuation * spacing;
+                    pia
+                })
+                .collect();
+
+            for (field, ratio) in [(Field::REF, 1.0), (Field::ZDR, ZPHI_DIFFERENTIAL_RATIO)] {
+                if let Some(values) = ray.data.get_mut(&field) {
+                    values
+                        .iter_mut()
+                        .zip(&pia)
+                        .filter(|(value, _)| !is_missing(**value))
+                        .for_each(|(value, pia)| *value += ratio * pia);
+                }
+            }
+        }
+    }
+}
+
+/// Gates with data in any field, indexed by ray then gate. Gates with echo are 1 and others are missing
+fn echo_mask(sweep: &Sweep) -> Gates {
+    sweep
+        .rays
+        .iter()
+        .map(|ray| {
+            let ngates = ray.data.values().map(|data| data.len()).max().unwrap_or(0);
+            (0..ngates)
+                .map(|gate| {
+                    if ray
+                        .data
+                        .values()
+                        .any(|data| data.get(gate).is_some_and(|v| !is_missing(*v)))
+                    {
+                        1.0
+                    } else {
+                        MISSING
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Blanks every field at the given gates of each ray
+fn blank_gates(sweep: &mut Sweep, gates: &[Vec<usize>]) {
+    for (ray, gates) in sweep.rays.iter_mut().zip(gates) {
+        for data in ray.data.values_mut() {
+            for &gate in gates {
+                if let Some(value) = data.get_mut(gate) {
+                    *value = MISSING;
+                }
+            }
+        }
+    }
+}
+
+/// Removes runs of echo shorter than `min_gates` along each ray, in every field
+#[instrument(skip_all)]
+pub fn despeckle(sweep: &mut Sweep, min_gates: usize) {
+    let mask = echo_mask(sweep);
+
+    let speckles: Vec<Vec<usize>> = mask
+        .iter()
+        .map(|ray| {
+            let mut speckles = Vec::new();
+            let mut start = 0;
+
+            for gate in 0..=ray.len() {
+                if ray.get(gate).is_some_and(|v| !is_missing(*v)) {
+                    continue;
+                }
+
+                if gate - start < min_gates {
+                    speckles.extend(start..gate);
+                }
+                start = gate + 1;
+            }
+
+            speckles
+        })
+        .collect();
+
+    blank_gates(sweep, &speckles);
+}
+
+/// Removes connected areas of echo with fewer than `min_area` gates, in every field
+#[instrument(skip_all)]
+pub fn despeckle_area(sweep: &mut Sweep, min_area: usize) {
+    let mask = echo_mask(sweep);
+    let wrap = full_circle(sweep);
+
+    let mut seen: Vec<Vec<bool>> = mask.iter().map(|ray| vec![false; ray.len()]).collect();
+    let mut speckles: Vec<Vec<usize>> = vec![Vec::new(); mask.len()];
+
+    for ray in 0..mask.len() {
+        for gate in 0..mask[ray].len() {
+            if seen[ray][gate] || is_missing(mask[ray][gate]) {
+                continue;
+            }
+
+            let mut area = vec![(ray, gate)];
+            let mut stack = vec![(ray, gate)];
+            seen[ray][gate] = true;
+
+            while let Some((r, g)) = stack.pop() {
+                for (nr, ng) in neighbors(&mask, wrap, r, g) {
+                    if !seen[nr][ng] && !is_missing(mask[nr][ng]) {
+                        seen[nr][ng] = true;
+                        area.push((nr, ng));
+                        stack.push((nr, ng));
+                    }
+                }
+            }
+
+            if area.len() < min_area {
+                area.into_iter().for_each(|(r, g)| speckles[r].push(g));
+            }
+        }
+    }
+
+    blank_gates(sweep, &speckles);
+}
+
+/// Filter applied over a window of gates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Median of the window
+    Median,
+
+    /// Mean of the window
+    Boxcar,
+}
+
+/// Smoothing of a field over a window of gates along range and rays across azimuth
+#[derive(Debug, Clone, PartialEq)]
+pub struct Smoothing {
+    pub field: Field,
+    pub filter: Filter,
+
+    /// Width of the window in gates
+    pub gates: usize,
+
+    /// Width of the window in rays
+    pub rays: usize,
+}
+
+impl FromStr for Smoothing {
+    type Err = String;
+
+    /// Parses a smoothing such as `phi:median:5`, or `vel:boxcar:5x3` for 5 gates by 3 rays
+    fn from_str(s: &str) -> Result<Smoothing, String> {
+        let parts: Vec<&str> = s.split(':').map(str::trim).collect();
+
+        let [field, filter, size] = parts[..] else {
+            return Err(format!("Smoothing {} should be field:filter:size", s));
+        };
+
+        let filter = match filter.to_lowercase().as_str() {
+            "median" => Filter::Median,
+            "boxcar" | "mean" => Filter::Boxcar,
+            _ => {
+                return Err(format!(
+                    "Unknown filter {}, expected median or boxcar",
+                    filter
+                ))
+            }
+        };
+
+        let (gates, rays) = size.split_once('x').unwrap_or((size, "1"));
+        let size = |n: &str| {
+            n.parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("Invalid window size in smoothing {}", s))
+        };
+
+        Ok(Smoothing {
+            field: field.parse().unwrap(),
+            filter,
+            gates: size(gates)?,
+            rays: size(rays)?,
+        })
+    }
+}
+
+/// Computes a value from the window of gates centered on each gate of a field, `gates` wide
+/// along range and `rays` wide across azimuth, for each ray with the field. Missing gates are
+/// left out of the window and stay missing, and the window wraps across north in full circle sweeps
+fn map_windows(
+    sweep: &Sweep,
+    field: &Field,
+    gates: usize,
+    rays: usize,
+    f: impl Fn(&mut Vec<f64>) -> f64,
+) -> Vec<Option<Vec<f64>>> {
+    let data: Gates = sweep
+        .rays
+        .iter()
+        .map(|ray| ray.data.get(field).cloned().unwrap_or_default())
+        .collect();
+    let nrays = data.len();
+    let wrap = full_circle(sweep);
+    let (half_gates, half_rays) = (gates / 2, rays / 2);
+
+    sweep
+        .rays
+        .iter()
+        .enumerate()
+        .map(|(r, ray)| {
+            let values = ray.data.get(field)?;
+
+            let rays: Vec<usize> = (r as isize - half_rays as isize..=(r + half_rays) as isize)
+                .filter_map(|i| match i {
+                    i if (0..nrays as isize).contains(&i) => Some(i as usize),
+                    i if wrap => Some(i.rem_euclid(nrays as isize) as usize),
+                    _ => None,
+                })
+                .collect();
+
+            let windowed = values
+                .iter()
+                .enumerate()
+                .map(|(gate, &value)| {
+                    if is_missing(value) {
+                        return MISSING;
+                    }
+
+                    let mut window: Vec<f64> = rays
+                        .iter()
+                        .flat_map(|&i| {
+                            data[i]
+                                .iter()
+                                .take(gate + half_gates + 1)
+                                .skip(gate.saturating_sub(half_gates))
+                        })
+                        .copied()
+                        .filter(|v| !is_missing(*v))
+                        .collect();
+
+                    f(&mut window)
+                })
+                .collect();
+
+            Some(windowed)
+        })
+        .collect()
+}
+
+/// Smooths a field with a window centered on each gate. Missing gates are left out of the
+/// window and stay missing, and the window wraps across north in full circle sweeps
+#[instrument(skip_all)]
+pub fn smooth(sweep: &mut Sweep, smoothing: &Smoothing) {
+    let smoothed = map_windows(
+        sweep,
+        &smoothing.field,
+        smoothing.gates,
+        smoothing.rays,
+        |window| match smoothing.filter {
+            Filter::Boxcar => window.iter().sum::<f64>() / window.len() as f64,
+            Filter::Median => median(window).unwrap_or(MISSING),
+        },
+    );
+
+    for (ray, values) in sweep.rays.iter_mut().zip(smoothed) {
+        if let Some(values) = values {
+            ray.data.insert(smoothing.field.clone(), values);
+        }
+    }
+}
+
+/// Texture of a field, the standard deviation over a window of gates along range and rays
+/// across azimuth
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+    pub field: Field,
+
+    /// Width of the window in gates
+    pub gates: usize,
+
+    /// Width of the window in rays
+    pub rays: usize,
+}
+
+impl Texture {
+    /// Field the texture is written to, such as `VEL_TEXTURE`
+    pub fn output(&self) -> Field {
+        Field::Other(format!("{}_TEXTURE", self.field))
+    }
+}
+
+impl FromStr for Texture {
+    type Err = String;
+
+    /// Parses a texture such as `vel` for a 3 by 3 window, or `vel:5x3` for 5 gates by 3 rays
+    fn from_str(s: &str) -> Result<Texture, String> {
+        let (field, size) = s.split_once(':').unwrap_or((s, "3x3"));
+        let (gates, rays) = size.split_once('x').unwrap_or((size, size));
+        let size = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 1)
+                .ok_or_else(|| format!("Invalid window size in texture {}", s))
+        };
+
+        Ok(Texture {
+            field: field.trim().parse().unwrap(),
+            gates: size(gates)?,
+            rays: size(rays)?,
+        })
+    }
+}
+
+/// Adds the texture of a field as a new field on the same gates. Gates with fewer than two
+/// gates with data in their window are missing
+#[instrument(skip_all)]
+pub fn texture(radar: &mut RadarFile, texture: &Texture) {
+    let Some(source) = radar.params.get(&texture.field) else {
+        return;
+    };
+
+    let output = texture.output();
+    let param = ParamDescription {
+        description: format!(
+            "Standard deviation of {} over {} gates by {} rays",
+            texture.field, texture.gates, texture.rays
+        ),
+        ..source.clone()
+    };
+    radar.params.insert(output.clone(), param);
+
+    for sweep in &mut radar.sweeps {
+        let textures = map_windows(
+            sweep,
+            &texture.field,
+            texture.gates,
+            texture.rays,
+            |window| {
+                if window.len() < 2 {
+                    return MISSING;
+                }
+
+                let mean = window.iter().sum::<f64>() / window.len() as f64;
+                (window.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (window.len() - 1) as f64)
+                    .sqrt()
+            },
+        );
+
+        if let Some(&geometry) = sweep.gates.get(&texture.field) {
+            sweep.gates.insert(output.clone(), geometry);
+        }
+
+        for (ray, values) in sweep.rays.iter_mut().zip(textures) {
+            if let Some(values) = values {
+                ray.data.insert(output.clone(), values);
+            }
+        }
+    }
+}
+
+/// Comparison made by a quality control threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+/// Quality control threshold that gates must pass to be kept, such as `RHO > 0.85`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    /// Field that is compared
+    pub field: Field,
+
+    pub comparison: Comparison,
+    pub value: f64,
+}
+
+impl Threshold {
+    /// Checks if a value of the field passes the threshold
+    pub fn passes(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Greater => value > self.value,
+            Comparison::GreaterEqual => value >= self.value,
+            Comparison::Less => value < self.value,
+            Comparison::LessEqual => value <= self.value,
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    /// Parses a threshold such as `rho>0.85` or `snr >= 3`
+    fn from_str(s: &str) -> Result<Threshold, String> {
+        let (index, op) = s
+            .find(['<', '>'])
+            .map(|i| (i, &s[i..]))
+            .ok_or_else(|| format!("No comparison in threshold {}", s))?;
+
+        let (comparison, len) = match op.as_bytes() {
+            [b'>', b'=', ..] => (Comparison::GreaterEqual, 2),
+            [b'>', ..] => (Comparison::Greater, 1),
+            [b'<', b'=', ..] => (Comparison::LessEqual, 2),
+            _ => (Comparison::Less, 1),
+        };
+
+        let field = s[..index].trim();
+        let value = s[index + len..]
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid value in threshold {}", s))?;
+
+        if field.is_empty() {
+            return Err(format!("No field in threshold {}", s));
+        }
+
+        Ok(Threshold {
+            field: field.parse().unwrap(),
+            comparison,
+            value,
+        })
+    }
+}
+
+/// Censors every field at gates that fail any of the thresholds. Gates where a threshold's
+/// field is missing are kept
+#[instrument(skip_all)]
+pub fn censor(sweep: &mut Sweep, thresholds: &[Threshold]) {
+    for ray in &mut sweep.rays {
+        let failed: Vec<usize> = thresholds
+            .iter()
+            .filter_map(|threshold| {
+                ray.data
+                    .iter()
+                    .find(|(field, _)| {
+                        field
+                            .as_str()
+                            .eq_ignore_ascii_case(threshold.field.as_str())
+                    })
+                    .map(|(_, data)| (threshold, data))
+            })
+            .flat_map(|(threshold, data)| {
+                data.iter()
+                    .enumerate()
+                    .filter(|(_, &value)| !is_missing(value) && !threshold.passes(value))
+                    .map(|(gate, _)| gate)
+            })
+            .collect();
+
+        for data in ray.data.values_mut() {
+            for &gate in &failed {
+                if let Some(value) = data.get_mut(gate) {
+                    *value = MISSING;
+                }
+            }
+        }
+    }
+}
+
+/// Power law relating reflectivity to rain rate, Z = aR^b with Z in mm^6/m^3 and R in mm/hr
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZR {
+    pub a: f64,
+    pub b: f64,
+}
+
+impl ZR {
+    /// Marshall-Palmer relation for stratiform rain
+    pub const MARSHALL_PALMER: ZR = ZR { a: 200.0, b: 1.6 };
+
+    /// WSR-88D relation for convective rain
+    pub const CONVECTIVE: ZR = ZR { a: 300.0, b: 1.4 };
+
+    /// Rosenfeld relation for tropical rain
+    pub const TROPICAL: ZR = ZR { a: 250.0, b: 1.2 };
+
+    /// Rain rate in mm/hr for a reflectivity in dBZ
+    pub fn rate(&self, dbz: f64) -> f64 {
+        (10f64.powf(dbz / 10.0) / self.a).powf(1.0 / self.b)
+    }
+}
+
+impl Default for ZR {
+    fn default() -> ZR {
+        ZR::MARSHALL_PALMER
+    }
+}
+
+impl FromStr for ZR {
+    type Err = String;
+
+    /// Parses a preset name, or the coefficients as `a,b`
+    fn from_str(s: &str) -> Result<ZR, String> {
+        match s.to_lowercase().as_str() {
+            "marshall-palmer" => Ok(ZR::MARSHALL_PALMER),
+            "convective" => Ok(ZR::CONVECTIVE),
+            "tropical" => Ok(ZR::TROPICAL),
+            _ => match s
+                .split_once(',')
+                .map(|(a, b)| (a.trim().parse(), b.trim().parse()))
+            {
+                Some((Ok(a), Ok(b))) => Ok(ZR { a, b }),
+                _ => Err(format!("Unknown Z-R relation {}", s)),
+            },
+        }
+    }
+}
+
+/// Adds a rain rate field on the gates of reflectivity, from a function of the fields of a ray,
+/// the gate, and its reflectivity
+fn add_rain_rate(
+    radar: &mut RadarFile,
+    description: String,
+    rate: impl Fn(&HashMap<Field, Vec<f64>>, usize, f64) -> f64,
+) {
+    let Some(reflectivity) = radar.params.get(&Field::REF) else {
+        return;
+    };
+
+    let param = ParamDescription {
+        description,
+        units: "mm/hr".to_string(),
+        ..reflectivity.clone()
+    };
+    radar.params.insert(Field::RATE, param);
+
+    for sweep in &mut radar.sweeps {
+        if let Some(&geometry) = sweep.gates.get(&Field::REF) {
+            sweep.gates.insert(Field::RATE, geometry);
+        }
+
+        for ray in &mut sweep.rays {
+            if let Some(data) = ray.data.get(&Field::REF) {
+                let values = data
+                    .iter()
+                    .enumerate()
+                    .map(|(gate, &dbz)| {
+                        if is_missing(dbz) {
+                            MISSING
+                        } else {
+                            rate(&ray.data, gate, dbz)
+                        }
+                    })
+                    .collect();
+                ray.data.insert(Field::RATE, values);
+            }
+        }
+    }
+}
+
+/// Adds a rain rate field computed from reflectivity, on the same gates
+#[instrument(skip_all)]
+pub fn rain_rate(radar: &mut RadarFile, zr: ZR) {
+    add_rain_rate(
+        radar,
+        format!("Rain rate from Z = {}R^{}", zr.a, zr.b),
+        |_, _, dbz| zr.rate(dbz),
+    );
+}
+
+/// Rain rate in mm/hr from specific differential phase in degrees/km, R = 44|KDP|^0.822
+pub fn rate_kdp(kdp: f64) -> f64 {
+    44.0 * kdp.abs().powf(0.822) * kdp.signum()
+}
+
+/// Rain rate in mm/hr from reflectivity and differential reflectivity in dB,
+/// R = 0.0067 Z^0.927 ZDR^-3.43 with both in linear units
+pub fn rate_z_zdr(dbz: f64, zdr: f64) -> f64 {
+    0.0067 * 10f64.powf(0.0927 * dbz) * 10f64.powf(-0.343 * zdr)
+}
+
+/// Adds a rain rate field that blends polarimetric estimators, as in the CSU blended algorithm:
+/// R(KDP) in heavy rain where KDP is at least 0.3 degrees/km and reflectivity at least 38 dBZ,
+/// R(Z, ZDR) where ZDR is at least 0.5 dB, and the Z-R relation elsewhere
+#[instrument(skip_all)]
+pub fn rain_rate_polarimetric(radar: &mut RadarFile, zr: ZR) {
+    let value = |data: &HashMap<Field, Vec<f64>>, field: &Field, gate: usize| {
+        data.get(field)
+            .and_then(|values| values.get(gate))
+            .copied()
+            .filter(|v| !is_missing(*v))
+    };
+
+    add_rain_rate(
+        radar,
+        format!(
+            "Rain rate blending R(KDP), R(Z, ZDR), and Z = {}R^{}",
+            zr.a, zr.b
+        ),
+        |data, gate, dbz| match (
+            value(data, &Field::KDP, gate),
+            value(data, &Field::ZDR, gate),
+        ) {
+            (Some(kdp), _) if kdp >= 0.3 && dbz >= 38.0 => rate_kdp(kdp),
+            (_, Some(zdr)) if zdr >= 0.5 => rate_z_zdr(dbz, zdr),
+            _ => zr.rate(dbz),
+        },
+    );
+}
+
+/// Ray nearest an azimuth, if one is within a ray's width of it
+fn nearest_ray(sweep: &Sweep, azimuth: f32) -> Option<&Ray> {
+    let width = sweep.azimuth_spacing().abs().max(1.0);
+
+    sweep
+        .rays
+        .iter()
+        .map(|ray| (azimuth_diff(ray.azimuth, azimuth).abs(), ray))
+        .filter(|&(diff, _)| diff <= width)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, ray)| ray)
+}
+
+/// Value of the gate nearest a range, if it isn't missing
+fn gate_at(values: &[f64], geometry: &GateGeometry, range: f32) -> Option<f64> {
+    let index = ((range - geometry.meters_to_first_cell) / geometry.meters_between_cells).round();
+    (index >= 0.0)
+        .then(|| values.get(index as usize))
+        .flatten()
+        .copied()
+        .filter(|v| !is_missing(*v))
+}
+
+/// Interpolates the height and value of each sweep's beam, in increasing height, to a height.
+/// Heights outside the beams or between a missing value are missing
+fn interpolate_height(beams: &[(f32, Option<f64>)], height: f32) -> f64 {
+    beams
+        .windows(2)
+        .find(|pair| pair[0].0 <= height && height <= pair[1].0)
+        .and_then(|pair| match (pair[0], pair[1]) {
+            ((h0, Some(v0)), (h1, Some(v1))) => {
+                Some(v0 + (v1 - v0) * ((height - h0) / (h1 - h0).max(f32::EPSILON)) as f64)
+            }
+            _ => None,
+        })
+        .unwrap_or(MISSING)
+}
+
+/// Sweeps of a volume that cover the area around the radar, in increasing elevation
+fn ppi_sweeps(radar: &RadarFile) -> Vec<&Sweep> {
+    let mut sweeps: Vec<&Sweep> = radar
+        .sweeps
+        .iter()
+        .filter(|sweep| {
+            !sweep.rays.is_empty() && !sweep.is_vertical() && sweep.scan_mode != ScanMode::RHI
+        })
+        .collect();
+    sweeps.sort_by(|a, b| a.elevation.total_cmp(&b.elevation));
+    sweeps
+}
+
+/// Builds pseudo-sweeps on the rays and gates of the lowest sweep, with a value for each field
+/// from the column of beams above each gate. The column has the beam height and value of each
+/// sweep, in increasing elevation
+fn columns(
+    radar: &RadarFile,
+    fields: &[Field],
+    value: impl Fn(&[(f32, Option<f64>)]) -> f64,
+) -> Option<Sweep> {
+    let sweeps = ppi_sweeps(radar);
+    let lowest = *sweeps.first()?;
+    let geometries: HashMap<&Field, Vec<GateGeometry>> = fields
+        .iter()
+        .map(|field| {
+            (
+                field,
+                sweeps
+                    .iter()
+                    .map(|sweep| radar.geometry(sweep, field))
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let rays = lowest
+        .rays
+        .iter()
+        .map(|template| {
+            let nearest: Vec<Option<&Ray>> = sweeps
+                .iter()
+                .map(|sweep| nearest_ray(sweep, template.azimuth))
+                .collect();
+
+            let data = fields
+                .iter()
+                .filter_map(|field| Some((field, template.data.get(field)?.len())))
+                .map(|(field, ngates)| {
+                    let geometry = &geometries[field];
+
+                    let values = (0..ngates)
+                        .map(|gate| {
+                            let distance = geometry[0].range(gate);
+
+                            let column: Vec<(f32, Option<f64>)> = sweeps
+                                .iter()
+                                .zip(&nearest)
+                                .zip(geometry)
+                                .map(|((sweep, ray), geometry)| {
+                                    let range = distance / sweep.elevation.to_radians().cos();
+                                    let value = ray
+                                        .and_then(|ray| ray.data.get(field))
+                                        .and_then(|values| gate_at(values, geometry, range));
+                                    (beam_height(range, sweep.elevation), value)
+                                })
+                                .collect();
+
+                            value(&column)
+                        })
+                        .collect();
+
+                    (field.clone(), values)
+                })
+                .collect();
+
+            Ray {
+                time: template.time,
+                azimuth: template.azimuth,
+                elevation: 0.0,
+                raw_azimuth: None,
+                platform: None,
+                transition: false,
+                data,
+            }
+        })
+        .collect();
+
+    Some(Sweep {
+        rays,
+        elevation: 0.0,
+        fixed_angle: None,
+        scan_rate: None,
+        ..lowest.clone()
+    })
+}
+
+/// Replaces the sweeps of a volume with constant altitude PPIs, one pseudo-sweep per height in
+/// meters above the radar. Each field is interpolated linearly in height between the sweeps
+/// above and below, on the rays and gates of the lowest sweep
+#[instrument(skip_all)]
+pub fn cappi(radar: &mut RadarFile, heights: &[f32], fields: &[Field]) {
+    let cappis: Option<Vec<Sweep>> = heights
+        .iter()
+        .map(|&height| {
+            Some(Sweep {
+                height: Some(height),
+                ..columns(radar, fields, |column| interpolate_height(column, height))?
+            })
+        })
+        .collect();
+
+    if let Some(cappis) = cappis {
+        radar.sweeps = cappis;
+    }
+}
+
+/// Highest height in a column where reflectivity reaches a threshold, interpolated toward the
+/// beam above when it has data below the threshold
+fn echo_top(column: &[(f32, Option<f64>)], threshold: f64) -> Option<f32> {
+    let top = column
+        .iter()
+        .rposition(|(_, value)| value.is_some_and(|v| v >= threshold))?;
+    let (height, dbz) = (column[top].0, column[top].1?);
+
+    match column.get(top + 1) {
+        Some(&(above, Some(weaker))) => {
+            Some(height + (above - height) * ((dbz - threshold) / (dbz - weaker)) as f32)
+        }
+        _ => Some(height),
+    }
+}
+
+/// Replaces the sweeps of a volume with a pseudo-sweep of echo top heights, the highest altitude
+/// in meters where reflectivity reaches `threshold` dBZ above each gate of the lowest sweep
+#[instrument(skip_all)]
+pub fn echo_tops(radar: &mut RadarFile, threshold: f64) {
+    let Some(reflectivity) = radar.params.get(&Field::REF) else {
+        return;
+    };
+
+    let altitude = radar.instrument.altitude.unwrap_or(0.0);
+    let Some(mut sweep) = columns(radar, &[Field::REF], |column| {
+        echo_top(column, threshold).map_or(MISSING, |top| (top + altitude) as f64)
+    }) else {
+        return;
+    };
+
+    for ray in &mut sweep.rays {
+        if let Some(data) = ray.data.remove(&Field::REF) {
+            ray.data.insert(Field::ETH, data);
+        }
+    }
+
+    if let Some(geometry) = sweep.gates.remove(&Field::REF) {
+        sweep.gates.insert(Field::ETH, geometry);
+    }
+
+    let param = ParamDescription {
+        description: format!("Echo top height of {} dBZ above sea level", threshold),
+        units: "m".to_string(),
+        ..reflectivity.clone()
+    };
+
+    radar.params = HashMap::from([(Field::ETH, param)]);
+    radar.sweeps = vec![sweep];
+}
@@ -0,0 +1,86 @@
+//! Arguments in the form taken by LROSE RadxConvert, so scripts written for it can run silv instead
+
+use crate::{Field, Format, RadyOptions};
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+/// Name format of RadxConvert CfRadial files, in a directory for each day
+const CFRADIAL_NAME: &str = "%Y%m%d/cfrad.%Y%m%d_%H%M%S%.3f_[icao]_v[vol].nc";
+
+/// Name format of NEXRAD files in the same layout
+const NEXRAD_NAME: &str = "%Y%m%d/[icao]%Y%m%d_%H%M%S_v[vol]";
+
+/// Parses a time given as "yyyy mm dd hh mm ss"
+fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    NaiveDateTime::parse_from_str(s.trim(), "%Y %m %d %H %M %S")
+        .map(|time| time.and_utc())
+        .map_err(|_| format!("Invalid time {}, expected \"yyyy mm dd hh mm ss\"", s))
+}
+
+/// Parses a RadxConvert field name, which uses the names of CfRadial files
+fn parse_field(name: &str) -> Field {
+    match name.to_uppercase().as_str() {
+        "DBZ" => Field::REF,
+        "WIDTH" => Field::SW,
+        "PHIDP" => Field::PHI,
+        "RHOHV" => Field::RHO,
+        _ => name.parse().unwrap(),
+    }
+}
+
+/// Converts RadxConvert arguments, not including the program name, to options. Files are given
+/// with `-f`, and are written below `-outdir` (`./output` by default) in a directory for each
+/// day, named with the time, radar, and volume number. `-ag` aggregates sweep files into volumes
+pub fn parse(args: &[String]) -> Result<RadyOptions, String> {
+    let mut options = RadyOptions {
+        format: Format::CFRADIAL,
+        outdir: Some("output".to_string()),
+        ..Default::default()
+    };
+
+    let mut files = Vec::new();
+    let mut outname = None;
+    let mut args = args.iter().peekable();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+
+        match arg.as_str() {
+            "-f" => files.extend(std::iter::from_fn(|| {
+                args.next_if(|arg| !arg.starts_with('-')).cloned()
+            })),
+            "-outdir" => options.outdir = Some(value()?),
+            "-outname" => outname = Some(value()?),
+            "-ag" => options.write_volumes = true,
+            "-cfradial" => options.format = Format::CFRADIAL,
+            "-nexrad" => options.format = Format::NEXRAD,
+            "-field" => options
+                .fields
+                .get_or_insert_with(Vec::new)
+                .push(parse_field(&value()?)),
+            "-start" => options.start = Some(parse_time(&value()?)?),
+            "-end" => options.end = Some(parse_time(&value()?)?),
+            "-debug" | "-verbose" | "-v" | "-vv" => {}
+            _ => return Err(format!("RadxConvert argument {} isn't supported", arg)),
+        }
+    }
+
+    if files.is_empty() {
+        return Err("-f needs at least one file".to_string());
+    }
+
+    options.files = files.remove(0);
+    options.extra_files = files;
+
+    let name = if options.format == Format::NEXRAD {
+        NEXRAD_NAME
+    } else {
+        CFRADIAL_NAME
+    };
+    options.name_format = Some(outname.unwrap_or_else(|| name.to_string()));
+
+    Ok(options)
+}
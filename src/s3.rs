@@ -0,0 +1,155 @@
+//! Listing and downloading NEXRAD Level II archive files from the public NOAA bucket on S3
+
+use crate::Error;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+/// Bucket of the NEXRAD Level II archive
+pub const NEXRAD_BUCKET: &str = "noaa-nexrad-level2";
+
+/// A set of objects in a bucket, given as `s3://bucket/prefix`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Path {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl S3Path {
+    /// Parses an `s3://` URL, or returns None for anything else
+    pub fn parse(url: &str) -> Option<S3Path> {
+        let rest = url.strip_prefix("s3://")?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+
+        Some(S3Path {
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+/// Keys of a page of a bucket listing, and the token for the next page if there is one
+pub fn parse_listing(xml: &str) -> (Vec<String>, Option<String>) {
+    lazy_static::lazy_static! {
+        static ref KEY: Regex = Regex::new("<Key>([^<]*)</Key>").unwrap();
+        static ref TOKEN: Regex = Regex::new("<NextContinuationToken>([^<]*)</NextContinuationToken>").unwrap();
+    }
+
+    let keys = KEY.captures_iter(xml).map(|c| unescape(&c[1])).collect();
+    let token = TOKEN.captures(xml).map(|c| unescape(&c[1]));
+
+    (keys, token)
+}
+
+/// Replaces the XML entities that can appear in keys
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Error for a request to a bucket that failed
+fn request_error(action: &str, bucket: &str, key: &str, e: impl std::fmt::Display) -> Error {
+    Error::Io(std::io::Error::other(format!(
+        "Can't {} s3://{}/{}: {}",
+        action, bucket, key, e
+    )))
+}
+
+/// Keys of every object in a bucket that starts with the prefix
+pub fn list(bucket: &str, prefix: &str) -> Result<Vec<String>, Error> {
+    let url = format!("https://{}.s3.amazonaws.com/", bucket);
+    let mut keys = Vec::new();
+    let mut token: Option<String> = None;
+
+    loop {
+        let mut request = ureq::get(&url)
+            .query("list-type", "2")
+            .query("prefix", prefix);
+        if let Some(token) = &token {
+            request = request.query("continuation-token", token);
+        }
+
+        let xml = request
+            .call()
+            .map_err(|e| request_error("list", bucket, prefix, e))?
+            .into_string()
+            .map_err(|e| request_error("list", bucket, prefix, e))?;
+
+        let (page, next) = parse_listing(&xml);
+        keys.extend(page);
+
+        match next {
+            Some(next) => token = Some(next),
+            None => return Ok(keys),
+        }
+    }
+}
+
+/// Start time of a volume from the name of its archive file, such as
+/// `2022/05/04/KTLX/KTLX20220504_233000_V06`
+pub fn key_time(key: &str) -> Option<DateTime<Utc>> {
+    let name = key.rsplit('/').next()?;
+    let time = name.get(4..19)?;
+
+    NaiveDateTime::parse_from_str(time, "%Y%m%d_%H%M%S")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+/// Keys of the archive files of a site with volumes that start between two times. The metadata
+/// files next to the volumes are skipped
+pub fn site_keys(
+    site: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    let site = site.to_uppercase();
+    let mut keys = Vec::new();
+    let mut day = start.date_naive();
+
+    while day <= end.date_naive() {
+        let prefix = format!("{}/{}/", day.format("%Y/%m/%d"), site);
+
+        keys.extend(list(NEXRAD_BUCKET, &prefix)?.into_iter().filter(|key| {
+            !key.ends_with("_MDM") && key_time(key).is_some_and(|time| time >= start && time <= end)
+        }));
+
+        day += Duration::days(1);
+    }
+
+    Ok(keys)
+}
+
+/// Downloads an object into a directory, under its key, and returns its path. Objects that were
+/// already downloaded are reused
+pub fn download(bucket: &str, key: &str, dir: &Path) -> Result<PathBuf, Error> {
+    let path = dir.join(bucket).join(key);
+
+    if path.is_file() {
+        return Ok(path);
+    }
+
+    std::fs::create_dir_all(path.parent().unwrap_or(dir))?;
+    eprintln!("Downloading s3://{}/{}", bucket, key);
+
+    let response = ureq::get(&format!("https://{}.s3.amazonaws.com/{}", bucket, key))
+        .call()
+        .map_err(|e| request_error("download", bucket, key, e))?;
+
+    // Written under another name first, so an interrupted download isn't reused
+    let partial = path.with_extension("part");
+    let mut file = std::fs::File::create(&partial)?;
+    std::io::copy(&mut response.into_reader(), &mut file)
+        .map_err(|e| request_error("download", bucket, key, e))?;
+    std::fs::rename(&partial, &path)?;
+
+    Ok(path)
+}
+
+/// Directory that downloaded files are kept in between runs
+pub fn download_dir() -> PathBuf {
+    std::env::temp_dir().join("silv-s3")
+}
@@ -0,0 +1,214 @@
+//! Checks that each codec decodes and round-trips small built-in samples, so a build can be
+//! validated (including its netCDF linkage) before it is relied on
+
+use crate::testdata::SyntheticVolume;
+use crate::{files_in, read, read_from_bytes, write, Field, Format, RadarFile, RadyOptions};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+/// Result of checking a codec
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// What was checked, such as "decode nexrad"
+    pub name: String,
+
+    /// Why the check failed
+    pub result: Result<(), String>,
+}
+
+/// Compares a radar to the sweeps of a sample, within a tolerance
+fn compare(
+    radar: &RadarFile,
+    sample: &SyntheticVolume,
+    sweeps: &[usize],
+    tolerance: f64,
+) -> Result<(), String> {
+    if radar.sweeps.len() != sweeps.len() {
+        return Err(format!(
+            "{} sweeps instead of {}",
+            radar.sweeps.len(),
+            sweeps.len()
+        ));
+    }
+
+    for (sweep, &index) in radar.sweeps.iter().zip(sweeps) {
+        if sweep.rays.len() != sample.nrays {
+            return Err(format!(
+                "sweep {} has {} rays instead of {}",
+                index,
+                sweep.rays.len(),
+                sample.nrays
+            ));
+        }
+
+        if (sweep.elevation - sample.elevation(index)).abs() > 0.01 {
+            return Err(format!(
+                "sweep {} is at {} degrees instead of {}",
+                index,
+                sweep.elevation,
+                sample.elevation(index)
+            ));
+        }
+
+        for (i, ray) in sweep.rays.iter().enumerate() {
+            for field in &sample.fields {
+                let data = ray
+                    .data
+                    .get(field)
+                    .ok_or_else(|| format!("sweep {} ray {} has no {}", index, i, field))?;
+
+                for (gate, &value) in data.iter().enumerate() {
+                    let expected = sample.value(field, index, i, gate);
+
+                    if (value - expected).abs() > tolerance {
+                        return Err(format!(
+                            "{} sweep {} ray {} gate {}: {} != {}",
+                            field, index, i, gate, value, expected
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs a check, turning a panic into a failure
+fn check(name: &str, test: impl FnOnce() -> Result<(), String>) -> Check {
+    let result = catch_unwind(AssertUnwindSafe(test)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned());
+        Err(message.unwrap_or_else(|| "panicked".to_string()))
+    });
+
+    println!(
+        "{} {}{}",
+        if result.is_ok() { "PASS" } else { "FAIL" },
+        name,
+        result
+            .as_ref()
+            .err()
+            .map(|e| format!(": {}", e))
+            .unwrap_or_default()
+    );
+    Check {
+        name: name.to_string(),
+        result,
+    }
+}
+
+/// Reads a sample written to a file in the scratch directory
+fn read_sample(dir: &Path, name: &str, bytes: &[u8]) -> Result<RadarFile, String> {
+    let path = dir.join(name);
+    std::fs::write(&path, bytes).map_err(|e| e.to_string())?;
+    Ok(read(&path, &RadyOptions::default()))
+}
+
+/// Decodes a sample of each format, then converts a NEXRAD sample to each output format and
+/// reads it back, printing whether each check passed
+pub fn run() -> Vec<Check> {
+    let dir = std::env::temp_dir().join(format!("silv-selftest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let sample = SyntheticVolume {
+        nrays: 36,
+        ngates: 20,
+        fields: vec![Field::REF, Field::VEL, Field::ZDR],
+        ..Default::default()
+    };
+    let compressed = SyntheticVolume {
+        compress: true,
+        ..sample.clone()
+    };
+
+    // Panics are reported as failures, so the default message is silenced while checking
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let mut checks = vec![
+        check("decode dorade", || {
+            compare(
+                &read_sample(&dir, "swp", &sample.dorade(0))?,
+                &sample,
+                &[0],
+                0.006,
+            )
+        }),
+        check("decode compressed dorade", || {
+            compare(
+                &read_sample(&dir, "swp", &compressed.dorade(1))?,
+                &compressed,
+                &[1],
+                0.006,
+            )
+        }),
+        check("decode nexrad", || {
+            compare(
+                &read_sample(&dir, "vol", &sample.nexrad())?,
+                &sample,
+                &[0, 1],
+                0.0,
+            )
+        }),
+        check("decode compressed nexrad", || {
+            compare(
+                &read_sample(&dir, "vol", &compressed.nexrad())?,
+                &compressed,
+                &[0, 1],
+                0.0,
+            )
+        }),
+        #[cfg(feature = "netcdf")]
+        check("decode cfradial", || {
+            let path = dir.join("vol.nc");
+            sample.write_cfradial(&path);
+            compare(
+                &read(&path, &RadyOptions::default()),
+                &sample,
+                &[0, 1],
+                0.006,
+            )
+        }),
+    ];
+
+    let formats = [
+        Format::NEXRAD,
+        #[cfg(feature = "netcdf")]
+        Format::CFRADIAL,
+    ];
+
+    for format in formats {
+        checks.push(check(&format!("round trip {}", format), || {
+            let options = RadyOptions {
+                format,
+                ..Default::default()
+            };
+            let decoded = read_from_bytes(&sample.nexrad(), &options)
+                .map_err(|e| e.to_string())?
+                .ok_or("The sample can't be decoded")?;
+
+            let out = dir.join(format.name());
+            write(decoded, &out, &options).map_err(|e| e.to_string())?;
+
+            let mut radar: Option<RadarFile> = None;
+            for path in files_in(&out).map_err(|e| e.to_string())? {
+                let piece = read(&path, &RadyOptions::default());
+
+                match &mut radar {
+                    Some(radar) => radar.sweeps.extend(piece.sweeps),
+                    None => radar = Some(piece),
+                }
+            }
+
+            compare(&radar.ok_or("Nothing was written")?, &sample, &[0, 1], 0.01)
+        }));
+    }
+
+    std::panic::set_hook(hook);
+    std::fs::remove_dir_all(&dir).ok();
+
+    checks
+}
@@ -0,0 +1,407 @@
+//! Running as an ingest service, which converts the files that appear in watched directories and
+//! writes each to several output directories, and reports its status on a control socket
+
+use crate::{
+    guard, is_included, parse_chunk_name, passes_check, try_read, write, ChunkFeed, Error,
+    Failures, Format, Inventory, Naming, RadarFile, RadyOptions, WATCH_DEBOUNCE,
+};
+use chrono::{DateTime, Utc};
+use notify::RecursiveMode;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Configuration of the service, read from a TOML file such as
+///
+/// ```toml
+/// control = "127.0.0.1:7474"
+///
+/// [[input]]
+/// dir = "/data/incoming"
+///
+/// [[input]]
+/// dir = "/data/feed"
+/// realtime = true
+///
+/// [[output]]
+/// dir = "/data/cfradial"
+/// format = "cfradial"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServeConfig {
+    /// Address that the control socket listens on. There is no control socket if None
+    pub control: Option<String>,
+
+    /// Directories that are watched for new files
+    #[serde(rename = "input")]
+    pub inputs: Vec<ServeInput>,
+
+    /// Directories that every converted file is written to
+    #[serde(rename = "output")]
+    pub outputs: Vec<ServeOutput>,
+}
+
+/// A directory that is watched for new files
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServeInput {
+    pub dir: PathBuf,
+
+    /// Also watches the subdirectories
+    #[serde(default)]
+    pub recursive: bool,
+
+    /// The files are chunks of the real-time Level II feed
+    #[serde(default)]
+    pub realtime: bool,
+}
+
+/// A directory that converted files are written to, and how they are written
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServeOutput {
+    pub dir: PathBuf,
+
+    /// Format of the files, such as nexrad or cfradial. Default is the format from the command line
+    pub format: Option<String>,
+
+    /// Name format of the files, as with --name
+    pub name: Option<String>,
+
+    /// Convention for the names of fields in CfRadial files, as with --naming
+    pub naming: Option<String>,
+}
+
+impl FromStr for ServeConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ServeConfig, String> {
+        let config: ServeConfig = toml::from_str(s).map_err(|e| e.to_string())?;
+
+        if config.inputs.is_empty() || config.outputs.is_empty() {
+            return Err(
+                "The configuration needs at least one [[input]] and one [[output]]".to_string(),
+            );
+        }
+
+        for output in &config.outputs {
+            output.format.as_deref().map(Format::from_str).transpose()?;
+            output.naming.as_deref().map(Naming::from_str).transpose()?;
+        }
+
+        Ok(config)
+    }
+}
+
+impl ServeConfig {
+    /// Reads the configuration from a TOML file
+    pub fn read(path: impl AsRef<Path>) -> Result<ServeConfig, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| setup_error(format!("Can't read {}", path.display()), e))?;
+        contents
+            .parse()
+            .map_err(|e| Error::Format(format!("Invalid configuration {}: {}", path.display(), e)))
+    }
+}
+
+impl ServeOutput {
+    /// Options for writing to this output, on top of the options from the command line
+    fn options(&self, options: &RadyOptions) -> RadyOptions {
+        let mut options = RadyOptions {
+            outdir: Some(self.dir.to_str().unwrap().to_string()),
+            ..options.clone()
+        };
+
+        if let Some(format) = &self.format {
+            options.format = format.parse().unwrap();
+        }
+
+        if let Some(name) = &self.name {
+            options.name_format = Some(name.clone());
+        }
+
+        if let Some(naming) = &self.naming {
+            options.naming = naming.parse().unwrap();
+        }
+
+        options
+    }
+}
+
+/// What the service has done since it started, as reported on the control socket
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub started: DateTime<Utc>,
+
+    /// Number of files that were converted
+    pub converted_files: usize,
+
+    /// Number of files that weren't radar data
+    pub skipped_files: usize,
+
+    /// Number of files that couldn't be converted
+    pub failed_files: usize,
+
+    /// Last file that was converted, and when
+    pub last_file: Option<(PathBuf, DateTime<Utc>)>,
+
+    /// Volumes of the real-time feed that are being assembled
+    pub open_volumes: usize,
+
+    /// Inventories of the output directories
+    pub outputs: Vec<(PathBuf, Inventory)>,
+}
+
+/// Events handled by the service
+enum Event {
+    Files(Vec<PathBuf>),
+    Stop,
+}
+
+/// Sends a command, `status` or `stop`, to the control socket of a running service and returns
+/// its reply
+pub fn request(address: &str, command: &str) -> std::io::Result<String> {
+    let mut stream = TcpStream::connect(address)?;
+    writeln!(stream, "{}", command)?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply.trim_end().to_string())
+}
+
+/// Answers the commands sent to the control socket, one line each
+fn control(listener: TcpListener, status: Arc<Mutex<Status>>, events: Sender<Event>) {
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+
+        let mut command = String::new();
+        if BufReader::new(&stream).read_line(&mut command).is_err() {
+            continue;
+        }
+
+        let reply = match command.trim() {
+            "status" => serde_json::to_string(&*status.lock().unwrap()).unwrap(),
+            "stop" => {
+                events.send(Event::Stop).ok();
+                "stopping".to_string()
+            }
+            command => format!("Unknown command {}, expected status or stop", command),
+        };
+
+        writeln!(stream, "{}", reply).ok();
+    }
+}
+
+/// Error for a directory, socket, or watcher that the service can't set up
+fn setup_error(context: String, e: impl std::fmt::Display) -> Error {
+    Error::Io(std::io::Error::other(format!("{}: {}", context, e)))
+}
+
+/// Runs the service until it is stopped from the control socket. Files are converted once they
+/// stop changing, and written to every output, with an inventory for each output. Returns an
+/// error if a directory can't be watched or written to, or the control socket can't be opened
+pub fn serve(config: &ServeConfig, options: &RadyOptions) -> Result<(), Error> {
+    let outputs: Vec<(PathBuf, RadyOptions)> = config
+        .outputs
+        .iter()
+        .map(|output| {
+            std::fs::create_dir_all(&output.dir)
+                .and_then(|_| output.dir.canonicalize())
+                .map(|dir| (dir, output.options(options)))
+                .map_err(|e| setup_error(format!("Can't write to {}", output.dir.display()), e))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let inputs: Vec<PathBuf> = config
+        .inputs
+        .iter()
+        .map(|input| {
+            input
+                .dir
+                .canonicalize()
+                .map_err(|e| setup_error(format!("Can't watch {}", input.dir.display()), e))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let status = Arc::new(Mutex::new(Status {
+        started: Utc::now(),
+        converted_files: 0,
+        skipped_files: 0,
+        failed_files: 0,
+        last_file: None,
+        open_volumes: 0,
+        outputs: outputs
+            .iter()
+            .map(|(dir, _)| (dir.clone(), Inventory::default()))
+            .collect(),
+    }));
+
+    let (tx, rx) = channel();
+
+    if let Some(address) = &config.control {
+        let listener = TcpListener::bind(address)
+            .map_err(|e| setup_error(format!("Can't listen on {}", address), e))?;
+        let (status, events) = (status.clone(), tx.clone());
+        std::thread::spawn(move || control(listener, status, events));
+        eprintln!("Listening for commands on {}", address);
+    }
+
+    let mut debouncer = notify_debouncer_mini::new_debouncer(
+        WATCH_DEBOUNCE,
+        move |events: notify_debouncer_mini::DebounceEventResult| match events {
+            Ok(events) => {
+                tx.send(Event::Files(
+                    events.into_iter().map(|event| event.path).collect(),
+                ))
+                .ok();
+            }
+            Err(error) => eprintln!("Error watching: {}", error),
+        },
+    )
+    .map_err(|e| setup_error("Can't watch".to_string(), e))?;
+
+    for (input, dir) in config.inputs.iter().zip(&inputs) {
+        let mode = if input.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        debouncer
+            .watcher()
+            .watch(dir, mode)
+            .map_err(|e| setup_error(format!("Can't watch {}", dir.display()), e))?;
+        eprintln!("Watching {} for new files", dir.display());
+    }
+
+    let mut feeds: Vec<ChunkFeed> = inputs.iter().map(|_| ChunkFeed::default()).collect();
+    let failures = Failures::default();
+
+    while let Ok(Event::Files(mut files)) = rx.recv() {
+        // Chunks of the real-time feed that arrive together are added in order
+        files.sort();
+
+        for file in files {
+            let Ok(file) = file.canonicalize() else {
+                continue;
+            };
+
+            if !file.is_file()
+                || outputs.iter().any(|(dir, _)| file.starts_with(dir))
+                || !is_included(&file, options)
+            {
+                continue;
+            }
+
+            let Some(i) = inputs.iter().position(|dir| file.starts_with(dir)) else {
+                continue;
+            };
+
+            let radars = guard(&file, &failures, || {
+                if config.inputs[i].realtime {
+                    return feeds[i].add(&file, options).map(Some);
+                }
+
+                Ok(try_read(&file, options)?.map(|radar| vec![radar]))
+            });
+
+            let mut status = status.lock().unwrap();
+            status.open_volumes = feeds.iter().map(|feed| feed.volumes.len()).sum();
+
+            let realtime = config.inputs[i].realtime;
+            if realtime && parse_chunk_name(&file).is_some_and(|(_, number, _)| number == 1) {
+                status
+                    .outputs
+                    .iter_mut()
+                    .for_each(|(_, inventory)| inventory.volumes += 1);
+            }
+
+            let Some(radars) = radars else {
+                status.failed_files = failures.lock().unwrap().len();
+                continue;
+            };
+
+            let Some(radars) = radars else {
+                eprintln!("Skipping {}: not radar data", file.display());
+                status.skipped_files += 1;
+                continue;
+            };
+
+            for mut radar in radars {
+                if !options.in_time_range(&radar) {
+                    continue;
+                }
+
+                if guard(&file, &failures, || options.apply_options(&mut radar)).is_none() {
+                    continue;
+                }
+
+                if (options.check && !passes_check(&file, &radar)) || radar.sweeps.is_empty() {
+                    continue;
+                }
+
+                let status = &mut *status;
+                for ((dir, options), (_, inventory)) in outputs.iter().zip(&mut status.outputs) {
+                    if !write_output(&file, radar.clone(), realtime, dir, options, &failures) {
+                        continue;
+                    }
+
+                    if realtime {
+                        inventory.add_sweeps(&radar);
+                    } else {
+                        inventory.add(&radar);
+                    }
+
+                    inventory.write(dir);
+                }
+            }
+
+            status.failed_files = failures.lock().unwrap().len();
+            status.converted_files += 1;
+            status.last_file = Some((file, Utc::now()));
+        }
+    }
+
+    eprintln!("Stopped");
+    Ok(())
+}
+
+/// Writes a radar to an output, with each sweep of the real-time feed in its own file, and
+/// returns whether it was written
+fn write_output(
+    file: &Path,
+    radar: RadarFile,
+    separate: bool,
+    dir: &Path,
+    options: &RadyOptions,
+    failures: &Failures,
+) -> bool {
+    eprintln!("Converting {} into {}", file.display(), dir.display());
+
+    guard(file, failures, || {
+        if separate {
+            for sweep in &radar.sweeps {
+                write(
+                    RadarFile {
+                        sweeps: vec![sweep.clone()],
+                        ..radar.clone()
+                    },
+                    dir,
+                    options,
+                )?;
+            }
+
+            Ok(())
+        } else {
+            write(radar, dir, options)
+        }
+    })
+    .is_some()
+}
@@ -0,0 +1,719 @@
+//! Synthetic radar files for tests and bug reports
+//!
+//! [`SyntheticVolume`] describes a small volume filled with a known data pattern and can
+//! encode it as DORADE, NEXRAD, or CfRadial, so the readers and writers can be exercised
+//! without real data, and reproducer files can be made without sharing a full archive.
+
+use crate::{Field, InstrumentInfo, Platform, MISSING};
+use bzip2::{write::BzEncoder, Compression};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use std::io::Write;
+#[cfg(feature = "netcdf")]
+use std::path::Path;
+
+/// Description of a synthetic volume
+#[derive(Clone)]
+pub struct SyntheticVolume {
+    /// Name of the radar
+    pub name: String,
+
+    /// Number of sweeps
+    pub nsweeps: usize,
+
+    /// Number of rays in each sweep
+    pub nrays: usize,
+
+    /// Number of gates in each ray
+    pub ngates: usize,
+
+    /// Fields to generate
+    pub fields: Vec<Field>,
+
+    /// Compresses the data (HRD for DORADE, bzip2 for NEXRAD, deflate for CfRadial)
+    pub compress: bool,
+
+    /// Time of the first ray
+    pub start_time: DateTime<Utc>,
+
+    /// Latitude of the radar
+    pub latitude: f32,
+
+    /// Longitude of the radar
+    pub longitude: f32,
+
+    /// Nyquist velocity for every sweep
+    pub nyquist_velocity: f32,
+
+    /// Meters to the first cell in the ray
+    pub meters_to_first_cell: f32,
+
+    /// Meters between each cell
+    pub meters_between_cells: f32,
+
+    /// Leaves every seventh gate without data
+    pub missing_gates: bool,
+
+    /// Number of rays at the start of each sweep taken while the antenna is still moving
+    /// up from the previous tilt
+    pub transition_rays: usize,
+
+    /// Antenna and transmitter characteristics, written to the DORADE radar description
+    pub instrument: InstrumentInfo,
+
+    /// Aircraft carrying a DORADE tail radar, whose rotation angle is the azimuth of each ray.
+    /// The radar is on the ground if None
+    pub platform: Option<Platform>,
+}
+
+impl Default for SyntheticVolume {
+    fn default() -> Self {
+        SyntheticVolume {
+            name: "KTST".to_string(),
+            nsweeps: 2,
+            nrays: 360,
+            ngates: 100,
+            fields: vec![Field::REF, Field::VEL],
+            compress: false,
+            start_time: Utc.with_ymd_and_hms(2022, 5, 4, 23, 30, 0).unwrap(),
+            latitude: 35.33,
+            longitude: -97.28,
+            nyquist_velocity: 26.5,
+            meters_to_first_cell: 2125.0,
+            meters_between_cells: 250.0,
+            missing_gates: false,
+            transition_rays: 0,
+            instrument: InstrumentInfo::default(),
+            platform: None,
+        }
+    }
+}
+
+impl SyntheticVolume {
+    /// Fixed elevation of a sweep
+    pub fn elevation(&self, sweep: usize) -> f32 {
+        0.5 + sweep as f32
+    }
+
+    /// Measured elevation of a ray, which is below the fixed elevation for transition rays
+    pub fn ray_elevation(&self, sweep: usize, ray: usize) -> f32 {
+        if ray < self.transition_rays {
+            self.elevation(sweep) - 0.5
+        } else {
+            self.elevation(sweep)
+        }
+    }
+
+    /// Azimuth of a ray
+    pub fn azimuth(&self, ray: usize) -> f32 {
+        ray as f32 * 360.0 / self.nrays as f32
+    }
+
+    /// Collection time of a ray. Rays are 100 ms apart
+    pub fn time(&self, sweep: usize, ray: usize) -> DateTime<Utc> {
+        self.start_time + Duration::milliseconds(((sweep * self.nrays + ray) * 100) as i64)
+    }
+
+    /// Value of a gate. The pattern is exactly representable in the NEXRAD REF and VEL encodings
+    pub fn value(&self, field: &Field, sweep: usize, ray: usize, gate: usize) -> f64 {
+        if self.missing_gates && gate % 7 == 3 {
+            return MISSING;
+        }
+
+        let base = ((sweep + ray + gate) % 40) as f64 * 0.5;
+
+        match field {
+            Field::REF => base,
+            Field::VEL => base - 10.0,
+            Field::SW => base / 4.0,
+            Field::ZDR => base / 8.0 - 1.0,
+            Field::PHI => base * 4.0,
+            Field::RHO => 0.8 + base / 200.0,
+            _ => base,
+        }
+    }
+
+    /// Encodes a single sweep as a DORADE sweep file
+    pub fn dorade(&self, sweep: usize) -> Vec<u8> {
+        let mut w = ByteWriter::little_endian();
+        let time = self.time(sweep, 0);
+
+        // Super sweep identification block
+        w.id("SSWB");
+        w.u32(196);
+        w.u32(0);
+        w.u32(time.timestamp() as u32);
+        w.u32(self.time(sweep, self.nrays - 1).timestamp() as u32);
+        w.u32(0);
+        w.u32(0);
+        w.u32(time.timestamp() as u32);
+        w.u32(self.fields.len() as u32);
+        w.string(&self.name, 8);
+        w.f64(time.timestamp() as f64);
+        w.f64(self.time(sweep, self.nrays - 1).timestamp() as f64);
+        w.u32(1);
+        w.u32(0);
+        w.u32(0);
+        w.zeros(28 + 96);
+
+        // Volume description block
+        w.id("VOLD");
+        w.u32(72);
+        w.u16(1);
+        w.u16(1);
+        w.u32(0);
+        w.string("SYNTHETIC", 20);
+        w.u16(time.year() as u16);
+        w.u16(time.month() as u16);
+        w.u16(time.day() as u16);
+        w.u16(time.hour() as u16);
+        w.u16(time.minute() as u16);
+        w.u16(time.second() as u16);
+        w.zeros(16);
+        w.u16(time.year() as u16);
+        w.u16(time.month() as u16);
+        w.u16(time.day() as u16);
+        w.u16(1);
+
+        // Radar description block
+        w.id("RADD");
+        w.u32(144);
+        w.string(&self.name, 8);
+        w.f32(self.instrument.radar_constant.unwrap_or_default());
+        w.zeros(20);
+        w.f32(self.instrument.horizontal_beam_width.unwrap_or_default());
+        w.f32(self.instrument.vertical_beam_width.unwrap_or_default());
+        w.u16(if self.platform.is_some() { 3 } else { 0 }); // Tail or ground
+        w.u16(1); // PPI
+        w.zeros(12);
+        w.u16(self.fields.len() as u16);
+        w.u16(0);
+        w.u16(if self.compress { 1 } else { 0 });
+        w.u16(0);
+        w.zeros(8);
+        w.f32(self.longitude);
+        w.f32(self.latitude);
+        w.f32(self.instrument.altitude.unwrap_or_default() / 1000.0);
+        w.f32(self.nyquist_velocity);
+        w.f32(0.0);
+        w.u16(1);
+        w.u16(1);
+        w.f32(
+            self.instrument
+                .wavelength
+                .map_or(0.0, |wavelength| crate::SPEED_OF_LIGHT / wavelength / 1e9),
+        );
+        w.zeros(16);
+        w.f32(self.instrument.prf.map_or(0.0, |prf| 1000.0 / prf));
+        w.zeros(16);
+
+        // Parameter descriptions, stored as 16 bit integers
+        for field in &self.fields {
+            w.id("PARM");
+            w.u32(104);
+            w.string(dorade_name(field), 8);
+            w.string(field.as_str(), 40);
+            w.string("", 8);
+            w.zeros(4);
+            w.f32(0.0);
+            w.u16(self.instrument.pulse_width.map_or(0, |width| {
+                (width * 1e-6 * crate::SPEED_OF_LIGHT).round() as u16
+            }));
+            w.zeros(4);
+            w.u16(2);
+            w.zeros(12);
+            w.f32(100.0);
+            w.f32(0.0);
+            w.u32(-32768i32 as u32);
+        }
+
+        // Cell vector
+        w.id("CELV");
+        w.u32(6012);
+        w.u32(self.ngates as u32);
+        for gate in 0..1500 {
+            if gate < self.ngates {
+                w.f32(self.meters_to_first_cell + gate as f32 * self.meters_between_cells);
+            } else {
+                w.f32(0.0);
+            }
+        }
+
+        // Sweep information
+        w.id("SWIB");
+        w.u32(40);
+        w.string(&self.name, 8);
+        w.u32(sweep as u32);
+        w.u32(self.nrays as u32);
+        w.f32(self.azimuth(0));
+        w.f32(self.azimuth(self.nrays - 1));
+        w.f32(self.elevation(sweep));
+        w.zeros(4);
+
+        for ray in 0..self.nrays {
+            let time = self.time(sweep, ray);
+
+            // Ray information
+            w.id("RYIB");
+            w.u32(44);
+            w.u32(sweep as u32);
+            w.u32(time.ordinal());
+            w.u16(time.hour() as u16);
+            w.u16(time.minute() as u16);
+            w.u16(time.second() as u16);
+            w.u16((time.nanosecond() / 1_000_000) as u16);
+            w.f32(self.azimuth(ray));
+            w.f32(self.ray_elevation(sweep, ray));
+            w.f32(0.0);
+            w.f32(360.0 / (self.nrays as f32 * 0.1));
+            w.u32(if ray < self.transition_rays { 1 } else { 0 });
+
+            // Platform information
+            w.id("ASIB");
+            w.u32(80);
+            match &self.platform {
+                Some(platform) => {
+                    w.f32(platform.longitude);
+                    w.f32(platform.latitude);
+                    w.f32(platform.altitude / 1000.0);
+                    w.zeros(4);
+                    platform.velocity.iter().for_each(|&v| w.f32(v));
+                    w.f32(platform.heading);
+                    w.f32(platform.roll);
+                    w.f32(platform.pitch);
+                    w.f32(platform.drift);
+                    w.f32(self.azimuth(ray));
+                    w.f32(platform.tilt);
+                    w.zeros(20);
+                }
+                None => {
+                    w.f32(self.longitude);
+                    w.f32(self.latitude);
+                    w.zeros(64);
+                }
+            }
+
+            for field in &self.fields {
+                let data: Vec<u16> = (0..self.ngates)
+                    .map(|gate| match self.value(field, sweep, ray, gate) {
+                        value if value.is_nan() => -32768i16 as u16,
+                        value => (value * 100.0).round() as i16 as u16,
+                    })
+                    .collect();
+
+                let data = if self.compress {
+                    compress_hrd(&data)
+                } else {
+                    data
+                };
+
+                w.id("RDAT");
+                w.u32(16 + 2 * data.len() as u32);
+                w.string(dorade_name(field), 8);
+                data.into_iter().for_each(|v| w.u16(v));
+            }
+        }
+
+        w.id("NULL");
+        w.u32(8);
+
+        w.bytes
+    }
+
+    /// Encodes the volume as a NEXRAD Level II archive file
+    pub fn nexrad(&self) -> Vec<u8> {
+        let mut w = ByteWriter::big_endian();
+        let (date, ms) = nexrad_date(self.start_time);
+
+        // Volume header
+        w.string("AR2V0006.", 9);
+        w.string("001", 3);
+        w.u32(date);
+        w.u32(ms);
+        w.string(&self.name, 4);
+
+        // Every message is preceded by the 12 byte CTM header
+        let records: Vec<Vec<u8>> = (0..self.nsweeps)
+            .map(|sweep| {
+                let mut record = Vec::new();
+                for ray in 0..self.nrays {
+                    record.extend([0u8; 12]);
+                    record.extend(self.nexrad_message(sweep, ray));
+                }
+                record
+            })
+            .collect();
+
+        if self.compress {
+            for record in records {
+                let mut encoder = BzEncoder::new(Vec::new(), Compression::best());
+                encoder.write_all(&record).unwrap();
+                let compressed = encoder.finish().unwrap();
+
+                w.u32(compressed.len() as u32);
+                w.bytes.extend(compressed);
+            }
+        } else {
+            records
+                .into_iter()
+                .for_each(|record| w.bytes.extend(record));
+        }
+
+        w.bytes
+    }
+
+    /// Encodes a single ray as a message 31
+    fn nexrad_message(&self, sweep: usize, ray: usize) -> Vec<u8> {
+        let fields: Vec<&Field> = self
+            .fields
+            .iter()
+            .filter(|f| {
+                matches!(
+                    f,
+                    Field::REF | Field::VEL | Field::SW | Field::ZDR | Field::PHI | Field::RHO
+                )
+            })
+            .collect();
+        let block_count = 3 + fields.len();
+
+        let mut blocks = ByteWriter::big_endian();
+        let mut ptrs = Vec::new();
+        let header_len = 32 + 4 * block_count as u32;
+
+        // Volume data block
+        ptrs.push(header_len + blocks.bytes.len() as u32);
+        blocks.string("R", 1);
+        blocks.string("VOL", 3);
+        blocks.u16(44);
+        blocks.u8(1);
+        blocks.u8(0);
+        blocks.f32(self.latitude);
+        blocks.f32(self.longitude);
+        blocks.zeros(28);
+
+        // Elevation data block
+        ptrs.push(header_len + blocks.bytes.len() as u32);
+        blocks.string("R", 1);
+        blocks.string("ELV", 3);
+        blocks.u16(12);
+        blocks.zeros(6);
+
+        // Radial data block
+        ptrs.push(header_len + blocks.bytes.len() as u32);
+        blocks.string("R", 1);
+        blocks.string("RAD", 3);
+        blocks.u16(20);
+        blocks.zeros(10);
+        blocks.u16((self.nyquist_velocity * 100.0) as u16);
+        blocks.u16(0);
+
+        for field in fields {
+            let (scale, offset) = nexrad_scale_offset(field);
+            let word_size = if *field == Field::PHI { 16 } else { 8 };
+
+            ptrs.push(header_len + blocks.bytes.len() as u32);
+            blocks.string("D", 1);
+            blocks.bytes.extend(format!("{:<3}", field).as_bytes());
+            blocks.u32(0);
+            blocks.u16(self.ngates as u16);
+            blocks.u16(self.meters_to_first_cell as u16);
+            blocks.u16(self.meters_between_cells as u16);
+            blocks.zeros(4);
+            blocks.u8(0);
+            blocks.u8(word_size);
+            blocks.f32(scale);
+            blocks.f32(offset);
+
+            for gate in 0..self.ngates {
+                let val =
+                    (self.value(field, sweep, ray, gate) * scale as f64 + offset as f64).round();
+
+                if word_size == 16 {
+                    blocks.u16(val as u16);
+                } else {
+                    blocks.u8(val as u8);
+                }
+            }
+        }
+
+        if !blocks.bytes.len().is_multiple_of(2) {
+            blocks.u8(0);
+        }
+
+        let radial_status = if ray == 0 && sweep == 0 {
+            3
+        } else if ray == self.nrays - 1 && sweep == self.nsweeps - 1 {
+            4
+        } else if ray == 0 {
+            0
+        } else if ray == self.nrays - 1 {
+            2
+        } else {
+            1
+        };
+
+        let (date, ms) = nexrad_date(self.time(sweep, ray));
+        let mut w = ByteWriter::big_endian();
+
+        // Message header, with the size in halfwords
+        w.u16(((16 + header_len as usize + blocks.bytes.len()) / 2) as u16);
+        w.u8(0);
+        w.u8(31);
+        w.u16(0);
+        w.u16(date as u16);
+        w.u32(ms);
+        w.u16(1);
+        w.u16(1);
+
+        // Message 31 header
+        w.string(&self.name, 4);
+        w.u32(ms);
+        w.u16(date as u16);
+        w.u16(ray as u16 + 1);
+        w.f32(self.azimuth(ray));
+        w.u8(0);
+        w.u8(0);
+        w.u16(0);
+        w.u8(1);
+        w.u8(radial_status);
+        w.u8(sweep as u8 + 1);
+        w.u8(0);
+        w.f32(self.ray_elevation(sweep, ray));
+        w.u8(0);
+        w.u8(0);
+        w.u16(block_count as u16);
+
+        ptrs.into_iter().for_each(|ptr| w.u32(ptr));
+        w.bytes.extend(blocks.bytes);
+
+        w.bytes
+    }
+
+    /// Writes the volume as a CfRadial file
+    #[cfg(feature = "netcdf")]
+    pub fn write_cfradial(&self, path: impl AsRef<Path>) {
+        let nrays = self.nsweeps * self.nrays;
+        let mut file = netcdf::create(path).unwrap();
+
+        file.add_attribute("Conventions", "CF/Radial").unwrap();
+        file.add_attribute("instrument_name", self.name.as_str())
+            .unwrap();
+        file.add_attribute(
+            "time_coverage_start",
+            self.start_time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        )
+        .unwrap();
+
+        file.add_dimension("time", nrays).unwrap();
+        file.add_dimension("range", self.ngates).unwrap();
+        file.add_dimension("sweep", self.nsweeps).unwrap();
+
+        let times: Vec<f64> = (0..self.nsweeps)
+            .flat_map(|sweep| (0..self.nrays).map(move |ray| (sweep, ray)))
+            .map(|(sweep, ray)| {
+                (self.time(sweep, ray) - self.start_time).num_milliseconds() as f64 / 1000.0
+            })
+            .collect();
+        let mut var = file.add_variable::<f64>("time", &["time"]).unwrap();
+        var.add_attribute(
+            "units",
+            format!(
+                "seconds since {}",
+                self.start_time.format("%Y-%m-%dT%H:%M:%SZ")
+            ),
+        )
+        .unwrap();
+        var.put_values(&times, ..).unwrap();
+
+        let ranges: Vec<f32> = (0..self.ngates)
+            .map(|gate| self.meters_to_first_cell + gate as f32 * self.meters_between_cells)
+            .collect();
+        let mut var = file.add_variable::<f32>("range", &["range"]).unwrap();
+        var.add_attribute("meters_to_center_of_first_gate", self.meters_to_first_cell)
+            .unwrap();
+        var.add_attribute("meters_between_gates", self.meters_between_cells)
+            .unwrap();
+        var.put_values(&ranges, ..).unwrap();
+
+        let mut var = file.add_variable::<f64>("latitude", &[]).unwrap();
+        var.put_value(self.latitude as f64, ..).unwrap();
+        let mut var = file.add_variable::<f64>("longitude", &[]).unwrap();
+        var.put_value(self.longitude as f64, ..).unwrap();
+
+        let starts: Vec<i32> = (0..self.nsweeps).map(|s| (s * self.nrays) as i32).collect();
+        let ends: Vec<i32> = (0..self.nsweeps)
+            .map(|s| ((s + 1) * self.nrays - 1) as i32)
+            .collect();
+        let mut var = file
+            .add_variable::<i32>("sweep_start_ray_index", &["sweep"])
+            .unwrap();
+        var.put_values(&starts, ..).unwrap();
+        let mut var = file
+            .add_variable::<i32>("sweep_end_ray_index", &["sweep"])
+            .unwrap();
+        var.put_values(&ends, ..).unwrap();
+
+        let fixed_angles: Vec<f32> = (0..self.nsweeps).map(|s| self.elevation(s)).collect();
+        let mut var = file.add_variable::<f32>("fixed_angle", &["sweep"]).unwrap();
+        var.put_values(&fixed_angles, ..).unwrap();
+
+        let azimuths: Vec<f32> = (0..nrays).map(|i| self.azimuth(i % self.nrays)).collect();
+        let elevations: Vec<f32> = (0..nrays)
+            .map(|i| self.ray_elevation(i / self.nrays, i % self.nrays))
+            .collect();
+        let nyquist = vec![self.nyquist_velocity; nrays];
+        file.add_variable::<f32>("azimuth", &["time"])
+            .unwrap()
+            .put_values(&azimuths, ..)
+            .unwrap();
+        file.add_variable::<f32>("elevation", &["time"])
+            .unwrap()
+            .put_values(&elevations, ..)
+            .unwrap();
+        file.add_variable::<f32>("nyquist_velocity", &["time"])
+            .unwrap()
+            .put_values(&nyquist, ..)
+            .unwrap();
+
+        for field in &self.fields {
+            let data: Vec<f32> = (0..nrays)
+                .flat_map(|i| (0..self.ngates).map(move |gate| (i, gate)))
+                .map(|(i, gate)| self.value(field, i / self.nrays, i % self.nrays, gate) as f32)
+                .collect();
+
+            let mut var = file
+                .add_variable::<f32>(cfradial_name(field), &["time", "range"])
+                .unwrap();
+            if self.compress {
+                var.compression(4, true).unwrap();
+            }
+            var.put_values(&data, ..).unwrap();
+        }
+    }
+}
+
+/// Writes binary blocks with a fixed byte order
+struct ByteWriter {
+    bytes: Vec<u8>,
+    big_endian: bool,
+}
+
+impl ByteWriter {
+    fn little_endian() -> Self {
+        ByteWriter {
+            bytes: Vec::new(),
+            big_endian: false,
+        }
+    }
+
+    fn big_endian() -> Self {
+        ByteWriter {
+            bytes: Vec::new(),
+            big_endian: true,
+        }
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        let b = if self.big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        };
+        self.bytes.extend(b);
+    }
+
+    fn u32(&mut self, v: u32) {
+        let b = if self.big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        };
+        self.bytes.extend(b);
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.u32(v.to_bits());
+    }
+
+    fn f64(&mut self, v: f64) {
+        let b = if self.big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        };
+        self.bytes.extend(b);
+    }
+
+    fn zeros(&mut self, n: usize) {
+        self.bytes.resize(self.bytes.len() + n, 0);
+    }
+
+    fn id(&mut self, id: &str) {
+        self.string(id, 4);
+    }
+
+    /// Writes a string truncated or zero padded to `len` bytes
+    fn string(&mut self, s: &str, len: usize) {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize(len, 0);
+        self.bytes.extend(bytes);
+    }
+}
+
+/// Compresses 16 bit data using HRD run length encoding
+fn compress_hrd(data: &[u16]) -> Vec<u16> {
+    let mut out = Vec::with_capacity(data.len() + 2);
+
+    for chunk in data.chunks(0x7fff) {
+        out.push(0x8000 | chunk.len() as u16);
+        out.extend_from_slice(chunk);
+    }
+
+    out.push(1);
+    out
+}
+
+/// Converts to the date and time format NEXRAD uses
+fn nexrad_date(time: DateTime<Utc>) -> (u32, u32) {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+
+    (
+        (time.date_naive() - epoch).num_days() as u32 + 1,
+        time.num_seconds_from_midnight() * 1000 + time.timestamp_subsec_millis(),
+    )
+}
+
+fn nexrad_scale_offset(field: &Field) -> (f32, f32) {
+    match field {
+        Field::REF => (2.0, 66.0),
+        Field::VEL => (2.0, 129.0),
+        Field::SW => (2.0, 129.9),
+        Field::ZDR => (16.0, 128.0),
+        Field::PHI => (2.8261, 2.0),
+        Field::RHO => (300.0, -60.5),
+        _ => (1.0, 0.0),
+    }
+}
+
+fn dorade_name(field: &Field) -> &str {
+    match field {
+        Field::REF => "DBZ",
+        Field::SW => "WIDTH",
+        Field::RHO => "RHOHV",
+        field => field.as_str(),
+    }
+}
+
+#[cfg(feature = "netcdf")]
+fn cfradial_name(field: &Field) -> &str {
+    match field {
+        Field::REF => "DBZ",
+        Field::SW => "WIDTH",
+        Field::RHO => "RHOHV",
+        Field::PHI => "PHIDP",
+        field => field.as_str(),
+    }
+}
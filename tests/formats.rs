@@ -0,0 +1,4302 @@
+use silv::testdata::SyntheticVolume;
+use silv::{Field, RadarFile, RadyOptions};
+use std::path::Path;
+
+fn assert_matches(radar: &RadarFile, synth: &SyntheticVolume, sweeps: &[usize], tolerance: f64) {
+    assert_eq!(radar.name, synth.name);
+    assert_eq!(radar.sweeps.len(), sweeps.len());
+
+    for (sweep, &index) in radar.sweeps.iter().zip(sweeps) {
+        assert_eq!(sweep.rays.len(), synth.nrays);
+        assert!((sweep.elevation - synth.elevation(index)).abs() < 0.01);
+        assert!((sweep.latitude - synth.latitude).abs() < 1e-4);
+
+        for (i, ray) in sweep.rays.iter().enumerate() {
+            assert!((ray.azimuth - synth.azimuth(i)).abs() < 1e-3);
+            assert_eq!(ray.time, synth.time(index, i));
+
+            for field in &synth.fields {
+                let data = &ray.data[field];
+                assert_eq!(data.len(), synth.ngates);
+
+                for (gate, &value) in data.iter().enumerate() {
+                    let expected = synth.value(field, index, i, gate);
+                    assert!(
+                        (value - expected).abs() <= tolerance
+                            || (value.is_nan() && expected.is_nan()),
+                        "{field} sweep {index} ray {i} gate {gate}: {value} != {expected}"
+                    );
+                }
+            }
+        }
+    }
+
+    for field in &synth.fields {
+        let param = &radar.params[field];
+        assert_eq!(param.meters_to_first_cell, synth.meters_to_first_cell);
+        assert_eq!(param.meters_between_cells, synth.meters_between_cells);
+    }
+}
+
+fn read_bytes(dir: &Path, name: &str, bytes: &[u8]) -> RadarFile {
+    let path = dir.join(name);
+    std::fs::write(&path, bytes).unwrap();
+    silv::read(&path, &RadyOptions::default())
+}
+
+/// Puts a DORADE CFAC block with the given correction factors before the radar description
+fn add_cfac(bytes: &mut Vec<u8>, corrections: [f32; 16]) {
+    let cfac = [
+        &b"CFAC"[..],
+        &72u32.to_le_bytes(),
+        &corrections.map(f32::to_le_bytes).concat(),
+    ]
+    .concat();
+    let at = bytes
+        .windows(4)
+        .position(|window| window == b"RADD")
+        .unwrap();
+    bytes.splice(at..at, cfac);
+}
+
+#[test]
+fn reads_dorade() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let radar = read_bytes(dir.path(), "swp", &synth.dorade(1));
+    assert_matches(&radar, &synth, &[1], 0.006);
+}
+
+#[test]
+fn reads_compressed_dorade() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        compress: true,
+        fields: vec![Field::REF, Field::VEL, Field::ZDR],
+        ..Default::default()
+    };
+
+    let radar = read_bytes(dir.path(), "swp", &synth.dorade(0));
+    assert_matches(&radar, &synth, &[0], 0.006);
+}
+
+#[test]
+fn reads_nexrad() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    assert_matches(&radar, &synth, &[0, 1], 0.0);
+}
+
+#[test]
+fn reads_compressed_nexrad() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        compress: true,
+        nsweeps: 3,
+        ..Default::default()
+    };
+
+    let radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    assert_matches(&radar, &synth, &[0, 1, 2], 0.0);
+}
+
+#[test]
+fn decodes_nexrad_sweeps_in_parallel() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        compress: true,
+        nsweeps: 4,
+        fields: vec![Field::REF, Field::VEL, Field::ZDR],
+        ..Default::default()
+    };
+
+    let path = dir.path().join("vol");
+    std::fs::write(&path, synth.nexrad()).unwrap();
+
+    let radar = silv::read(
+        &path,
+        &RadyOptions {
+            jobs: 4,
+            ..Default::default()
+        },
+    );
+    assert_matches(&radar, &synth, &[0, 1, 2, 3], 0.0);
+
+    let fields = RadyOptions {
+        jobs: 4,
+        fields: Some(vec![Field::VEL]),
+        ..Default::default()
+    };
+    assert_eq!(
+        silv::read(&path, &fields).params.keys().collect::<Vec<_>>(),
+        [&Field::VEL]
+    );
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn reads_cfradial() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        compress: true,
+        fields: vec![Field::REF, Field::VEL, Field::RHO],
+        ..Default::default()
+    };
+
+    let path = dir.path().join("cfrad.nc");
+    synth.write_cfradial(&path);
+
+    let radar = silv::read(&path, &RadyOptions::default());
+    assert_matches(&radar, &synth, &[0, 1], 1e-5);
+}
+
+#[test]
+fn nexrad_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::VEL, Field::SW],
+        ngates: 120,
+        ..Default::default()
+    };
+
+    let options = RadyOptions::default();
+    let radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let out = dir.path().join("output");
+    silv::write(radar, &out, &options).unwrap();
+
+    let written: Vec<_> = std::fs::read_dir(&out).unwrap().collect();
+    assert_eq!(written.len(), 1);
+
+    let radar = silv::read(written[0].as_ref().unwrap().path(), &options);
+    assert_matches(&radar, &synth, &[0, 1], 0.5);
+}
+
+/// Stores a radar as just its name, behind a "TOY!" magic
+struct Toy;
+
+impl silv::RadarRead for Toy {
+    fn name(&self) -> &'static str {
+        "toy"
+    }
+
+    fn sniff(&self, path: &Path) -> bool {
+        std::fs::read(path)
+            .map(|b| b.starts_with(b"TOY!"))
+            .unwrap_or(false)
+    }
+
+    fn read(&self, path: &Path, _options: &RadyOptions) -> Result<RadarFile, silv::Error> {
+        let bytes = std::fs::read(path)?;
+        Ok(RadarFile {
+            name: String::from_utf8(bytes[4..].to_vec()).unwrap(),
+            sweeps: Vec::new(),
+            params: Default::default(),
+            instrument: Default::default(),
+            scan_strategy: None,
+            volume_number: None,
+            corrections: None,
+        })
+    }
+}
+
+impl silv::RadarWrite for Toy {
+    fn name(&self) -> &'static str {
+        "toy"
+    }
+
+    fn write(
+        &self,
+        radar: RadarFile,
+        path: &Path,
+        _options: &RadyOptions,
+    ) -> Result<(), silv::Error> {
+        std::fs::create_dir_all(path)?;
+        std::fs::write(
+            path.join("radar.toy"),
+            [b"TOY!", radar.name.as_bytes()].concat(),
+        )?;
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_format_registry() {
+    let dir = tempfile::tempdir().unwrap();
+    silv::register_reader(Toy);
+    silv::register_writer(Toy);
+
+    assert_eq!("Toy".parse(), Ok(silv::Format::Custom("toy")));
+    #[cfg(feature = "netcdf")]
+    assert_eq!("CfRadial".parse(), Ok(silv::Format::CFRADIAL));
+    #[cfg(not(feature = "netcdf"))]
+    assert_eq!(
+        "CfRadial".parse::<silv::Format>(),
+        Err("This build can't write CfRadial".to_string())
+    );
+    assert!("uf".parse::<silv::Format>().is_err());
+    assert_eq!(silv::Format::NEXRAD.to_string(), "nexrad");
+
+    let options = RadyOptions {
+        format: "toy".parse().unwrap(),
+        ..Default::default()
+    };
+
+    let synth = SyntheticVolume::default();
+    let radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let out = dir.path().join("output");
+    silv::write(radar, &out, &options).unwrap();
+
+    assert_eq!(silv::detect(&out.join("radar.toy")).unwrap().name(), "toy");
+    assert_eq!(silv::read(out.join("radar.toy"), &options).name, synth.name);
+}
+
+#[test]
+fn builds_custom_nexrad_messages() {
+    use silv::nexrad::{
+        pack_volume_header, write_message, MomentBlock, Msg31Builder, RadialStatus,
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let mut bytes = pack_volume_header(&synth.name, synth.time(0, 0));
+    for ray in 0..synth.nrays {
+        let status = match ray {
+            0 => RadialStatus::StartOfVolume,
+            r if r == synth.nrays - 1 => RadialStatus::EndOfVolume,
+            _ => RadialStatus::Intermediate,
+        };
+
+        let values: Vec<f64> = (0..synth.ngates)
+            .map(|gate| synth.value(&Field::REF, 0, ray, gate))
+            .collect();
+        let clutter: Vec<f64> = (0..synth.ngates).map(|gate| (gate % 4) as f64).collect();
+
+        let msg = Msg31Builder::new(
+            &synth.name,
+            synth.time(0, ray),
+            synth.azimuth(ray),
+            synth.elevation(0),
+        )
+        .azimuth_number(ray as u16 + 1)
+        .radial_status(status)
+        .location(synth.latitude, synth.longitude)
+        .moment(MomentBlock::new(Field::REF, 2125, 250, &values))
+        .moment(MomentBlock::new(Field::CFP, 2125, 250, &clutter));
+
+        write_message(&mut bytes, &msg.build()).unwrap();
+    }
+
+    let radar = read_bytes(dir.path(), "vol", &bytes);
+    assert_eq!(radar.sweeps.len(), 1);
+    assert_eq!(radar.sweeps[0].rays.len(), synth.nrays);
+
+    let ray = &radar.sweeps[0].rays[7];
+    assert_eq!(ray.data[&Field::REF][3], synth.value(&Field::REF, 0, 7, 3));
+    assert_eq!(ray.data[&Field::CFP][3], 3.0);
+}
+
+#[test]
+fn decodes_only_requested_fields() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::VEL, Field::ZDR],
+        ..Default::default()
+    };
+
+    let options = RadyOptions {
+        fields: Some(vec![Field::REF]),
+        ..Default::default()
+    };
+
+    for (name, bytes) in [("vol", synth.nexrad()), ("swp", synth.dorade(0))] {
+        let path = dir.path().join(name);
+        std::fs::write(&path, bytes).unwrap();
+
+        let radar = silv::read(&path, &options);
+        assert_eq!(radar.params.keys().collect::<Vec<_>>(), [&Field::REF]);
+        assert!(radar.sweeps[0].rays.iter().all(|ray| ray.data.len() == 1));
+    }
+
+    let mut lazy = silv::nexrad::LazyNexrad::open(dir.path().join("vol")).unwrap();
+    assert_eq!(lazy.fields().len(), 3);
+    assert!(lazy.radar().sweeps[0].rays[0].data.is_empty());
+
+    lazy.load(&Field::VEL).unwrap();
+    let radar = lazy.into_radar();
+    assert_eq!(radar.params.keys().collect::<Vec<_>>(), [&Field::VEL]);
+    assert_eq!(
+        radar.sweeps[1].rays[5].data[&Field::VEL][9],
+        synth.value(&Field::VEL, 1, 5, 9)
+    );
+}
+
+#[test]
+fn reassembles_segmented_messages() {
+    use silv::nexrad::{
+        pack_volume_header, write_message, MomentBlock, Msg31Builder, RadialStatus,
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let mut bytes = pack_volume_header(&synth.name, synth.time(0, 0));
+    for ray in 0..synth.nrays {
+        let status = match ray {
+            0 => RadialStatus::StartOfVolume,
+            r if r == synth.nrays - 1 => RadialStatus::EndOfVolume,
+            _ => RadialStatus::Intermediate,
+        };
+
+        let values: Vec<f64> = (0..synth.ngates)
+            .map(|gate| synth.value(&Field::REF, 0, ray, gate))
+            .collect();
+        let msg = Msg31Builder::new(
+            &synth.name,
+            synth.time(0, ray),
+            synth.azimuth(ray),
+            synth.elevation(0),
+        )
+        .radial_status(status)
+        .moment(MomentBlock::new(Field::REF, 2125, 250, &values))
+        .build();
+
+        // Split the message body into two segments, each with its own header
+        let (header, body) = msg.split_at(16);
+        let (first, second) = body.split_at(body.len() / 4 * 2);
+
+        for (seg_num, part) in [(1u16, first), (2, second)] {
+            let mut segment = header.to_vec();
+            segment[0..2].copy_from_slice(&(((16 + part.len()) / 2) as u16).to_be_bytes());
+            segment[12..14].copy_from_slice(&2u16.to_be_bytes());
+            segment[14..16].copy_from_slice(&seg_num.to_be_bytes());
+            segment.extend_from_slice(part);
+
+            write_message(&mut bytes, &segment).unwrap();
+        }
+    }
+
+    let radar = read_bytes(dir.path(), "vol", &bytes);
+    assert_eq!(radar.sweeps.len(), 1);
+    assert_eq!(radar.sweeps[0].rays.len(), synth.nrays);
+
+    let ray = &radar.sweeps[0].rays[11];
+    assert_eq!(ray.data[&Field::REF].len(), synth.ngates);
+    assert_eq!(
+        ray.data[&Field::REF][99],
+        synth.value(&Field::REF, 0, 11, 99)
+    );
+}
+
+#[test]
+fn falls_back_to_radial_icao() {
+    use silv::nexrad::{
+        pack_volume_header, write_message, MomentBlock, Msg31Builder, RadialStatus,
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    // Blank ICAO in the volume header
+    let mut bytes = pack_volume_header("", synth.time(0, 0));
+    for (ray, status) in [
+        (0, RadialStatus::StartOfVolume),
+        (1, RadialStatus::EndOfVolume),
+    ] {
+        let values = vec![10.0; synth.ngates];
+        let msg = Msg31Builder::new(
+            &synth.name,
+            synth.time(0, ray),
+            synth.azimuth(ray),
+            synth.elevation(0),
+        )
+        .radial_status(status)
+        .moment(MomentBlock::new(Field::REF, 2125, 250, &values));
+
+        write_message(&mut bytes, &msg.build()).unwrap();
+    }
+
+    assert_eq!(read_bytes(dir.path(), "vol", &bytes).name, synth.name);
+}
+
+#[test]
+fn counts_clipped_gates() {
+    use silv::nexrad::{ClipCounts, MomentBlock};
+
+    let moment = MomentBlock::new(Field::REF, 2125, 250, &[-40.0, 10.0, 200.0, silv::MISSING]);
+    assert_eq!(moment.gates, [0, 86, 0, 0]);
+    assert_eq!(moment.clipped, ClipCounts { below: 1, above: 1 });
+}
+
+#[test]
+fn shifts_leading_edge_azimuths() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let options = RadyOptions {
+        azimuth_in: silv::AzimuthRef::LeadingEdge,
+        ..Default::default()
+    };
+
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    options.apply_options(&mut radar).unwrap();
+
+    let sweep = &radar.sweeps[0];
+    assert_eq!(sweep.azimuth_spacing(), 1.0);
+    assert_eq!(sweep.rays[0].azimuth, 359.5);
+    assert_eq!(sweep.rays[10].azimuth, 9.5);
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn writes_vertical_pointing_cfradial() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        nsweeps: 1,
+        ..Default::default()
+    };
+
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    let sweep = &mut radar.sweeps[0];
+    sweep.scan_mode = silv::ScanMode::Vertical;
+    sweep.elevation = 90.0;
+
+    // A slowly wobbling antenna, which the rotation heuristics would reorder
+    for (i, ray) in sweep.rays.iter_mut().enumerate() {
+        ray.azimuth = [10.0, 350.0, 5.0][i % 3];
+    }
+
+    let options = RadyOptions {
+        format: silv::Format::CFRADIAL,
+        trim_rays: true,
+        split_overlap_rays: true,
+        sort_rays_by_azimuth: true,
+        time_height: true,
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+    assert_eq!(radar.sweeps[0].rays[1].azimuth, 350.0);
+
+    let out = dir.path().join("output");
+    silv::write(radar, &out, &options).unwrap();
+
+    let mut written: Vec<_> = std::fs::read_dir(&out)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    written.sort();
+    assert_eq!(written.len(), 2);
+
+    let radar = silv::read(&written[0], &RadyOptions::default());
+    assert_eq!(radar.sweeps[0].scan_mode, silv::ScanMode::Vertical);
+    assert_eq!(radar.sweeps[0].rays.len(), synth.nrays);
+    assert_eq!(radar.sweeps[0].rays[4].azimuth, 350.0);
+    assert_eq!(
+        radar.sweeps[0].rays[4].data[&Field::REF][7],
+        synth.value(&Field::REF, 0, 4, 7)
+    );
+
+    let time_height = netcdf::open(&written[1]).unwrap();
+    assert_eq!(time_height.dimension("time").unwrap().len(), synth.nrays);
+    assert_eq!(time_height.dimension("height").unwrap().len(), synth.ngates);
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn translates_missing_gates() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        missing_gates: true,
+        fields: vec![Field::REF, Field::VEL],
+        ..Default::default()
+    };
+
+    let cfradial = dir.path().join("cfrad.nc");
+    synth.write_cfradial(&cfradial);
+
+    for radar in [
+        read_bytes(dir.path(), "swp", &synth.dorade(0)),
+        read_bytes(dir.path(), "vol", &synth.nexrad()),
+        silv::read(&cfradial, &RadyOptions::default()),
+    ] {
+        let mask = radar.sweeps[0].mask(&Field::REF);
+        assert!(!mask[5][3] && !mask[5][10] && mask[5][4]);
+        assert!(radar.sweeps[0].rays[5].data[&Field::VEL][3].is_nan());
+    }
+
+    let options = RadyOptions {
+        format: silv::Format::CFRADIAL,
+        ..Default::default()
+    };
+
+    let out = dir.path().join("output");
+    silv::write(
+        read_bytes(dir.path(), "vol", &synth.nexrad()),
+        &out,
+        &options,
+    )
+    .unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    assert_matches(
+        &silv::read(written, &RadyOptions::default()),
+        &synth,
+        &[0, 1],
+        1e-5,
+    );
+}
+
+#[test]
+fn masks_rays_without_a_field() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::VEL],
+        ..Default::default()
+    };
+
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    let sweep = &mut radar.sweeps[0];
+    sweep.rays[5].data.remove(&Field::VEL);
+    let ngates = sweep.ngates(&Field::VEL);
+
+    let mask = sweep.mask(&Field::VEL);
+    assert_eq!(mask[5], vec![false; ngates]);
+    assert!(mask[4].iter().any(|&valid| valid));
+
+    let data = sweep.get_data(&Field::VEL);
+    assert_eq!(data[5].len(), ngates);
+    assert!(data[5].iter().all(|v| v.is_nan()));
+
+    assert!(sweep.mask(&Field::ZDR).iter().all(Vec::is_empty));
+}
+
+#[test]
+fn drops_calibration_sweeps() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        nsweeps: 3,
+        ..Default::default()
+    };
+
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    radar.sweeps[0].scan_mode = silv::ScanMode::Calibration;
+    radar.sweeps[2].scan_mode = silv::ScanMode::Idle;
+
+    let options = RadyOptions {
+        keep_calibration: true,
+        ..Default::default()
+    };
+
+    let mut kept = radar.clone();
+    options.apply_options(&mut kept).unwrap();
+    assert_eq!(kept.sweeps.len(), 3);
+
+    RadyOptions::default().apply_options(&mut radar).unwrap();
+    assert_eq!(radar.sweeps.len(), 1);
+    assert_eq!(radar.sweeps[0].elevation, synth.elevation(1));
+}
+
+#[test]
+fn uses_dorade_fixed_angle() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        transition_rays: 3,
+        ..Default::default()
+    };
+
+    let radar = read_bytes(dir.path(), "swp", &synth.dorade(1));
+    assert_eq!(radar.sweeps[0].elevation, synth.elevation(1));
+    assert_eq!(radar.sweeps[0].rays[0].elevation, synth.ray_elevation(1, 0));
+    assert_eq!(radar.sweeps[0].rays[3].elevation, synth.elevation(1));
+}
+
+#[test]
+fn georeferences_airborne_dorade() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        platform: Some(silv::Platform {
+            heading: 45.0,
+            roll: 10.0,
+            altitude: 3000.0,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let mut bytes = synth.dorade(0);
+
+    // Recorded angles are kept, along with the attitude of the aircraft
+    let radar = read_bytes(dir.path(), "swp", &bytes);
+    let ray = &radar.sweeps[0].rays[90];
+    assert_eq!(
+        (ray.azimuth, ray.elevation),
+        (synth.azimuth(90), synth.elevation(0))
+    );
+    assert_eq!(ray.platform.unwrap().rotation, 90.0);
+    assert_eq!(ray.platform.unwrap().altitude, 3000.0);
+
+    // The beam pointing out the right wing tilts down with it
+    let options = RadyOptions {
+        georeference: true,
+        ..Default::default()
+    };
+    let radar = silv::read_from_bytes(&bytes, &options).unwrap().unwrap();
+    let angles = |ray: &silv::Ray| (ray.azimuth, ray.elevation);
+    let (azimuth, elevation) = angles(&radar.sweeps[0].rays[90]);
+    assert!((azimuth - 135.0).abs() < 1e-3 && (elevation + 10.0).abs() < 1e-3);
+    let (azimuth, elevation) = angles(&radar.sweeps[0].rays[0]);
+    assert!((azimuth - 135.0).abs() < 1e-3 && (elevation - 80.0).abs() < 1e-3);
+
+    // A correction factor for the roll levels the beam
+    let mut corrections = [0f32; 16];
+    corrections[11] = -10.0;
+    add_cfac(&mut bytes, corrections);
+
+    let radar = silv::read_from_bytes(&bytes, &options).unwrap().unwrap();
+    let (azimuth, elevation) = angles(&radar.sweeps[0].rays[90]);
+    assert!((azimuth - 135.0).abs() < 1e-3 && elevation.abs() < 1e-3);
+}
+
+#[test]
+fn applies_dorade_corrections() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut bytes = synth.dorade(0);
+
+    // Azimuth, elevation, range delay, and latitude corrections
+    let mut corrections = [0f32; 16];
+    corrections[..4].copy_from_slice(&[1.5, -0.25, 30.0, 0.0]);
+    corrections[4] = 0.01;
+    add_cfac(&mut bytes, corrections);
+
+    // The corrections are recorded and listed, but not applied unless asked for
+    let mut radar = read_bytes(dir.path(), "swp", &bytes);
+    assert_matches(&radar, &synth, &[0], 0.006);
+    let recorded = radar.corrections.unwrap();
+    assert_eq!(
+        (recorded.azimuth, recorded.range_delay, recorded.latitude),
+        (1.5, 30.0, 0.01)
+    );
+
+    let info = silv::FileInfo::new(&radar).to_string();
+    assert!(
+        info.contains("Corrections: azimuth 1.5, elevation -0.25, range delay 30, latitude 0.01")
+    );
+
+    let options = RadyOptions {
+        apply_corrections: true,
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    let sweep = &radar.sweeps[0];
+    let ray = sweep
+        .rays
+        .iter()
+        .find(|ray| ray.time == synth.time(0, 10))
+        .unwrap();
+    assert_eq!(ray.azimuth, synth.azimuth(10) + 1.5);
+    assert_eq!(ray.elevation, synth.elevation(0) - 0.25);
+    assert_eq!(sweep.latitude, synth.latitude + 0.01);
+    assert_eq!(
+        radar.params[&Field::REF].meters_to_first_cell,
+        synth.meters_to_first_cell + 30.0
+    );
+}
+
+#[test]
+fn keeps_fixed_angles_apart_from_measured() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        nsweeps: 1,
+        transition_rays: 36,
+        ..Default::default()
+    };
+
+    let radar = read_bytes(dir.path(), "swp", &synth.dorade(0));
+    assert_eq!(radar.sweeps[0].fixed_angle, Some(synth.elevation(0)));
+
+    // Without a type 5 message, the elevation is measured from the rays, which are low while
+    // the antenna moves to the cut
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    assert_eq!(radar.sweeps[0].fixed_angle, None);
+    assert!(radar.sweeps[0].elevation < 0.46);
+
+    radar.sweeps[0].fixed_angle = Some(synth.elevation(0));
+    let out = dir.path().join("out");
+    silv::write(radar, &out, &RadyOptions::default()).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    assert!(written.to_str().unwrap().ends_with("_0.5"));
+
+    let radar = silv::read(written, &RadyOptions::default());
+    let sweep = &radar.sweeps[0];
+    assert!((sweep.fixed_angle.unwrap() - 0.5).abs() < 0.01);
+    assert!(sweep
+        .rays
+        .iter()
+        .all(|ray| (ray.elevation - 0.5).abs() < 0.01));
+}
+
+#[test]
+fn drops_antenna_transition_rays() {
+    use silv::nexrad::{
+        pack_vcp, pack_volume_header, write_message, MomentBlock, Msg31Builder, RadialStatus,
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        nsweeps: 1,
+        transition_rays: 5,
+        ..Default::default()
+    };
+
+    // DORADE flags the transition rays in their ray information
+    let mut radar = read_bytes(dir.path(), "swp", &synth.dorade(0));
+    let flagged: Vec<bool> = radar.sweeps[0]
+        .rays
+        .iter()
+        .map(|ray| ray.transition)
+        .collect();
+    assert_eq!(
+        flagged,
+        (0..synth.nrays).map(|ray| ray < 5).collect::<Vec<_>>()
+    );
+
+    #[cfg(feature = "netcdf")]
+    {
+        let out = dir.path().join("cfradial");
+        silv::write(
+            radar.clone(),
+            &out,
+            &RadyOptions {
+                format: silv::Format::CFRADIAL,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let written = std::fs::read_dir(&out)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let written = silv::read(written, &RadyOptions::default());
+        assert_eq!(
+            written.sweeps[0]
+                .rays
+                .iter()
+                .filter(|ray| ray.transition)
+                .count(),
+            5
+        );
+    }
+
+    let options = RadyOptions {
+        drop_transitions: true,
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+    assert_eq!(radar.sweeps[0].rays.len(), synth.nrays - 5);
+    assert_eq!(radar.sweeps[0].rays[0].azimuth, synth.azimuth(5));
+
+    // NEXRAD rays far below the cut of the type 5 message are transitions
+    let strategy = silv::ScanStrategy {
+        vcp: 212,
+        cuts: vec![silv::ScanCut {
+            elevation: 0.5,
+            waveform: Default::default(),
+        }],
+    };
+    let mut bytes = pack_volume_header(&synth.name, synth.time(0, 0));
+    write_message(&mut bytes, &pack_vcp(&strategy, synth.time(0, 0))).unwrap();
+    for ray in 0..synth.nrays {
+        let status = match ray {
+            0 => RadialStatus::StartOfVolume,
+            r if r == synth.nrays - 1 => RadialStatus::EndOfVolume,
+            _ => RadialStatus::Intermediate,
+        };
+
+        let values: Vec<f64> = (0..synth.ngates)
+            .map(|gate| synth.value(&Field::REF, 0, ray, gate))
+            .collect();
+        let msg = Msg31Builder::new(
+            &synth.name,
+            synth.time(0, ray),
+            synth.azimuth(ray),
+            if ray < 5 { -0.4 } else { 0.4 },
+        )
+        .azimuth_number(ray as u16 + 1)
+        .elevation_number(1)
+        .radial_status(status)
+        .moment(MomentBlock::new(Field::REF, 2125, 250, &values));
+
+        write_message(&mut bytes, &msg.build()).unwrap();
+    }
+
+    let radar = read_bytes(dir.path(), "vol", &bytes);
+    let transitions = radar.sweeps[0]
+        .rays
+        .iter()
+        .filter(|ray| ray.transition)
+        .count();
+    assert_eq!(transitions, 5);
+}
+
+#[test]
+fn skips_unknown_dorade_blocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut bytes = synth.dorade(0);
+
+    // Blocks the reader doesn't model, put before the first of another block
+    let block = |id: &[u8; 4]| [&id[..], &16u32.to_le_bytes(), &[0xab; 8]].concat();
+    for (before, id) in [
+        (b"VOLD", b"XTRA"),
+        (b"PARM", b"FRIB"),
+        (b"RYIB", b"SEDS"),
+        (b"RDAT", b"FRAD"),
+        (b"NULL", b"RKTB"),
+    ] {
+        let at = bytes
+            .windows(4)
+            .position(|window| window == before)
+            .unwrap();
+        bytes.splice(at..at, block(id));
+    }
+
+    let radar = read_bytes(dir.path(), "swp", &bytes);
+    assert_matches(&radar, &synth, &[0], 0.006);
+}
+
+#[test]
+fn serializes_radar_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        missing_gates: true,
+        fields: vec![Field::REF, Field::VEL, Field::Other("NCP".to_string())],
+        ..Default::default()
+    };
+
+    let radar = read_bytes(dir.path(), "swp", &synth.dorade(1));
+
+    let cached: RadarFile = bincode::deserialize(&bincode::serialize(&radar).unwrap()).unwrap();
+    assert_matches(&cached, &synth, &[1], 0.006);
+
+    let cached: RadarFile = serde_json::from_str(&serde_json::to_string(&radar).unwrap()).unwrap();
+    assert_matches(&cached, &synth, &[1], 0.006);
+    assert_eq!(cached.sweeps[0].scan_mode, radar.sweeps[0].scan_mode);
+
+    // Sweeps saved before they had their own gate geometry still deserialize
+    let mut json = serde_json::to_value(&radar).unwrap();
+    for sweep in json["sweeps"].as_array_mut().unwrap() {
+        sweep.as_object_mut().unwrap().remove("gates");
+    }
+    let cached: RadarFile = serde_json::from_value(json).unwrap();
+    assert_matches(&cached, &synth, &[1], 0.006);
+}
+
+#[test]
+fn writes_cut_type_from_scan_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        nsweeps: 1,
+        ..Default::default()
+    };
+
+    // Offset of the azimuth resolution in the first type 31 header, after the volume header,
+    // the fixed size type 5 message, CTM record, and message header
+    let header = 24 + 2432 + silv::nexrad::CTM_SIZE + 16 + 20;
+
+    for (scan_mode, expected) in [
+        (silv::ScanMode::PPI, [2, 1, 100]),
+        (silv::ScanMode::RHI, [2, 0, 0]),
+    ] {
+        let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+        radar.sweeps[0].scan_mode = scan_mode;
+
+        let out = dir.path().join(format!("{:?}", scan_mode));
+        silv::write(radar, &out, &RadyOptions::default()).unwrap();
+
+        let written = std::fs::read_dir(&out)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let bytes = std::fs::read(written).unwrap();
+        assert_eq!(
+            [bytes[header], bytes[header + 3], bytes[header + 9]],
+            expected
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn keeps_rhi_and_sector_scans() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // An RHI at 270 degrees with its rays out of order, and a sector across north scanned
+    // counterclockwise
+    let rhi = &mut radar.sweeps[0];
+    rhi.scan_mode = silv::ScanMode::RHI;
+    rhi.elevation = 270.0;
+    rhi.rays.truncate(90);
+    for (i, ray) in rhi.rays.iter_mut().enumerate() {
+        (ray.azimuth, ray.elevation) = (270.0, ((i * 37) % 90) as f32);
+    }
+
+    let sector = &mut radar.sweeps[1];
+    sector.scan_mode = silv::ScanMode::Sector;
+    sector.rays.truncate(40);
+    for (i, ray) in sector.rays.iter_mut().enumerate() {
+        ray.azimuth = (20.0 - i as f32).rem_euclid(360.0);
+    }
+
+    let options = RadyOptions {
+        trim_rays: true,
+        split_overlap_rays: true,
+        sort_rays_by_azimuth: true,
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    assert_eq!(radar.sweeps.len(), 2);
+    let elevations: Vec<f32> = radar.sweeps[0]
+        .rays
+        .iter()
+        .map(|ray| ray.elevation)
+        .collect();
+    assert_eq!(elevations, (0..90).map(|e| e as f32).collect::<Vec<_>>());
+
+    // A ray without a measured elevation goes last instead of stopping the sort
+    let mut unmeasured = radar.sweeps[0].clone();
+    unmeasured.rays[3].elevation = f32::NAN;
+    unmeasured.sort_rays_by_azimuth();
+    assert!(unmeasured.rays.last().unwrap().elevation.is_nan());
+
+    let azimuths: Vec<f32> = radar.sweeps[1].rays.iter().map(|ray| ray.azimuth).collect();
+    assert_eq!(
+        (azimuths.len(), azimuths[0], azimuths[39]),
+        (40, 341.0, 20.0)
+    );
+
+    // Files of RHIs are named with their azimuth
+    let options = RadyOptions {
+        format: silv::Format::CFRADIAL,
+        write_separate: true,
+        ..Default::default()
+    };
+    silv::write(radar, dir.path().join("out"), &options).unwrap();
+
+    let written = |suffix: &str| {
+        let path = std::fs::read_dir(dir.path().join("out"))
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|path| path.to_str().unwrap().ends_with(suffix))
+            .unwrap();
+        silv::read(path, &RadyOptions::default()).sweeps.remove(0)
+    };
+
+    let rhi = written("_rhi_270.0.nc");
+    assert_eq!(
+        (rhi.scan_mode, rhi.elevation, rhi.rays.len()),
+        (silv::ScanMode::RHI, 270.0, 90)
+    );
+    assert_eq!(written("_1.5.nc").scan_mode, silv::ScanMode::Sector);
+}
+
+#[test]
+fn merges_fields_and_sweeps() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::VEL],
+        ..Default::default()
+    };
+
+    let single = |field: Field, sweep: usize| {
+        let synth = SyntheticVolume {
+            fields: vec![field],
+            ..synth.clone()
+        };
+        read_bytes(dir.path(), "swp", &synth.dorade(sweep))
+    };
+
+    let mut radar = single(Field::REF, 0);
+    radar.merge(single(Field::VEL, 0));
+    radar.merge(single(Field::VEL, 1));
+    radar.merge(single(Field::REF, 1));
+    radar.merge(single(Field::REF, 1));
+
+    assert_matches(&radar, &synth, &[0, 1], 0.006);
+
+    // A sweep without rays has no time, so it goes last
+    let mut empty = single(Field::ZDR, 0);
+    empty.sweeps[0].rays.clear();
+    radar.merge(empty);
+    assert!(radar.sweeps.last().unwrap().rays.is_empty());
+
+    let options = RadyOptions {
+        write_volumes: true,
+        ..Default::default()
+    };
+    silv::write(radar, dir.path().join("out"), &options).unwrap();
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn aggregates_volumes_along_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = SyntheticVolume::default();
+    let second = SyntheticVolume {
+        start_time: first.start_time + chrono::Duration::minutes(5),
+        ..Default::default()
+    };
+
+    let radars = vec![
+        read_bytes(dir.path(), "vol2", &second.nexrad()),
+        read_bytes(dir.path(), "vol1", &first.nexrad()),
+    ];
+
+    let out = dir.path().join("output");
+    silv::write_aggregate(radars, &out, &RadyOptions::default()).unwrap();
+
+    let written: Vec<_> = std::fs::read_dir(&out).unwrap().collect();
+    assert_eq!(written.len(), 1);
+
+    let radar = silv::read(written[0].as_ref().unwrap().path(), &RadyOptions::default());
+    let times: Vec<_> = radar.sweeps.iter().map(|sweep| sweep.time()).collect();
+    assert_eq!(
+        times,
+        [
+            first.time(0, 0),
+            first.time(1, 0),
+            second.time(0, 0),
+            second.time(1, 0)
+        ]
+    );
+    assert_eq!(
+        radar.sweeps[3].rays[20].data[&Field::VEL][30],
+        second.value(&Field::VEL, 1, 20, 30)
+    );
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn maps_field_names_from_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let vraddh = Field::Other("VRADDH".to_string());
+    let dbzhc_v = Field::Other("DBZHC_V".to_string());
+
+    std::fs::write(dir.path().join("map.toml"), "VRADDH = \"VEL\"\n").unwrap();
+    std::fs::write(dir.path().join("map.json"), r#"{"DBZHC_V": "ref"}"#).unwrap();
+
+    let synth = SyntheticVolume {
+        fields: vec![vraddh.clone(), dbzhc_v.clone()],
+        ..Default::default()
+    };
+
+    let options = RadyOptions {
+        field_map: silv::read_field_map(dir.path().join("map.toml")),
+        ..Default::default()
+    };
+    std::fs::write(dir.path().join("swp"), synth.dorade(0)).unwrap();
+    let radar = silv::read(dir.path().join("swp"), &options);
+    assert!(radar.params.contains_key(&Field::VEL) && radar.params.contains_key(&dbzhc_v));
+    assert_eq!(
+        radar.sweeps[0].rays[2].data[&Field::VEL][5],
+        synth.value(&vraddh, 0, 2, 5)
+    );
+
+    let options = RadyOptions {
+        field_map: silv::read_field_map(dir.path().join("map.json")),
+        ..Default::default()
+    };
+    synth.write_cfradial(dir.path().join("cfrad.nc"));
+    let radar = silv::read(dir.path().join("cfrad.nc"), &options);
+    assert_eq!(radar.params.keys().collect::<Vec<_>>(), [&Field::REF]);
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn keeps_per_ray_elevations() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        transition_rays: 2,
+        ..Default::default()
+    };
+
+    let path = dir.path().join("cfrad.nc");
+    synth.write_cfradial(&path);
+
+    let radar = silv::read(&path, &RadyOptions::default());
+    assert_matches(&radar, &synth, &[0, 1], 1e-5);
+
+    let options = RadyOptions {
+        format: silv::Format::CFRADIAL,
+        ..Default::default()
+    };
+
+    let out = dir.path().join("output");
+    silv::write(radar, &out, &options).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let radar = silv::read(written, &RadyOptions::default());
+
+    for (index, sweep) in radar.sweeps.iter().enumerate() {
+        assert_eq!(sweep.elevation, synth.elevation(index));
+        assert_eq!(sweep.rays[1].elevation, synth.ray_elevation(index, 1));
+        assert_eq!(sweep.rays[2].elevation, synth.ray_elevation(index, 2));
+    }
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn splits_fields_into_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let options = RadyOptions {
+        format: silv::Format::Custom("cfradial_fields"),
+        ..Default::default()
+    };
+
+    let out = dir.path().join("output");
+    silv::write(
+        read_bytes(dir.path(), "vol", &synth.nexrad()),
+        &out,
+        &options,
+    )
+    .unwrap();
+
+    for field in &synth.fields {
+        let mut written: Vec<_> = std::fs::read_dir(out.join(field.as_str()))
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        written.sort();
+        assert_eq!(written.len(), synth.nsweeps);
+
+        let radar = silv::read(&written[1], &RadyOptions::default());
+        assert_eq!(radar.params.keys().collect::<Vec<_>>(), [field]);
+        assert_eq!(radar.sweeps.len(), 1);
+        assert_eq!(radar.sweeps[0].elevation, synth.elevation(1));
+    }
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn resamples_fields_on_different_gates() {
+    let dir = tempfile::tempdir().unwrap();
+    let coarse = SyntheticVolume {
+        fields: vec![Field::REF],
+        ..Default::default()
+    };
+    let fine = SyntheticVolume {
+        fields: vec![Field::VEL],
+        meters_between_cells: 125.0,
+        ngates: 200,
+        ..Default::default()
+    };
+
+    let mut radar = read_bytes(dir.path(), "coarse", &coarse.dorade(0));
+    radar.merge(read_bytes(dir.path(), "fine", &fine.dorade(0)));
+
+    let geometry = radar.geometry(&radar.sweeps[0], &Field::VEL);
+    assert_eq!(geometry.meters_between_cells, 125.0);
+    assert_eq!(
+        radar
+            .geometry(&radar.sweeps[0], &Field::REF)
+            .meters_between_cells,
+        250.0
+    );
+
+    let options = RadyOptions {
+        format: silv::Format::CFRADIAL,
+        ..Default::default()
+    };
+
+    let out = dir.path().join("output");
+    silv::write(radar, &out, &options).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let radar = silv::read(written, &RadyOptions::default());
+    assert_eq!(radar.params[&Field::REF].meters_between_cells, 125.0);
+
+    let ray = &radar.sweeps[0].rays[8];
+    assert!((ray.data[&Field::VEL][31] - fine.value(&Field::VEL, 0, 8, 31)).abs() < 0.006);
+    assert!((ray.data[&Field::REF][6] - coarse.value(&Field::REF, 0, 8, 3)).abs() < 0.006);
+    assert!(ray.data[&Field::REF][199].is_nan());
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn smooths_noisy_azimuths() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // Encoder noise alternating around the true azimuth
+    let jitter = |i: usize| if i.is_multiple_of(2) { 0.3 } else { -0.3 };
+    for sweep in radar.sweeps.iter_mut() {
+        for (i, ray) in sweep.rays.iter_mut().enumerate() {
+            ray.azimuth = (synth.azimuth(i) + jitter(i)).rem_euclid(360.0);
+        }
+
+        sweep.smooth_azimuths();
+    }
+
+    for sweep in &radar.sweeps {
+        for (i, ray) in sweep.rays.iter().enumerate() {
+            let error = (ray.azimuth - synth.azimuth(i) + 180.0).rem_euclid(360.0) - 180.0;
+            assert!(error.abs() < 0.1, "ray {} smoothed to {}", i, ray.azimuth);
+            assert_eq!(
+                ray.raw_azimuth,
+                Some((synth.azimuth(i) + jitter(i)).rem_euclid(360.0))
+            );
+        }
+    }
+
+    let options = RadyOptions {
+        format: silv::Format::CFRADIAL,
+        ..Default::default()
+    };
+
+    let out = dir.path().join("output");
+    silv::write(radar, &out, &options).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let radar = silv::read(written, &RadyOptions::default());
+    for (i, ray) in radar.sweeps[0].rays.iter().enumerate() {
+        assert_eq!(
+            ray.raw_azimuth,
+            Some((synth.azimuth(i) + jitter(i)).rem_euclid(360.0))
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn carries_instrument_metadata() {
+    let dir = tempfile::tempdir().unwrap();
+    let instrument = silv::InstrumentInfo {
+        altitude: Some(370.0),
+        horizontal_beam_width: Some(0.95),
+        vertical_beam_width: Some(0.9),
+        wavelength: Some(0.107),
+        prf: Some(1000.0),
+        pulse_width: Some(1.5),
+        radar_constant: Some(-33.5),
+    };
+    let synth = SyntheticVolume {
+        instrument,
+        ..Default::default()
+    };
+
+    let close =
+        |a: Option<f32>, b: Option<f32>| (a.unwrap() - b.unwrap()).abs() < 1e-3 * b.unwrap().abs();
+    let assert_close = |read: &silv::InstrumentInfo| {
+        assert!(close(read.altitude, instrument.altitude));
+        assert!(close(
+            read.horizontal_beam_width,
+            instrument.horizontal_beam_width
+        ));
+        assert!(close(
+            read.vertical_beam_width,
+            instrument.vertical_beam_width
+        ));
+        assert!(close(read.wavelength, instrument.wavelength));
+        assert!(close(read.prf, instrument.prf));
+        assert!(close(read.pulse_width, instrument.pulse_width));
+        assert!(close(read.radar_constant, instrument.radar_constant));
+    };
+
+    let radar = read_bytes(dir.path(), "swp", &synth.dorade(0));
+    assert_close(&radar.instrument);
+
+    let options = RadyOptions {
+        format: silv::Format::CFRADIAL,
+        ..Default::default()
+    };
+
+    let out = dir.path().join("cfradial");
+    silv::write(radar.clone(), &out, &options).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    assert_close(&silv::read(written, &RadyOptions::default()).instrument);
+
+    // NEXRAD only records the antenna height and calibration constant
+    let out = dir.path().join("nexrad");
+    silv::write(radar, &out, &RadyOptions::default()).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let read = silv::read(written, &RadyOptions::default()).instrument;
+    assert_eq!(read.altitude, instrument.altitude);
+    assert_eq!(read.radar_constant, instrument.radar_constant);
+    assert_eq!(read.wavelength, None);
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn flips_velocity_toward_radar() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let path = dir.path().join("cfrad.nc");
+    synth.write_cfradial(&path);
+
+    let mut file = netcdf::append(&path).unwrap();
+    file.variable_mut("VEL")
+        .unwrap()
+        .add_attribute(
+            "standard_name",
+            "radial_velocity_of_scatterers_toward_instrument",
+        )
+        .unwrap();
+    drop(file);
+
+    let toward = silv::read(&path, &RadyOptions::default());
+
+    let mut flipped = read_bytes(dir.path(), "vol", &synth.nexrad());
+    let options = RadyOptions {
+        flip_velocity: true,
+        ..Default::default()
+    };
+    options.apply_options(&mut flipped).unwrap();
+
+    for radar in [toward, flipped] {
+        let ray = &radar.sweeps[0].rays[5];
+        for (gate, &value) in ray.data[&Field::VEL].iter().enumerate() {
+            assert_eq!(value, -synth.value(&Field::VEL, 0, 5, gate));
+        }
+        assert_eq!(
+            ray.data[&Field::REF][10],
+            synth.value(&Field::REF, 0, 5, 10)
+        );
+    }
+}
+
+#[test]
+fn writes_scan_strategy() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    // Synthesized from the sweeps when the input has no scan strategy
+    let radar = read_bytes(dir.path(), "swp", &synth.dorade(1));
+    assert_eq!(radar.scan_strategy, None);
+
+    let out = dir.path().join("synthesized");
+    silv::write(radar, &out, &RadyOptions::default()).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let strategy = silv::read(written, &RadyOptions::default())
+        .scan_strategy
+        .unwrap();
+    assert_eq!(strategy.vcp, 0);
+    assert_eq!(strategy.cuts.len(), 1);
+    assert!((strategy.cuts[0].elevation - synth.elevation(1)).abs() < 0.01);
+    assert_eq!(strategy.cuts[0].waveform, silv::Waveform::Batch);
+
+    // Kept from the input otherwise
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    let cuts = [
+        (0.5, silv::Waveform::ContiguousSurveillance),
+        (0.5, silv::Waveform::ContiguousDoppler),
+        (-0.2, silv::Waveform::Batch),
+    ];
+    radar.scan_strategy = Some(silv::ScanStrategy {
+        vcp: 212,
+        cuts: cuts
+            .iter()
+            .map(|&(elevation, waveform)| silv::ScanCut {
+                elevation,
+                waveform,
+            })
+            .collect(),
+    });
+
+    let out = dir.path().join("kept");
+    silv::write(radar, &out, &RadyOptions::default()).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let strategy = silv::read(written, &RadyOptions::default())
+        .scan_strategy
+        .unwrap();
+    assert_eq!(strategy.vcp, 212);
+    for (cut, &(elevation, waveform)) in strategy.cuts.iter().zip(&cuts) {
+        assert!((cut.elevation - elevation).abs() < 0.01);
+        assert_eq!(cut.waveform, waveform);
+    }
+}
+
+#[test]
+fn converts_linear_reflectivity() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut linear = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    for ray in linear.sweeps.iter_mut().flat_map(|sweep| &mut sweep.rays) {
+        ray.data
+            .get_mut(&Field::REF)
+            .unwrap()
+            .iter_mut()
+            .for_each(|v| *v = 10f64.powf(*v / 10.0));
+    }
+
+    // Values up to 20 dBZ stay below 100 in linear units, so only the units give them away
+    let mut strong = linear.clone();
+    linear.params.get_mut(&Field::REF).unwrap().units = "mm6/m3".to_string();
+    RadyOptions::default().apply_options(&mut linear).unwrap();
+    assert_matches(&linear, &synth, &[0, 1], 1e-9);
+    assert_eq!(linear.params[&Field::REF].units, "dBZ");
+
+    // Detected from the range of values once they are 30 dB stronger
+    for ray in strong.sweeps.iter_mut().flat_map(|sweep| &mut sweep.rays) {
+        ray.data
+            .get_mut(&Field::REF)
+            .unwrap()
+            .iter_mut()
+            .for_each(|v| *v *= 1000.0);
+    }
+
+    let mut kept = strong.clone();
+    let options = RadyOptions {
+        ref_units: silv::RefUnits::Decibels,
+        ..Default::default()
+    };
+    options.apply_options(&mut kept).unwrap();
+    assert!(kept.sweeps[0].rays[0].data[&Field::REF]
+        .iter()
+        .any(|&v| v > 100.0));
+
+    RadyOptions::default().apply_options(&mut strong).unwrap();
+    for (gate, &value) in strong.sweeps[0].rays[0].data[&Field::REF]
+        .iter()
+        .enumerate()
+    {
+        assert!((value - synth.value(&Field::REF, 0, 0, gate) - 30.0).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn interpolates_to_regular_azimuths() {
+    use silv::AzimuthInterpolation;
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut sweep = read_bytes(dir.path(), "swp", &synth.dorade(0))
+        .sweeps
+        .remove(0);
+
+    // Leave a gap from 100 to 104 degrees
+    sweep
+        .rays
+        .retain(|ray| !(100.0..105.0).contains(&ray.azimuth));
+
+    let expected = |ray: usize, gate: usize| synth.value(&Field::REF, 0, ray, gate);
+
+    let mut nearest = sweep.clone();
+    nearest.interpolate_to_regular_azimuths(1.0, AzimuthInterpolation::Nearest);
+    assert_eq!(nearest.rays.len(), 360);
+    assert_eq!(nearest.rays[10].azimuth, 10.5);
+    assert!([expected(10, 0), expected(11, 0)].contains(&nearest.rays[10].data[&Field::REF][0]));
+    assert_eq!(nearest.rays[99].data[&Field::REF][0], expected(99, 0));
+    assert!(nearest.rays[101].data[&Field::REF]
+        .iter()
+        .all(|v| v.is_nan()));
+
+    let mut linear = sweep.clone();
+    linear.interpolate_to_regular_azimuths(1.0, AzimuthInterpolation::Linear);
+    assert_eq!(
+        linear.rays[10].data[&Field::REF][0],
+        (expected(10, 0) + expected(11, 0)) / 2.0
+    );
+    assert_eq!(linear.rays[99].data[&Field::REF][0], expected(99, 0));
+    assert!(linear.rays[101].data[&Field::REF]
+        .iter()
+        .all(|v| v.is_nan()));
+
+    // Sector scans only cover the sector
+    let mut sector = sweep;
+    sector
+        .rays
+        .retain(|ray| (10.0..=50.0).contains(&ray.azimuth));
+    sector.interpolate_to_regular_azimuths(0.5, AzimuthInterpolation::Nearest);
+    assert_eq!(sector.rays.len(), 80);
+    assert_eq!(sector.rays[0].azimuth, 10.25);
+    assert_eq!(sector.rays[79].azimuth, 49.75);
+}
+
+#[test]
+fn writes_inventory() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let input = dir.path().join("input");
+    std::fs::create_dir(&input).unwrap();
+    for sweep in 0..synth.nsweeps {
+        std::fs::write(input.join(format!("swp.{}", sweep)), synth.dorade(sweep)).unwrap();
+    }
+
+    let out = dir.path().join("output");
+    let options = RadyOptions {
+        files: input.join("*").to_str().unwrap().to_string(),
+        outdir: Some(out.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    silv::convert(&options);
+
+    let inventory: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(out.join("INVENTORY.json")).unwrap()).unwrap();
+    assert_eq!(inventory["radars"], serde_json::json!(["KTST"]));
+    assert_eq!(inventory["volumes"], 2);
+    assert_eq!(inventory["fields"], serde_json::json!(["REF", "VEL"]));
+    assert_eq!(inventory["tilts"].as_array().unwrap().len(), 2);
+    assert_eq!(inventory["tilts"][0]["sweeps"], 1);
+
+    let start: chrono::DateTime<chrono::Utc> =
+        inventory["start_time"].as_str().unwrap().parse().unwrap();
+    let end: chrono::DateTime<chrono::Utc> =
+        inventory["end_time"].as_str().unwrap().parse().unwrap();
+    assert_eq!(start, synth.time(0, 0));
+    assert_eq!(end, synth.time(1, synth.nrays - 1));
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn assembles_volumes_across_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = SyntheticVolume::default();
+    let second = SyntheticVolume {
+        start_time: first.start_time + chrono::Duration::minutes(5),
+        ..Default::default()
+    };
+
+    // One sweep per file, as DORADE is usually distributed
+    let input = dir.path().join("input");
+    std::fs::create_dir(&input).unwrap();
+    for (volume, synth) in [first, second].iter().enumerate() {
+        for sweep in 0..synth.nsweeps {
+            std::fs::write(
+                input.join(format!("swp.{}.{}", volume, sweep)),
+                synth.dorade(sweep),
+            )
+            .unwrap();
+        }
+    }
+
+    for format in [silv::Format::NEXRAD, silv::Format::CFRADIAL] {
+        let out = dir.path().join(format.name());
+        let options = RadyOptions {
+            files: input.join("swp.*").to_str().unwrap().to_string(),
+            outdir: Some(out.to_str().unwrap().to_string()),
+            write_volumes: true,
+            format,
+            ..Default::default()
+        };
+        silv::convert(&options);
+
+        let mut written: Vec<_> = std::fs::read_dir(&out)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|path| path.file_name().unwrap() != "INVENTORY.json")
+            .collect();
+        written.sort();
+        assert_eq!(written.len(), 2);
+
+        for path in written {
+            let radar = silv::read(path, &RadyOptions::default());
+            assert_eq!(radar.sweeps.len(), 2);
+            assert!(radar.sweeps[1].elevation > radar.sweeps[0].elevation);
+        }
+    }
+
+    // The type 5 message is filled in with every cut of the volume once it is finished
+    let path = std::fs::read_dir(dir.path().join("nexrad"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|path| path.file_name().unwrap() != "INVENTORY.json")
+        .unwrap();
+    assert_eq!(
+        silv::read(path, &RadyOptions::default())
+            .scan_strategy
+            .unwrap()
+            .cuts
+            .len(),
+        2
+    );
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn splits_volumes_by_gap_and_step() {
+    let dir = tempfile::tempdir().unwrap();
+    let early = SyntheticVolume {
+        nsweeps: 4,
+        ..Default::default()
+    };
+    let late = SyntheticVolume {
+        start_time: early.start_time + chrono::Duration::minutes(10),
+        ..early.clone()
+    };
+
+    // Ascending sweeps, with a pause before the last two
+    let input = dir.path().join("input");
+    std::fs::create_dir(&input).unwrap();
+    for sweep in 0..4 {
+        let synth = if sweep < 2 { &early } else { &late };
+        std::fs::write(input.join(format!("swp.{}", sweep)), synth.dorade(sweep)).unwrap();
+    }
+
+    let volumes = |name: &str, volume_step: f32, volume_gap: Option<f64>| {
+        let out = dir.path().join(name);
+        let options = RadyOptions {
+            files: input.join("swp.*").to_str().unwrap().to_string(),
+            outdir: Some(out.to_str().unwrap().to_string()),
+            write_volumes: true,
+            volume_step,
+            volume_gap,
+            format: silv::Format::CFRADIAL,
+            ..Default::default()
+        };
+        silv::convert(&options);
+
+        let mut sweeps: Vec<usize> = std::fs::read_dir(&out)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|path| path.file_name().unwrap() != "INVENTORY.json")
+            .map(|path| silv::read(path, &RadyOptions::default()).sweeps.len())
+            .collect();
+        sweeps.sort();
+        sweeps
+    };
+
+    assert_eq!(volumes("default", 0.1, None), vec![4]);
+    assert_eq!(volumes("gap", 0.1, Some(60.0)), vec![2, 2]);
+    assert_eq!(volumes("step", 1.5, None), vec![1, 1, 1, 1]);
+}
+
+#[test]
+fn validates_radar_files() {
+    use silv::Issue;
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    assert_eq!(radar.validate(), vec![]);
+
+    let sweep = &mut radar.sweeps[0];
+    sweep.rays[3].azimuth = f32::NAN;
+    sweep.rays[5].time = sweep.rays[4].time - chrono::Duration::seconds(1);
+    sweep.rays[7].data.get_mut(&Field::REF).unwrap().pop();
+    sweep.rays[9]
+        .data
+        .insert(Field::KDP, vec![0.0; synth.ngates]);
+    radar.sweeps.push(silv::Sweep::default());
+
+    assert_eq!(
+        radar.validate(),
+        vec![
+            Issue::NanAzimuth { sweep: 0, ray: 3 },
+            Issue::NonMonotonicTime { sweep: 0, ray: 5 },
+            Issue::MismatchedGates {
+                sweep: 0,
+                ray: 7,
+                field: Field::REF
+            },
+            Issue::EmptySweep { sweep: 2 },
+            Issue::MissingParam { field: Field::KDP },
+        ]
+    );
+}
+
+#[test]
+fn dealiases_velocity_by_region() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let sweep = &mut radar.sweeps[0];
+    sweep.nyquist_velocity = 10.0;
+
+    let truth = |azimuth: f32, gate: usize| {
+        30.0 * (azimuth as f64).to_radians().sin() * gate as f64 / synth.ngates as f64
+    };
+    for ray in &mut sweep.rays {
+        let azimuth = ray.azimuth;
+        for (gate, value) in ray
+            .data
+            .get_mut(&Field::VEL)
+            .unwrap()
+            .iter_mut()
+            .enumerate()
+        {
+            *value = (truth(azimuth, gate) + 10.0).rem_euclid(20.0) - 10.0;
+        }
+    }
+
+    let options = RadyOptions {
+        dealias: Some(silv::Dealias::Region),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    for ray in &radar.sweeps[0].rays {
+        for (gate, &value) in ray.data[&Field::VEL].iter().enumerate() {
+            assert!((value - truth(ray.azimuth, gate)).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn dealiases_velocity_with_sounding() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // Westerly wind increasing from 20 to 40 m/s over the lowest 10 km
+    let path = dir.path().join("sounding.csv");
+    std::fs::write(&path, "height,direction,speed\n0,270,20\n10000,270,40\n").unwrap();
+    let sounding = silv::processing::Sounding::read(&path);
+    let (u, v) = sounding.wind(5000.0);
+    assert!((u - 30.0).abs() < 1e-4 && v.abs() < 1e-4);
+
+    let geometry = radar.geometry(&radar.sweeps[0], &Field::VEL);
+    let sweep = &mut radar.sweeps[0];
+    sweep.nyquist_velocity = 10.0;
+
+    let elevation = sweep.elevation;
+    let altitude = radar.instrument.altitude.unwrap_or(0.0);
+    let truth = |azimuth: f32, gate: usize| {
+        let speed = sounding
+            .wind(altitude + silv::processing::beam_height(geometry.range(gate), elevation))
+            .0;
+        (speed * azimuth.to_radians().sin() * elevation.to_radians().cos()) as f64
+    };
+
+    for ray in &mut sweep.rays {
+        let azimuth = ray.azimuth;
+        for (gate, value) in ray
+            .data
+            .get_mut(&Field::VEL)
+            .unwrap()
+            .iter_mut()
+            .enumerate()
+        {
+            *value = (truth(azimuth, gate) + 10.0).rem_euclid(20.0) - 10.0;
+        }
+    }
+
+    let options = RadyOptions {
+        dealias: Some(silv::Dealias::Sounding),
+        sounding: Some(sounding.clone()),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    for ray in &radar.sweeps[0].rays {
+        for (gate, &value) in ray.data[&Field::VEL].iter().enumerate() {
+            assert!((value - truth(ray.azimuth, gate)).abs() < 1e-3);
+        }
+    }
+}
+
+#[test]
+fn masks_gates_by_geometry() {
+    use silv::processing::beam_height;
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let options = RadyOptions {
+        min_range: Some(3000.0),
+        max_height: Some(500.0),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    let mut masked = 0;
+    for (index, sweep) in radar.sweeps.iter().enumerate() {
+        for (i, ray) in sweep.rays.iter().enumerate() {
+            for (gate, &value) in ray.data[&Field::REF].iter().enumerate() {
+                let range = synth.meters_to_first_cell + gate as f32 * synth.meters_between_cells;
+
+                if range < 3000.0 || beam_height(range, sweep.elevation) > 500.0 {
+                    assert!(silv::is_missing(value));
+                    masked += 1;
+                } else {
+                    assert_eq!(value, synth.value(&Field::REF, index, i, gate));
+                }
+            }
+        }
+    }
+
+    assert!(masked > 4 * synth.nrays * radar.sweeps.len());
+}
+
+#[test]
+fn corrects_rain_attenuation() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::ZDR, Field::PHI],
+        ..Default::default()
+    };
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    assert_eq!(radar.instrument.wavelength, None);
+
+    // Uniform rain of 40 dBZ with 0.1 dB/km of attenuation, at 0.04 dB/degree for S band
+    let attenuation = 0.1;
+    let km = synth.meters_between_cells as f64 / 1000.0;
+    for ray in &mut radar.sweeps[0].rays {
+        for gate in 0..synth.ngates {
+            let pia = attenuation * km * (2 * gate + 1) as f64;
+            ray.data.get_mut(&Field::REF).unwrap()[gate] = 40.0 - pia;
+            ray.data.get_mut(&Field::ZDR).unwrap()[gate] = 1.0 - 0.14 * pia;
+            ray.data.get_mut(&Field::PHI).unwrap()[gate] =
+                30.0 + 2.0 * attenuation / 0.04 * km * gate as f64;
+        }
+    }
+
+    let options = RadyOptions {
+        correct_attenuation: true,
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    for ray in &radar.sweeps[0].rays {
+        for gate in 0..synth.ngates {
+            assert!((ray.data[&Field::REF][gate] - 40.0).abs() < 0.1);
+            assert!((ray.data[&Field::ZDR][gate] - 1.0).abs() < 0.02);
+        }
+    }
+}
+
+#[test]
+fn writes_negative_elevations() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // A mountaintop radar looking below the horizon
+    let sweep = &mut radar.sweeps[0];
+    sweep.elevation = -0.5;
+    sweep.rays.iter_mut().for_each(|ray| ray.elevation = -0.5);
+    radar.scan_strategy = None;
+
+    let out = dir.path().join("volume");
+    silv::write(radar.clone(), &out, &RadyOptions::default()).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    assert!(written.to_str().unwrap().ends_with("_-0.5"));
+
+    let volume = silv::read(&written, &RadyOptions::default());
+    let elevations: Vec<f32> = volume.sweeps.iter().map(|sweep| sweep.elevation).collect();
+    assert_eq!(elevations, vec![-0.5, 1.5]);
+    assert!((volume.scan_strategy.unwrap().cuts[0].elevation + 0.5).abs() < 0.01);
+
+    let out = dir.path().join("separate");
+    let options = RadyOptions {
+        write_separate: true,
+        ..Default::default()
+    };
+    silv::write(radar, &out, &options).unwrap();
+
+    let mut written: Vec<_> = std::fs::read_dir(&out)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    written.sort();
+    assert!(written[0].to_str().unwrap().ends_with("_-0.5"));
+    assert_eq!(
+        silv::read(&written[0], &RadyOptions::default()).sweeps[0].elevation,
+        -0.5
+    );
+}
+
+#[test]
+fn keeps_sector_scans_above_min_rays() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // A full rotation with ten rays of the next one, and a narrow sector
+    let extra: Vec<_> = radar.sweeps[0].rays[..10].to_vec();
+    radar.sweeps[0].rays.extend(extra);
+    radar.sweeps[1].rays.truncate(10);
+
+    let mut split = radar.clone();
+    split.split_overlap_rays(None);
+    let rays: Vec<usize> = split.sweeps.iter().map(|sweep| sweep.rays.len()).collect();
+    assert_eq!(rays, vec![360]);
+
+    radar.sweeps[1].scan_mode = silv::ScanMode::Manual;
+    let mut split = radar.clone();
+    split.split_overlap_rays(None);
+    let rays: Vec<usize> = split.sweeps.iter().map(|sweep| sweep.rays.len()).collect();
+    assert_eq!(rays, vec![360, 10]);
+
+    let options = RadyOptions {
+        split_overlap_rays: true,
+        min_rays: Some(5),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+    let rays: Vec<usize> = radar.sweeps.iter().map(|sweep| sweep.rays.len()).collect();
+    assert_eq!(rays, vec![360, 10, 10]);
+
+    // Sweeps shorter than the five rays the direction is judged from are kept whole
+    radar.sweeps[2].rays.truncate(3);
+    radar.sweeps[1].rays.truncate(1);
+    radar.sweeps[1].scan_mode = silv::ScanMode::PPI;
+    let options = RadyOptions {
+        trim_rays: true,
+        min_rays: Some(1),
+        ..options
+    };
+    options.apply_options(&mut radar).unwrap();
+    let rays: Vec<usize> = radar.sweeps.iter().map(|sweep| sweep.rays.len()).collect();
+    assert_eq!(rays, vec![360, 1, 3]);
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn keeps_staggered_gate_counts() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::ZDR],
+        ..Default::default()
+    };
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // Dual-pol moments end before reflectivity
+    for sweep in &mut radar.sweeps {
+        sweep
+            .rays
+            .iter_mut()
+            .for_each(|ray| ray.data.get_mut(&Field::ZDR).unwrap().truncate(60));
+    }
+    assert_eq!(radar.sweeps[0].ngates(&Field::REF), synth.ngates);
+    assert_eq!(radar.sweeps[0].ngates(&Field::ZDR), 60);
+
+    for format in [silv::Format::NEXRAD, silv::Format::CFRADIAL] {
+        let out = dir.path().join(format.name());
+        let options = RadyOptions {
+            format,
+            ..Default::default()
+        };
+        silv::write(radar.clone(), &out, &options).unwrap();
+
+        let written = std::fs::read_dir(&out)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let read = silv::read(written, &options);
+
+        for (index, sweep) in read.sweeps.iter().enumerate() {
+            assert_eq!(sweep.ngates(&Field::REF), synth.ngates);
+
+            for (i, ray) in sweep.rays.iter().enumerate() {
+                let zdr = &ray.data[&Field::ZDR];
+                for (gate, &value) in zdr[..60].iter().enumerate() {
+                    assert!((value - synth.value(&Field::ZDR, index, i, gate)).abs() < 0.1);
+                }
+                assert!(zdr[60..].iter().all(|&value| silv::is_missing(value)));
+                assert!(
+                    (ray.data[&Field::REF][90] - synth.value(&Field::REF, index, i, 90)).abs()
+                        < 0.5
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn despeckles_isolated_echoes() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+    radar.sweeps.truncate(1);
+
+    // A short run, a long run along one ray, and a small block of echo
+    let echoes = [(10..11, 20..23), (20..21, 30..40), (50..55, 50..55)];
+    for (i, ray) in radar.sweeps[0].rays.iter_mut().enumerate() {
+        for data in ray.data.values_mut() {
+            for (gate, value) in data.iter_mut().enumerate() {
+                if !echoes
+                    .iter()
+                    .any(|(rays, gates)| rays.contains(&i) && gates.contains(&gate))
+                {
+                    *value = silv::MISSING;
+                }
+            }
+        }
+    }
+
+    let echo_gates = |radar: &RadarFile| -> usize {
+        radar.sweeps[0]
+            .rays
+            .iter()
+            .map(|ray| {
+                ray.data[&Field::VEL]
+                    .iter()
+                    .filter(|v| !silv::is_missing(**v))
+                    .count()
+            })
+            .sum()
+    };
+    assert_eq!(echo_gates(&radar), 3 + 10 + 25);
+
+    let options = RadyOptions {
+        despeckle: Some(4),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+    assert_eq!(echo_gates(&radar), 10 + 25);
+    assert!(silv::is_missing(
+        radar.sweeps[0].rays[10].data[&Field::REF][21]
+    ));
+
+    let options = RadyOptions {
+        despeckle_area: Some(20),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+    assert_eq!(echo_gates(&radar), 25);
+    assert_eq!(
+        radar.sweeps[0].rays[52].data[&Field::REF][52],
+        synth.value(&Field::REF, 0, 52, 52)
+    );
+}
+
+#[test]
+fn converts_bytes_in_memory() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let options = RadyOptions {
+        flip_velocity: true,
+        ..Default::default()
+    };
+    let output =
+        silv::convert_bytes(&synth.nexrad(), None, silv::Format::NEXRAD, &options).unwrap();
+    let radar = read_bytes(dir.path(), "vol", &output);
+    assert_eq!(radar.sweeps.len(), synth.nsweeps);
+    assert_eq!(
+        radar.sweeps[1].rays[3].data[&Field::VEL][5],
+        -synth.value(&Field::VEL, 1, 3, 5)
+    );
+
+    let output = silv::convert_bytes(
+        &synth.dorade(0),
+        Some(silv::Format::DORADE),
+        silv::Format::NEXRAD,
+        &RadyOptions::default(),
+    )
+    .unwrap();
+    assert_matches(&read_bytes(dir.path(), "swp", &output), &synth, &[0], 0.006);
+
+    // Formats without an in-memory codec are refused instead of going through the file system
+    assert!(matches!(
+        silv::convert_bytes(&synth.nexrad(), None, silv::Format::CFRADIAL, &options),
+        Err(silv::Error::Unsupported(_))
+    ));
+    assert!(silv::convert_bytes(
+        b"not a radar file",
+        None,
+        silv::Format::NEXRAD,
+        &RadyOptions::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn censors_gates_failing_qc() {
+    use silv::processing::{Comparison, Threshold};
+
+    let threshold: Threshold = "rho > 0.85".parse().unwrap();
+    assert_eq!(
+        threshold,
+        Threshold {
+            field: Field::RHO,
+            comparison: Comparison::Greater,
+            value: 0.85
+        }
+    );
+    assert_eq!(
+        "SNR>=3".parse::<Threshold>().unwrap().comparison,
+        Comparison::GreaterEqual
+    );
+    assert!("rho".parse::<Threshold>().is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::VEL, Field::RHO],
+        ..Default::default()
+    };
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let snr = Field::Other("snr".to_string());
+    for ray in &mut radar.sweeps[0].rays {
+        let rho = ray.data.get_mut(&Field::RHO).unwrap();
+        rho[10] = 0.5;
+        rho[11] = silv::MISSING;
+        ray.data.insert(
+            snr.clone(),
+            (0..synth.ngates)
+                .map(|gate| if gate == 20 { 1.0 } else { 10.0 })
+                .collect(),
+        );
+    }
+
+    let options = RadyOptions {
+        qc: vec!["rho>0.85".parse().unwrap(), "SNR>3".parse().unwrap()],
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    for (i, ray) in radar.sweeps[0].rays.iter().enumerate() {
+        for gate in [10, 20] {
+            assert!(silv::is_missing(ray.data[&Field::REF][gate]));
+            assert!(silv::is_missing(ray.data[&Field::VEL][gate]));
+        }
+        assert_eq!(
+            ray.data[&Field::REF][11],
+            synth.value(&Field::REF, 0, i, 11)
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn derives_rain_rate() {
+    use silv::processing::ZR;
+
+    assert_eq!("convective".parse(), Ok(ZR::CONVECTIVE));
+    assert_eq!("300, 1.4".parse(), Ok(ZR::CONVECTIVE));
+    assert!("heavy".parse::<ZR>().is_err());
+    assert!((ZR::MARSHALL_PALMER.rate(23.0) - 1.0).abs() < 0.01);
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let options = RadyOptions {
+        rain_rate: Some(ZR::TROPICAL),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+    assert_eq!(radar.params[&Field::RATE].units, "mm/hr");
+    assert_eq!(radar.validate(), vec![]);
+
+    let out = dir.path().join("output");
+    let options = RadyOptions {
+        format: silv::Format::CFRADIAL,
+        ..Default::default()
+    };
+    silv::write(radar, &out, &options).unwrap();
+
+    let written = std::fs::read_dir(&out)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let radar = silv::read(written, &options);
+
+    for (index, sweep) in radar.sweeps.iter().enumerate() {
+        for (i, ray) in sweep.rays.iter().enumerate() {
+            for (gate, &rate) in ray.data[&Field::RATE].iter().enumerate() {
+                let dbz = synth.value(&Field::REF, index, i, gate);
+                assert!((rate - ZR::TROPICAL.rate(dbz)).abs() <= 1e-4 * rate.max(1.0));
+            }
+        }
+    }
+}
+
+#[test]
+fn writes_chrome_trace() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let input = dir.path().join("vol");
+    std::fs::write(&input, synth.nexrad()).unwrap();
+
+    let trace = dir.path().join("trace.json");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_silv"))
+        .args([
+            "-f",
+            input.to_str().unwrap(),
+            "--despeckle",
+            "3",
+            "--trace-output",
+            trace.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+
+    let events: Vec<serde_json::Value> =
+        serde_json::from_str(&std::fs::read_to_string(&trace).unwrap()).unwrap();
+    let names: Vec<&str> = events
+        .iter()
+        .filter_map(|event| event["name"].as_str())
+        .collect();
+    for span in ["read", "apply_options", "despeckle", "write"] {
+        assert!(names.contains(&span), "no {} span in {:?}", span, names);
+    }
+}
+
+#[test]
+fn derives_polarimetric_rain_rate() {
+    use silv::processing::{rate_kdp, rate_z_zdr, ZR};
+
+    assert!((rate_kdp(1.0) - 44.0).abs() < 1e-9);
+    assert!(
+        (rate_z_zdr(40.0, 1.0) - 0.0067 * 1e4f64.powf(0.927) * 10f64.powf(0.1).powf(-3.43)).abs()
+            < 1e-6
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::ZDR],
+        ..Default::default()
+    };
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // Heavy rain, light rain with big drops, drizzle, and heavy rain without KDP
+    let gates = [
+        (10, 45.0, 2.0, 1.0),
+        (20, 30.0, 1.0, 0.1),
+        (30, 30.0, 0.2, 0.1),
+        (40, 45.0, 0.1, silv::MISSING),
+    ];
+    for ray in &mut radar.sweeps[0].rays {
+        let mut kdp = vec![silv::MISSING; synth.ngates];
+        for &(gate, dbz, zdr, k) in &gates {
+            ray.data.get_mut(&Field::REF).unwrap()[gate] = dbz;
+            ray.data.get_mut(&Field::ZDR).unwrap()[gate] = zdr;
+            kdp[gate] = k;
+        }
+        ray.data.insert(Field::KDP, kdp);
+    }
+
+    let options = RadyOptions {
+        polarimetric_rain: true,
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    let rate = &radar.sweeps[0].rays[0].data[&Field::RATE];
+    assert_eq!(rate[10], rate_kdp(1.0));
+    assert_eq!(rate[20], rate_z_zdr(30.0, 1.0));
+    assert_eq!(rate[30], ZR::MARSHALL_PALMER.rate(30.0));
+    assert_eq!(rate[40], ZR::MARSHALL_PALMER.rate(45.0));
+}
+
+#[test]
+fn skips_files_that_are_not_radar_data() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let input = dir.path().join("input");
+    std::fs::create_dir(&input).unwrap();
+    std::fs::write(input.join("vol"), synth.nexrad()).unwrap();
+    std::fs::write(input.join("empty"), b"").unwrap();
+    std::fs::write(input.join("notes.txt"), b"AR2").unwrap();
+    std::fs::write(input.join("run.log"), b"Finished the scan at 23:30\n").unwrap();
+
+    let out = dir.path().join("output");
+    let options = RadyOptions {
+        files: input.join("*").to_str().unwrap().to_string(),
+        outdir: Some(out.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    silv::convert(&options);
+
+    let inventory: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(out.join("INVENTORY.json")).unwrap()).unwrap();
+    assert_eq!(inventory["volumes"], 1);
+    assert_eq!(inventory["skipped_files"], 3);
+    assert!(silv::try_read(input.join("empty"), &options)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn indexes_tilts_written_separately() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let input = dir.path().join("input");
+    std::fs::create_dir(&input).unwrap();
+    std::fs::write(input.join("vol"), synth.nexrad()).unwrap();
+
+    let out = dir.path().join("output");
+    let options = RadyOptions {
+        files: input.join("*").to_str().unwrap().to_string(),
+        outdir: Some(out.to_str().unwrap().to_string()),
+        write_separate: true,
+        index: true,
+        ..Default::default()
+    };
+    silv::convert(&options);
+
+    let index: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(out.join("INDEX.json")).unwrap()).unwrap();
+    let volumes = index["volumes"].as_array().unwrap();
+    assert_eq!(volumes.len(), 1);
+
+    let sweeps = volumes[0]["sweeps"].as_array().unwrap();
+    assert_eq!(sweeps.len(), 2);
+
+    for (sweep, elevation) in sweeps.iter().zip([0.5, 1.5]) {
+        assert_eq!(sweep["elevation"].as_f64().unwrap(), elevation);
+
+        let files = sweep["files"].as_array().unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(out.join(files[0].as_str().unwrap()).is_file());
+    }
+}
+
+#[test]
+fn interpolates_constant_altitude_ppis() {
+    use silv::processing::beam_height;
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    for (sweep, value) in radar.sweeps.iter_mut().zip([10.0, 20.0]) {
+        for ray in &mut sweep.rays {
+            ray.data
+                .get_mut(&Field::REF)
+                .unwrap()
+                .iter_mut()
+                .for_each(|gate| *gate = value);
+        }
+    }
+
+    let options = RadyOptions {
+        cappi: vec![250.0, 1000.0],
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+    assert_eq!(radar.sweeps.len(), 2);
+    assert!(radar
+        .sweeps
+        .iter()
+        .all(|sweep| sweep.rays.len() == synth.nrays));
+
+    let ray = &radar.sweeps[0].rays[90];
+    assert!(!ray.data.contains_key(&Field::VEL));
+
+    let distance = 2125.0 + 50.0 * 250.0;
+    let beam = |elevation: f32| beam_height(distance / elevation.to_radians().cos(), elevation);
+    let expected = 10.0 + 10.0 * ((250.0 - beam(0.5)) / (beam(1.5) - beam(0.5))) as f64;
+    assert!((ray.data[&Field::REF][50] - expected).abs() < 0.5);
+
+    // Beams are above 250 meters far from the radar and below it close by
+    assert!(silv::is_missing(ray.data[&Field::REF][0]));
+    assert!(silv::is_missing(ray.data[&Field::REF][99]));
+    assert!(radar.sweeps[1].rays[90].data[&Field::REF]
+        .iter()
+        .all(|&gate| silv::is_missing(gate)));
+
+    // The heights are recorded instead of the lowest tilt's angle, so the sweeps are named and
+    // counted apart
+    assert_eq!(
+        radar
+            .sweeps
+            .iter()
+            .map(|sweep| sweep.height)
+            .collect::<Vec<_>>(),
+        [Some(250.0), Some(1000.0)]
+    );
+    assert!(radar.sweeps.iter().all(|sweep| sweep.fixed_angle.is_none()));
+
+    let mut inventory = silv::Inventory::default();
+    inventory.add(&radar);
+    assert_eq!(inventory.tilts.len(), 2);
+
+    let out = dir.path().join("cappi");
+    silv::write(
+        radar,
+        &out,
+        &RadyOptions {
+            write_separate: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(std::fs::read_dir(&out).unwrap().count(), 2);
+}
+
+#[test]
+fn derives_storm_relative_velocity() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let options = RadyOptions {
+        storm_motion: Some((10.0, -5.0)),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+    assert_eq!(
+        radar.params[&Field::SRV].units,
+        radar.params[&Field::VEL].units
+    );
+    assert_eq!(radar.validate(), vec![]);
+
+    for ray in &radar.sweeps[1].rays {
+        let azimuth = ray.azimuth.to_radians();
+        let motion = ((10.0 * azimuth.sin() - 5.0 * azimuth.cos())
+            * ray.elevation.to_radians().cos()) as f64;
+
+        for (&srv, &vel) in ray.data[&Field::SRV].iter().zip(&ray.data[&Field::VEL]) {
+            assert!((srv - (vel - motion)).abs() < 1e-4);
+        }
+    }
+}
+
+#[test]
+fn grids_fields_onto_cartesian_points() {
+    use silv::grid::{grid, Axis, GridSpec, RadiusOfInfluence, Weighting};
+
+    assert_eq!("Barnes".parse(), Ok(Weighting::Barnes));
+    assert!(
+        Weighting::Cressman.weight(0.0, 1000.0) == 1.0
+            && Weighting::Cressman.weight(1000.0, 1000.0) == 0.0
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    for sweep in &mut radar.sweeps {
+        for ray in &mut sweep.rays {
+            ray.data
+                .get_mut(&Field::REF)
+                .unwrap()
+                .iter_mut()
+                .for_each(|gate| *gate = 30.0);
+        }
+    }
+
+    for weighting in [Weighting::Cressman, Weighting::Barnes] {
+        let spec = GridSpec {
+            x: Axis::centered(2000.0, 21),
+            y: Axis::centered(2000.0, 21),
+            z: Some(Axis {
+                start: 250.0,
+                spacing: 10_000.0,
+                count: 2,
+            }),
+            weighting,
+            radius: RadiusOfInfluence::Beam {
+                min: 1000.0,
+                beam_width: 1.0,
+            },
+        };
+        let grid = grid(&radar, &Field::REF, &spec);
+        assert_eq!(grid.values.len(), 21 * 21 * 2);
+
+        // The radar is at the center, inside the first gate
+        assert_eq!(spec.x.value(10), 0.0);
+        assert!(silv::is_missing(grid.get(10, 10, 0)));
+        assert!((grid.get(15, 10, 0) - 30.0).abs() < 1e-6);
+        assert!((grid.get(10, 3, 0) - 30.0).abs() < 1e-6);
+        assert!((0..21).all(|i| silv::is_missing(grid.get(i, 10, 1))));
+    }
+}
+
+#[test]
+fn grids_alike_on_every_backend() {
+    use silv::grid::{grid, grid_with, Axis, Backend, GridSpec, RadiusOfInfluence, Weighting};
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    for (z, weighting, radius) in [
+        (
+            None,
+            Weighting::Cressman,
+            RadiusOfInfluence::Constant(1500.0),
+        ),
+        (
+            Some(Axis {
+                start: 0.0,
+                spacing: 500.0,
+                count: 3,
+            }),
+            Weighting::Barnes,
+            RadiusOfInfluence::Beam {
+                min: 1000.0,
+                beam_width: 1.0,
+            },
+        ),
+    ] {
+        let spec = GridSpec {
+            x: Axis::centered(1000.0, 31),
+            y: Axis::centered(1000.0, 31),
+            z,
+            weighting,
+            radius,
+        };
+
+        // With the gpu feature, the default backend runs on the GPU if there is one
+        let cpu = grid_with(&radar, &Field::REF, &spec, Backend::Cpu);
+        let auto = grid(&radar, &Field::REF, &spec);
+        assert!(cpu.values.iter().any(|&value| !silv::is_missing(value)));
+
+        for (&a, &b) in cpu.values.iter().zip(&auto.values) {
+            assert_eq!(silv::is_missing(a), silv::is_missing(b));
+            assert!(
+                silv::is_missing(a) || (a - b).abs() < 1e-3,
+                "{} != {}",
+                a,
+                b
+            );
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn writes_a_sweep_at_a_time_with_low_memory() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let input = dir.path().join("input");
+    std::fs::create_dir(&input).unwrap();
+    std::fs::write(input.join("vol"), synth.nexrad()).unwrap();
+
+    for (format, files) in [(silv::Format::NEXRAD, 1), (silv::Format::CFRADIAL, 2)] {
+        let out = dir.path().join(format.to_string());
+        let options = RadyOptions {
+            files: input.join("*").to_str().unwrap().to_string(),
+            outdir: Some(out.to_str().unwrap().to_string()),
+            format,
+            low_memory: true,
+            ..Default::default()
+        };
+        silv::convert(&options);
+
+        let inventory: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(out.join("INVENTORY.json")).unwrap()).unwrap();
+        assert_eq!(inventory["volumes"], 1);
+
+        let written: Vec<_> = std::fs::read_dir(&out)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap() != "INVENTORY.json")
+            .collect();
+        assert_eq!(written.len(), files);
+
+        let sweeps: Vec<_> = written
+            .iter()
+            .flat_map(|path| silv::read(path, &options).sweeps)
+            .collect();
+        assert_eq!(sweeps.len(), 2);
+        assert!(sweeps.iter().all(|sweep| sweep.rays.len() == synth.nrays));
+    }
+}
+
+#[test]
+fn crops_gates_beyond_max_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // Gates are centered every 250 m from 2125 m, so the gate at 12125 m is the last one kept
+    let options = RadyOptions {
+        max_range: Some(12_200.0),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    for (index, sweep) in radar.sweeps.iter().enumerate() {
+        assert_eq!(sweep.ngates(&Field::REF), 41);
+        assert_eq!(sweep.ngates(&Field::VEL), 41);
+
+        for (gate, &value) in sweep.rays[0].data[&Field::REF].iter().enumerate() {
+            assert_eq!(value, synth.value(&Field::REF, index, 0, gate));
+        }
+    }
+
+    assert_eq!(radar.validate(), vec![]);
+}
+
+#[test]
+fn smooths_fields_along_range_and_azimuth() {
+    use silv::processing::{Filter, Smoothing};
+
+    let smoothing: Smoothing = "phi:median:5x3".parse().unwrap();
+    assert_eq!(
+        smoothing,
+        Smoothing {
+            field: Field::PHI,
+            filter: Filter::Median,
+            gates: 5,
+            rays: 3
+        }
+    );
+    assert_eq!("vel:boxcar:3".parse::<Smoothing>().unwrap().rays, 1);
+    assert!("vel:gaussian:3".parse::<Smoothing>().is_err());
+    assert!("vel:boxcar:0".parse::<Smoothing>().is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    for sweep in &mut radar.sweeps {
+        for ray in &mut sweep.rays {
+            ray.data
+                .get_mut(&Field::REF)
+                .unwrap()
+                .iter_mut()
+                .for_each(|gate| *gate = 10.0);
+            ray.data
+                .get_mut(&Field::VEL)
+                .unwrap()
+                .iter_mut()
+                .for_each(|gate| *gate = 10.0);
+        }
+
+        sweep.rays[10].data.get_mut(&Field::REF).unwrap()[50] = 100.0;
+        sweep.rays[10].data.get_mut(&Field::VEL).unwrap()[50] = 100.0;
+        sweep.rays[10].data.get_mut(&Field::VEL).unwrap()[52] = silv::MISSING;
+    }
+
+    let options = RadyOptions {
+        smooth: vec![
+            "ref:median:3x3".parse().unwrap(),
+            "vel:boxcar:3".parse().unwrap(),
+        ],
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    let ray = &radar.sweeps[0].rays[10];
+    assert!(ray.data[&Field::REF].iter().all(|&gate| gate == 10.0));
+    assert_eq!(ray.data[&Field::VEL][49..52], [40.0, 40.0, 55.0]);
+    assert!(silv::is_missing(ray.data[&Field::VEL][52]));
+    assert!(radar.sweeps[0].rays[11].data[&Field::VEL]
+        .iter()
+        .all(|&gate| gate == 10.0));
+}
+
+#[test]
+fn passes_selftest() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_silv"))
+        .arg("selftest")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+
+    let checks = [
+        "decode dorade",
+        "decode nexrad",
+        "round trip nexrad",
+        #[cfg(feature = "netcdf")]
+        "decode cfradial",
+        #[cfg(feature = "netcdf")]
+        "round trip cfradial",
+    ];
+
+    for check in checks {
+        assert!(stdout.contains(&format!("PASS {}", check)), "{}", stdout);
+    }
+}
+
+#[test]
+fn changes_gate_spacing() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let original = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let mut radar = original.clone();
+    let options = RadyOptions {
+        gate_spacing: Some(1000.0),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    assert_eq!(radar.params[&Field::REF].meters_between_cells, 1000.0);
+    assert_eq!(radar.params[&Field::REF].meters_to_first_cell, 2500.0);
+    assert_eq!(radar.validate(), vec![]);
+
+    let ray = &radar.sweeps[1].rays[7];
+    assert_eq!(ray.data[&Field::VEL].len(), 25);
+
+    for (gate, &value) in ray.data[&Field::VEL].iter().enumerate() {
+        let expected = (4 * gate..4 * gate + 4)
+            .map(|g| synth.value(&Field::VEL, 1, 7, g))
+            .sum::<f64>()
+            / 4.0;
+        assert!((value - expected).abs() < 1e-9);
+    }
+
+    let mut radar = original;
+    let options = RadyOptions {
+        gate_spacing: Some(125.0),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    assert_eq!(radar.params[&Field::REF].meters_to_first_cell, 2062.5);
+    let ray = &radar.sweeps[0].rays[3];
+    for (gate, &value) in ray.data[&Field::REF].iter().enumerate() {
+        assert_eq!(value, synth.value(&Field::REF, 0, 3, gate / 2));
+    }
+}
+
+#[test]
+fn merges_nexrad_split_cuts() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // A surveillance cut with reflectivity, then a Doppler cut with velocity at the same angle
+    radar.sweeps[0].nyquist_velocity = 0.0;
+    radar.sweeps[1].elevation = radar.sweeps[0].elevation;
+
+    for (sweep, field) in radar.sweeps.iter_mut().zip([Field::VEL, Field::REF]) {
+        for ray in &mut sweep.rays {
+            ray.data.remove(&field);
+        }
+    }
+
+    let options = RadyOptions {
+        merge_split_cuts: true,
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    assert_eq!(radar.sweeps.len(), 1);
+    assert_eq!(radar.sweeps[0].nyquist_velocity, synth.nyquist_velocity);
+
+    for (i, ray) in radar.sweeps[0].rays.iter().enumerate() {
+        assert_eq!(
+            ray.data[&Field::REF][20],
+            synth.value(&Field::REF, 0, i, 20)
+        );
+        assert_eq!(
+            ray.data[&Field::VEL][20],
+            synth.value(&Field::VEL, 1, i, 20)
+        );
+    }
+}
+
+#[test]
+fn derives_echo_tops() {
+    use silv::processing::beam_height;
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    for (sweep, value) in radar.sweeps.iter_mut().zip([30.0, 10.0]) {
+        for ray in &mut sweep.rays {
+            ray.data
+                .get_mut(&Field::REF)
+                .unwrap()
+                .iter_mut()
+                .for_each(|gate| *gate = value);
+        }
+    }
+
+    // Echoes reach the second tilt on one ray
+    radar.sweeps[1].rays[90]
+        .data
+        .get_mut(&Field::REF)
+        .unwrap()
+        .iter_mut()
+        .for_each(|gate| *gate = 25.0);
+
+    let options = RadyOptions {
+        echo_tops: Some(18.0),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+    assert_eq!(radar.sweeps.len(), 1);
+    assert_eq!(radar.params[&Field::ETH].units, "m");
+    assert_eq!(radar.validate(), vec![]);
+
+    let altitude = radar.instrument.altitude.unwrap_or(0.0) as f64;
+    let distance = 2125.0 + 40.0 * 250.0;
+    let beam =
+        |elevation: f32| beam_height(distance / elevation.to_radians().cos(), elevation) as f64;
+
+    let expected = altitude + beam(0.5) + (beam(1.5) - beam(0.5)) * 0.6;
+    assert!((radar.sweeps[0].rays[10].data[&Field::ETH][40] - expected).abs() < 1.0);
+    assert!((radar.sweeps[0].rays[90].data[&Field::ETH][40] - (altitude + beam(1.5))).abs() < 1.0);
+}
+
+#[test]
+fn derives_texture_fields() {
+    use silv::processing::Texture;
+
+    assert_eq!(
+        "vel".parse(),
+        Ok(Texture {
+            field: Field::VEL,
+            gates: 3,
+            rays: 3
+        })
+    );
+    assert_eq!(
+        "ref:5x3".parse(),
+        Ok(Texture {
+            field: Field::REF,
+            gates: 5,
+            rays: 3
+        })
+    );
+    assert!("ref:1".parse::<Texture>().is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    for sweep in &mut radar.sweeps {
+        for ray in &mut sweep.rays {
+            ray.data
+                .get_mut(&Field::VEL)
+                .unwrap()
+                .iter_mut()
+                .for_each(|gate| *gate = 10.0);
+        }
+
+        sweep.rays[10].data.get_mut(&Field::VEL).unwrap()[50] = 100.0;
+    }
+
+    let options = RadyOptions {
+        texture: vec!["vel".parse().unwrap()],
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    let texture = Field::Other("VEL_TEXTURE".to_string());
+    assert_eq!(
+        radar.params[&texture].units,
+        radar.params[&Field::VEL].units
+    );
+    assert_eq!(radar.validate(), vec![]);
+
+    let sweep = &radar.sweeps[0];
+    assert!((sweep.rays[10].data[&texture][50] - 30.0).abs() < 1e-9);
+    assert!((sweep.rays[11].data[&texture][51] - 30.0).abs() < 1e-9);
+    assert_eq!(sweep.rays[10].data[&texture][52], 0.0);
+    assert_eq!(sweep.rays[200].data[&texture][50], 0.0);
+}
+
+#[test]
+fn removes_field_biases_on_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::VEL, Field::ZDR],
+        ..Default::default()
+    };
+
+    let path = dir.path().join("vol");
+    std::fs::write(&path, synth.nexrad()).unwrap();
+
+    let options = RadyOptions {
+        bias: [(Field::ZDR, -0.25), (Field::REF, 1.5)]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let radar = silv::read(&path, &options);
+
+    for (index, sweep) in radar.sweeps.iter().enumerate() {
+        for (i, ray) in sweep.rays.iter().enumerate() {
+            for gate in [0, 33, 99] {
+                assert_eq!(
+                    ray.data[&Field::ZDR][gate],
+                    synth.value(&Field::ZDR, index, i, gate) - 0.25
+                );
+                assert_eq!(
+                    ray.data[&Field::REF][gate],
+                    synth.value(&Field::REF, index, i, gate) + 1.5
+                );
+                assert_eq!(
+                    ray.data[&Field::VEL][gate],
+                    synth.value(&Field::VEL, index, i, gate)
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn unfolds_phidp_and_removes_system_phase() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::PHI],
+        ..Default::default()
+    };
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // Phase rises 4 degrees a gate after the first 20 gates, on a 100 degree system phase, and
+    // wraps from 180 to -180
+    let phase = |gate: usize| 4.0 * gate.saturating_sub(20) as f64;
+    for ray in radar.sweeps.iter_mut().flat_map(|sweep| &mut sweep.rays) {
+        let data = ray.data.get_mut(&Field::PHI).unwrap();
+        for (gate, value) in data.iter_mut().enumerate() {
+            *value = (100.0 + phase(gate) + 180.0).rem_euclid(360.0) - 180.0;
+        }
+    }
+
+    let options = RadyOptions {
+        process_phidp: Some(1),
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    for ray in radar.sweeps.iter().flat_map(|sweep| &sweep.rays) {
+        for (gate, &value) in ray.data[&Field::PHI].iter().enumerate() {
+            assert!(
+                (value - phase(gate)).abs() < 1e-9,
+                "gate {}: {} != {}",
+                gate,
+                value,
+                phase(gate)
+            );
+        }
+    }
+}
+
+#[test]
+fn filters_gates_with_expressions() {
+    use silv::expr::Expr;
+
+    let parse = |s: &str| s.parse::<Expr>().unwrap();
+    assert_eq!(
+        parse("1 + 2 * 3 ^ 2 ^ 0.5 < -2^2 || !rho").to_string(),
+        "(((1 + (2 * (3 ^ (2 ^ 0.5)))) < -(2 ^ 2)) || !RHO)"
+    );
+    assert_eq!(
+        parse("abs(VEL) >= 1e-1 && REF != 3").fields(),
+        vec![Field::VEL, Field::REF]
+    );
+    assert!("REF <".parse::<Expr>().is_err());
+    assert!("(REF < 5".parse::<Expr>().is_err());
+    assert!("REF % 5".parse::<Expr>().is_err());
+
+    let value = |field: &Field| match field {
+        Field::REF => Some(2.0),
+        _ => None,
+    };
+    assert_eq!(parse("REF < 5 || RHO < 0.8").eval(&value), Some(1.0));
+    assert_eq!(parse("REF > 5 && RHO < 0.8").eval(&value), Some(0.0));
+    assert_eq!(parse("REF < 5 && RHO < 0.8").eval(&value), None);
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let options = RadyOptions {
+        filter: vec![parse("REF < 5 || vel > 5")],
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    for (index, sweep) in radar.sweeps.iter().enumerate() {
+        for (i, ray) in sweep.rays.iter().enumerate() {
+            for gate in 0..synth.ngates {
+                let (dbz, vel) = (
+                    synth.value(&Field::REF, index, i, gate),
+                    synth.value(&Field::VEL, index, i, gate),
+                );
+                let censored = dbz < 5.0 || vel > 5.0;
+
+                assert_eq!(silv::is_missing(ray.data[&Field::REF][gate]), censored);
+                assert_eq!(silv::is_missing(ray.data[&Field::VEL][gate]), censored);
+            }
+        }
+    }
+}
+
+#[test]
+fn derives_fields_from_expressions() {
+    use silv::expr::Derived;
+
+    assert!("VEL_KTS VEL".parse::<Derived>().is_err());
+    assert!("VEL KTS = VEL".parse::<Derived>().is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    let options = RadyOptions {
+        derive: vec![
+            "VEL_KTS = VEL * 1.944".parse().unwrap(),
+            "dbz_lin = 10^(REF/10) + VEL_KTS - VEL_KTS".parse().unwrap(),
+        ],
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    let (kts, lin) = (
+        Field::Other("VEL_KTS".into()),
+        Field::Other("dbz_lin".into()),
+    );
+    assert_eq!(
+        radar.params[&kts].meters_to_first_cell,
+        radar.params[&Field::VEL].meters_to_first_cell
+    );
+
+    for (index, sweep) in radar.sweeps.iter().enumerate() {
+        assert_eq!(sweep.gates.get(&kts), sweep.gates.get(&Field::VEL));
+
+        for (i, ray) in sweep.rays.iter().enumerate() {
+            for gate in 0..synth.ngates {
+                let (dbz, vel) = (
+                    synth.value(&Field::REF, index, i, gate),
+                    synth.value(&Field::VEL, index, i, gate),
+                );
+
+                assert!((ray.data[&kts][gate] - vel * 1.944).abs() < 1e-9);
+                assert!((ray.data[&lin][gate] - 10f64.powf(dbz / 10.0)).abs() < 1e-6);
+            }
+        }
+    }
+
+    // A derived field is found by later expressions whatever the case it is written in
+    let options = RadyOptions {
+        derive: vec![
+            "dbz2 = REF * 2".parse().unwrap(),
+            "DBZ4 = DBZ2 * 2".parse().unwrap(),
+            "Dbz2 = dbz4 / 4".parse().unwrap(),
+        ],
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    let (dbz2, dbz4) = (Field::Other("dbz2".into()), Field::Other("DBZ4".into()));
+    assert!(!radar.params.contains_key(&Field::Other("Dbz2".into())));
+    let ray = &radar.sweeps[0].rays[0];
+    assert_eq!(ray.data[&dbz4][0], 4.0 * synth.value(&Field::REF, 0, 0, 0));
+    assert_eq!(ray.data[&dbz2][0], synth.value(&Field::REF, 0, 0, 0));
+
+    // A field can't be derived from fields the radar doesn't have
+    let options = RadyOptions {
+        derive: vec!["KDP2 = KDP * 2".parse().unwrap()],
+        ..Default::default()
+    };
+    assert!(matches!(
+        options.apply_options(&mut radar),
+        Err(silv::Error::Unsupported(_))
+    ));
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn converts_files_in_parallel() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input");
+    std::fs::create_dir(&input).unwrap();
+
+    let synth = SyntheticVolume::default();
+    for i in 0..6 {
+        let volume = SyntheticVolume {
+            start_time: synth.start_time + chrono::Duration::minutes(5 * i),
+            ..synth.clone()
+        };
+        std::fs::write(input.join(format!("vol{}", i)), volume.nexrad()).unwrap();
+    }
+    std::fs::write(input.join("notes.txt"), "not radar data").unwrap();
+
+    let mut listings = Vec::new();
+    for jobs in [1, 4] {
+        let out = dir.path().join(format!("jobs{}", jobs));
+        let options = RadyOptions {
+            files: input.join("*").to_str().unwrap().to_string(),
+            outdir: Some(out.to_str().unwrap().to_string()),
+            format: silv::Format::CFRADIAL,
+            jobs,
+            ..Default::default()
+        };
+        silv::convert(&options);
+
+        let inventory: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(out.join("INVENTORY.json")).unwrap()).unwrap();
+        assert_eq!(inventory["volumes"], 6);
+        assert_eq!(inventory["skipped_files"], 1);
+
+        let mut written: Vec<_> = std::fs::read_dir(&out)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        written.sort();
+        listings.push(written);
+    }
+
+    assert_eq!(listings[0], listings[1]);
+    assert_eq!(listings[0].len(), 7);
+}
+
+#[test]
+fn reads_directories_recursively_with_patterns() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("archive");
+    std::fs::create_dir_all(archive.join("day1/radar")).unwrap();
+    std::fs::create_dir_all(archive.join("day2")).unwrap();
+
+    let synth = SyntheticVolume::default();
+    for (i, path) in ["day1/radar/a.vol", "day2/b.vol", "c.vol", "day2/skip.vol"]
+        .iter()
+        .enumerate()
+    {
+        let volume = SyntheticVolume {
+            start_time: synth.start_time + chrono::Duration::minutes(5 * i as i64),
+            ..synth.clone()
+        };
+        std::fs::write(archive.join(path), volume.nexrad()).unwrap();
+    }
+    std::fs::write(archive.join("day1/log.txt"), "not radar data").unwrap();
+
+    let out = dir.path().join("out");
+    let options = RadyOptions {
+        files: archive.to_str().unwrap().to_string(),
+        outdir: Some(out.to_str().unwrap().to_string()),
+        recursive: true,
+        include: vec![
+            glob::Pattern::new("*.vol").unwrap(),
+            glob::Pattern::new("*.txt").unwrap(),
+        ],
+        exclude: vec![
+            glob::Pattern::new("skip*").unwrap(),
+            glob::Pattern::new("*.txt").unwrap(),
+        ],
+        ..Default::default()
+    };
+    silv::convert(&options);
+
+    let inventory: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(out.join("INVENTORY.json")).unwrap()).unwrap();
+    assert_eq!(inventory["volumes"], 3);
+    assert_eq!(inventory["skipped_files"], 0);
+}
+
+#[test]
+#[cfg(feature = "watch")]
+fn watches_a_directory_for_new_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let incoming = dir.path().join("incoming");
+    let out = dir.path().join("out");
+    std::fs::create_dir(&incoming).unwrap();
+
+    let mut watcher = std::process::Command::new(env!("CARGO_BIN_EXE_silv"))
+        .args([
+            "--watch",
+            incoming.to_str().unwrap(),
+            "-o",
+            out.to_str().unwrap(),
+            "--exclude",
+            "*.part",
+        ])
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // The watch starts once the output directory is made
+    let started = std::time::Instant::now();
+    while !out.exists() && started.elapsed().as_secs() < 30 {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    let synth = SyntheticVolume::default();
+    std::fs::write(incoming.join("vol.part"), synth.nexrad()).unwrap();
+    std::fs::rename(incoming.join("vol.part"), incoming.join("vol")).unwrap();
+
+    let inventory = out.join("INVENTORY.json");
+    while !inventory.exists() && started.elapsed().as_secs() < 60 {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    watcher.kill().unwrap();
+    watcher.wait().unwrap();
+
+    let inventory: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(inventory).unwrap()).unwrap();
+    assert_eq!(inventory["volumes"], 1);
+}
+
+#[test]
+#[cfg(feature = "watch")]
+fn serves_files_to_several_outputs() {
+    use silv::serve::request;
+
+    let dir = tempfile::tempdir().unwrap();
+    let incoming = dir.path().join("incoming");
+    std::fs::create_dir(&incoming).unwrap();
+
+    let address = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let config = dir.path().join("serve.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "control = \"{}\"\n\n[[input]]\ndir = {:?}\n\n[[output]]\ndir = {:?}\n\n[[output]]\ndir = {:?}\nname = \"[icao]_%H%M%S\"\n",
+            address,
+            incoming,
+            dir.path().join("a"),
+            dir.path().join("b"),
+        ),
+    )
+    .unwrap();
+
+    let mut service = std::process::Command::new(env!("CARGO_BIN_EXE_silv"))
+        .args([
+            "serve",
+            "--config",
+            config.to_str().unwrap(),
+            "--exclude",
+            "*.part",
+        ])
+        .spawn()
+        .unwrap();
+
+    // The control socket is opened before the inputs are watched
+    let started = std::time::Instant::now();
+    while request(&address, "status").is_err() && started.elapsed().as_secs() < 30 {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    let synth = SyntheticVolume::default();
+    std::fs::write(incoming.join("vol.part"), synth.nexrad()).unwrap();
+    std::fs::rename(incoming.join("vol.part"), incoming.join("vol")).unwrap();
+
+    let mut status = serde_json::Value::Null;
+    while status["converted_files"] != 1 && started.elapsed().as_secs() < 60 {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        status = serde_json::from_str(&request(&address, "status").unwrap()).unwrap();
+    }
+
+    assert_eq!(request(&address, "stop").unwrap(), "stopping");
+    assert!(service.wait().unwrap().success());
+
+    assert_eq!(status["converted_files"], 1);
+    assert_eq!(status["outputs"][1][1]["volumes"], 1);
+    assert_matches(
+        &silv::read(dir.path().join("b/KTST_233000"), &RadyOptions::default()),
+        &synth,
+        &[0, 1],
+        0.0,
+    );
+    assert!(dir.path().join("a/INVENTORY.json").exists());
+}
+
+#[test]
+fn streams_through_stdin_and_stdout() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_silv"))
+        .args(["-F", "nexrad", "-f", "-", "-o", "-", "--flip-velocity"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&synth.nexrad())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let radar = read_bytes(dir.path(), "streamed", &output.stdout);
+    assert_eq!(radar.sweeps.len(), synth.nsweeps);
+    assert_eq!(
+        radar.sweeps[0].rays[3].data[&Field::VEL][5],
+        -synth.value(&Field::VEL, 0, 3, 5)
+    );
+
+    // Input from stdin is written to a folder like any other file
+    let mut child = Command::new(env!("CARGO_BIN_EXE_silv"))
+        .current_dir(dir.path())
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&synth.nexrad())
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+
+    let inventory: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(dir.path().join("output/INVENTORY.json")).unwrap())
+            .unwrap();
+    assert_eq!(inventory["volumes"], 1);
+}
+
+#[test]
+fn selects_fields_when_reading() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        fields: vec![Field::REF, Field::VEL, Field::ZDR],
+        ..Default::default()
+    };
+    let path = dir.path().join("vol");
+    std::fs::write(&path, synth.nexrad()).unwrap();
+
+    let keys = |options: &RadyOptions| {
+        let radar = silv::read(&path, options);
+        let mut fields: Vec<Field> = radar.params.keys().cloned().collect();
+        fields.sort();
+
+        for ray in radar.sweeps.iter().flat_map(|sweep| &sweep.rays) {
+            let mut data: Vec<Field> = ray.data.keys().cloned().collect();
+            data.sort();
+            assert_eq!(data, fields);
+        }
+
+        fields
+    };
+
+    let listed = RadyOptions {
+        fields: Some(vec![Field::REF, Field::ZDR]),
+        ..Default::default()
+    };
+    assert_eq!(keys(&listed), vec![Field::REF, Field::ZDR]);
+
+    let excluded = RadyOptions {
+        exclude_fields: vec![Field::VEL],
+        ..Default::default()
+    };
+    assert_eq!(keys(&excluded), vec![Field::REF, Field::ZDR]);
+
+    let both = RadyOptions {
+        fields: Some(vec![Field::REF, Field::ZDR]),
+        exclude_fields: vec![Field::ZDR],
+        ..Default::default()
+    };
+    assert_eq!(keys(&both), vec![Field::REF]);
+}
+
+#[test]
+fn selects_sweeps_by_position_and_elevation() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        nsweeps: 4,
+        ..Default::default()
+    };
+    let path = dir.path().join("vol");
+    std::fs::write(&path, synth.nexrad()).unwrap();
+
+    let elevations = |options: &RadyOptions| -> Vec<f32> {
+        silv::read(&path, options)
+            .sweeps
+            .iter()
+            .map(|sweep| sweep.elevation)
+            .collect()
+    };
+    let expected =
+        |indices: &[usize]| -> Vec<f32> { indices.iter().map(|&i| synth.elevation(i)).collect() };
+
+    let by_position = RadyOptions {
+        sweeps: Some(vec![0, 2, 7]),
+        ..Default::default()
+    };
+    assert_eq!(elevations(&by_position), expected(&[0, 2]));
+
+    let by_elevation = RadyOptions {
+        elevations: Some(vec![synth.elevation(1) + 0.1, synth.elevation(3) - 0.3]),
+        ..Default::default()
+    };
+    assert_eq!(elevations(&by_elevation), expected(&[1]));
+
+    let wider = RadyOptions {
+        elevation_tolerance: 0.4,
+        ..by_elevation
+    };
+    assert_eq!(elevations(&wider), expected(&[1, 3]));
+}
+
+#[test]
+fn selects_and_counts_sweeps_by_fixed_angle() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let mut radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    // Fixed angles far from the measured elevations, as when the antenna lags its schedule
+    for (sweep, fixed_angle) in radar.sweeps.iter_mut().zip([3.0, 4.0]) {
+        sweep.fixed_angle = Some(fixed_angle);
+    }
+
+    let mut inventory = silv::Inventory::default();
+    inventory.add(&radar);
+    assert_eq!(
+        inventory
+            .tilts
+            .iter()
+            .map(|tilt| tilt.elevation)
+            .collect::<Vec<_>>(),
+        [3.0, 4.0]
+    );
+
+    let info = silv::FileInfo::new(&radar);
+    assert_eq!(info.sweeps[0].elevation, 3.0);
+
+    // A measured elevation that isn't a number doesn't stop sorting by fixed angle
+    let mut sorted = radar.clone();
+    sorted.sweeps[0].fixed_angle = Some(5.0);
+    sorted.sweeps[1].elevation = f32::NAN;
+    sorted.sort_sweeps_by_elevation();
+    assert_eq!(sorted.sweeps[0].fixed_angle, Some(4.0));
+
+    let by_measured = RadyOptions {
+        elevations: Some(vec![synth.elevation(0)]),
+        ..Default::default()
+    };
+    let mut selected = radar.clone();
+    by_measured.select_sweeps(&mut selected);
+    assert!(selected.sweeps.is_empty());
+
+    let by_fixed = RadyOptions {
+        elevations: Some(vec![3.1]),
+        ..Default::default()
+    };
+    by_fixed.select_sweeps(&mut radar);
+    assert_eq!(radar.sweeps.len(), 1);
+    assert_eq!(radar.sweeps[0].fixed_angle, Some(3.0));
+}
+
+#[test]
+fn skips_files_outside_the_time_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input");
+    std::fs::create_dir(&input).unwrap();
+
+    // Volumes start every 5 minutes from 23:30 and last 72 seconds
+    let synth = SyntheticVolume::default();
+    for i in 0..4 {
+        let volume = SyntheticVolume {
+            start_time: synth.start_time + chrono::Duration::minutes(5 * i),
+            ..synth.clone()
+        };
+        std::fs::write(input.join(format!("vol{}", i)), volume.nexrad()).unwrap();
+    }
+
+    let out = dir.path().join("out");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_silv"))
+        .args([
+            "-f",
+            input.join("*").to_str().unwrap(),
+            "-o",
+            out.to_str().unwrap(),
+        ])
+        .args([
+            "--start",
+            "2022-05-04 23:36:00",
+            "--end",
+            "2022-05-04T23:40:00Z",
+        ])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let inventory: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(out.join("INVENTORY.json")).unwrap()).unwrap();
+    assert_eq!(inventory["volumes"], 2);
+    assert_eq!(inventory["start_time"], "2022-05-04T23:35:00Z");
+}
+
+#[test]
+fn follows_the_overwrite_policy() {
+    use silv::OnConflict;
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("vol");
+    std::fs::write(&input, SyntheticVolume::default().nexrad()).unwrap();
+
+    let out = dir.path().join("out");
+    let written = || {
+        let mut files: Vec<_> = std::fs::read_dir(&out)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap() != "INVENTORY.json")
+            .collect();
+        files.sort();
+        files
+    };
+
+    let convert = |on_conflict| {
+        let options = RadyOptions {
+            files: input.to_str().unwrap().to_string(),
+            outdir: Some(out.to_str().unwrap().to_string()),
+            on_conflict,
+            ..Default::default()
+        };
+        silv::convert(&options);
+    };
+
+    convert(OnConflict::Suffix);
+    let first = written();
+    assert_eq!(first.len(), 1);
+
+    convert(OnConflict::Suffix);
+    let files = written();
+    assert_eq!(files.len(), 2);
+    assert_eq!(
+        files[1].file_name().unwrap().to_str().unwrap(),
+        format!("{}_1", first[0].file_name().unwrap().to_str().unwrap())
+    );
+
+    // A skipped file is left as it was, and an overwritten one is replaced
+    std::fs::write(&first[0], b"kept").unwrap();
+    convert(OnConflict::Skip);
+    assert_eq!(written().len(), 2);
+    assert_eq!(std::fs::read(&first[0]).unwrap(), b"kept");
+
+    convert(OnConflict::Overwrite);
+    assert_eq!(written().len(), 2);
+    assert_eq!(
+        silv::read(&first[0], &RadyOptions::default()).sweeps.len(),
+        2
+    );
+}
+
+#[test]
+fn continues_past_files_that_fail() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input");
+    std::fs::create_dir(&input).unwrap();
+
+    std::fs::write(input.join("good"), SyntheticVolume::default().nexrad()).unwrap();
+    std::fs::write(input.join("truncated"), b"AR2V0006.").unwrap();
+    assert!(matches!(
+        silv::try_read(input.join("truncated"), &RadyOptions::default()),
+        Err(silv::Error::Format(_))
+    ));
+
+    let out = dir.path().join("out");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_silv"))
+        .args([
+            "-f",
+            input.join("*").to_str().unwrap(),
+            "-o",
+            out.to_str().unwrap(),
+        ])
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(
+        stderr.contains("Failed to convert") && stderr.contains("1 files failed to convert"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+
+    let inventory: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(out.join("INVENTORY.json")).unwrap()).unwrap();
+    assert_eq!(inventory["volumes"], 1);
+    assert_eq!(inventory["failed_files"], 1);
+}
+
+#[test]
+fn reports_damaged_files_as_format_errors() {
+    let synth = SyntheticVolume::default();
+
+    for bytes in [synth.nexrad(), synth.dorade(0)] {
+        for i in 1..50 {
+            let end = bytes.len() * i / 50;
+
+            // Cut short, and with a run of bytes overwritten, files either read or fail to
+            let mut damaged = bytes.clone();
+            damaged[end..(end + 8).min(bytes.len())].fill(0xff);
+
+            for bytes in [&bytes[..end], &damaged[..]] {
+                match silv::read_from_bytes(bytes, &RadyOptions::default()) {
+                    Ok(_) | Err(silv::Error::Format(_)) => {}
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn generates_completions_and_man_page() {
+    let run = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_silv"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    for shell in ["bash", "zsh", "fish"] {
+        let script = run(&["completions", shell]);
+        assert!(
+            script.contains("despeckle") && script.contains("silv"),
+            "{}",
+            script
+        );
+    }
+
+    let page = silv::man_page();
+    assert_eq!(run(&["--man"]), page);
+    assert!(page.starts_with(".TH SILV 1"));
+    assert!(page.contains("\\fB\\-f\\fR, \\fB\\-\\-file\\fR \\fIFILES\\fR"));
+    assert!(page.contains("\\fBselftest\\fR"));
+    assert!(!page.contains("completions"));
+}
+
+#[test]
+fn prints_file_info() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let input = dir.path().join("vol");
+    std::fs::write(&input, synth.nexrad()).unwrap();
+
+    let info = silv::FileInfo::new(&silv::read(&input, &RadyOptions::default()));
+    assert_eq!(info.sweeps.len(), synth.nsweeps);
+    assert_eq!(info.sweeps[1].rays, synth.nrays);
+    assert_eq!(info.sweeps[1].gates["VEL"], synth.ngates);
+    assert_eq!(
+        info.fields["REF"].meters_to_first_cell,
+        synth.meters_to_first_cell
+    );
+
+    let run = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_silv"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let json: serde_json::Value =
+        serde_json::from_str(&run(&["info", "--json", "-f", input.to_str().unwrap()])).unwrap();
+    assert_eq!(json[0]["file"], input.to_str().unwrap());
+    assert_eq!(json[0]["name"], synth.name);
+    assert_eq!(json[0]["sweeps"].as_array().unwrap().len(), synth.nsweeps);
+    assert_eq!(json[0]["sweeps"][0]["elevation"], 0.5);
+    assert_eq!(
+        json[0]["fields"]["VEL"]["meters_between_cells"],
+        synth.meters_between_cells as f64
+    );
+
+    let text = run(&["info", "-f", input.to_str().unwrap()]);
+    assert!(
+        text.contains(&format!("Radar: {}", synth.name)) && text.contains("Sweeps: 2"),
+        "{}",
+        text
+    );
+    assert!(
+        std::fs::read_dir(dir.path()).unwrap().count() == 1,
+        "info wrote files"
+    );
+
+    // A file that can't be read fails the run, after the others are summarized
+    let damaged = tempfile::tempdir().unwrap();
+    std::fs::write(damaged.path().join("a"), synth.nexrad()).unwrap();
+    std::fs::write(damaged.path().join("b"), &synth.nexrad()[..100]).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_silv"))
+        .args(["info", "-f", damaged.path().join("*").to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Sweeps: 2"));
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn verifies_written_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("vol");
+    let synth = SyntheticVolume::default();
+    std::fs::write(&input, synth.nexrad()).unwrap();
+
+    for format in [silv::Format::NEXRAD, silv::Format::CFRADIAL] {
+        let options = RadyOptions {
+            files: input.to_str().unwrap().to_string(),
+            outdir: Some(dir.path().join(format.name()).to_str().unwrap().to_string()),
+            format,
+            verify: true,
+            ..Default::default()
+        };
+        assert_eq!(silv::convert(&options), 0);
+    }
+
+    // A writer that loses a sweep fails verification, which counts the file as failed
+    struct Lossy;
+
+    impl silv::RadarWrite for Lossy {
+        fn name(&self) -> &'static str {
+            "lossy"
+        }
+
+        fn write(
+            &self,
+            mut radar: RadarFile,
+            path: &Path,
+            options: &RadyOptions,
+        ) -> Result<(), silv::Error> {
+            radar.sweeps.pop();
+            silv::writer("nexrad").unwrap().write(radar, path, options)
+        }
+    }
+
+    silv::register_writer(Lossy);
+    let options = RadyOptions {
+        files: input.to_str().unwrap().to_string(),
+        outdir: Some(dir.path().join("lossy").to_str().unwrap().to_string()),
+        format: silv::Format::Custom("lossy"),
+        verify: true,
+        ..Default::default()
+    };
+    assert_eq!(silv::convert(&options), 1);
+
+    // Dropped sweeps and values that changed by more than the tolerance are reported
+    let source = silv::read(&input, &RadyOptions::default());
+    let mut written = source.clone();
+    written.sweeps.pop();
+    assert_eq!(
+        source.differences(&written, |_| 0.5),
+        vec!["1 sweeps were written instead of 2".to_string()]
+    );
+
+    written.sweeps.push(source.sweeps[1].clone());
+    written.sweeps[1].rays[10]
+        .data
+        .get_mut(&Field::VEL)
+        .unwrap()[5] += 1.0;
+    assert_eq!(
+        source.differences(&written, |_| 0.5),
+        vec!["sweep 1 has 1 VEL gates that changed, by up to 1".to_string()]
+    );
+    assert!(source.differences(&written, |_| 1.5).is_empty());
+}
+
+#[test]
+fn reads_files_from_a_list() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    for name in ["a", "b", "c"] {
+        std::fs::write(dir.path().join(name), synth.nexrad()).unwrap();
+    }
+
+    let list = dir.path().join("list.txt");
+    std::fs::write(
+        &list,
+        format!(
+            "{}\n\n  {}\n",
+            dir.path().join("a").display(),
+            dir.path().join("c").display()
+        ),
+    )
+    .unwrap();
+
+    let options = RadyOptions {
+        file_list: Some(list.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    assert_eq!(silv::convert(&options), 0);
+
+    // Output goes next to the list, and only the listed files are converted
+    let written = std::fs::read_dir(dir.path().join("output"))
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().file_name() != "INVENTORY.json")
+        .count();
+    assert_eq!(written, 2);
+}
+
+#[test]
+fn converts_with_radxconvert_arguments() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+
+    let mut args = vec!["-f".to_string()];
+    for sweep in 0..synth.nsweeps {
+        let path = dir.path().join(format!("swp.{}", sweep));
+        std::fs::write(&path, synth.dorade(sweep)).unwrap();
+        args.push(path.to_str().unwrap().to_string());
+    }
+
+    let outdir = dir.path().join("out");
+    args.extend(
+        [
+            "-outdir",
+            outdir.to_str().unwrap(),
+            "-ag",
+            "-nexrad",
+            "-debug",
+        ]
+        .map(String::from),
+    );
+
+    let options = silv::radx::parse(&args).unwrap();
+    assert_eq!(silv::convert(&options), 0);
+
+    // The sweep files are aggregated into a volume, in a directory for its day, named with the
+    // volume number from the DORADE volume description
+    let radar = silv::read(
+        outdir.join("20220504/KTST20220504_233000_v1"),
+        &RadyOptions::default(),
+    );
+    assert_matches(&radar, &synth, &[0, 1], 0.5);
+    assert_eq!(radar.volume_number, Some(1));
+
+    assert!(silv::radx::parse(&["-f".to_string(), "a".to_string(), "-foray".to_string()]).is_err());
+    assert!(silv::radx::parse(&["-outdir".to_string()]).is_err());
+}
+
+#[test]
+fn reads_and_writes_in_memory() {
+    let synth = SyntheticVolume::default();
+
+    let radar = silv::read_from_bytes(&synth.nexrad(), &RadyOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_matches(&radar, &synth, &[0, 1], 0.0);
+
+    let sweep = silv::read_from_bytes(&synth.dorade(1), &RadyOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_matches(&sweep, &synth, &[1], 0.006);
+
+    let written =
+        silv::write_to_bytes(radar, silv::Format::NEXRAD, &RadyOptions::default()).unwrap();
+    assert_matches(
+        &silv::read_from_bytes(&written, &RadyOptions::default())
+            .unwrap()
+            .unwrap(),
+        &synth,
+        &[0, 1],
+        0.0,
+    );
+
+    // CfRadial goes through the netCDF library, which only works on files
+    assert!(
+        silv::read_from_bytes(b"\x89HDF\r\n\x1a\n", &RadyOptions::default())
+            .unwrap()
+            .is_none()
+    );
+    assert!(silv::write_to_bytes(sweep, silv::Format::CFRADIAL, &RadyOptions::default()).is_none());
+}
+
+#[test]
+#[cfg(feature = "s3")]
+fn parses_s3_listings_and_archive_names() {
+    use chrono::{TimeZone, Utc};
+    use silv::s3::{key_time, parse_listing, S3Path};
+
+    assert_eq!(
+        S3Path::parse("s3://noaa-nexrad-level2/2022/05/04/KTLX/"),
+        Some(S3Path {
+            bucket: "noaa-nexrad-level2".to_string(),
+            prefix: "2022/05/04/KTLX/".to_string()
+        })
+    );
+    assert_eq!(
+        S3Path::parse("s3://bucket"),
+        Some(S3Path {
+            bucket: "bucket".to_string(),
+            prefix: String::new()
+        })
+    );
+    assert_eq!(S3Path::parse("data/KTLX"), None);
+
+    let page = "<ListBucketResult><IsTruncated>true</IsTruncated>\
+        <Contents><Key>2022/05/04/KTLX/KTLX20220504_233000_V06</Key></Contents>\
+        <Contents><Key>2022/05/04/KTLX/KTLX20220504_233000_V06_MDM</Key></Contents>\
+        <NextContinuationToken>a&amp;b</NextContinuationToken></ListBucketResult>";
+    let (keys, token) = parse_listing(page);
+    assert_eq!(
+        keys,
+        [
+            "2022/05/04/KTLX/KTLX20220504_233000_V06",
+            "2022/05/04/KTLX/KTLX20220504_233000_V06_MDM"
+        ]
+    );
+    assert_eq!(token.as_deref(), Some("a&b"));
+    assert_eq!(
+        parse_listing("<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>"),
+        (vec![], None)
+    );
+
+    assert_eq!(
+        key_time(&keys[0]),
+        Some(Utc.with_ymd_and_hms(2022, 5, 4, 23, 30, 0).unwrap())
+    );
+    assert_eq!(
+        key_time("2011/05/20/KTLX/KTLX20110520_000358_V03.gz"),
+        Some(Utc.with_ymd_and_hms(2011, 5, 20, 0, 3, 58).unwrap())
+    );
+    assert_eq!(key_time("2022/05/04/KTLX/NOTES.txt"), None);
+}
+
+#[test]
+fn assembles_chunks_of_the_realtime_feed() {
+    use silv::nexrad::ChunkedVolume;
+
+    let synth = SyntheticVolume {
+        nsweeps: 3,
+        compress: true,
+        ..Default::default()
+    };
+    let bytes = synth.nexrad();
+
+    // The feed sends the volume header with the first record, then a record in each chunk
+    let mut chunks = vec![bytes[..24].to_vec()];
+    let mut rest = &bytes[24..];
+    while !rest.is_empty() {
+        let len = 4 + u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        chunks.push(rest[..len].to_vec());
+        rest = &rest[len..];
+    }
+    let first = chunks.remove(0);
+    chunks[0].splice(0..0, first);
+    assert_eq!(chunks.len(), 3);
+
+    let mut volume = ChunkedVolume::default();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let radar = volume.push(chunk, &RadyOptions::default()).unwrap();
+        assert_eq!(radar.sweeps.len(), 1);
+        assert!((radar.sweeps[0].elevation - synth.elevation(i)).abs() < 0.01);
+    }
+
+    // Chunks can end partway through a sweep, which is returned by the chunk that ends it
+    let uncompressed = SyntheticVolume {
+        nsweeps: 2,
+        ..Default::default()
+    };
+    let bytes = uncompressed.nexrad();
+    let mut ends = Vec::new();
+    let mut end = 24;
+    while end < bytes.len() {
+        end += 12 + 2 * u16::from_be_bytes(bytes[end + 12..end + 14].try_into().unwrap()) as usize;
+        ends.push(end);
+    }
+
+    let mut volume = ChunkedVolume::default();
+    let mut sweeps = Vec::new();
+    let mut start = 0;
+    for &end in ends.iter().skip(6).step_by(7).chain(ends.last()) {
+        let radar = volume
+            .push(&bytes[start..end], &RadyOptions::default())
+            .unwrap();
+        sweeps.extend(radar.sweeps);
+        start = end;
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let whole = read_bytes(dir.path(), "vol", &bytes);
+    assert_eq!(sweeps.len(), 2);
+    for (sweep, expected) in sweeps.iter().zip(&whole.sweeps) {
+        assert_eq!(sweep.rays.len(), uncompressed.nrays);
+        let (data, expected) = (sweep.get_data(&Field::REF), expected.get_data(&Field::REF));
+        assert!(data
+            .iter()
+            .flatten()
+            .zip(expected.iter().flatten())
+            .all(|(a, b)| a == b || (a.is_nan() && b.is_nan())));
+    }
+
+    // Chunks are added in order of their numbers, and sweeps are written as they complete
+    let feed = dir.path().join("KTLX");
+    std::fs::create_dir(&feed).unwrap();
+    for (name, chunk) in ["20220504-233000-001-S", "20220504-233000-003-E"]
+        .iter()
+        .zip([&chunks[0], &chunks[2]])
+    {
+        std::fs::write(feed.join(name), chunk).unwrap();
+    }
+
+    let out = dir.path().join("out");
+    let options = RadyOptions {
+        files: feed.join("*").to_str().unwrap().to_string(),
+        outdir: Some(out.to_str().unwrap().to_string()),
+        realtime: true,
+        ..Default::default()
+    };
+    let written = || {
+        std::fs::read_dir(&out)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name() != "INVENTORY.json")
+            .count()
+    };
+
+    assert_eq!(silv::convert(&options), 0);
+    assert_eq!(written(), 1);
+
+    std::fs::write(feed.join("20220504-233000-002-I"), &chunks[1]).unwrap();
+    assert_eq!(
+        silv::convert(&RadyOptions {
+            on_conflict: silv::OnConflict::Skip,
+            ..options
+        }),
+        0
+    );
+    assert_eq!(written(), 3);
+}
+
+#[test]
+#[cfg(feature = "http")]
+fn converts_files_from_urls() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let body = SyntheticVolume::default().nexrad();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/KTLX/vol", listener.local_addr().unwrap());
+
+    // The second request gets a file too large to download
+    let server = std::thread::spawn(move || {
+        for len in [body.len() as u64, 1 << 40] {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                len
+            )
+            .unwrap();
+            if len == body.len() as u64 {
+                stream.write_all(&body).unwrap();
+            }
+        }
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let options = RadyOptions {
+        files: url,
+        outdir: Some(out.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+
+    assert_eq!(silv::convert(&options), 0);
+    assert_eq!(silv::convert(&options), 1);
+    server.join().unwrap();
+
+    let written: Vec<_> = std::fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.file_name().unwrap() != "INVENTORY.json")
+        .collect();
+    assert_eq!(written.len(), 1);
+    assert_matches(
+        &silv::read(&written[0], &RadyOptions::default()),
+        &SyntheticVolume::default(),
+        &[0, 1],
+        0.0,
+    );
+}
+
+#[test]
+#[cfg(feature = "netcdf")]
+fn names_cfradial_fields_by_convention() {
+    use silv::Naming;
+
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume::default();
+    let radar = read_bytes(dir.path(), "vol", &synth.nexrad());
+
+    for (naming, reflectivity, velocity) in [
+        (Naming::Short, "DBZ", "VEL"),
+        (Naming::PyArt, "reflectivity", "velocity"),
+        (
+            Naming::Cf,
+            "equivalent_reflectivity_factor",
+            "radial_velocity_of_scatterers_away_from_instrument",
+        ),
+        (Naming::Opera, "DBZH", "VRADH"),
+    ] {
+        let out = dir.path().join(format!("{:?}", naming));
+        let options = RadyOptions {
+            format: silv::Format::CFRADIAL,
+            naming,
+            ..Default::default()
+        };
+        silv::write(radar.clone(), &out, &options).unwrap();
+
+        let written = std::fs::read_dir(&out)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let file = netcdf::open(&written).unwrap();
+        let standard_name = |name: &str| {
+            file.variable(name)
+                .unwrap()
+                .attribute("standard_name")
+                .map(|a| a.value().unwrap())
+        };
+
+        assert!(file.variable(reflectivity).is_some(), "{:?}", naming);
+        assert_eq!(
+            standard_name(velocity),
+            Some(netcdf::AttrValue::Str(
+                "radial_velocity_of_scatterers_away_from_instrument".to_string()
+            ))
+        );
+        assert_eq!(
+            standard_name(reflectivity).is_some(),
+            naming != Naming::Short
+        );
+
+        // Each convention is read back
+        assert_matches(
+            &silv::read(&written, &RadyOptions::default()),
+            &synth,
+            &[0, 1],
+            0.006,
+        );
+    }
+
+    assert_eq!("PyART".parse(), Ok(Naming::PyArt));
+    assert!("radx".parse::<Naming>().is_err());
+}
+
+#[test]
+fn drops_duplicate_azimuth_rays() {
+    let dir = tempfile::tempdir().unwrap();
+    let synth = SyntheticVolume {
+        nsweeps: 1,
+        ..Default::default()
+    };
+    let mut radar = read_bytes(dir.path(), "swp", &synth.dorade(0));
+    let rays = &mut radar.sweeps[0].rays;
+
+    // A later repeat with fewer gates, a later repeat just off the azimuth, and an earlier one across north
+    let mut sparse = rays[10].clone();
+    sparse.time += chrono::Duration::seconds(60);
+    for values in sparse.data.values_mut() {
+        values[synth.ngates / 2..].fill(silv::MISSING);
+    }
+
+    let mut later = rays[20].clone();
+    later.time += chrono::Duration::seconds(60);
+    later.azimuth += 0.05;
+
+    let mut earlier = rays[0].clone();
+    earlier.time -= chrono::Duration::seconds(60);
+    earlier.azimuth = (earlier.azimuth - 0.05).rem_euclid(360.0);
+
+    rays.insert(11, sparse);
+    rays.push(later.clone());
+    rays.push(earlier);
+
+    let options = RadyOptions {
+        dedup_rays: true,
+        ..Default::default()
+    };
+    options.apply_options(&mut radar).unwrap();
+
+    let rays = &radar.sweeps[0].rays;
+    assert_eq!(rays.len(), synth.nrays);
+    assert_eq!(rays[0].time, synth.time(0, 0));
+    assert_eq!(rays[10].time, synth.time(0, 10));
+    assert!(rays[10]
+        .data
+        .values()
+        .flatten()
+        .all(|&value| !silv::is_missing(value)));
+    assert!(!rays[..synth.nrays - 1]
+        .iter()
+        .any(|ray| ray.azimuth == synth.azimuth(20)));
+    assert_eq!(rays[synth.nrays - 1].azimuth, later.azimuth);
+}